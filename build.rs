@@ -13,6 +13,7 @@ fn main() {
     BuildEnv::request_reruns();
     let config = load_config_from_env_or_default();
     config.print_boolean_feature_flags();
+    print_smp_feature_flag(&config);
     println!("ferros build.rs config: {:#?}", config);
 
     let out_dir = PathBuf::from(env::var("OUT_DIR").expect("Required env var OUT_DIR not set"));
@@ -23,6 +24,16 @@ fn main() {
     generate_kernel_retype_fan_out_limit_types(&out_dir, &config)
 }
 
+/// The kernel derives its SMP support from the node count rather than
+/// taking a boolean, so the flag has to be worked out here.
+fn print_smp_feature_flag(config: &Contextualized) {
+    if let Some(SingleValue::Integer(nodes)) = config.sel4_config.get("KernelMaxNumNodes") {
+        if *nodes > 1 {
+            println!("cargo:rustc-cfg=KernelEnableSMPSupport");
+        }
+    }
+}
+
 fn generate_root_task_stack_types(out_dir: &Path, config: &Contextualized) {
     // TODO - check against target-pointer-width or similar for 32/64 bit
     // differences and panic if unsupported Gleaned from:
@@ -87,6 +87,12 @@ echo "====================== ./smart_alloc ==========================="
     cargo test
 )
 
+echo "====================== ./rpc_service ==========================="
+(
+    cd rpc_service
+    cargo test
+)
+
 echo "====================== ./cross_queue ==========================="
 (
     cd cross_queue
@@ -122,6 +122,7 @@ fn run(raw_bootinfo: &'static sel_claw::seL4_BootInfo) -> Result<(), TopLevelErr
             ut, // tcb_ut
             slots,
             &tpa, // priority_authority
+            ThreadPriorities::default(),
             None, // fault
         )?;
     });
@@ -139,4 +140,3 @@ fn run(raw_bootinfo: &'static sel_claw::seL4_BootInfo) -> Result<(), TopLevelErr
 fn panic(info: &core::panic::PanicInfo) -> ! {
     loop {}
 }
-
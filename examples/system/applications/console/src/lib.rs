@@ -25,11 +25,8 @@ pub struct ProcParams<Role: CNodeRole> {
     pub int_consumer: InterruptConsumer<uart1::Irq, Role>,
 
     /// IPC to the storage driver
-    pub storage_caller: Caller<
-        persistent_storage::Request,
-        Result<persistent_storage::Response, persistent_storage::ErrorCode>,
-        Role,
-    >,
+    pub storage_caller:
+        Caller<persistent_storage::StorageRequest, persistent_storage::StorageResponse, Role>,
 
     /// Producer of UDP messages destined to the TCP/IP driver
     pub udp_producer: Producer<Role, IpcUdpTransmitBuffer>,
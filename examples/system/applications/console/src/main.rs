@@ -6,14 +6,12 @@ use selfe_runtime as _;
 use console::ProcParams;
 use core::fmt::{self, Write as WriteFmt};
 use debug_logger::DebugLogger;
-use ferros::{
-    cap::role,
-    userland::{Caller, Producer},
-};
+use ferros::{cap::role, userland::Producer};
 use imx6_hal::embedded_hal::serial::Read;
 use imx6_hal::{pac::uart1::UART1, serial::Serial};
 use menu::*;
 use net_types::{EthernetFrameBuffer, IpcUdpTransmitBuffer};
+use persistent_storage::StorageClient;
 
 static LOGGER: DebugLogger = DebugLogger;
 
@@ -30,7 +28,7 @@ pub extern "C" fn _start(params: ProcParams<role::Local>) -> ! {
     let serial = Serial::new(params.uart);
     let context = Context {
         serial,
-        storage_caller: params.storage_caller,
+        storage: StorageClient::new(params.storage_caller),
         udp_producer: params.udp_producer,
     };
 
@@ -53,11 +51,7 @@ pub extern "C" fn _start(params: ProcParams<role::Local>) -> ! {
 
 pub struct Context {
     serial: Serial<UART1>,
-    storage_caller: Caller<
-        persistent_storage::Request,
-        Result<persistent_storage::Response, persistent_storage::ErrorCode>,
-        role::Local,
-    >,
+    storage: StorageClient<role::Local>,
     udp_producer: Producer<role::Local, IpcUdpTransmitBuffer>,
 }
 
@@ -175,14 +169,10 @@ fn enter_root_menu(_menu: &Menu<Context>, context: &mut Context) {
 
 mod storage {
     use super::*;
-    use persistent_storage::{ErrorCode, Key, Request, Response, Value};
+    use persistent_storage::{Key, StorageResponse, Value};
 
-    fn print_resp(context: &mut Context, resp: &Result<Response, ErrorCode>) {
-        if let Ok(r) = resp {
-            writeln!(context.serial, "{}", r).unwrap();
-        } else {
-            writeln!(context.serial, "{:?}", resp).unwrap();
-        }
+    fn print_resp(context: &mut Context, resp: &StorageResponse) {
+        writeln!(context.serial, "{}", resp).unwrap();
     }
 
     pub mod append {
@@ -209,11 +199,11 @@ mod storage {
             );
 
             let resp = context
-                .storage_caller
-                .blocking_call(&Request::AppendKey(key, value))
+                .storage
+                .append_key(key, value)
                 .expect("Failed to perform a blocking_call");
 
-            print_resp(context, &resp);
+            print_resp(context, &StorageResponse::AppendKey(resp));
         }
     }
 
@@ -236,11 +226,11 @@ mod storage {
             log::debug!("[console] Get storage value for key='{}'", key);
 
             let resp = context
-                .storage_caller
-                .blocking_call(&Request::Get(key))
+                .storage
+                .get(key)
                 .expect("Failed to perform a blocking_call");
 
-            print_resp(context, &resp);
+            print_resp(context, &StorageResponse::Get(resp));
         }
     }
 
@@ -263,11 +253,11 @@ mod storage {
             log::debug!("[console] Invalidate storage key='{}'", key);
 
             let resp = context
-                .storage_caller
-                .blocking_call(&Request::InvalidateKey(key))
+                .storage
+                .invalidate_key(key)
                 .expect("Failed to perform a blocking_call");
 
-            print_resp(context, &resp);
+            print_resp(context, &StorageResponse::InvalidateKey(resp));
         }
     }
 
@@ -288,11 +278,11 @@ mod storage {
             log::debug!("[console] Garbage collect storage");
 
             let resp = context
-                .storage_caller
-                .blocking_call(&Request::GarbageCollect)
+                .storage
+                .garbage_collect()
                 .expect("Failed to perform a blocking_call");
 
-            print_resp(context, &resp);
+            print_resp(context, &StorageResponse::GarbageCollect(resp));
         }
     }
 }
@@ -2,7 +2,7 @@
 
 use core::fmt;
 use ferros::cap::{role, CNodeRole};
-use ferros::userland::{Caller, Responder, RetypeForSetup};
+use ferros::userland::{rpc_service, Caller, Responder, RetypeForSetup};
 use ferros::vspace::{shared_status, MappedMemoryRegion};
 use heapless::String;
 use imx6_hal::pac::{
@@ -18,41 +18,41 @@ pub type Key = String<MAX_KEY_SIZE>;
 pub const MAX_VALUE_SIZE: usize = 256;
 pub type Value = String<MAX_VALUE_SIZE>;
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum Request {
-    AppendKey(Key, Value),
-    Get(Key),
-    InvalidateKey(Key),
-    GarbageCollect,
+/// The operations persistent-storage serves over IPC
+#[rpc_service(derive(Debug, Clone, PartialEq), allow(clippy::large_enum_variant))]
+pub trait Storage {
+    fn append_key(&mut self, key: Key, value: Value) -> Result<SuccessCode, ErrorCode>;
+    fn get(&mut self, key: Key) -> Result<Value, ErrorCode>;
+    fn invalidate_key(&mut self, key: Key) -> Result<SuccessCode, ErrorCode>;
+    fn garbage_collect(&mut self) -> Result<usize, ErrorCode>;
 }
 
-impl fmt::Display for Request {
+impl fmt::Display for StorageRequest {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Request::AppendKey(k, v) => write!(f, "AppendKey({}, {})", k.as_str(), v.as_str()),
-            Request::Get(k) => write!(f, "Get({})", k.as_str()),
-            Request::InvalidateKey(k) => write!(f, "InvalidateKey({})", k.as_str()),
-            Request::GarbageCollect => write!(f, "GarbageCollect"),
+            StorageRequest::AppendKey(k, v) => {
+                write!(f, "AppendKey({}, {})", k.as_str(), v.as_str())
+            }
+            StorageRequest::Get(k) => write!(f, "Get({})", k.as_str()),
+            StorageRequest::InvalidateKey(k) => write!(f, "InvalidateKey({})", k.as_str()),
+            StorageRequest::GarbageCollect => write!(f, "GarbageCollect"),
         }
     }
 }
 
-#[allow(clippy::large_enum_variant)]
-#[derive(Debug, Clone, PartialEq)]
-pub enum Response {
-    KeyAppended(SuccessCode),
-    Value(Value),
-    KeyInvalidated(SuccessCode),
-    GarbageCollected(usize),
-}
-
-impl fmt::Display for Response {
+impl fmt::Display for StorageResponse {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Response::KeyAppended(sc) => write!(f, "KeyAppended({:?})", sc),
-            Response::Value(v) => write!(f, "Value({})", v.as_str()),
-            Response::KeyInvalidated(sc) => write!(f, "KeyInvalidated({:?})", sc),
-            Response::GarbageCollected(size) => write!(f, "GarbageCollected({} bytes freed)", size),
+            StorageResponse::AppendKey(Ok(sc)) => write!(f, "KeyAppended({:?})", sc),
+            StorageResponse::Get(Ok(v)) => write!(f, "Value({})", v.as_str()),
+            StorageResponse::InvalidateKey(Ok(sc)) => write!(f, "KeyInvalidated({:?})", sc),
+            StorageResponse::GarbageCollect(Ok(size)) => {
+                write!(f, "GarbageCollected({} bytes freed)", size)
+            }
+            StorageResponse::AppendKey(Err(e))
+            | StorageResponse::Get(Err(e))
+            | StorageResponse::InvalidateKey(Err(e))
+            | StorageResponse::GarbageCollect(Err(e)) => write!(f, "Err({:?})", e),
         }
     }
 }
@@ -70,7 +70,7 @@ pub struct ProcParams<Role: CNodeRole> {
     pub spi: ECSPI1,
     pub gpio3: GPIO3,
     pub iomux_caller: Caller<iomux::Request, iomux::Response, Role>,
-    pub responder: Responder<Request, Result<Response, ErrorCode>, Role>,
+    pub responder: Responder<StorageRequest, StorageResponse, Role>,
     pub storage_buffer: MappedMemoryRegion<StorageBufferSizeBits, shared_status::Exclusive>,
     pub scratchpad_buffer: MappedMemoryRegion<ScratchpadBufferSizeBits, shared_status::Exclusive>,
 }
@@ -16,7 +16,7 @@ use imx6_hal::{
     spi_nor_flash::{SpiNorFlash, ERASE_SIZE_BYTES},
 };
 use persistent_storage::{
-    ProcParams, Request, Response, StorageBufferSizeBytes, Value, MAX_VALUE_SIZE,
+    Key, ProcParams, Storage, StorageBufferSizeBytes, SuccessCode, Value, MAX_VALUE_SIZE,
 };
 use siphasher::sip::SipHasher;
 use static_assertions::const_assert_eq;
@@ -49,9 +49,6 @@ pub extern "C" fn _start(params: ProcParams<role::Local>) -> ! {
         params.scratchpad_buffer.size_bytes()
     );
 
-    // Local storage for a Value on the stack
-    let mut value_buffer: [u8; MAX_VALUE_SIZE] = [0; MAX_VALUE_SIZE];
-
     // Scratchpad mem to deal with flash sub-page size writes (read-modify-write)
     let mut scratchpad_buffer = params.scratchpad_buffer;
     let scratchpad_buffer_slice = scratchpad_buffer.as_mut_slice();
@@ -86,46 +83,19 @@ pub extern "C" fn _start(params: ProcParams<role::Local>) -> ! {
     MAIN_KEY.hash(&mut hasher);
     tickv.initalise(hasher.finish()).unwrap();
 
+    let service = StorageService {
+        tickv,
+        value_buffer: [0; MAX_VALUE_SIZE],
+    };
+
     params
         .responder
-        .reply_recv(move |req| {
+        .reply_recv_with_state(service, |req, mut service| {
             log::debug!("[persistent-storage] Processing request {}", req);
-            let resp = match req {
-                Request::AppendKey(key, value) => {
-                    let key_hash = get_hashed_key(key.as_bytes());
-                    tickv
-                        .append_key(key_hash, value.as_bytes())
-                        .map(Response::KeyAppended)
-                }
-                Request::Get(key) => {
-                    let key_hash = get_hashed_key(key.as_bytes());
-                    value_buffer.fill(0);
-                    match tickv.get_key(key_hash, &mut value_buffer) {
-                        Ok(_sc) => {
-                            // Make sure it's UTF-8
-                            if let Ok(s) = str::from_utf8(&value_buffer) {
-                                Ok(Response::Value(Value::from(s)))
-                            } else {
-                                Err(ErrorCode::CorruptData)
-                            }
-                        }
-                        Err(ec) => Err(ec),
-                    }
-                }
-                Request::InvalidateKey(key) => {
-                    let key_hash = get_hashed_key(key.as_bytes());
-                    tickv.invalidate_key(key_hash).map(Response::KeyInvalidated)
-                }
-                Request::GarbageCollect => tickv.garbage_collect().map(Response::GarbageCollected),
-            };
-            if let Ok(r) = &resp {
-                log::debug!("[persistent-storage] Response {}", r);
-            } else {
-                log::debug!("[persistent-storage] Response {:?}", resp);
-            }
-            resp
+            let resp = req.dispatch(&mut service);
+            log::debug!("[persistent-storage] Response {}", resp);
+            (resp, service)
         })
-        .expect("Could not set up a reply_recv")
         .expect("Failure on reply_recv");
 
     unsafe {
@@ -135,6 +105,39 @@ pub extern "C" fn _start(params: ProcParams<role::Local>) -> ! {
     }
 }
 
+struct StorageService<'a> {
+    tickv: TicKV<'a, SpiNorFlashController<'a>, ERASE_SIZE_BYTES>,
+
+    /// Local storage for a Value
+    value_buffer: [u8; MAX_VALUE_SIZE],
+}
+
+impl<'a> Storage for StorageService<'a> {
+    fn append_key(&mut self, key: Key, value: Value) -> Result<SuccessCode, ErrorCode> {
+        let key_hash = get_hashed_key(key.as_bytes());
+        self.tickv.append_key(key_hash, value.as_bytes())
+    }
+
+    fn get(&mut self, key: Key) -> Result<Value, ErrorCode> {
+        let key_hash = get_hashed_key(key.as_bytes());
+        self.value_buffer.fill(0);
+        self.tickv.get_key(key_hash, &mut self.value_buffer)?;
+        // Make sure it's UTF-8
+        str::from_utf8(&self.value_buffer)
+            .map(Value::from)
+            .map_err(|_| ErrorCode::CorruptData)
+    }
+
+    fn invalidate_key(&mut self, key: Key) -> Result<SuccessCode, ErrorCode> {
+        let key_hash = get_hashed_key(key.as_bytes());
+        self.tickv.invalidate_key(key_hash)
+    }
+
+    fn garbage_collect(&mut self) -> Result<usize, ErrorCode> {
+        self.tickv.garbage_collect()
+    }
+}
+
 fn get_hashed_key(unhashed_key: &[u8]) -> u64 {
     let mut hash_function = SipHasher::new();
     unhashed_key.hash(&mut hash_function);
@@ -183,6 +183,7 @@ fn run(raw_bootinfo: &'static selfe_sys::seL4_BootInfo) -> Result<(), TopLevelEr
             ut, // tcb_ut
             slots,
             &tpa, // priority_authority
+            ThreadPriorities::default(),
             None, // fault
         )?;
 
@@ -354,6 +355,7 @@ fn run(raw_bootinfo: &'static selfe_sys::seL4_BootInfo) -> Result<(), TopLevelEr
             ut, // tcb_ut
             slots,
             &tpa, // priority_authority
+            ThreadPriorities::default(),
             None, // fault
         )?;
 
@@ -406,6 +408,7 @@ fn run(raw_bootinfo: &'static selfe_sys::seL4_BootInfo) -> Result<(), TopLevelEr
             ut, // tcb_ut
             slots,
             &tpa, // priority_authority
+            ThreadPriorities::default(),
             None, // fault
         )?;
 
@@ -508,6 +511,7 @@ fn run(raw_bootinfo: &'static selfe_sys::seL4_BootInfo) -> Result<(), TopLevelEr
             ut, // tcb_ut
             slots,
             &tpa, // priority_authority
+            ThreadPriorities::default(),
             None, // fault
         )?;
 
@@ -590,6 +594,7 @@ fn run(raw_bootinfo: &'static selfe_sys::seL4_BootInfo) -> Result<(), TopLevelEr
             ut, // tcb_ut
             slots,
             &tpa, // priority_authority
+            ThreadPriorities::default(),
             None, // fault
         )?;
     });
@@ -599,12 +604,12 @@ fn run(raw_bootinfo: &'static selfe_sys::seL4_BootInfo) -> Result<(), TopLevelEr
     simple_yield_delay(1000);
 
     enet_process.set_name("enet-driver");
-    unsafe { selfe_sys::seL4_TCB_SetAffinity(enet_process.unsafe_get_tcb_cptr(), 1) };
+    enet_process.set_affinity(1)?;
     enet_process.start()?;
     simple_yield_delay(1000);
 
     tcpip_process.set_name("tcpip-driver");
-    unsafe { selfe_sys::seL4_TCB_SetAffinity(tcpip_process.unsafe_get_tcb_cptr(), 2) };
+    tcpip_process.set_affinity(2)?;
     tcpip_process.start()?;
     simple_yield_delay(1000);
 
@@ -613,7 +618,7 @@ fn run(raw_bootinfo: &'static selfe_sys::seL4_BootInfo) -> Result<(), TopLevelEr
     simple_yield_delay(1000);
 
     console_process.set_name("console");
-    unsafe { selfe_sys::seL4_TCB_SetAffinity(console_process.unsafe_get_tcb_cptr(), 3) };
+    console_process.set_affinity(3)?;
     console_process.start()?;
 
     // NOTE: we could stop the root-task here instead
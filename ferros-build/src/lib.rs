@@ -1,10 +1,16 @@
 //! Code you might need in a build script for a program built with ferros.
 
 use memmap::Mmap;
+#[cfg(feature = "symbols")]
+use rustc_demangle::demangle;
 use selfe_arc;
 use std::fs::{self, File};
 use std::path::{Path, PathBuf};
 use xmas_elf;
+#[cfg(feature = "symbols")]
+use xmas_elf::sections::{SectionData, ShType};
+#[cfg(feature = "symbols")]
+use xmas_elf::symbol_table::{self, Entry};
 
 /// A resource that can be embedded in a ferros binary
 pub trait Resource {
@@ -35,7 +41,8 @@ impl Resource for DataResource {
 }
 
 /// An elf binary resource. This will generate a struct and an `impl ElfProc`,
-/// based on the binary's structure.
+/// based on the binary's structure. With the `symbols` feature, this includes
+/// the binary's function symbols if it hasn't been stripped.
 pub struct ElfResource {
     pub path: PathBuf,
     /// The name this will get in the embedded selfe-arc
@@ -58,6 +65,86 @@ fn format_as_typenum(n: u64) -> String {
     }
 }
 
+/// A function symbol, as (address, size, demangled name)
+#[cfg(feature = "symbols")]
+type FunctionSymbol = (u64, u64, String);
+
+#[cfg(feature = "symbols")]
+fn collect_function_symbols<E: Entry>(
+    elf_file: &xmas_elf::ElfFile,
+    entries: &[E],
+    symbols: &mut Vec<FunctionSymbol>,
+) {
+    for entry in entries {
+        if entry.get_type() != Ok(symbol_table::Type::Func) || entry.value() == 0 {
+            continue;
+        }
+        if let Ok(name) = entry.get_name(elf_file) {
+            // Mask off the Thumb bit, which marks Thumb functions on 32-bit
+            // ARM but isn't part of their address.
+            symbols.push((
+                entry.value() & !1,
+                entry.size(),
+                format!("{:#}", demangle(name)),
+            ));
+        }
+    }
+}
+
+/// All the function symbols in the binary's symbol table, sorted by address.
+#[cfg(feature = "symbols")]
+fn function_symbols(elf_file: &xmas_elf::ElfFile) -> Vec<FunctionSymbol> {
+    let mut symbols = Vec::new();
+    for section in elf_file
+        .section_iter()
+        .filter(|s| s.get_type() == Ok(ShType::SymTab))
+    {
+        match section.get_data(elf_file) {
+            Ok(SectionData::SymbolTable32(entries)) => {
+                collect_function_symbols(elf_file, entries, &mut symbols)
+            }
+            Ok(SectionData::SymbolTable64(entries)) => {
+                collect_function_symbols(elf_file, entries, &mut symbols)
+            }
+            _ => (),
+        }
+    }
+    symbols.sort_by_key(|&(address, _, _)| address);
+    symbols.dedup_by_key(|&mut (address, _, _)| address);
+    symbols
+}
+
+/// Format symbols as the elements of a `&[ferros::userland::Symbol]`.
+#[cfg(feature = "symbols")]
+fn format_symbols(symbols: &[FunctionSymbol]) -> String {
+    symbols
+        .iter()
+        .map(|(address, size, name)| {
+            format!(
+                "        ferros::userland::Symbol {{ address: {:#x}, size: {}, name: {:?} }},\n",
+                address, size, name
+            )
+        })
+        .collect()
+}
+
+/// The `SYMBOLS` item of the generated `impl ElfProc`, holding the binary's
+/// function symbols.
+#[cfg(feature = "symbols")]
+fn symbols_item(elf_file: &xmas_elf::ElfFile) -> String {
+    format!(
+        "    const SYMBOLS: &'static [ferros::userland::Symbol] = &[\n{}    ];\n",
+        format_symbols(&function_symbols(elf_file))
+    )
+}
+
+/// Without the `symbols` feature, the generated `impl ElfProc` keeps the
+/// empty default `SYMBOLS`, so the symbol table stays out of the image.
+#[cfg(not(feature = "symbols"))]
+fn symbols_item(_elf_file: &xmas_elf::ElfFile) -> String {
+    "".to_owned()
+}
+
 fn round_down_to_page_boundary(addr: u64) -> u64 {
     addr & !0xfff
 }
@@ -128,7 +215,7 @@ impl ferros::vspace::ElfProc for {} {{
     type WritablePages = {};
     type RequiredMemoryBits = {};
     type StackSizeBits = {};
-}}
+{}}}
 "#,
             self.type_name,
             self.type_name,
@@ -136,7 +223,8 @@ impl ferros::vspace::ElfProc for {} {{
             format_as_typenum(required_pages),
             format_as_typenum(writable_pages),
             format_as_typenum(required_memory_bits.into()),
-            format_as_typenum(stack_size_bits)
+            format_as_typenum(stack_size_bits),
+            symbols_item(&elf_file)
         )
     }
 }
@@ -185,4 +273,22 @@ mod test {
         assert_eq!(format_as_typenum(4), "typenum::UInt<typenum::UInt<typenum::UInt<typenum::UTerm, typenum::B1>, typenum::B0>, typenum::B0>".to_string());
     }
 
+    #[cfg(feature = "symbols")]
+    #[test]
+    fn test_format_symbols() {
+        assert_eq!(format_symbols(&[]), "".to_string());
+
+        let formatted = format_symbols(&[
+            (0x1000, 16, "main".to_string()),
+            (0x1010, 0, "quote\"d".to_string()),
+        ]);
+        let lines: Vec<&str> = formatted.lines().map(str::trim).collect();
+        assert_eq!(
+            lines,
+            vec![
+                r#"ferros::userland::Symbol { address: 0x1000, size: 16, name: "main" },"#,
+                r#"ferros::userland::Symbol { address: 0x1010, size: 0, name: "quote\"d" },"#,
+            ]
+        );
+    }
 }
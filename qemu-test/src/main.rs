@@ -30,6 +30,8 @@ macro_rules! sequential_test {
 pub enum TestPlatform {
     /// A virtual aarch64 platform similar to the tx1
     VirtTx1Aarch64,
+    /// The same virtual platform, with a kernel using the MCS scheduler
+    VirtMcsAarch64,
     /// The sabre aarch32
     SabreAarch32,
 }
@@ -38,15 +40,26 @@ impl TestPlatform {
     fn sel4_arch(&self) -> &'static str {
         match self {
             TestPlatform::VirtTx1Aarch64 => "aarch64",
+            TestPlatform::VirtMcsAarch64 => "aarch64",
             TestPlatform::SabreAarch32 => "aarch32",
         }
     }
     fn platform(&self) -> &'static str {
         match self {
             TestPlatform::VirtTx1Aarch64 => "virt",
+            TestPlatform::VirtMcsAarch64 => "virt_mcs",
             TestPlatform::SabreAarch32 => "sabre",
         }
     }
+    /// Extra qemu arguments, which have to agree with the platform's
+    /// kernel config in test-project/sel4.toml
+    fn qemu_args(&self) -> &'static [&'static str] {
+        match self {
+            TestPlatform::VirtTx1Aarch64 => &["-smp", "4", "-m", "2048"],
+            TestPlatform::VirtMcsAarch64 => &[],
+            TestPlatform::SabreAarch32 => &[],
+        }
+    }
 }
 
 fn run_qemu_test<F>(
@@ -57,7 +70,7 @@ fn run_qemu_test<F>(
     serial_override: Option<&str>,
     test_platform: TestPlatform,
 ) where
-    F: Fn(),
+    F: Fn(&str),
 {
     let rust_identifier_regex: Regex =
         Regex::new("(^[a-zA-Z][a-zA-Z0-9_]*$)|(^_[a-zA-Z0-9_]+$)").unwrap();
@@ -104,9 +117,14 @@ fn run_qemu_test<F>(
         .current_dir("test-project")
         .env("TEST_CASE", test_case);
 
+    if !test_platform.qemu_args().is_empty() {
+        sim_command.arg("--").args(test_platform.qemu_args());
+    }
+
     println!(r#"running: TEST_CASE={} {:?}"#, test_case, sim_command);
 
-    let mut sim = spawn_command(sim_command, Some(100000)).expect("Couldn't start simulate command");
+    let mut sim =
+        spawn_command(sim_command, Some(100000)).expect("Couldn't start simulate command");
 
     match ready_line_and_func {
         Some((rl, rl_func)) => {
@@ -119,7 +137,7 @@ fn run_qemu_test<F>(
                 println!("{}", line);
 
                 if !ready_fired && rl.is_match(&line) {
-                    rl_func();
+                    rl_func(&line);
                     ready_fired = true;
                 }
 
@@ -159,9 +177,9 @@ mod tests {
 
     sequential_test! {
         fn unified_tests_sabre() {
-            run_qemu_test::<fn()>(
+            run_qemu_test::<fn(&str)>(
                 "unified_tests",
-                Regex::new(".*test result: ok\\. 23 passed;.*").unwrap(),
+                Regex::new(".*test result: ok\\. 48 passed;.*").unwrap(),
                 Regex::new(".*Root task should never return from main.*").unwrap(),
                 None,
                 None,
@@ -172,9 +190,9 @@ mod tests {
 
     sequential_test! {
         fn unified_tests_virt() {
-            run_qemu_test::<fn()>(
+            run_qemu_test::<fn(&str)>(
                 "unified_tests",
-                Regex::new(".*test result: ok\\. 23 passed;.*").unwrap(),
+                Regex::new(".*test result: ok\\. 48 passed;.*").unwrap(),
                 Regex::new(".*Root task should never return from main.*").unwrap(),
                 None,
                 None,
@@ -193,7 +211,7 @@ mod tests {
                 Regex::new(".*got byte: 1.*").unwrap(),
                 Regex::new(".*Root task should never return from main.*").unwrap(),
                 Some((Regex::new(".*thou art ready.*").unwrap(),
-                || {
+                |_: &str| {
                     let mut stream = TcpStream::connect("localhost:8888").expect("connect stream");
                     stream.write(&[1]).expect("write stream");
                 })),
@@ -202,4 +220,225 @@ mod tests {
             );
         }
     }
+
+    /// Debug the gdbstub test case's child over the UART its stub
+    /// listens on, which is simulated on port 8888. `ready_line` says
+    /// where the child's breakpoint target is, and `registers` how the
+    /// platform's registers go over the wire.
+    fn gdbstub_session(ready_line: &str, registers: GdbRegisters) {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+
+        /// Send one remote serial protocol packet and return the reply,
+        /// if one is expected.
+        fn command(stream: &mut TcpStream, packet: &str, reply: bool) -> String {
+            let checksum = packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+            write!(stream, "${}#{:02x}", packet, checksum).expect("write stream");
+            let mut byte = [0u8];
+            stream.read_exact(&mut byte).expect("read ack");
+            assert_eq!(byte[0], b'+', "packet {} was not acknowledged", packet);
+            if !reply {
+                return String::new();
+            }
+
+            let mut data = Vec::new();
+            loop {
+                stream.read_exact(&mut byte).expect("read reply");
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+            loop {
+                stream.read_exact(&mut byte).expect("read reply");
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0]);
+            }
+            let mut checksum = [0u8; 2];
+            stream.read_exact(&mut checksum).expect("read checksum");
+            stream.write_all(b"+").expect("write ack");
+            String::from_utf8(data).expect("reply should be ascii")
+        }
+
+        /// A register's value as it goes over the wire, least
+        /// significant byte first.
+        fn wire_value(value: u64, registers: &GdbRegisters) -> String {
+            let digits = format!("{:016x}", value.swap_bytes());
+            digits[..registers.word_digits].to_string()
+        }
+
+        let target = Regex::new("breakpoint target at 0x([0-9a-f]+)")
+            .unwrap()
+            .captures(ready_line)
+            .and_then(|c| u64::from_str_radix(&c[1], 16).ok())
+            .expect("the ready line should give the breakpoint target");
+
+        let mut stream = TcpStream::connect("localhost:8888").expect("connect stream");
+        assert!(command(&mut stream, "qSupported", true).contains("qXfer:features:read+"));
+        // The child hasn't started yet
+        assert_eq!(command(&mut stream, "?", true), "S05");
+
+        // Breakpoints are hidden from memory reads
+        let code = command(&mut stream, &format!("m{:x},4", target), true);
+        assert_eq!(code.len(), 8);
+        assert_eq!(
+            command(&mut stream, &format!("Z0,{:x},4", target), true),
+            "OK"
+        );
+        assert_eq!(
+            command(&mut stream, &format!("m{:x},4", target), true),
+            code
+        );
+
+        assert_eq!(command(&mut stream, "c", true), "S05");
+        let all = command(&mut stream, "g", true);
+        assert_eq!(all.len(), registers.all_digits);
+        let pc = command(&mut stream, &format!("p{:x}", registers.pc), true);
+        assert_eq!(pc, wire_value(target, &registers));
+        let sp_start = registers.sp * registers.word_digits;
+        let sp = u64::from_str_radix(&all[sp_start..sp_start + registers.word_digits], 16)
+            .expect("sp should be hex")
+            .swap_bytes()
+            >> (64 - 4 * registers.word_digits);
+
+        let original = command(&mut stream, &format!("m{:x},4", sp), true);
+        assert_eq!(original.len(), 8);
+        assert_eq!(
+            command(&mut stream, &format!("M{:x},4:efbeadde", sp), true),
+            "OK"
+        );
+        assert_eq!(
+            command(&mut stream, &format!("m{:x},4", sp), true),
+            "efbeadde"
+        );
+        assert_eq!(
+            command(&mut stream, &format!("M{:x},4:{}", sp, original), true),
+            "OK"
+        );
+        assert!(command(&mut stream, "m4,4", true).starts_with('E'));
+
+        // Stepping runs the instruction the breakpoint replaced, and
+        // stops on the next one, where another breakpoint is waiting
+        let next = target + 4;
+        assert_eq!(
+            command(&mut stream, &format!("Z0,{:x},4", next), true),
+            "OK"
+        );
+        assert_eq!(command(&mut stream, "s", true), "S05");
+        let pc = command(&mut stream, &format!("p{:x}", registers.pc), true);
+        assert_eq!(pc, wire_value(next, &registers));
+
+        // Continuing steps over that breakpoint rather than hitting it
+        // again, and the child runs on into its fault
+        assert_eq!(command(&mut stream, "c", true), "S0b");
+        command(&mut stream, "k", false);
+    }
+
+    /// How a platform's registers go over the wire to GDB.
+    struct GdbRegisters {
+        /// Hex digits in a reply to `g`
+        all_digits: usize,
+        /// Hex digits in a general purpose register
+        word_digits: usize,
+        sp: usize,
+        pc: usize,
+    }
+
+    sequential_test! {
+        fn gdbstub_sabre() {
+            run_qemu_test(
+                "gdbstub",
+                Regex::new(".*gdbstub session ended.*").unwrap(),
+                Regex::new(".*Root task should never return from main.*").unwrap(),
+                Some((Regex::new(".*gdbstub ready.*").unwrap(),
+                |line: &str| {
+                    // r0-r12, sp, lr, pc and cpsr, 32 bits apiece
+                    gdbstub_session(line, GdbRegisters {
+                        all_digits: 17 * 8,
+                        word_digits: 8,
+                        sp: 13,
+                        pc: 15,
+                    })
+                })),
+                Some("-serial tcp:localhost:8888,server,nowait,nodelay -serial mon:stdio"),
+                TestPlatform::SabreAarch32,
+            );
+        }
+    }
+
+    sequential_test! {
+        fn gdbstub_virt() {
+            run_qemu_test(
+                "gdbstub",
+                Regex::new(".*gdbstub session ended.*").unwrap(),
+                Regex::new(".*Root task should never return from main.*").unwrap(),
+                Some((Regex::new(".*gdbstub ready.*").unwrap(),
+                |line: &str| {
+                    // x0-x30, sp and pc, 64 bits apiece, then a 32 bit cpsr
+                    gdbstub_session(line, GdbRegisters {
+                        all_digits: 33 * 16 + 8,
+                        word_digits: 16,
+                        sp: 31,
+                        pc: 32,
+                    })
+                })),
+                // The kernel prints to the first UART, the stub talks over the second
+                Some("-serial mon:stdio -serial tcp:localhost:8888,server,nowait,nodelay"),
+                TestPlatform::VirtTx1Aarch64,
+            );
+        }
+    }
+
+    sequential_test! {
+        fn hardware_debug_sabre() {
+            run_qemu_test::<fn(&str)>(
+                "hardware_debug",
+                Regex::new(".*hardware_debug test passed.*").unwrap(),
+                Regex::new(".*Root task should never return from main.*").unwrap(),
+                None,
+                None,
+                TestPlatform::SabreAarch32,
+            );
+        }
+    }
+
+    sequential_test! {
+        fn huge_page_mapping_virt() {
+            run_qemu_test::<fn(&str)>(
+                "huge_page_mapping",
+                Regex::new(".*huge_page_mapping test passed.*").unwrap(),
+                Regex::new(".*Root task should never return from main.*").unwrap(),
+                None,
+                None,
+                TestPlatform::VirtTx1Aarch64,
+            );
+        }
+    }
+
+    sequential_test! {
+        fn sched_context_virt() {
+            run_qemu_test::<fn(&str)>(
+                "sched_context",
+                Regex::new(".*sched_context test passed.*").unwrap(),
+                Regex::new(".*Root task should never return from main.*").unwrap(),
+                None,
+                None,
+                TestPlatform::VirtMcsAarch64,
+            );
+        }
+    }
+
+    sequential_test! {
+        fn smp_virt() {
+            run_qemu_test::<fn(&str)>(
+                "smp",
+                Regex::new(".*smp test passed.*").unwrap(),
+                Regex::new(".*Root task should never return from main.*").unwrap(),
+                None,
+                None,
+                TestPlatform::VirtTx1Aarch64,
+            );
+        }
+    }
 }
@@ -15,4 +15,7 @@ echo "======================= building elf-process ======================"
 cargo xbuild -p elf-process $@;
 
 echo "======================== building root-task ======================="
-cargo xbuild -p root-task $@;
+# Backtraces are walked through the frame records of processes run from the
+# root task's image, so it alone is built with frame pointers. Cargo adds
+# CARGO_BUILD_RUSTFLAGS to the rustflags in .cargo/config.
+CARGO_BUILD_RUSTFLAGS="-C force-frame-pointers=yes" cargo xbuild -p root-task $@;
@@ -11,9 +11,10 @@ static mut MUT_GLOBAL: u32 = 0;
 
 #[no_mangle]
 pub extern "C" fn _start(params: ProcParams<role::Local>) -> ! {
-    // try to set the mut global, to see that BSS was mapped
+    // try to set the mut global, to see that BSS was mapped. It's never
+    // read, so the write has to be volatile to be kept.
     unsafe {
-        MUT_GLOBAL = 42;
+        core::ptr::write_volatile(&mut MUT_GLOBAL, 42);
     }
 
     params
@@ -0,0 +1,186 @@
+use super::TopLevelError;
+use ferros::alloc::{smart_alloc, ut_buddy};
+use ferros::bootstrap::UserImage;
+use ferros::cap::{
+    retype, retype_cnode, role, ASIDPool, CNodeRole, ChildCNodeSlots, LocalCNode, LocalCNodeSlots,
+    LocalCap, ThreadPriorities, ThreadPriorityAuthority, Untyped,
+};
+use ferros::userland::*;
+use ferros::vspace::*;
+use typenum::*;
+
+type U33768 = op!(U32768 + U1000);
+
+#[ferros_test::ferros_test]
+pub fn badged_callers(
+    local_slots: LocalCNodeSlots<U33768>,
+    local_ut: LocalCap<Untyped<U20>>,
+    asid_pool: LocalCap<ASIDPool<U2>>,
+    local_mapped_region: MappedMemoryRegion<U18, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (caller_asid, asid_pool) = asid_pool.alloc();
+        let (responder_asid, _asid_pool) = asid_pool.alloc();
+        let caller_root = retype(ut, slots)?;
+        let caller_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let caller_vspace_ut: LocalCap<Untyped<U15>> = ut;
+
+        let mut caller_vspace = VSpace::new(
+            caller_root,
+            caller_asid,
+            caller_vspace_slots.weaken(),
+            caller_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let responder_root = retype(ut, slots)?;
+        let responder_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let responder_vspace_ut: LocalCap<Untyped<U15>> = ut;
+
+        let mut responder_vspace = VSpace::new(
+            responder_root,
+            responder_asid,
+            responder_vspace_slots.weaken(),
+            responder_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let (caller_cnode, caller_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (responder_cnode, responder_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (slots_r, _responder_slots) = responder_slots.alloc();
+        let (ipc_setup, responder) = call_channel(ut, &root_cnode, slots, slots_r)?;
+
+        let (slots_c, caller_slots): (ChildCNodeSlots<U2>, _) = caller_slots.alloc();
+        let mut callers = ipc_setup.create_badged_callers(slots_c);
+        let first_caller = callers.next().expect("first badged caller")?;
+        let second_caller = callers.next().expect("second badged caller")?;
+        let (child_fault_source_slot, _caller_slots) = caller_slots.alloc();
+        let (fault_source, outcome_sender, handler) =
+            fault_or_message_channel(&root_cnode, ut, slots, child_fault_source_slot, slots)?;
+
+        let caller_params = CallerParams::<role::Child> {
+            first_caller,
+            second_caller,
+            outcome_sender,
+        };
+
+        let responder_params = ResponderParams::<role::Child> { responder };
+
+        let (caller_region, responder_region) = local_mapped_region.split()?;
+
+        let mut caller_process = StandardProcess::new(
+            &mut caller_vspace,
+            caller_cnode,
+            caller_region,
+            root_cnode,
+            caller_proc as extern "C" fn(_) -> (),
+            caller_params,
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            None, // fault
+        )?;
+        caller_process.start()?;
+
+        let mut responder_process = StandardProcess::new(
+            &mut responder_vspace,
+            responder_cnode,
+            responder_region,
+            &root_cnode,
+            responder_proc as extern "C" fn(_) -> (),
+            responder_params,
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            None, // fault
+        )?;
+        responder_process.start()?;
+    });
+
+    match handler.await_message()? {
+        FaultOrMessage::Message(true) => Ok(()),
+        _ => Err(TopLevelError::TestAssertionFailure(
+            "Child process should have reported success",
+        )),
+    }
+}
+
+#[derive(Debug)]
+pub struct WhoAmIRequest;
+
+#[derive(Debug)]
+pub struct WhoAmIResponse {
+    badge: usize,
+    calls_from_this_badge: usize,
+}
+
+#[derive(Debug)]
+pub struct CallerParams<Role: CNodeRole> {
+    pub first_caller: Caller<WhoAmIRequest, WhoAmIResponse, Role>,
+    pub second_caller: Caller<WhoAmIRequest, WhoAmIResponse, Role>,
+    pub outcome_sender: Sender<bool, Role>,
+}
+
+impl RetypeForSetup for CallerParams<role::Local> {
+    type Output = CallerParams<role::Child>;
+}
+
+#[derive(Debug)]
+pub struct ResponderParams<Role: CNodeRole> {
+    pub responder: Responder<WhoAmIRequest, WhoAmIResponse, Role>,
+}
+
+impl RetypeForSetup for ResponderParams<role::Local> {
+    type Output = ResponderParams<role::Child>;
+}
+
+pub extern "C" fn caller_proc(p: CallerParams<role::Local>) {
+    let call = |caller: &Caller<WhoAmIRequest, WhoAmIResponse, role::Local>| {
+        caller.blocking_call(&WhoAmIRequest).expect("blocking_call")
+    };
+    let a = call(&p.first_caller);
+    let b = call(&p.second_caller);
+    let c = call(&p.first_caller);
+
+    p.outcome_sender
+        .blocking_send(
+            &(a.badge == 1
+                && a.calls_from_this_badge == 1
+                && b.badge == 2
+                && b.calls_from_this_badge == 1
+                && c.badge == 1
+                && c.calls_from_this_badge == 2),
+        )
+        .expect("could not send outcome");
+}
+
+pub extern "C" fn responder_proc(p: ResponderParams<role::Local>) {
+    // Per-client call counts, indexed by badge
+    let initial_state = [0usize; 3];
+    p.responder
+        .reply_recv_with_badge_and_state(initial_state, |badge, _req, mut calls| {
+            let badge: usize = badge.into();
+            calls[badge] += 1;
+            (
+                WhoAmIResponse {
+                    badge,
+                    calls_from_this_badge: calls[badge],
+                },
+                calls,
+            )
+        })
+        .expect("reply_recv_with_badge_and_state");
+}
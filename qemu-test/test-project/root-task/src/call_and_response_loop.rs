@@ -3,7 +3,7 @@ use ferros::alloc::{smart_alloc, ut_buddy};
 use ferros::bootstrap::UserImage;
 use ferros::cap::{
     retype, retype_cnode, role, ASIDPool, Badge, CNodeRole, Cap, LocalCNode, LocalCNodeSlots,
-    LocalCap, Notification, ThreadPriorityAuthority, Untyped,
+    LocalCap, Notification, ThreadPriorities, ThreadPriorityAuthority, Untyped,
 };
 use ferros::userland::*;
 use ferros::vspace::*;
@@ -92,6 +92,7 @@ pub fn call_and_response_loop(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             None, // fault
         )?;
         caller_process.start()?;
@@ -107,6 +108,7 @@ pub fn call_and_response_loop(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             None, // fault
         )?;
 
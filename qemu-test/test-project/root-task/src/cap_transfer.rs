@@ -0,0 +1,218 @@
+use super::TopLevelError;
+use ferros::alloc::{smart_alloc, ut_buddy};
+use ferros::arch::PageBits;
+use ferros::bootstrap::UserImage;
+use ferros::cap::{
+    retype, retype_cnode, role, ASIDPool, CNodeRole, CNodeSlotsData, Cap, ChildCNodeSlots,
+    LocalCNode, LocalCNodeSlots, LocalCap, Notification, ThreadPriorities, ThreadPriorityAuthority,
+    Untyped,
+};
+use ferros::userland::*;
+use ferros::vspace::*;
+use typenum::*;
+
+type U33768 = op!(U32768 + U1000);
+
+#[ferros_test::ferros_test]
+pub fn cap_transfer(
+    local_slots: LocalCNodeSlots<U33768>,
+    local_ut: LocalCap<Untyped<U20>>,
+    asid_pool: LocalCap<ASIDPool<U2>>,
+    local_mapped_region: MappedMemoryRegion<U18, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (caller_asid, asid_pool) = asid_pool.alloc();
+        let (responder_asid, _asid_pool) = asid_pool.alloc();
+        let caller_root = retype(ut, slots)?;
+        let caller_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let caller_vspace_ut: LocalCap<Untyped<U15>> = ut;
+
+        let mut caller_vspace = VSpace::new(
+            caller_root,
+            caller_asid,
+            caller_vspace_slots.weaken(),
+            caller_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let responder_root = retype(ut, slots)?;
+        let responder_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let responder_vspace_ut: LocalCap<Untyped<U15>> = ut;
+
+        let mut responder_vspace = VSpace::new(
+            responder_root,
+            responder_asid,
+            responder_vspace_slots.weaken(),
+            responder_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let (caller_cnode, caller_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (responder_cnode, responder_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (slots_r, responder_slots) = responder_slots.alloc();
+        let (ipc_setup, responder) = call_channel(ut, &root_cnode, slots, slots_r)?;
+        let (slots_r, responder_slots): (ChildCNodeSlots<U2>, _) = responder_slots.alloc();
+        let (_responder_cnode_for_child, receive_slot) =
+            responder_cnode.generate_self_reference::<U1>(&root_cnode, slots_r)?;
+
+        // A page for the responder to hand back with its response
+        let (slots_r, _responder_slots): (ChildCNodeSlots<U1>, _) = responder_slots.alloc();
+        let page: UnmappedMemoryRegion<PageBits, _> = UnmappedMemoryRegion::new(ut, slots)?;
+        let (responder_page, _page) = page.share(slots_r, root_cnode, CapRights::RW)?;
+
+        let (slots_c, caller_slots) = caller_slots.alloc();
+        let caller = ipc_setup.create_caller(slots_c)?;
+        let (child_fault_source_slot, caller_slots) = caller_slots.alloc();
+        let (fault_source, outcome_sender, handler) =
+            fault_or_message_channel(&root_cnode, ut, slots, child_fault_source_slot, slots)?;
+
+        let notification: LocalCap<Notification> = retype(ut, slots)?;
+        let (slots_c, caller_slots) = caller_slots.alloc();
+        let caller_notification = notification.copy(root_cnode, slots_c, CapRights::RWG)?;
+        let (slots_c, _caller_slots): (ChildCNodeSlots<U2>, _) = caller_slots.alloc();
+        let (_caller_cnode_for_child, caller_receive_slot) =
+            caller_cnode.generate_self_reference::<U1>(&root_cnode, slots_c)?;
+
+        let caller_params = CallerParams::<role::Child> {
+            caller,
+            notification: caller_notification,
+            receive_slot: caller_receive_slot,
+            outcome_sender,
+        };
+
+        let responder_params = ResponderParams::<role::Child> {
+            responder,
+            receive_slot,
+            page: responder_page,
+        };
+
+        let (caller_region, responder_region) = local_mapped_region.split()?;
+
+        let mut caller_process = StandardProcess::new(
+            &mut caller_vspace,
+            caller_cnode,
+            caller_region,
+            root_cnode,
+            caller_proc as extern "C" fn(_) -> (),
+            caller_params,
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            None, // fault
+        )?;
+        caller_process.start()?;
+
+        let mut responder_process = StandardProcess::new(
+            &mut responder_vspace,
+            responder_cnode,
+            responder_region,
+            &root_cnode,
+            responder_proc as extern "C" fn(_) -> (),
+            responder_params,
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            None, // fault
+        )?;
+        responder_process.start()?;
+    });
+
+    match handler.await_message()? {
+        FaultOrMessage::Message(true) => Ok(()),
+        _ => Err(TopLevelError::TestAssertionFailure(
+            "Child process should have reported success",
+        )),
+    }
+}
+
+#[derive(Debug)]
+pub struct SignalMeRequest {
+    times: usize,
+}
+
+#[derive(Debug)]
+pub struct SignalMeResponse {
+    page_paddr: usize,
+}
+
+/// Requests carry the notification to signal, and responses carry a
+/// page from the responder.
+pub type SignalMeCaller<Role> = Caller<
+    WithCap<SignalMeRequest, LocalCap<Notification>>,
+    WithCap<SignalMeResponse, UnmappedMemoryRegion<PageBits, shared_status::Shared>>,
+    Role,
+>;
+pub type SignalMeResponder<Role> = Responder<
+    WithCap<SignalMeRequest, LocalCap<Notification>>,
+    WithCap<SignalMeResponse, UnmappedMemoryRegion<PageBits, shared_status::Shared>>,
+    Role,
+>;
+
+pub struct CallerParams<Role: CNodeRole> {
+    pub caller: SignalMeCaller<Role>,
+    pub notification: Cap<Notification, Role>,
+    pub receive_slot: Cap<CNodeSlotsData<U1, Role>, Role>,
+    pub outcome_sender: Sender<bool, Role>,
+}
+
+impl RetypeForSetup for CallerParams<role::Local> {
+    type Output = CallerParams<role::Child>;
+}
+
+pub struct ResponderParams<Role: CNodeRole> {
+    pub responder: SignalMeResponder<Role>,
+    pub receive_slot: Cap<CNodeSlotsData<U1, Role>, Role>,
+    pub page: UnmappedMemoryRegion<PageBits, shared_status::Shared, Role>,
+}
+
+impl RetypeForSetup for ResponderParams<role::Local> {
+    type Output = ResponderParams<role::Child>;
+}
+
+pub extern "C" fn caller_proc(p: CallerParams<role::Local>) {
+    let (rsp, page) = p
+        .caller
+        .blocking_call_with_cap(
+            &SignalMeRequest { times: 1 },
+            &p.notification,
+            p.receive_slot,
+        )
+        .expect("blocking_call_with_cap");
+
+    // Only returns if the responder signaled its copy of our notification
+    p.notification.wait();
+
+    // The page we were sent should be the very one the responder has
+    let same_page = page.paddr().expect("page paddr") == rsp.page_paddr;
+
+    p.outcome_sender
+        .blocking_send(&same_page)
+        .expect("could not send outcome");
+}
+
+pub extern "C" fn responder_proc(p: ResponderParams<role::Local>) {
+    let page_paddr = p.page.paddr().expect("page paddr");
+    let page = p.page;
+    let _page = p
+        .responder
+        .recv_reply_once_with_cap(p.receive_slot, move |req, notification| {
+            for _ in 0..req.times {
+                notification.signal();
+            }
+            (SignalMeResponse { page_paddr }, page)
+        })
+        .expect("recv_reply_once_with_cap");
+}
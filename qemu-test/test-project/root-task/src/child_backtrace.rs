@@ -0,0 +1,242 @@
+use core::mem::size_of;
+
+use typenum::*;
+
+use ferros::alloc::{smart_alloc, ut_buddy};
+use ferros::arch::fault::Fault;
+use ferros::bootstrap::UserImage;
+use ferros::cap::{
+    retype, retype_cnode, role, ASIDPool, Badge, LocalCNode, LocalCNodeSlots, LocalCap,
+    ThreadPriorities, ThreadPriorityAuthority, Untyped,
+};
+use ferros::userland::{
+    lookup_symbol, Backtrace, FaultSinkSetup, FrameStart, MappedChildMemory, RetypeForSetup,
+    StandardProcess, Symbol, MAX_BACKTRACE_DEPTH,
+};
+use ferros::vspace::*;
+
+use super::TopLevelError;
+
+/// Walk a hand-built frame record chain, then the stack of a child that
+/// faults a few calls deep.
+#[ferros_test::ferros_test]
+pub fn child_backtrace(
+    local_slots: LocalCNodeSlots<U32768>,
+    local_ut: LocalCap<Untyped<U20>>,
+    asid_pool: LocalCap<ASIDPool<U1>>,
+    local_mapped_region: MappedMemoryRegion<U17, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+) -> Result<(), TopLevelError> {
+    walk_synthetic_stack()?;
+
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (child_asid, _asid_pool) = asid_pool.alloc();
+        let child_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let child_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut child_vspace = VSpace::new(
+            retype(ut, slots)?,
+            child_asid,
+            child_vspace_slots.weaken(),
+            child_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let (child_cnode, child_slots) = retype_cnode::<U12>(ut, slots)?;
+        let params = ProcParams {};
+
+        let setup = FaultSinkSetup::new(&root_cnode, ut, slots, slots)?;
+        let (child_slot_for_fault_source, _child_slots) = child_slots.alloc();
+        let fault_source =
+            setup.add_fault_source(&root_cnode, child_slot_for_fault_source, Badge::from(0))?;
+        let sink = setup.sink();
+
+        let mut child_process = StandardProcess::new(
+            &mut child_vspace,
+            child_cnode,
+            local_mapped_region,
+            root_cnode,
+            proc_main as extern "C" fn(_) -> (),
+            params,
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            Some(fault_source),
+        )?;
+    });
+
+    child_process.start()?;
+
+    let program_counter = match sink.wait_for_fault() {
+        Fault::VMFault(fault) => fault.program_counter,
+        _ => {
+            return Err(TopLevelError::TestAssertionFailure(
+                "unexpected fault in child_backtrace",
+            ))
+        }
+    };
+
+    let backtrace = child_process.backtrace()?;
+    debug_println!("child backtrace:\n{}", backtrace);
+    if backtrace.addresses()[0] != program_counter {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Backtrace should start at the faulting instruction",
+        ));
+    }
+    // The faulting instruction in third, then returns into second, first
+    // and proc_main
+    if backtrace.addresses().len() < 4 || backtrace.is_truncated() {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Backtrace should reach back through every call",
+        ));
+    }
+
+    Ok(())
+}
+
+fn walk_synthetic_stack() -> Result<(), TopLevelError> {
+    const CHILD_STACK: usize = 0x8000;
+    const WORD: usize = size_of::<usize>();
+
+    // Three frame records, each pointing at the one above it, with the
+    // outermost ending the chain.
+    let mut stack = [0usize; 8];
+    stack[0] = CHILD_STACK + 2 * WORD;
+    stack[1] = 0x1010;
+    stack[2] = CHILD_STACK + 6 * WORD;
+    stack[3] = 0x2020;
+    stack[6] = 0;
+    stack[7] = 0x3030;
+    let start = FrameStart {
+        program_counter: 0x1000,
+        frame_pointer: CHILD_STACK,
+    };
+    let backtrace = Backtrace::walk(
+        start,
+        &MappedChildMemory::new(as_bytes(&stack), CHILD_STACK),
+    );
+    if backtrace.addresses() != [0x1000, 0x1010, 0x2020, 0x3030] || backtrace.is_truncated() {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Backtrace should follow the frame record chain",
+        ));
+    }
+
+    // The chain can run off the end of the memory we can see
+    let backtrace = Backtrace::walk(
+        FrameStart {
+            program_counter: 0x1000,
+            frame_pointer: CHILD_STACK + 8 * WORD,
+        },
+        &MappedChildMemory::new(as_bytes(&stack), CHILD_STACK),
+    );
+    if backtrace.addresses() != [0x1000] {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Backtrace should stop at unreadable memory",
+        ));
+    }
+
+    // A record which points back at itself is only walked once
+    stack[0] = CHILD_STACK;
+    let backtrace = Backtrace::walk(
+        start,
+        &MappedChildMemory::new(as_bytes(&stack), CHILD_STACK),
+    );
+    if backtrace.addresses() != [0x1000, 0x1010] {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Backtrace should stop at a corrupt frame record",
+        ));
+    }
+
+    // A chain that's longer than a backtrace can hold
+    let mut deep_stack = [0usize; 2 * MAX_BACKTRACE_DEPTH + 2];
+    for (n, record) in deep_stack.chunks_mut(2).enumerate() {
+        record[0] = CHILD_STACK + (n + 1) * 2 * WORD;
+        record[1] = 0x1000 + n;
+    }
+    let backtrace = Backtrace::walk(
+        start,
+        &MappedChildMemory::new(as_bytes(&deep_stack), CHILD_STACK),
+    );
+    if backtrace.addresses().len() != MAX_BACKTRACE_DEPTH || !backtrace.is_truncated() {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Backtrace should be truncated at the maximum depth",
+        ));
+    }
+
+    let symbols = [
+        Symbol {
+            address: 0x1000,
+            size: 0x10,
+            name: "inner",
+        },
+        Symbol {
+            address: 0x2000,
+            size: 0x40,
+            name: "outer",
+        },
+    ];
+    match (
+        lookup_symbol(&symbols, 0x100c),
+        lookup_symbol(&symbols, 0x1010),
+        lookup_symbol(&symbols, 0x2020),
+        lookup_symbol(&symbols, 0x0fff),
+    ) {
+        (Some((inner, 0xc)), None, Some((outer, 0x20)), None)
+            if inner.name == "inner" && outer.name == "outer" =>
+        {
+            Ok(())
+        }
+        _ => Err(TopLevelError::TestAssertionFailure(
+            "Symbol lookup should find the containing function",
+        )),
+    }
+}
+
+fn as_bytes(words: &[usize]) -> &[u8] {
+    unsafe {
+        core::slice::from_raw_parts(
+            words.as_ptr() as *const u8,
+            words.len() * size_of::<usize>(),
+        )
+    }
+}
+
+pub struct ProcParams {}
+
+impl RetypeForSetup for ProcParams {
+    type Output = ProcParams;
+}
+
+// Each of these does some work with the result of the call it makes, so
+// that none of the calls becomes a tail call and every function keeps a
+// frame of its own.
+
+#[inline(never)]
+fn first() -> usize {
+    second().wrapping_mul(3)
+}
+
+#[inline(never)]
+fn second() -> usize {
+    third().wrapping_mul(5)
+}
+
+#[inline(never)]
+fn third() -> usize {
+    unsafe { core::ptr::read_volatile(0x88888888usize as *const usize) }
+}
+
+pub extern "C" fn proc_main(_params: ProcParams) {
+    let value = first();
+    debug_println!(
+        "This is after the faulting code, and should not be printed: {}",
+        value
+    );
+}
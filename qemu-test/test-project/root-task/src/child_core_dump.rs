@@ -0,0 +1,211 @@
+use core::convert::TryInto;
+use core::fmt;
+
+use typenum::*;
+
+use ferros::alloc::{smart_alloc, ut_buddy};
+use ferros::arch::fault::Fault;
+use ferros::bootstrap::UserImage;
+use ferros::cap::{
+    retype, retype_cnode, role, ASIDPool, Badge, LocalCNode, LocalCNodeSlots, LocalCap,
+    ThreadPriorities, ThreadPriorityAuthority, Untyped,
+};
+use ferros::userland::{
+    FaultSinkSetup, HexCoreDumpWriter, RetypeForSetup, SliceCoreDumpWriter, StandardProcess,
+};
+use ferros::vspace::*;
+
+use super::TopLevelError;
+
+/// Dump the core of a child that faults, both into a memory region and
+/// as hex text. Its stack is read from the parent's mapping of it, and
+/// the rest of its writable memory through a window onto its VSpace.
+#[ferros_test::ferros_test]
+pub fn child_core_dump(
+    local_slots: LocalCNodeSlots<U32768>,
+    local_ut: LocalCap<Untyped<U20>>,
+    asid_pool: LocalCap<ASIDPool<U1>>,
+    local_mapped_region: MappedMemoryRegion<U19, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+    local_vspace_scratch: &mut ScratchRegion,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    let (stack_region, mut output_region) = local_mapped_region.split()?;
+    let (stack_region, _) = stack_region.split()?;
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (child_asid, _asid_pool) = asid_pool.alloc();
+        let child_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let child_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut child_vspace = VSpace::new(
+            retype(ut, slots)?,
+            child_asid,
+            child_vspace_slots.weaken(),
+            child_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let (child_cnode, child_slots) = retype_cnode::<U12>(ut, slots)?;
+        let params = ProcParams {};
+
+        let setup = FaultSinkSetup::new(&root_cnode, ut, slots, slots)?;
+        let (child_slot_for_fault_source, _child_slots) = child_slots.alloc();
+        let fault_source =
+            setup.add_fault_source(&root_cnode, child_slot_for_fault_source, Badge::from(0))?;
+        let sink = setup.sink();
+        let window_slot: LocalCNodeSlots<U1> = slots;
+
+        let mut child_process = StandardProcess::new(
+            &mut child_vspace,
+            child_cnode,
+            stack_region,
+            root_cnode,
+            proc_main as extern "C" fn(_) -> (),
+            params,
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            Some(fault_source),
+        )?;
+    });
+
+    child_process.start()?;
+
+    let fault = sink.wait_for_fault();
+    match fault {
+        Fault::VMFault(_) => (),
+        _ => {
+            return Err(TopLevelError::TestAssertionFailure(
+                "unexpected fault in child_core_dump",
+            ))
+        }
+    }
+
+    let dump = child_process.core_dump(&child_vspace, Some(&fault))?;
+    let size = dump.size();
+    let mut window = MemoryWindow::new(&child_vspace, local_vspace_scratch, window_slot)?;
+    let mut writer = SliceCoreDumpWriter::new(output_region.as_mut_slice());
+    dump.write_to(&mut writer, Some(&mut window))?;
+    if writer.position() != size {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Core dump should be exactly as big as it said it would be",
+        ));
+    }
+
+    let core = &output_region.as_slice()[..size];
+    let u16_at = |offset: usize| u16::from_le_bytes(core[offset..offset + 2].try_into().unwrap());
+    let u32_at = |offset: usize| u32::from_le_bytes(core[offset..offset + 4].try_into().unwrap());
+    if core[..4] != [0x7f, b'E', b'L', b'F'] || u16_at(16) != 4 {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Core dump should start with an ELF core file header",
+        ));
+    }
+    // e_phnum: the note, plus every writable mapping, the stack and the
+    // IPC buffer among them
+    let phnum_offset = if cfg!(target_pointer_width = "64") {
+        56
+    } else {
+        44
+    };
+    let writable_mappings = child_vspace
+        .mappings()?
+        .iter()
+        .filter(|m| m.rights().is_writable())
+        .count();
+    if writable_mappings < 2 || u16_at(phnum_offset) as usize != 1 + writable_mappings {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Core dump should have a note and a segment for each writable mapping",
+        ));
+    }
+    // The note comes straight after the program headers, and the signal
+    // is 12 bytes into its description
+    let (header_size, program_header_size) = if cfg!(target_pointer_width = "64") {
+        (64, 56)
+    } else {
+        (52, 32)
+    };
+    let note = header_size + u16_at(phnum_offset) as usize * program_header_size;
+    if u32_at(note + 8) != 1 || &core[note + 12..note + 16] != b"CORE" || u16_at(note + 32) != 11 {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Core dump should report a segfault in its status note",
+        ));
+    }
+
+    // The stack is the first PT_LOAD segment
+    let stack = child_process.stack_memory();
+    let stack_header = header_size + program_header_size;
+    let stack_offset = if cfg!(target_pointer_width = "64") {
+        u32_at(stack_header + 8) as usize
+    } else {
+        u32_at(stack_header + 4) as usize
+    };
+    if core[stack_offset..stack_offset + stack.as_slice().len()] != *stack.as_slice() {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Core dump should hold the contents of the stack",
+        ));
+    }
+
+    let mut counter = HexCounter::default();
+    {
+        let mut hex_writer = HexCoreDumpWriter::new(&mut counter);
+        dump.write_to(&mut hex_writer, Some(&mut window))?;
+        hex_writer.finish()?;
+    }
+    let _ = window.into_slot();
+    if counter.digits != 2 * size || counter.lines != (size + 31) / 32 {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Hex core dump should encode every byte on prefixed lines",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Counts the lines of a hex core dump, and the hex digits after each
+/// line's prefix.
+#[derive(Default)]
+struct HexCounter {
+    digits: usize,
+    lines: usize,
+    after_prefix: bool,
+}
+
+impl fmt::Write for HexCounter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                ':' => self.after_prefix = true,
+                '\n' => {
+                    self.lines += 1;
+                    self.after_prefix = false;
+                }
+                c if self.after_prefix && c.is_ascii_hexdigit() => self.digits += 1,
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct ProcParams {}
+
+impl RetypeForSetup for ProcParams {
+    type Output = ProcParams;
+}
+
+pub extern "C" fn proc_main(_params: ProcParams) {
+    unsafe {
+        let x: *const usize = 0x88888888usize as _;
+        let y = *x;
+        debug_println!("Value from arbitrary memory is: {}", y);
+    }
+
+    debug_println!("This is after the segfaulting code, and should not be printed.");
+}
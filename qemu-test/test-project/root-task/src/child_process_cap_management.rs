@@ -73,6 +73,7 @@ pub fn child_process_cap_management(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             None, // fault
         )?;
     });
@@ -59,6 +59,7 @@ pub fn child_process_runs(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             None, // fault
         )?;
     });
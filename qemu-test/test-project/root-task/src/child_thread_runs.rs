@@ -24,7 +24,8 @@ pub fn child_thread_runs(
 
     smart_alloc!(|slots: local_slots, ut: uts| {
         let (child_cnode, child_slots) = retype_cnode::<U12>(ut, slots)?;
-        let (child_fault_source_slot, _child_slots) = child_slots.alloc();
+        let (child_fault_source_slot, child_slots) = child_slots.alloc();
+        let (thread_slots, _child_slots) = child_slots.alloc();
         let (fault_source, outcome_sender, handler) =
             fault_or_message_channel(&root_cnode, ut, slots, child_fault_source_slot, slots)?;
         let params = ProcParams {
@@ -36,17 +37,22 @@ pub fn child_thread_runs(
             vspace_paging_root,
             child_cnode,
             stack_mapped_region,
+            None, // stack guard
+            root_cnode,
             proc_main,
             params,
             ipc_buffer_region,
             ut,
+            ut,
             slots,
+            thread_slots,
             tpa,
+            ThreadPriorities::default(),
             None, // fault
         )?;
     });
 
-    child_process.start()?;
+    let _child_process = child_process.start()?;
 
     match handler.await_message()? {
         FaultOrMessage::Message(true) => Ok(()),
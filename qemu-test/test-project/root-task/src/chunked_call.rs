@@ -0,0 +1,124 @@
+use super::TopLevelError;
+use ferros::alloc::{smart_alloc, ut_buddy};
+use ferros::bootstrap::UserImage;
+use ferros::cap::{
+    retype, retype_cnode, role, ASIDPool, CNodeRole, LocalCNode, LocalCNodeSlots, LocalCap,
+    ThreadPriorities, ThreadPriorityAuthority, Untyped,
+};
+use ferros::userland::*;
+use ferros::vspace::*;
+use typenum::*;
+
+type U33768 = op!(U32768 + U1000);
+
+const REQUEST_WORDS: usize = 300;
+const RESPONSE_WORDS: usize = 200;
+
+/// Make calls whose request and response are both several times
+/// larger than the IPC buffer.
+#[ferros_test::ferros_test]
+pub fn chunked_call(
+    local_slots: LocalCNodeSlots<U33768>,
+    local_ut: LocalCap<Untyped<U20>>,
+    asid_pool: LocalCap<ASIDPool<U1>>,
+    local_mapped_region: MappedMemoryRegion<U17, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (responder_asid, _asid_pool) = asid_pool.alloc();
+        let responder_root = retype(ut, slots)?;
+        let responder_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let responder_vspace_ut: LocalCap<Untyped<U15>> = ut;
+
+        let mut responder_vspace = VSpace::new(
+            responder_root,
+            responder_asid,
+            responder_vspace_slots.weaken(),
+            responder_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let (responder_cnode, responder_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (slots_r, _responder_slots) = responder_slots.alloc();
+        let (ipc_setup, responder) = call_channel(ut, &root_cnode, slots, slots_r)?;
+        let caller = ipc_setup.create_caller(slots)?;
+
+        let mut responder_process = StandardProcess::new(
+            &mut responder_vspace,
+            responder_cnode,
+            local_mapped_region,
+            &root_cnode,
+            responder_proc as extern "C" fn(_) -> (),
+            ResponderParams::<role::Child> { responder },
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            None, // fault
+        )?;
+        responder_process.start()?;
+    });
+
+    for offset in 0..3 {
+        let mut request = LargeRequest {
+            words: [0; REQUEST_WORDS],
+        };
+        for (i, w) in request.words.iter_mut().enumerate() {
+            *w = (i + offset) as u64;
+        }
+
+        let response = caller.blocking_call(&request)?;
+
+        let expected_sum = request.words.iter().sum::<u64>();
+        let doubled_correctly = response
+            .doubled
+            .iter()
+            .enumerate()
+            .all(|(i, w)| *w == 2 * (i + offset) as u64);
+        if response.sum != expected_sum || !doubled_correctly {
+            return Err(TopLevelError::TestAssertionFailure(
+                "Chunked call returned the wrong response",
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub struct LargeRequest {
+    words: [u64; REQUEST_WORDS],
+}
+
+pub struct LargeResponse {
+    sum: u64,
+    doubled: [u64; RESPONSE_WORDS],
+}
+
+pub struct ResponderParams<Role: CNodeRole> {
+    pub responder: Responder<LargeRequest, LargeResponse, Role>,
+}
+
+impl RetypeForSetup for ResponderParams<role::Local> {
+    type Output = ResponderParams<role::Child>;
+}
+
+pub extern "C" fn responder_proc(p: ResponderParams<role::Local>) {
+    p.responder
+        .reply_recv(|req| {
+            let mut doubled = [0; RESPONSE_WORDS];
+            for (d, w) in doubled.iter_mut().zip(req.words.iter()) {
+                *d = 2 * w;
+            }
+            LargeResponse {
+                sum: req.words.iter().sum(),
+                doubled,
+            }
+        })
+        .expect("reply_recv");
+}
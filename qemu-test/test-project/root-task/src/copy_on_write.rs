@@ -0,0 +1,237 @@
+use ferros::alloc::ut_buddy::weak_ut_buddy;
+use ferros::alloc::{smart_alloc, ut_buddy};
+use typenum::*;
+
+use ferros::arch::{self, fault::Fault, PageBytes};
+use ferros::bootstrap::UserImage;
+use ferros::cap::*;
+use ferros::userland::{FaultSinkSetup, Pager, PagerEvent, RetypeForSetup, StandardProcess};
+use ferros::vspace::*;
+
+use super::TopLevelError;
+
+type U33768 = Sum<U32768, U1000>;
+
+/// Where a child faults once it's done, depending on whether it saw
+/// what it expected to. Both are in the never-mapped null page.
+const SUCCESS_VADDR: usize = 0x10;
+const FAILURE_VADDR: usize = 0x20;
+
+/// What the template's pages start with, and what each child puts in
+/// its first one.
+const ORIGINAL: u8 = 0x5a;
+const WRITER_WROTE: u8 = 0xa5;
+const READER_WROTE: u8 = 0xc3;
+
+/// Map a two page template copy-on-write into two children, with one
+/// pager looking after both. Each writes to the first page and should
+/// get a private copy of it, without seeing what the other wrote.
+#[ferros_test::ferros_test]
+pub fn copy_on_write(
+    local_slots: LocalCNodeSlots<U33768>,
+    local_ut: LocalCap<Untyped<U20>>,
+    asid_pool: LocalCap<ASIDPool<U2>>,
+    local_mapped_region: MappedMemoryRegion<U18, shared_status::Exclusive>,
+    local_vspace_scratch: &mut ScratchRegion,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (writer_asid, asid_pool) = asid_pool.alloc();
+        let (reader_asid, _asid_pool) = asid_pool.alloc();
+
+        let writer_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let writer_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut writer_vspace = VSpace::new(
+            retype(ut, slots)?,
+            writer_asid,
+            writer_vspace_slots.weaken(),
+            writer_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+        let reader_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let reader_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut reader_vspace = VSpace::new(
+            retype(ut, slots)?,
+            reader_asid,
+            reader_vspace_slots.weaken(),
+            reader_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let template_ut: LocalCap<Untyped<U13>> = ut;
+        let mut template: UnmappedMemoryRegion<U13, _> =
+            UnmappedMemoryRegion::new(template_ut, slots)?;
+        local_vspace_scratch.temporarily_map_region(&mut template, |mapped| {
+            for page in mapped.as_mut_slice().chunks_mut(PageBytes::USIZE) {
+                page[0] = ORIGINAL;
+            }
+        })?;
+        let template = template.to_copy_on_write();
+
+        let writer_data = writer_vspace.map_copy_on_write_region(
+            &template,
+            arch::vm_attributes::PROGRAM_DATA,
+            slots,
+            root_cnode,
+        )?;
+        let reader_data = reader_vspace.map_copy_on_write_region(
+            &template,
+            arch::vm_attributes::PROGRAM_DATA,
+            slots,
+            root_cnode,
+        )?;
+        let writer_data_vaddr = writer_data.vaddr();
+        let reader_data_vaddr = reader_data.vaddr();
+
+        let (writer_cnode, writer_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (reader_cnode, reader_slots) = retype_cnode::<U12>(ut, slots)?;
+
+        let setup = FaultSinkSetup::new(&root_cnode, ut, slots, slots)?;
+        let (writer_slot_for_fault_source, _writer_slots) = writer_slots.alloc();
+        let writer_fault_source =
+            setup.add_fault_source(&root_cnode, writer_slot_for_fault_source, Badge::from(0))?;
+        let (reader_slot_for_fault_source, _reader_slots) = reader_slots.alloc();
+        let reader_fault_source =
+            setup.add_fault_source(&root_cnode, reader_slot_for_fault_source, Badge::from(1))?;
+        let sink = setup.sink();
+
+        let (writer_region, reader_region) = local_mapped_region.split()?;
+
+        let mut writer_process = StandardProcess::new(
+            &mut writer_vspace,
+            writer_cnode,
+            writer_region,
+            root_cnode,
+            writer_main as extern "C" fn(_) -> (),
+            ProcParams {
+                data_vaddr: writer_data_vaddr,
+            },
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            Some(writer_fault_source),
+        )?;
+        let mut reader_process = StandardProcess::new(
+            &mut reader_vspace,
+            reader_cnode,
+            reader_region,
+            root_cnode,
+            reader_main as extern "C" fn(_) -> (),
+            ProcParams {
+                data_vaddr: reader_data_vaddr,
+            },
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            Some(reader_fault_source),
+        )?;
+
+        let pager_ut: LocalCap<Untyped<U14>> = ut;
+        let pager_slots: LocalCNodeSlots<U32> = slots;
+        let mut pager = Pager::new(
+            sink,
+            weak_ut_buddy(pager_ut.weaken()),
+            pager_slots.weaken(),
+            slots,
+        )
+        .with_scratch(local_vspace_scratch);
+    });
+    pager.add_vspace(&mut writer_vspace, Badge::from(0))?;
+    pager.add_vspace(&mut reader_vspace, Badge::from(1))?;
+    pager.add_copy_on_write_region(&writer_data, &template, arch::vm_attributes::PROGRAM_DATA)?;
+    pager.add_copy_on_write_region(&reader_data, &template, arch::vm_attributes::PROGRAM_DATA)?;
+    writer_process.start()?;
+
+    match pager.handle_next_fault()? {
+        PagerEvent::CopiedOnWrite(vaddr) if vaddr == writer_data_vaddr => (),
+        _ => {
+            return Err(TopLevelError::TestAssertionFailure(
+                "Writing to a copy-on-write page should have the pager copy it",
+            ))
+        }
+    }
+    match pager.run()? {
+        PagerEvent::Unhandled(Fault::VMFault(ref fault)) if fault.address == SUCCESS_VADDR => (),
+        _ => {
+            return Err(TopLevelError::TestAssertionFailure(
+                "The writer should see the template's contents and then its own write",
+            ))
+        }
+    }
+
+    reader_process.start()?;
+    match pager.handle_next_fault()? {
+        PagerEvent::CopiedOnWrite(vaddr) if vaddr == reader_data_vaddr => (),
+        _ => {
+            return Err(TopLevelError::TestAssertionFailure(
+                "The reader's write should get it a copy of its own",
+            ))
+        }
+    }
+    match pager.run()? {
+        PagerEvent::Unhandled(Fault::VMFault(ref fault)) if fault.address == SUCCESS_VADDR => {
+            Ok(())
+        }
+        _ => Err(TopLevelError::TestAssertionFailure(
+            "The reader should see the template's contents and then its own write",
+        )),
+    }
+}
+
+pub struct ProcParams {
+    pub data_vaddr: usize,
+}
+
+impl RetypeForSetup for ProcParams {
+    type Output = ProcParams;
+}
+
+fn signal_done(success: bool) {
+    let done = if success {
+        SUCCESS_VADDR
+    } else {
+        FAILURE_VADDR
+    };
+    unsafe {
+        core::ptr::write_volatile(done as *mut usize, 0);
+    }
+}
+
+pub extern "C" fn writer_main(params: ProcParams) {
+    let first = params.data_vaddr as *mut u8;
+    let second = (params.data_vaddr + PageBytes::USIZE) as *const u8;
+    let success = unsafe {
+        let copied = core::ptr::read_volatile(first) == ORIGINAL;
+        core::ptr::write_volatile(first, WRITER_WROTE);
+        copied
+            && core::ptr::read_volatile(first) == WRITER_WROTE
+            && core::ptr::read_volatile(second) == ORIGINAL
+    };
+    signal_done(success);
+
+    debug_println!("This is after the writer signalled it was done, and should not be printed.");
+}
+
+pub extern "C" fn reader_main(params: ProcParams) {
+    let first = params.data_vaddr as *mut u8;
+    let success = unsafe {
+        let untouched = core::ptr::read_volatile(first) == ORIGINAL;
+        core::ptr::write_volatile(first, READER_WROTE);
+        untouched && core::ptr::read_volatile(first) == READER_WROTE
+    };
+    signal_done(success);
+
+    debug_println!("This is after the reader signalled it was done, and should not be printed.");
+}
@@ -0,0 +1,241 @@
+use super::TopLevelError;
+
+use typenum::*;
+
+use ferros::alloc::{smart_alloc, ut_buddy};
+use ferros::bootstrap::UserImage;
+use ferros::cap::*;
+use ferros::userland::{
+    call_channel, fault_or_message_channel, Caller, FaultOrMessage, Responder, RetypeForSetup,
+    Sender, StandardProcess,
+};
+use ferros::vspace::*;
+
+type U66536 = Sum<U65536, U1000>;
+
+/// Two callers each send a request to a responder which holds onto
+/// the first request's reply until the second request has arrived.
+/// Each response is the sum of both requests, so it can only be
+/// computed while both callers are blocked at the same time.
+#[ferros_test::ferros_test]
+pub fn deferred_reply(
+    local_slots: LocalCNodeSlots<U66536>,
+    local_ut: LocalCap<Untyped<U27>>,
+    asid_pool: LocalCap<ASIDPool<U4>>,
+    local_mapped_region: MappedMemoryRegion<U19, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (responder_asid, asid_pool) = asid_pool.alloc();
+        let (caller_a_asid, asid_pool) = asid_pool.alloc();
+        let (caller_b_asid, _asid_pool) = asid_pool.alloc();
+
+        let (responder_cnode, responder_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (caller_a_cnode, caller_a_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (caller_b_cnode, caller_b_slots) = retype_cnode::<U12>(ut, slots)?;
+
+        // vspace setup
+        let responder_root = retype(ut, slots)?;
+        let responder_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let responder_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut responder_vspace = VSpace::new(
+            responder_root,
+            responder_asid,
+            responder_vspace_slots.weaken(),
+            responder_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let caller_a_root = retype(ut, slots)?;
+        let caller_a_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let caller_a_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut caller_a_vspace = VSpace::new(
+            caller_a_root,
+            caller_a_asid,
+            caller_a_vspace_slots.weaken(),
+            caller_a_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let caller_b_root = retype(ut, slots)?;
+        let caller_b_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let caller_b_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut caller_b_vspace = VSpace::new(
+            caller_b_root,
+            caller_b_asid,
+            caller_b_vspace_slots.weaken(),
+            caller_b_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let (slots_r, responder_slots) = responder_slots.alloc();
+        let (ipc_setup, responder) = call_channel(ut, &root_cnode, slots, slots_r)?;
+        let (slots_r, _responder_slots): (ChildCNodeSlots<U3>, _) = responder_slots.alloc();
+        let (_responder_cnode_for_child, reply_slots) =
+            responder_cnode.generate_self_reference::<U2>(&root_cnode, slots_r)?;
+
+        let (slots_a, caller_a_slots) = caller_a_slots.alloc();
+        let caller_a = ipc_setup.create_caller(slots_a)?;
+        let (outcome_slot_a, _caller_a_slots) = caller_a_slots.alloc();
+        let (_fault_source_a, outcome_sender_a, handler_a) =
+            fault_or_message_channel(&root_cnode, ut, slots, outcome_slot_a, slots)?;
+
+        let (slots_b, caller_b_slots) = caller_b_slots.alloc();
+        let caller_b = ipc_setup.create_caller(slots_b)?;
+        let (outcome_slot_b, _caller_b_slots) = caller_b_slots.alloc();
+        let (_fault_source_b, outcome_sender_b, handler_b) =
+            fault_or_message_channel(&root_cnode, ut, slots, outcome_slot_b, slots)?;
+
+        let responder_params = ResponderParams::<role::Child> {
+            responder,
+            reply_slots,
+        };
+        let caller_a_params = CallerParams::<role::Child> {
+            caller: caller_a,
+            value: 1,
+            outcome_sender: outcome_sender_a,
+        };
+        let caller_b_params = CallerParams::<role::Child> {
+            caller: caller_b,
+            value: 2,
+            outcome_sender: outcome_sender_b,
+        };
+
+        let (u18_region_a, u18_region_b) = local_mapped_region.split()?;
+        let (responder_region, caller_a_region) = u18_region_a.split()?;
+        let (caller_b_region, _spare_region) = u18_region_b.split()?;
+
+        let mut responder_process = StandardProcess::new(
+            &mut responder_vspace,
+            responder_cnode,
+            responder_region,
+            root_cnode,
+            responder_proc as extern "C" fn(_) -> (),
+            responder_params,
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            None, // fault
+        )?;
+
+        let mut caller_a_process = StandardProcess::new(
+            &mut caller_a_vspace,
+            caller_a_cnode,
+            caller_a_region,
+            root_cnode,
+            caller_proc as extern "C" fn(_) -> (),
+            caller_a_params,
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            None, // fault
+        )?;
+
+        let mut caller_b_process = StandardProcess::new(
+            &mut caller_b_vspace,
+            caller_b_cnode,
+            caller_b_region,
+            root_cnode,
+            caller_proc as extern "C" fn(_) -> (),
+            caller_b_params,
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            None, // fault
+        )?;
+
+        responder_process.start()?;
+        caller_a_process.start()?;
+        caller_b_process.start()?;
+    });
+
+    for handler in [handler_a, handler_b].iter() {
+        match handler.await_message()? {
+            FaultOrMessage::Message(true) => (),
+            _ => {
+                return Err(TopLevelError::TestAssertionFailure(
+                    "Caller should have received the sum of both requests",
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct AddRequest {
+    value: usize,
+}
+
+#[derive(Debug)]
+pub struct AddResponse {
+    sum: usize,
+}
+
+pub struct ResponderParams<Role: CNodeRole> {
+    pub responder: Responder<AddRequest, AddResponse, Role>,
+    pub reply_slots: Cap<CNodeSlotsData<U2, Role>, Role>,
+}
+
+impl RetypeForSetup for ResponderParams<role::Local> {
+    type Output = ResponderParams<role::Child>;
+}
+
+pub struct CallerParams<Role: CNodeRole> {
+    pub caller: Caller<AddRequest, AddResponse, Role>,
+    pub value: usize,
+    pub outcome_sender: Sender<bool, Role>,
+}
+
+impl RetypeForSetup for CallerParams<role::Local> {
+    type Output = CallerParams<role::Child>;
+}
+
+pub extern "C" fn responder_proc(p: ResponderParams<role::Local>) {
+    let (first_slot, reply_slots) = p.reply_slots.alloc();
+    let (second_slot, _reply_slots) = reply_slots.alloc();
+
+    let (first_req, first_reply) = p
+        .responder
+        .recv_deferred(first_slot)
+        .map_err(|(e, _slot)| e)
+        .expect("first recv_deferred");
+    let (second_req, second_reply) = p
+        .responder
+        .recv_deferred(second_slot)
+        .map_err(|(e, _slot)| e)
+        .expect("second recv_deferred");
+
+    let sum = first_req.value + second_req.value;
+
+    // Answer in the opposite order from which the requests arrived
+    let _second_slot = second_reply.reply(&AddResponse { sum });
+    let _first_slot = first_reply.reply(&AddResponse { sum });
+}
+
+pub extern "C" fn caller_proc(p: CallerParams<role::Local>) {
+    let rsp = p
+        .caller
+        .blocking_call(&AddRequest { value: p.value })
+        .expect("blocking_call");
+
+    p.outcome_sender
+        .blocking_send(&(rsp.sum == 3))
+        .expect("could not send outcome");
+}
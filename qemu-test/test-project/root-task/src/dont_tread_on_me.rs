@@ -98,6 +98,7 @@ pub fn dont_tread_on_me<'a, 'b, 'c>(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             None, // fault
         )?;
         proc1_process.start()?;
@@ -113,6 +114,7 @@ pub fn dont_tread_on_me<'a, 'b, 'c>(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             None, // fault
         )?;
         proc2_process.start()?;
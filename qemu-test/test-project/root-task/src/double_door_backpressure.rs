@@ -169,6 +169,7 @@ pub fn double_door_backpressure(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             None, // fault
         )?;
 
@@ -183,6 +184,7 @@ pub fn double_door_backpressure(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             None, // fault
         )?;
 
@@ -197,6 +199,7 @@ pub fn double_door_backpressure(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             None, // fault
         )?;
 
@@ -211,6 +214,7 @@ pub fn double_door_backpressure(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             None, // fault
         )?;
 
@@ -0,0 +1,190 @@
+use super::TopLevelError;
+
+use ferros::alloc::ut_buddy::weak_ut_buddy;
+use ferros::alloc::{smart_alloc, ut_buddy};
+use typenum::*;
+
+use elf_process;
+use ferros::bootstrap::UserImage;
+use ferros::cap::*;
+use ferros::userland::{
+    fault_or_message_channel, FaultOrMessage, FaultSinkSetup, Pager, PagerEvent, StandardProcess,
+};
+use ferros::vspace::*;
+use selfe_arc;
+
+/// Spawn two processes from the elf-process image, sharing one copy of
+/// its writable segments. Each writes to its BSS as soon as it starts,
+/// and should be given a copy of that page of its own by the pager
+/// looking after both of them.
+#[ferros_test::ferros_test]
+pub fn elf_copy_on_write(
+    local_slots: LocalCNodeSlots<U32768>,
+    local_ut: LocalCap<Untyped<U20>>,
+    asid_pool: LocalCap<ASIDPool<U2>>,
+    stack_mem: MappedMemoryRegion<U18, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+    local_vspace_scratch: &mut ScratchRegion,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    let archive_slice: &[u8] = unsafe {
+        core::slice::from_raw_parts(
+            &crate::_selfe_arc_data_start,
+            &crate::_selfe_arc_data_end as *const _ as usize
+                - &crate::_selfe_arc_data_start as *const _ as usize,
+        )
+    };
+
+    let archive = selfe_arc::read::Archive::from_slice(archive_slice);
+    let elf_data = archive
+        .file(crate::resources::ElfProcess::IMAGE_NAME)
+        .expect("find elf-process in arc");
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let template = ElfDataTemplate::new::<crate::resources::ElfProcess>(
+            &elf_data,
+            slots, // page_slots
+            ut,    // elf_writable_mem
+            local_vspace_scratch,
+        )?;
+
+        let (first_asid, asid_pool) = asid_pool.alloc();
+        let (second_asid, _asid_pool) = asid_pool.alloc();
+
+        let first_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let first_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let (mut first_vspace, first_data) =
+            VSpace::new_from_elf_copy_on_write::<crate::resources::ElfProcess>(
+                retype(ut, slots)?,
+                first_asid,
+                first_vspace_slots.weaken(),
+                first_vspace_ut.weaken(),
+                &elf_data,
+                slots, // page_slots
+                &template,
+                &user_image,
+                &root_cnode,
+            )?;
+        let second_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let second_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let (mut second_vspace, second_data) =
+            VSpace::new_from_elf_copy_on_write::<crate::resources::ElfProcess>(
+                retype(ut, slots)?,
+                second_asid,
+                second_vspace_slots.weaken(),
+                second_vspace_ut.weaken(),
+                &elf_data,
+                slots, // page_slots
+                &template,
+                &user_image,
+                &root_cnode,
+            )?;
+
+        let (first_cnode, first_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (second_cnode, second_slots) = retype_cnode::<U12>(ut, slots)?;
+
+        let (first_outcome_slot, first_slots) = first_slots.alloc();
+        let (_first_outcome_fault_source, first_outcome_sender, first_outcome_handler) =
+            fault_or_message_channel(&root_cnode, ut, slots, first_outcome_slot, slots)?;
+        let (second_outcome_slot, second_slots) = second_slots.alloc();
+        let (_second_outcome_fault_source, second_outcome_sender, second_outcome_handler) =
+            fault_or_message_channel(&root_cnode, ut, slots, second_outcome_slot, slots)?;
+
+        let setup = FaultSinkSetup::new(&root_cnode, ut, slots, slots)?;
+        let (first_slot_for_fault_source, _first_slots) = first_slots.alloc();
+        let first_fault_source =
+            setup.add_fault_source(&root_cnode, first_slot_for_fault_source, Badge::from(0))?;
+        let (second_slot_for_fault_source, _second_slots) = second_slots.alloc();
+        let second_fault_source =
+            setup.add_fault_source(&root_cnode, second_slot_for_fault_source, Badge::from(1))?;
+        let sink = setup.sink();
+
+        let (first_stack, second_stack) = stack_mem.split()?;
+
+        let mut first_process = StandardProcess::new::<elf_process::ProcParams<_>, _>(
+            &mut first_vspace,
+            first_cnode,
+            first_stack,
+            root_cnode,
+            elf_data,
+            elf_process::ProcParams {
+                value: 42,
+                outcome_sender: first_outcome_sender,
+            },
+            ut, // ipc_buffer_ut
+            ut, // tcb_ut
+            slots,
+            tpa, // priority_authority
+            ThreadPriorities::default(),
+            Some(first_fault_source),
+        )?;
+        let mut second_process = StandardProcess::new::<elf_process::ProcParams<_>, _>(
+            &mut second_vspace,
+            second_cnode,
+            second_stack,
+            root_cnode,
+            elf_data,
+            elf_process::ProcParams {
+                value: 42,
+                outcome_sender: second_outcome_sender,
+            },
+            ut, // ipc_buffer_ut
+            ut, // tcb_ut
+            slots,
+            tpa, // priority_authority
+            ThreadPriorities::default(),
+            Some(second_fault_source),
+        )?;
+
+        let pager_ut: LocalCap<Untyped<U14>> = ut;
+        let pager_slots: LocalCNodeSlots<U32> = slots;
+        let mut pager = Pager::new(
+            sink,
+            weak_ut_buddy(pager_ut.weaken()),
+            pager_slots.weaken(),
+            slots,
+        )
+        .with_scratch(local_vspace_scratch);
+    });
+    pager.add_vspace(&mut first_vspace, Badge::from(0))?;
+    pager.add_vspace(&mut second_vspace, Badge::from(1))?;
+    pager.add_elf_data(&first_data, &template)?;
+    pager.add_elf_data(&second_data, &template)?;
+
+    first_process.start()?;
+    match pager.handle_next_fault()? {
+        PagerEvent::CopiedOnWrite(_) => (),
+        _ => {
+            return Err(TopLevelError::TestAssertionFailure(
+                "The first process's write to its BSS should get it a copy of the page",
+            ))
+        }
+    }
+    match first_outcome_handler.await_message()? {
+        FaultOrMessage::Message(true) => (),
+        _ => {
+            return Err(TopLevelError::TestAssertionFailure(
+                "The first process should have reported success",
+            ))
+        }
+    }
+
+    second_process.start()?;
+    match pager.handle_next_fault()? {
+        PagerEvent::CopiedOnWrite(_) => (),
+        _ => {
+            return Err(TopLevelError::TestAssertionFailure(
+                "The second process should get a copy of its own, not the first's",
+            ))
+        }
+    }
+    match second_outcome_handler.await_message()? {
+        FaultOrMessage::Message(true) => Ok(()),
+        _ => Err(TopLevelError::TestAssertionFailure(
+            "The second process should have reported success",
+        )),
+    }
+}
@@ -75,7 +75,8 @@ pub fn elf_process_runs(
             ut, // ipc_buffer_ut
             ut, // tcb_ut
             slots,
-            tpa,  // priority_authority
+            tpa, // priority_authority
+            ThreadPriorities::default(),
             None, // fault
         )?;
     });
@@ -0,0 +1,248 @@
+use super::TopLevelError;
+use ferros::alloc::{smart_alloc, ut_buddy};
+use ferros::bootstrap::UserImage;
+use ferros::cap::{
+    retype, retype_cnode, role, ASIDPool, CNodeRole, LocalCNode, LocalCNodeSlots, LocalCap,
+    ThreadPriorities, ThreadPriorityAuthority, Untyped,
+};
+use ferros::userland::*;
+use ferros::vspace::*;
+use typenum::*;
+
+type U33768 = op!(U32768 + U1000);
+
+const ROUND_TRIPS: u64 = 1000;
+
+/// Compare the round trip latency of calls small enough to travel in
+/// the message registers against calls which must use the IPC buffer.
+///
+/// Timing under emulation is too noisy to assert on, so the figures
+/// are only reported.
+#[ferros_test::ferros_test]
+pub fn fastpath_benchmark(
+    local_slots: LocalCNodeSlots<U33768>,
+    local_ut: LocalCap<Untyped<U20>>,
+    asid_pool: LocalCap<ASIDPool<U2>>,
+    local_mapped_region: MappedMemoryRegion<U18, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (small_asid, asid_pool) = asid_pool.alloc();
+        let (large_asid, _asid_pool) = asid_pool.alloc();
+
+        let small_root = retype(ut, slots)?;
+        let small_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let small_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut small_vspace = VSpace::new(
+            small_root,
+            small_asid,
+            small_vspace_slots.weaken(),
+            small_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let large_root = retype(ut, slots)?;
+        let large_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let large_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut large_vspace = VSpace::new(
+            large_root,
+            large_asid,
+            large_vspace_slots.weaken(),
+            large_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let (small_cnode, small_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (slots_r, _small_slots) = small_slots.alloc();
+        let (small_setup, small_responder) = call_channel(ut, &root_cnode, slots, slots_r)?;
+        let small_caller: Caller<SmallMessage, SmallMessage, _> =
+            small_setup.create_caller(slots)?;
+
+        let (large_cnode, large_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (slots_r, _large_slots) = large_slots.alloc();
+        let (large_setup, large_responder) = call_channel(ut, &root_cnode, slots, slots_r)?;
+        let large_caller: Caller<LargeMessage, LargeMessage, _> =
+            large_setup.create_caller(slots)?;
+
+        let (small_region, large_region) = local_mapped_region.split()?;
+
+        let mut small_process = StandardProcess::new(
+            &mut small_vspace,
+            small_cnode,
+            small_region,
+            root_cnode,
+            small_echo_proc as extern "C" fn(_) -> (),
+            SmallParams::<role::Child> {
+                responder: small_responder,
+            },
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            None, // fault
+        )?;
+        small_process.start()?;
+
+        let mut large_process = StandardProcess::new(
+            &mut large_vspace,
+            large_cnode,
+            large_region,
+            root_cnode,
+            large_echo_proc as extern "C" fn(_) -> (),
+            LargeParams::<role::Child> {
+                responder: large_responder,
+            },
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            None, // fault
+        )?;
+        large_process.start()?;
+    });
+
+    cycle_counter::enable();
+
+    let start = cycle_counter::read();
+    for i in 0..ROUND_TRIPS {
+        let rsp = small_caller.blocking_call(&SmallMessage { words: [i; 2] })?;
+        if rsp.words != [i + 1; 2] {
+            return Err(TopLevelError::TestAssertionFailure(
+                "Register-sized call returned the wrong response",
+            ));
+        }
+    }
+    let small_cycles = cycle_counter::elapsed_since(start);
+
+    let start = cycle_counter::read();
+    for i in 0..ROUND_TRIPS {
+        let rsp = large_caller.blocking_call(&LargeMessage { words: [i; 16] })?;
+        if rsp.words != [i + 1; 16] {
+            return Err(TopLevelError::TestAssertionFailure(
+                "Buffer-sized call returned the wrong response",
+            ));
+        }
+    }
+    let large_cycles = cycle_counter::elapsed_since(start);
+
+    debug_println!(
+        "fastpath_benchmark: {} round trips, registers: {} cycles/call, ipc buffer: {} cycles/call",
+        ROUND_TRIPS,
+        small_cycles / ROUND_TRIPS,
+        large_cycles / ROUND_TRIPS
+    );
+    Ok(())
+}
+
+/// Fits in the message registers on both 32 and 64 bit ARM
+#[derive(Debug, Clone, Copy)]
+pub struct SmallMessage {
+    words: [u64; 2],
+}
+
+/// Too large for the message registers
+#[derive(Debug, Clone, Copy)]
+pub struct LargeMessage {
+    words: [u64; 16],
+}
+
+pub struct SmallParams<Role: CNodeRole> {
+    pub responder: Responder<SmallMessage, SmallMessage, Role>,
+}
+
+impl RetypeForSetup for SmallParams<role::Local> {
+    type Output = SmallParams<role::Child>;
+}
+
+pub struct LargeParams<Role: CNodeRole> {
+    pub responder: Responder<LargeMessage, LargeMessage, Role>,
+}
+
+impl RetypeForSetup for LargeParams<role::Local> {
+    type Output = LargeParams<role::Child>;
+}
+
+pub extern "C" fn small_echo_proc(p: SmallParams<role::Local>) {
+    p.responder
+        .reply_recv(|mut msg| {
+            for w in msg.words.iter_mut() {
+                *w += 1;
+            }
+            msg
+        })
+        .expect("small reply_recv");
+}
+
+pub extern "C" fn large_echo_proc(p: LargeParams<role::Local>) {
+    p.responder
+        .reply_recv(|mut msg| {
+            for w in msg.words.iter_mut() {
+                *w += 1;
+            }
+            msg
+        })
+        .expect("large reply_recv");
+}
+
+/// Access to the PMU cycle counter, which the kernel exports to user
+/// level when `KernelArmExportPMUUser` is set.
+#[cfg(target_arch = "aarch64")]
+mod cycle_counter {
+    use core::arch::asm;
+
+    pub fn enable() {
+        unsafe {
+            // PMCR_EL0: enable counters, reset the cycle counter
+            asm!("msr pmcr_el0, {}", in(reg) 0b101u64);
+            // PMCNTENSET_EL0: enable the cycle counter
+            asm!("msr pmcntenset_el0, {}", in(reg) 1u64 << 31);
+        }
+    }
+
+    pub fn read() -> u64 {
+        let count: u64;
+        unsafe { asm!("mrs {}, pmccntr_el0", out(reg) count) };
+        count
+    }
+
+    pub fn elapsed_since(start: u64) -> u64 {
+        read().wrapping_sub(start)
+    }
+}
+
+/// Access to the PMU cycle counter, which the kernel exports to user
+/// level when `KernelArmExportPMUUser` is set.
+#[cfg(target_arch = "arm")]
+mod cycle_counter {
+    use core::arch::asm;
+
+    pub fn enable() {
+        unsafe {
+            // PMCR: enable counters, reset the cycle counter
+            asm!("mcr p15, 0, {}, c9, c12, 0", in(reg) 0b101u32);
+            // PMCNTENSET: enable the cycle counter
+            asm!("mcr p15, 0, {}, c9, c12, 1", in(reg) 1u32 << 31);
+        }
+    }
+
+    pub fn read() -> u64 {
+        let count: u32;
+        unsafe { asm!("mrc p15, 0, {}, c9, c13, 0", out(reg) count) };
+        u64::from(count)
+    }
+
+    /// The 32 bit counter may wrap once between readings
+    pub fn elapsed_since(start: u64) -> u64 {
+        u64::from((read() as u32).wrapping_sub(start as u32))
+    }
+}
@@ -89,6 +89,7 @@ pub fn fault_or_message_handler(
                         ut,
                         slots,
                         tpa,
+                        ThreadPriorities::default(),
                         Some(source),
                     )?;
                 });
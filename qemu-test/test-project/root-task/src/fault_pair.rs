@@ -11,7 +11,7 @@ use ferros::arch::fault::Fault;
 use ferros::bootstrap::UserImage;
 use ferros::cap::{
     retype, retype_cnode, role, ASIDPool, CNodeRole, CNodeSlotsData, Cap, FaultReplyEndpoint,
-    LocalCNode, LocalCNodeSlots, LocalCap, ThreadPriorityAuthority, Untyped,
+    LocalCNode, LocalCNodeSlots, LocalCap, ThreadPriorities, ThreadPriorityAuthority, Untyped,
 };
 use ferros::userland::{
     fault_or_message_channel, setup_fault_endpoint_pair, FaultOrMessage, FaultSink, RetypeForSetup,
@@ -99,6 +99,7 @@ pub fn fault_pair(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             Some(fault_source),
         )?;
         mischief_maker_process.start()?;
@@ -114,6 +115,7 @@ pub fn fault_pair(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             Some(fault_source_for_the_handler),
         )?;
         fault_handler_process.start()?;
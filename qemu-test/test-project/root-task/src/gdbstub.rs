@@ -0,0 +1,327 @@
+use selfe_sys::*;
+
+use typenum::*;
+
+use ferros::alloc::micro_alloc::PageAlignedAddressRange;
+use ferros::alloc::{self, micro_alloc, smart_alloc};
+use ferros::arch;
+use ferros::bootstrap::{root_cnode, BootInfo};
+use ferros::cap::*;
+use ferros::userland::{
+    serve, CapRights, DebuggeeMemory, DefaultStackBitSize, FaultSinkSetup, GdbConnection,
+    GdbStubParams, RetypeForSetup, SelfHostedProcess, StandardProcess,
+};
+use ferros::vspace::*;
+
+use super::TopLevelError;
+
+// C.f. i.MX 6ULL Reference Manual Table 2.2.
+#[cfg(target_arch = "arm")]
+const UART_PADDR: usize = 0x02020000;
+
+// The virt board's second PL011, which the kernel leaves alone as it
+// prints to the first.
+#[cfg(target_arch = "aarch64")]
+const UART_PADDR: usize = 0x09040000;
+
+/// Set up a child with a writable copy of the code image, and a
+/// process running the gdbstub service that debugs it over a UART,
+/// which the test harness connects to.
+pub fn run(raw_boot_info: &'static seL4_BootInfo) -> Result<(), TopLevelError> {
+    let (mut allocator, mut device_allocator) = micro_alloc::bootstrap_allocators(&raw_boot_info)?;
+    let (root_cnode, local_slots) = root_cnode(&raw_boot_info);
+    let (root_vspace_slots, local_slots): (LocalCNodeSlots<U100>, _) = local_slots.alloc();
+    let BootInfo {
+        mut root_vspace,
+        asid_control,
+        user_image,
+        root_tcb,
+        ..
+    } = BootInfo::wrap(
+        &raw_boot_info,
+        allocator
+            .get_untyped::<U13>()
+            .expect("Initial untyped retrieval failure"),
+        root_vspace_slots,
+    );
+    let tpa = root_tcb.thread_priority_authority();
+    let code_pages_ut: LocalCap<Untyped<arch::TotalCodeSizeBits>> =
+        allocator.get_untyped().expect("code pages alloc failure");
+    let uts = alloc::ut_buddy(
+        allocator
+            .get_untyped::<U23>()
+            .expect("initial alloc failure"),
+    );
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let scratch_ut: LocalCap<Untyped<U12>> = ut;
+        let reserved_for_scratch = root_vspace.reserve(scratch_ut.retype(slots)?)?;
+        let mut scratch: ScratchRegion = reserved_for_scratch.as_scratch(&root_vspace)?;
+
+        let (asid_pool, _asid_control) = asid_control.allocate_asid_pool(ut, slots)?;
+        let (debuggee_asid, asid_pool) = asid_pool.alloc();
+        let (service_asid, _asid_pool) = asid_pool.alloc();
+
+        let debuggee_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let debuggee_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let code_pages_slots: LocalCNodeSlots<arch::CodePageCount> = slots;
+        let mut debuggee_vspace = VSpace::new(
+            retype(ut, slots)?,
+            debuggee_asid,
+            debuggee_vspace_slots.weaken(),
+            debuggee_vspace_ut.weaken(),
+            // Software breakpoints are written over the child's code
+            ProcessCodeImageConfig::ReadWritable {
+                parent_vspace_scratch: &mut scratch,
+                code_pages_ut,
+                code_pages_slots,
+            },
+            &user_image,
+            &root_cnode,
+        )?;
+
+        let service_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let service_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut service_vspace = VSpace::new(
+            retype(ut, slots)?,
+            service_asid,
+            service_vspace_slots.weaken(),
+            service_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            &user_image,
+            &root_cnode,
+        )?;
+
+        let uart_untyped = device_allocator
+            .get_untyped_by_address_range_slot_infallible(
+                PageAlignedAddressRange::new_by_size(UART_PADDR, arch::PageBytes::USIZE)
+                    .expect("failed to specify UART page range"),
+                slots,
+            )
+            .expect("find uart device memory")
+            .as_strong::<arch::PageBits>()
+            .expect("device untyped was not the right size!");
+        let uart_page = service_vspace.map_region(
+            UnmappedMemoryRegion::new_device(uart_untyped, slots)?,
+            CapRights::RW,
+            arch::vm_attributes::DEFAULT,
+        )?;
+
+        let (debuggee_cnode, debuggee_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (service_cnode, service_slots) = retype_cnode::<U14>(ut, slots)?;
+
+        let (debuggee_slot_for_fault_source, _debuggee_slots) = debuggee_slots.alloc();
+        let (service_slot_for_fault_sink, service_slots) = service_slots.alloc();
+        let setup = FaultSinkSetup::new(&root_cnode, ut, slots, service_slot_for_fault_sink)?;
+        let fault_source =
+            setup.add_fault_source(&root_cnode, debuggee_slot_for_fault_source, Badge::from(0))?;
+
+        let unmapped_stack: UnmappedMemoryRegion<DefaultStackBitSize, _> =
+            UnmappedMemoryRegion::new(ut, slots)?;
+        let stack =
+            root_vspace.map_region(unmapped_stack, CapRights::RW, arch::vm_attributes::DEFAULT)?;
+        let debuggee = StandardProcess::new(
+            &mut debuggee_vspace,
+            debuggee_cnode,
+            stack,
+            &root_cnode,
+            proc_main as extern "C" fn(_) -> (),
+            ProcParams {},
+            ut,
+            ut,
+            slots,
+            &tpa,
+            ThreadPriorities::default(),
+            Some(fault_source),
+        )?;
+
+        let page_ut: LocalCap<Untyped<arch::PageBits>> = ut;
+
+        smart_alloc! {|slots_s: service_slots| {
+            let memory_slots: LocalCap<CNodeSlotsData<U8192, role::Child>> = slots_s;
+            let cap_transfer_slots: LocalCap<CNodeSlotsData<U1024, role::Child>> = slots_s;
+            let (_service_cnode_for_service, slots_for_service): (_, ChildCap<CNodeSlotsData<U2048, role::Child>>) =
+                service_cnode.generate_self_reference(&root_cnode, slots_s)?;
+            let debuggee_tcb = debuggee.copy_tcb(&root_cnode, slots_s)?;
+            let service_page_ut = page_ut.move_to_slot(&root_cnode, slots_s)?;
+        }}
+
+        let (service_paging_slots, slots_for_service): (Cap<CNodeSlotsData<U1024, _>, _>, _) =
+            slots_for_service.alloc();
+        let (scratch_slots, _slots_for_service) = slots_for_service.alloc();
+
+        let params = ServiceParams {
+            uart_vaddr: uart_page.vaddr(),
+            gdbstub: GdbStubParams {
+                debuggee: debuggee_tcb,
+                sink: setup.sink(),
+                memory: DebuggeeMemory::new(
+                    &debuggee_vspace,
+                    &root_cnode,
+                    &mut memory_slots.weaken(),
+                )?,
+                scratch_ut: service_page_ut,
+                scratch_slots,
+            },
+        };
+
+        let service_stack: UnmappedMemoryRegion<U17, _> = UnmappedMemoryRegion::new(ut, slots)?;
+        let service_stack =
+            root_vspace.map_region(service_stack, CapRights::RW, arch::vm_attributes::DEFAULT)?;
+        let service = SelfHostedProcess::new(
+            service_vspace,
+            service_cnode,
+            service_stack,
+            &root_cnode,
+            service_main,
+            params,
+            ut,
+            ut,
+            slots,
+            cap_transfer_slots.weaken(),
+            service_paging_slots.weaken(),
+            &tpa,
+            ThreadPriorities::default(),
+            None,
+        )?;
+    });
+
+    debug_println!(
+        "gdbstub ready, breakpoint target at {:#x}",
+        breakpoint_target as usize
+    );
+    service.start()?;
+
+    Ok(())
+}
+
+pub struct ServiceParams<Role: CNodeRole> {
+    pub uart_vaddr: usize,
+    pub gdbstub: GdbStubParams<Role>,
+}
+
+impl RetypeForSetup for ServiceParams<role::Local> {
+    type Output = ServiceParams<role::Child>;
+}
+
+pub extern "C" fn service_main(vspace: VSpace, params: ServiceParams<role::Local>) {
+    let uart = Uart::new(params.uart_vaddr);
+    serve(vspace, params.gdbstub, uart).expect("gdbstub service failed");
+    debug_println!("gdbstub session ended");
+}
+
+pub struct ProcParams {}
+
+impl RetypeForSetup for ProcParams {
+    type Output = ProcParams;
+}
+
+/// The harness sets a breakpoint on `breakpoint_target` before starting
+/// the child, steps from it onto a second breakpoint, then lets the
+/// child run on into a fault.
+pub extern "C" fn proc_main(_params: ProcParams) {
+    breakpoint_target();
+    unsafe {
+        let x: *const usize = 0x88888888usize as _;
+        let y = core::ptr::read_volatile(x);
+        debug_println!("Value from arbitrary memory is: {}", y);
+    }
+}
+
+#[inline(never)]
+extern "C" fn breakpoint_target() {
+    unsafe { core::ptr::read_volatile(&0usize) };
+}
+
+// Register offsets and bits, c.f. i.MX 6ULL Reference Manual 55.15
+#[cfg(target_arch = "arm")]
+mod regs {
+    pub const URXD: usize = 0x00;
+    pub const URXD_CHARRDY: u32 = 1 << 15;
+    pub const UTXD: usize = 0x40;
+    pub const UCR1: usize = 0x80;
+    pub const UCR1_UARTEN: u32 = 1 << 0;
+    pub const UCR2: usize = 0x84;
+    pub const UCR2_SRST: u32 = 1 << 0;
+    pub const UCR2_RXEN: u32 = 1 << 1;
+    pub const UCR2_TXEN: u32 = 1 << 2;
+    pub const UCR2_WS: u32 = 1 << 5;
+    pub const UCR2_IRTS: u32 = 1 << 14;
+    pub const UTS: usize = 0xb4;
+    pub const UTS_TXFULL: u32 = 1 << 4;
+}
+
+// Register offsets and bits, c.f. PrimeCell UART (PL011) Technical
+// Reference Manual 3.2
+#[cfg(target_arch = "aarch64")]
+mod regs {
+    pub const UARTDR: usize = 0x00;
+    pub const UARTFR: usize = 0x18;
+    pub const UARTFR_RXFE: u32 = 1 << 4;
+    pub const UARTFR_TXFF: u32 = 1 << 5;
+}
+
+use regs::*;
+
+/// A polled connection over an i.MX6 UART, or a PL011 on virt.
+struct Uart {
+    base: usize,
+}
+
+impl Uart {
+    #[cfg(target_arch = "arm")]
+    fn new(base: usize) -> Self {
+        let uart = Uart { base };
+        uart.write(UCR1, uart.read(UCR1) | UCR1_UARTEN);
+        // Writing a 0 to SRST resets the UART, so keep it set
+        uart.write(
+            UCR2,
+            uart.read(UCR2) | UCR2_SRST | UCR2_RXEN | UCR2_TXEN | UCR2_WS | UCR2_IRTS,
+        );
+        uart
+    }
+
+    /// Qemu's PL011 comes up ready to use.
+    #[cfg(target_arch = "aarch64")]
+    fn new(base: usize) -> Self {
+        Uart { base }
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { core::ptr::read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { core::ptr::write_volatile((self.base + offset) as *mut u32, value) }
+    }
+}
+
+#[cfg(target_arch = "arm")]
+impl GdbConnection for Uart {
+    fn read_byte(&mut self) -> u8 {
+        loop {
+            let rx = self.read(URXD);
+            if rx & URXD_CHARRDY != 0 {
+                return rx as u8;
+            }
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        while self.read(UTS) & UTS_TXFULL != 0 {}
+        self.write(UTXD, byte as u32);
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+impl GdbConnection for Uart {
+    fn read_byte(&mut self) -> u8 {
+        while self.read(UARTFR) & UARTFR_RXFE != 0 {}
+        self.read(UARTDR) as u8
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        while self.read(UARTFR) & UARTFR_TXFF != 0 {}
+        self.write(UARTDR, byte as u32);
+    }
+}
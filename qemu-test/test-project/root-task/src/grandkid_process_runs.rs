@@ -97,6 +97,7 @@ pub fn grandkid_process_runs(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             Some(fault_source),
         )?;
     });
@@ -200,6 +201,7 @@ fn child_run(params: ChildParams<role::Local>) -> Result<(), TopLevelError> {
             ut,
             slots,
             &thread_priority_authority,
+            ThreadPriorities::default(),
             None,
         )?;
     });
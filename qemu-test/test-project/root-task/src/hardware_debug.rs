@@ -0,0 +1,135 @@
+//! Only builds against a kernel with its hardware debug API, so it has
+//! its own test case: build with `TEST_CASE=hardware_debug` for the
+//! sabre platform, which is configured with `HardwareDebugAPI`.
+
+use selfe_sys::*;
+
+use typenum::*;
+
+use ferros::alloc::{self, micro_alloc, smart_alloc};
+use ferros::arch::{self, fault::Fault};
+use ferros::bootstrap::{root_cnode, BootInfo};
+use ferros::cap::*;
+use ferros::userland::{CapRights, FaultSinkSetup, RetypeForSetup, StandardProcess};
+use ferros::vspace::*;
+
+use super::TopLevelError;
+
+const BREAKPOINT_NUMBER: u16 = 0;
+
+/// Set an instruction breakpoint on a function in a child before
+/// starting it, and check that the child raises a debug exception there.
+pub fn run(raw_boot_info: &'static seL4_BootInfo) -> Result<(), TopLevelError> {
+    let (mut allocator, _device_allocator) = micro_alloc::bootstrap_allocators(&raw_boot_info)?;
+    let (root_cnode, local_slots) = root_cnode(&raw_boot_info);
+    let (root_vspace_slots, local_slots): (LocalCNodeSlots<U100>, _) = local_slots.alloc();
+    let BootInfo {
+        mut root_vspace,
+        asid_control,
+        user_image,
+        root_tcb,
+        ..
+    } = BootInfo::wrap(
+        &raw_boot_info,
+        allocator
+            .get_untyped::<U13>()
+            .expect("Initial untyped retrieval failure"),
+        root_vspace_slots,
+    );
+    let tpa = root_tcb.thread_priority_authority();
+    let uts = alloc::ut_buddy(
+        allocator
+            .get_untyped::<U20>()
+            .expect("initial alloc failure"),
+    );
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (asid_pool, _asid_control) = asid_control.allocate_asid_pool(ut, slots)?;
+        let (child_asid, _asid_pool) = asid_pool.alloc();
+
+        let child_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let child_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut child_vspace = VSpace::new(
+            retype(ut, slots)?,
+            child_asid,
+            child_vspace_slots.weaken(),
+            child_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            &user_image,
+            &root_cnode,
+        )?;
+
+        let (child_cnode, child_slots) = retype_cnode::<U12>(ut, slots)?;
+
+        let setup = FaultSinkSetup::new(&root_cnode, ut, slots, slots)?;
+        let (child_slot_for_fault_source, _child_slots) = child_slots.alloc();
+        let fault_source =
+            setup.add_fault_source(&root_cnode, child_slot_for_fault_source, Badge::from(0))?;
+        let sink = setup.sink();
+
+        let child_stack: UnmappedMemoryRegion<U17, _> = UnmappedMemoryRegion::new(ut, slots)?;
+        let child_stack =
+            root_vspace.map_region(child_stack, CapRights::RW, arch::vm_attributes::DEFAULT)?;
+
+        let mut child_process = StandardProcess::new(
+            &mut child_vspace,
+            child_cnode,
+            child_stack,
+            &root_cnode,
+            proc_main as extern "C" fn(_) -> (),
+            ProcParams {},
+            ut,
+            ut,
+            slots,
+            &tpa,
+            ThreadPriorities::default(),
+            Some(fault_source),
+        )?;
+    });
+
+    let breakpoint = Breakpoint::instruction(breakpoint_target as usize);
+    child_process.set_breakpoint(BREAKPOINT_NUMBER, breakpoint)?;
+    if child_process.get_breakpoint(BREAKPOINT_NUMBER)? != Some(breakpoint) {
+        return Err(TopLevelError::TestAssertionFailure(
+            "A breakpoint that was set should read back",
+        ));
+    }
+    child_process.start()?;
+
+    match sink.wait_for_fault() {
+        Fault::DebugException(e)
+            if e.reason == BreakpointType::Instruction
+                && e.program_counter == breakpoint_target as usize => {}
+        _ => {
+            return Err(TopLevelError::TestAssertionFailure(
+                "The child should stop at its instruction breakpoint",
+            ))
+        }
+    }
+
+    child_process.unset_breakpoint(BREAKPOINT_NUMBER)?;
+    if child_process.get_breakpoint(BREAKPOINT_NUMBER)?.is_some() {
+        return Err(TopLevelError::TestAssertionFailure(
+            "An unset breakpoint should no longer be enabled",
+        ));
+    }
+
+    debug_println!("hardware_debug test passed");
+    Ok(())
+}
+
+pub struct ProcParams {}
+
+impl RetypeForSetup for ProcParams {
+    type Output = ProcParams;
+}
+
+pub extern "C" fn proc_main(_params: ProcParams) {
+    breakpoint_target();
+    debug_println!("This is after the breakpoint, and should not be printed.");
+}
+
+#[inline(never)]
+extern "C" fn breakpoint_target() {
+    unsafe { core::ptr::read_volatile(&0usize) };
+}
@@ -0,0 +1,78 @@
+//! Needs a 1 GiB untyped, which is more than the unified tests get, so
+//! it has its own test case: build with `TEST_CASE=huge_page_mapping`
+//! for the virt platform, which is given enough memory for one.
+
+use selfe_sys::*;
+
+use typenum::*;
+
+use ferros::alloc::{self, micro_alloc, smart_alloc};
+use ferros::arch::{self, HugePageBits};
+use ferros::bootstrap::{root_cnode, BootInfo};
+use ferros::cap::*;
+use ferros::userland::CapRights;
+use ferros::vspace::*;
+
+use super::TopLevelError;
+
+/// Let a 1 GiB region pick its own frames, and check that it's mapped
+/// as a single 1 GiB block on a boundary of that size.
+pub fn run(raw_boot_info: &'static seL4_BootInfo) -> Result<(), TopLevelError> {
+    let (mut allocator, _device_allocator) = micro_alloc::bootstrap_allocators(&raw_boot_info)?;
+    let (root_cnode, local_slots) = root_cnode(&raw_boot_info);
+    let (root_vspace_slots, local_slots): (LocalCNodeSlots<U100>, _) = local_slots.alloc();
+    let BootInfo {
+        asid_control,
+        user_image,
+        ..
+    } = BootInfo::wrap(
+        &raw_boot_info,
+        allocator
+            .get_untyped::<U13>()
+            .expect("Initial untyped retrieval failure"),
+        root_vspace_slots,
+    );
+    let region_ut: LocalCap<Untyped<HugePageBits>> = allocator
+        .get_untyped()
+        .expect("1 GiB untyped retrieval failure");
+    let uts = alloc::ut_buddy(
+        allocator
+            .get_untyped::<U20>()
+            .expect("initial alloc failure"),
+    );
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (asid_pool, _asid_control) = asid_control.allocate_asid_pool(ut, slots)?;
+        let (child_asid, _asid_pool) = asid_pool.alloc();
+
+        let child_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let child_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut child_vspace = VSpace::new(
+            retype(ut, slots)?,
+            child_asid,
+            child_vspace_slots.weaken(),
+            child_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            &user_image,
+            &root_cnode,
+        )?;
+
+        let region = UnmappedMemoryRegion::new_with_largest_granules(region_ut, slots)?;
+    });
+
+    if region.granule_bits() != HugePageBits::U8 {
+        return Err(TopLevelError::TestAssertionFailure(
+            "A 1 GiB region should be made of a single 1 GiB block",
+        ));
+    }
+    let mapped = child_vspace.map_region(region, CapRights::RW, arch::vm_attributes::DEFAULT)?;
+    if mapped.vaddr() % (1 << HugePageBits::USIZE) != 0 {
+        return Err(TopLevelError::TestAssertionFailure(
+            "A 1 GiB block should be mapped on a 1 GiB boundary",
+        ));
+    }
+    child_vspace.unmap_region(mapped)?;
+
+    debug_println!("huge_page_mapping test passed");
+    Ok(())
+}
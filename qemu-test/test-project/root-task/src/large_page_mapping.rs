@@ -0,0 +1,80 @@
+use typenum::*;
+
+use ferros::alloc::{smart_alloc, ut_buddy};
+use ferros::arch::{self, LargePageBits, PageBytes};
+use ferros::bootstrap::UserImage;
+use ferros::cap::{retype, role, ASIDPool, LocalCNode, LocalCNodeSlots, LocalCap, Untyped};
+use ferros::userland::CapRights;
+use ferros::vspace::*;
+
+use super::TopLevelError;
+
+type RegionBits = op!(LargePageBits + U1);
+
+/// Map a region made of two large pages, and check that it lands on a
+/// large page boundary and can't be mapped anywhere else.
+#[ferros_test::ferros_test]
+pub fn large_page_mapping(
+    local_slots: LocalCNodeSlots<U4096>,
+    local_ut: LocalCap<Untyped<U27>>,
+    asid_pool: LocalCap<ASIDPool<U1>>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (child_asid, _asid_pool) = asid_pool.alloc();
+        let child_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let child_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut child_vspace = VSpace::new(
+            retype(ut, slots)?,
+            child_asid,
+            child_vspace_slots.weaken(),
+            child_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let region_ut: LocalCap<Untyped<RegionBits>> = ut;
+        let region: UnmappedMemoryRegion<RegionBits, _, role::Local, LargePageBits> =
+            UnmappedMemoryRegion::new_with_granules(region_ut, slots)?;
+    });
+
+    let granule_bytes = 1 << LargePageBits::USIZE;
+    let mapped = child_vspace.map_region(region, CapRights::RW, arch::vm_attributes::DEFAULT)?;
+    let vaddr = mapped.vaddr();
+    if vaddr % granule_bytes != 0 {
+        return Err(TopLevelError::TestAssertionFailure(
+            "A region of large pages should be mapped on a large page boundary",
+        ));
+    }
+    let region = child_vspace.unmap_region(mapped)?;
+
+    let region = match child_vspace.map_region_at_addr(
+        region,
+        vaddr + PageBytes::USIZE,
+        CapRights::RW,
+        arch::vm_attributes::DEFAULT,
+    ) {
+        Err((VSpaceError::MappingError(MappingError::AddrNotPageAligned), Some(region))) => region,
+        _ => {
+            return Err(TopLevelError::TestAssertionFailure(
+                "Large pages shouldn't be mapped at an address that is only page aligned",
+            ))
+        }
+    };
+
+    match child_vspace.map_region_at_addr(
+        region,
+        vaddr,
+        CapRights::RW,
+        arch::vm_attributes::DEFAULT,
+    ) {
+        Ok(mapped) if mapped.vaddr() == vaddr => Ok(()),
+        _ => Err(TopLevelError::TestAssertionFailure(
+            "Large pages should be mapped again at an aligned address",
+        )),
+    }
+}
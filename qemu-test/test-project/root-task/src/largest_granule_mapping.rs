@@ -0,0 +1,119 @@
+use core::ops::Sub;
+
+use typenum::*;
+
+use ferros::alloc::{smart_alloc, ut_buddy};
+use ferros::arch::{self, LargePageBits, PageBits};
+use ferros::bootstrap::UserImage;
+use ferros::cap::{retype, role, ASIDPool, LocalCNode, LocalCNodeSlots, LocalCap, Untyped};
+use ferros::pow::{Pow, _Pow};
+use ferros::userland::CapRights;
+use ferros::vspace::*;
+
+use super::TopLevelError;
+
+type PageRegionBits = op!(PageBits + U1);
+#[cfg(target_arch = "aarch64")]
+type BlockRegionBits = op!(LargePageBits + U1);
+#[cfg(target_arch = "arm")]
+type LargePageRegionBits = op!(LargePageBits + U1);
+#[cfg(target_arch = "arm")]
+type SectionRegionBits = op!(arch::SectionBits + U1);
+#[cfg(target_arch = "arm")]
+type SupersectionRegionBits = op!(arch::SuperSectionBits + U1);
+
+/// Let the size of each region pick its frames, and check that every
+/// kind of frame small enough for a test's untyped gets used, and is
+/// mapped on a boundary of its own size. 1 GiB blocks need more memory
+/// than that, so they have their own test case, `huge_page_mapping`.
+#[ferros_test::ferros_test]
+pub fn largest_granule_mapping(
+    local_slots: LocalCNodeSlots<U2048>,
+    local_ut: LocalCap<Untyped<U27>>,
+    asid_pool: LocalCap<ASIDPool<U1>>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (child_asid, _asid_pool) = asid_pool.alloc();
+        let child_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let child_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut child_vspace = VSpace::new(
+            retype(ut, slots)?,
+            child_asid,
+            child_vspace_slots.weaken(),
+            child_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let page_region_ut: LocalCap<Untyped<PageRegionBits>> = ut;
+        let page_region = UnmappedMemoryRegion::new_with_largest_granules(page_region_ut, slots)?;
+    });
+    map_and_check(&mut child_vspace, page_region, PageBits::U8)?;
+
+    // 2 MiB blocks are the aarch64 counterpart of sections.
+    #[cfg(target_arch = "aarch64")]
+    {
+        smart_alloc!(|slots: local_slots, ut: uts| {
+            let block_region_ut: LocalCap<Untyped<BlockRegionBits>> = ut;
+            let block_region =
+                UnmappedMemoryRegion::new_with_largest_granules(block_region_ut, slots)?;
+        });
+        map_and_check(&mut child_vspace, block_region, LargePageBits::U8)?;
+    }
+
+    #[cfg(target_arch = "arm")]
+    {
+        smart_alloc!(|slots: local_slots, ut: uts| {
+            let large_page_region_ut: LocalCap<Untyped<LargePageRegionBits>> = ut;
+            let large_page_region =
+                UnmappedMemoryRegion::new_with_largest_granules(large_page_region_ut, slots)?;
+            let section_region_ut: LocalCap<Untyped<SectionRegionBits>> = ut;
+            let section_region =
+                UnmappedMemoryRegion::new_with_largest_granules(section_region_ut, slots)?;
+            let supersection_region_ut: LocalCap<Untyped<SupersectionRegionBits>> = ut;
+            let supersection_region =
+                UnmappedMemoryRegion::new_with_largest_granules(supersection_region_ut, slots)?;
+        });
+        map_and_check(&mut child_vspace, large_page_region, LargePageBits::U8)?;
+        map_and_check(&mut child_vspace, section_region, arch::SectionBits::U8)?;
+        map_and_check(
+            &mut child_vspace,
+            supersection_region,
+            arch::SuperSectionBits::U8,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn map_and_check<SizeBits: Unsigned, GranuleBits: Unsigned>(
+    vspace: &mut VSpace,
+    region: UnmappedMemoryRegion<SizeBits, shared_status::Exclusive, role::Local, GranuleBits>,
+    expected_granule_bits: u8,
+) -> Result<(), TopLevelError>
+where
+    SizeBits: IsGreaterOrEqual<GranuleBits>,
+    SizeBits: Sub<GranuleBits>,
+    <SizeBits as Sub<GranuleBits>>::Output: Unsigned,
+    <SizeBits as Sub<GranuleBits>>::Output: _Pow,
+    Pow<<SizeBits as Sub<GranuleBits>>::Output>: Unsigned,
+{
+    if region.granule_bits() != expected_granule_bits {
+        return Err(TopLevelError::TestAssertionFailure(
+            "A region should be made of the biggest frames that fit in it",
+        ));
+    }
+    let mapped = vspace.map_region(region, CapRights::RW, arch::vm_attributes::DEFAULT)?;
+    if mapped.vaddr() % (1 << GranuleBits::USIZE) != 0 {
+        return Err(TopLevelError::TestAssertionFailure(
+            "A region should be mapped on a boundary of its frames' size",
+        ));
+    }
+    vspace.unmap_region(mapped)?;
+    Ok(())
+}
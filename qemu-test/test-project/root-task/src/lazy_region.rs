@@ -0,0 +1,155 @@
+use ferros::alloc::ut_buddy::weak_ut_buddy;
+use ferros::alloc::{smart_alloc, ut_buddy};
+use typenum::*;
+
+use ferros::arch::{self, fault::Fault, PageBytes};
+use ferros::bootstrap::UserImage;
+use ferros::cap::*;
+use ferros::userland::{
+    CapRights, FaultSinkSetup, LazyRegion, Pager, PagerError, PagerEvent, RetypeForSetup,
+    StandardProcess,
+};
+use ferros::vspace::*;
+
+use super::TopLevelError;
+
+type U33768 = Sum<U32768, U1000>;
+
+/// Where the child faults if it doesn't read back what it wrote, in the
+/// never-mapped null page.
+const FAILURE_VADDR: usize = 0x10;
+
+/// Give a child a lazy region of four pages, and check that the two it
+/// touches are paged in when it touches them and keep what it writes.
+/// The pager only has memory for two pages, so touching a third fails,
+/// and the pager hands back the reply to let the child be dealt with.
+#[ferros_test::ferros_test]
+pub fn lazy_region(
+    local_slots: LocalCNodeSlots<U33768>,
+    local_ut: LocalCap<Untyped<U20>>,
+    asid_pool: LocalCap<ASIDPool<U1>>,
+    local_mapped_region: MappedMemoryRegion<U17, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (child_asid, _asid_pool) = asid_pool.alloc();
+        let child_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let child_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut child_vspace = VSpace::new(
+            retype(ut, slots)?,
+            child_asid,
+            child_vspace_slots.weaken(),
+            child_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let heap: LazyRegion<U4> = LazyRegion::new(
+            &mut child_vspace,
+            CapRights::RW,
+            arch::vm_attributes::PROGRAM_DATA,
+        )?;
+        let heap_vaddr = heap.vaddr();
+
+        let (child_cnode, child_slots) = retype_cnode::<U12>(ut, slots)?;
+        let params = ProcParams { heap_vaddr };
+
+        let setup = FaultSinkSetup::new(&root_cnode, ut, slots, slots)?;
+        let (child_slot_for_fault_source, _child_slots) = child_slots.alloc();
+        let fault_source =
+            setup.add_fault_source(&root_cnode, child_slot_for_fault_source, Badge::from(0))?;
+        let sink = setup.sink();
+
+        let mut child_process = StandardProcess::new(
+            &mut child_vspace,
+            child_cnode,
+            local_mapped_region,
+            root_cnode,
+            proc_main as extern "C" fn(_) -> (),
+            params,
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            Some(fault_source),
+        )?;
+
+        let pager_ut: LocalCap<Untyped<U13>> = ut;
+        let pager_slots: LocalCNodeSlots<U32> = slots;
+        let mut pager = Pager::new(
+            sink,
+            weak_ut_buddy(pager_ut.weaken()),
+            pager_slots.weaken(),
+            slots,
+        );
+    });
+    pager.add_vspace(&mut child_vspace, Badge::from(0))?;
+    pager.add_region(heap)?;
+    child_process.start()?;
+
+    for page in &[0, 2] {
+        match pager.handle_next_fault()? {
+            PagerEvent::PagedIn(vaddr) if vaddr == heap_vaddr + page * PageBytes::USIZE => (),
+            _ => {
+                return Err(TopLevelError::TestAssertionFailure(
+                    "Touching a lazy page should have the pager back it",
+                ))
+            }
+        }
+    }
+    match pager.run()? {
+        PagerEvent::Failed {
+            fault: Fault::VMFault(ref fault),
+            error: PagerError::UTBuddyError(_),
+            reply,
+        } if fault.address == heap_vaddr + 3 * PageBytes::USIZE => {
+            pager.restore_reply_slot(reply.destroy()?);
+        }
+        _ => {
+            return Err(TopLevelError::TestAssertionFailure(
+                "The child should read back what it wrote, then run the pager out of memory",
+            ))
+        }
+    }
+
+    if pager.into_parts().pages.len() != 2 {
+        return Err(TopLevelError::TestAssertionFailure(
+            "The pager should hand back the pages it backed",
+        ));
+    }
+    Ok(())
+}
+
+pub struct ProcParams {
+    pub heap_vaddr: usize,
+}
+
+impl RetypeForSetup for ProcParams {
+    type Output = ProcParams;
+}
+
+pub extern "C" fn proc_main(params: ProcParams) {
+    let first = params.heap_vaddr as *mut usize;
+    let third = (params.heap_vaddr + 2 * PageBytes::USIZE) as *mut usize;
+    let fourth = (params.heap_vaddr + 3 * PageBytes::USIZE) as *mut usize;
+    let done = unsafe {
+        core::ptr::write_volatile(first, 0xf00d);
+        core::ptr::write_volatile(third, 0xcafe);
+        if core::ptr::read_volatile(first) == 0xf00d && core::ptr::read_volatile(third) == 0xcafe {
+            fourth
+        } else {
+            FAILURE_VADDR as *mut usize
+        }
+    };
+    unsafe {
+        core::ptr::write_volatile(done, 0);
+    }
+
+    debug_println!("This is after the child signalled it was done, and should not be printed.");
+}
@@ -12,29 +12,80 @@ extern crate selfe_sys;
 #[macro_use]
 extern crate typenum;
 
+// Responders, pagers and supervisors need the classic scheduler, so
+// their tests aren't built for the MCS kernel `sched_context` runs on
+#[cfg(not(test_case = "sched_context"))]
+mod badged_callers;
+#[cfg(not(test_case = "sched_context"))]
 mod call_and_response_loop;
+#[cfg(not(test_case = "sched_context"))]
+mod cap_transfer;
+mod child_backtrace;
+mod child_core_dump;
 mod child_process_cap_management;
 mod child_process_runs;
 mod child_thread_runs;
+#[cfg(not(test_case = "sched_context"))]
+mod chunked_call;
+#[cfg(not(test_case = "sched_context"))]
+mod copy_on_write;
+#[cfg(not(test_case = "sched_context"))]
+mod deferred_reply;
+#[cfg(not(test_case = "sched_context"))]
 mod dont_tread_on_me;
 mod double_door_backpressure;
+#[cfg(not(test_case = "sched_context"))]
+mod elf_copy_on_write;
 mod elf_process_runs;
+#[cfg(not(test_case = "sched_context"))]
+mod fastpath_benchmark;
 mod fault_or_message_handler;
+#[cfg(not(test_case = "sched_context"))]
 mod fault_pair;
+mod gdbstub;
+#[cfg(test_case = "hardware_debug")]
+mod hardware_debug;
 mod grandkid_process_runs;
+#[cfg(test_case = "huge_page_mapping")]
+mod huge_page_mapping;
 mod irq_control_manipulation;
+mod large_page_mapping;
+mod largest_granule_mapping;
+#[cfg(not(test_case = "sched_context"))]
+mod lazy_region;
 mod memory_read_protection;
 mod memory_write_protection;
+mod notification_poll;
 mod over_register_size_params;
 mod polling_consumer;
+mod process_teardown;
+mod region_protection;
 mod reuse_slots;
 mod reuse_untyped;
+mod revoke_copies;
 mod root_task_runs;
+#[cfg(test_case = "sched_context")]
+mod sched_context;
 mod self_hosted_mem_mgmt;
+mod sender_try_send;
 mod shared_page_queue;
+#[cfg(test_case = "smp")]
+mod smp;
+mod stack_overflow;
 mod stack_setup;
+#[cfg(not(test_case = "sched_context"))]
+mod supervisor_restart;
+#[cfg(not(test_case = "sched_context"))]
+mod supervisor_resume;
+#[cfg(not(test_case = "sched_context"))]
+mod supervisor_stack_overflow;
+mod tcb_control;
+mod thread_join;
+mod thread_priorities;
 mod uart;
 mod weak_elf;
+#[cfg(not(test_case = "sched_context"))]
+mod wire_format_call;
 mod wutbuddy;
 
 mod resources {
@@ -51,37 +102,80 @@ use ferros::alloc::ut_buddy::UTBuddyError;
 use ferros::cap::IRQError;
 use ferros::cap::RetypeError;
 use ferros::error::SeL4Error;
+// Like the pager tests, not built for `sched_context`
+#[cfg(not(test_case = "sched_context"))]
+use ferros::userland::PagerError;
 use ferros::userland::{
-    FaultManagementError, IPCError, MultiConsumerError, ProcessSetupError, ThreadSetupError,
+    CoreDumpError, FaultManagementError, GdbStubError, IPCError, MultiConsumerError,
+    ProcessSetupError, SupervisorError, ThreadSetupError,
 };
 use ferros::vspace::VSpaceError;
 
-#[cfg(not(test_case = "uart"))]
+#[cfg(not(any(
+    test_case = "uart",
+    test_case = "gdbstub",
+    test_case = "hardware_debug",
+    test_case = "huge_page_mapping",
+    test_case = "sched_context",
+    test_case = "smp"
+)))]
 use ferros_test::ferros_test_main;
 
-#[cfg(not(test_case = "uart"))]
+#[cfg(not(any(
+    test_case = "uart",
+    test_case = "gdbstub",
+    test_case = "hardware_debug",
+    test_case = "huge_page_mapping",
+    test_case = "sched_context",
+    test_case = "smp"
+)))]
 ferros_test_main!(&[
+    &badged_callers::badged_callers,
     &call_and_response_loop::call_and_response_loop,
+    &cap_transfer::cap_transfer,
+    &child_backtrace::child_backtrace,
+    &child_core_dump::child_core_dump,
     &child_process_cap_management::child_process_cap_management,
     &child_process_runs::child_process_runs,
     &child_thread_runs::child_thread_runs,
+    &chunked_call::chunked_call,
+    &copy_on_write::copy_on_write,
+    &deferred_reply::deferred_reply,
     &dont_tread_on_me::dont_tread_on_me,
     &double_door_backpressure::double_door_backpressure,
+    &elf_copy_on_write::elf_copy_on_write,
     &elf_process_runs::elf_process_runs,
+    &fastpath_benchmark::fastpath_benchmark,
     &fault_or_message_handler::fault_or_message_handler,
     &fault_pair::fault_pair,
     &grandkid_process_runs::grandkid_process_runs,
     &irq_control_manipulation::irq_control_manipulation,
+    &large_page_mapping::large_page_mapping,
+    &largest_granule_mapping::largest_granule_mapping,
+    &lazy_region::lazy_region,
     &memory_read_protection::memory_read_protection,
     &memory_write_protection::memory_write_protection,
+    &notification_poll::notification_poll,
     &over_register_size_params::over_register_size_params,
     &polling_consumer::polling_consumer,
+    &process_teardown::process_teardown,
+    &region_protection::region_protection,
     &reuse_slots::reuse_slots,
     &reuse_untyped::reuse_untyped,
+    &revoke_copies::revoke_copies,
     &root_task_runs::root_task_runs,
     &self_hosted_mem_mgmt::self_hosted_mem_mgmt,
+    &sender_try_send::sender_try_send,
     &shared_page_queue::shared_page_queue,
+    &stack_overflow::stack_overflow,
     &stack_setup::stack_setup,
+    &supervisor_restart::supervisor_restart,
+    &supervisor_resume::supervisor_resume,
+    &supervisor_stack_overflow::supervisor_stack_overflow,
+    &tcb_control::tcb_control,
+    &thread_join::thread_join,
+    &thread_priorities::thread_priorities,
+    &wire_format_call::wire_format_call,
     &wutbuddy::wutbuddy,
     &weak_elf::weak_elf_process_runs,
 ]);
@@ -103,6 +197,66 @@ pub fn run(raw_boot_info: &'static selfe_sys::seL4_BootInfo) {
     }
 }
 
+#[cfg(test_case = "gdbstub")]
+fn main() {
+    debug_println!("Starting the test!");
+    let bootinfo = unsafe { &*selfe_start::BOOTINFO };
+    gdbstub::run(bootinfo).expect("run");
+    unsafe {
+        loop {
+            selfe_sys::seL4_Yield();
+        }
+    }
+}
+
+#[cfg(test_case = "hardware_debug")]
+fn main() {
+    debug_println!("Starting the test!");
+    let bootinfo = unsafe { &*selfe_start::BOOTINFO };
+    hardware_debug::run(bootinfo).expect("run");
+    unsafe {
+        loop {
+            selfe_sys::seL4_Yield();
+        }
+    }
+}
+
+#[cfg(test_case = "huge_page_mapping")]
+fn main() {
+    debug_println!("Starting the test!");
+    let bootinfo = unsafe { &*selfe_start::BOOTINFO };
+    huge_page_mapping::run(bootinfo).expect("run");
+    unsafe {
+        loop {
+            selfe_sys::seL4_Yield();
+        }
+    }
+}
+
+#[cfg(test_case = "sched_context")]
+fn main() {
+    debug_println!("Starting the test!");
+    let bootinfo = unsafe { &*selfe_start::BOOTINFO };
+    sched_context::run(bootinfo).expect("run");
+    unsafe {
+        loop {
+            selfe_sys::seL4_Yield();
+        }
+    }
+}
+
+#[cfg(test_case = "smp")]
+fn main() {
+    debug_println!("Starting the test!");
+    let bootinfo = unsafe { &*selfe_start::BOOTINFO };
+    smp::run(bootinfo).expect("run");
+    unsafe {
+        loop {
+            selfe_sys::seL4_Yield();
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum TopLevelError {
     AllocError(AllocError),
@@ -116,6 +270,11 @@ pub enum TopLevelError {
     ThreadSetupError(ThreadSetupError),
     UTBuddyError(UTBuddyError),
     RetypeError(RetypeError),
+    SupervisorError(SupervisorError),
+    CoreDumpError(CoreDumpError),
+    GdbStubError(GdbStubError),
+    #[cfg(not(test_case = "sched_context"))]
+    PagerError(PagerError),
     TestAssertionFailure(&'static str),
 }
 
@@ -184,3 +343,28 @@ impl From<RetypeError> for TopLevelError {
         TopLevelError::RetypeError(e)
     }
 }
+
+impl From<SupervisorError> for TopLevelError {
+    fn from(e: SupervisorError) -> Self {
+        TopLevelError::SupervisorError(e)
+    }
+}
+
+impl From<CoreDumpError> for TopLevelError {
+    fn from(e: CoreDumpError) -> Self {
+        TopLevelError::CoreDumpError(e)
+    }
+}
+
+impl From<GdbStubError> for TopLevelError {
+    fn from(e: GdbStubError) -> Self {
+        TopLevelError::GdbStubError(e)
+    }
+}
+
+#[cfg(not(test_case = "sched_context"))]
+impl From<PagerError> for TopLevelError {
+    fn from(e: PagerError) -> Self {
+        TopLevelError::PagerError(e)
+    }
+}
@@ -2,10 +2,11 @@ use typenum::*;
 
 use ferros::alloc::{smart_alloc, ut_buddy};
 use ferros::arch::fault::Fault;
+use ferros::arch::fault_status::{Access, FaultKind};
 use ferros::bootstrap::UserImage;
 use ferros::cap::{
     retype, retype_cnode, role, ASIDPool, Badge, LocalCNode, LocalCNodeSlots, LocalCap,
-    ThreadPriorityAuthority, Untyped,
+    ThreadPriorities, ThreadPriorityAuthority, Untyped,
 };
 use ferros::userland::{FaultSinkSetup, RetypeForSetup, StandardProcess};
 use ferros::vspace::*;
@@ -58,6 +59,7 @@ pub fn memory_read_protection(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             Some(fault_source),
         )?;
     });
@@ -65,7 +67,12 @@ pub fn memory_read_protection(
     child_process.start()?;
 
     match sink.wait_for_fault() {
-        Fault::VMFault(_) => Ok(()),
+        Fault::VMFault(ref fault)
+            if fault.status().kind == FaultKind::Translation
+                && fault.status().access == Access::Read =>
+        {
+            Ok(())
+        }
         _ => Err(TopLevelError::TestAssertionFailure(
             "unexpected fault in memory_read_protection",
         )),
@@ -2,6 +2,7 @@ use ferros::alloc::{smart_alloc, ut_buddy};
 use typenum::*;
 
 use ferros::arch::fault::Fault;
+use ferros::arch::fault_status::{Access, FaultKind};
 use ferros::bootstrap::UserImage;
 use ferros::cap::*;
 use ferros::userland::{FaultSinkSetup, RetypeForSetup, StandardProcess};
@@ -57,14 +58,20 @@ pub fn memory_write_protection<'a, 'b, 'c>(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             Some(fault_source),
         )?;
     });
     child_process.start()?;
 
     match sink.wait_for_fault() {
-        Fault::VMFault(_) => Ok(()),
-        o => Err(TopLevelError::TestAssertionFailure(
+        Fault::VMFault(ref fault)
+            if fault.status().kind == FaultKind::Permission
+                && fault.status().access == Access::Write =>
+        {
+            Ok(())
+        }
+        _ => Err(TopLevelError::TestAssertionFailure(
             "unexpected fault in memory_read_protection",
         )),
     }
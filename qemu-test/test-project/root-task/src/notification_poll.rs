@@ -0,0 +1,44 @@
+use typenum::*;
+
+use ferros::alloc::{smart_alloc, ut_buddy};
+use ferros::cap::{retype, Badge, LocalCNode, LocalCNodeSlots, LocalCap, Notification, Untyped};
+use ferros::userland::CapRights;
+
+use super::TopLevelError;
+
+#[ferros_test::ferros_test]
+pub fn notification_poll(
+    local_slots: LocalCNodeSlots<U16>,
+    local_ut: LocalCap<Untyped<U10>>,
+    root_cnode: &LocalCap<LocalCNode>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let notification: LocalCap<Notification> = retype(ut, slots)?;
+        let first = notification.mint(root_cnode, slots, CapRights::RWG, Badge::from(0b01))?;
+        let second = notification.mint(root_cnode, slots, CapRights::RWG, Badge::from(0b10))?;
+    });
+
+    if notification.poll().is_some() {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Polling an unsignaled notification should not find a badge",
+        ));
+    }
+
+    first.signal();
+    second.signal();
+
+    // Both signals are combined into a single badge, which polling clears.
+    if notification.poll() != Some(Badge::from(0b11)) {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Polling should return the combined badge of all pending signals",
+        ));
+    }
+    if notification.poll().is_some() {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Polling should clear the pending badge",
+        ));
+    }
+    Ok(())
+}
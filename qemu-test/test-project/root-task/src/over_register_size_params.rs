@@ -61,6 +61,7 @@ pub fn over_register_size_params<'a, 'b, 'c>(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             None,
         )?;
     });
@@ -109,6 +109,7 @@ pub fn polling_consumer(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             None, // fault
         )?;
 
@@ -123,6 +124,7 @@ pub fn polling_consumer(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             None, // fault
         )?;
 
@@ -0,0 +1,126 @@
+use super::TopLevelError;
+
+use ferros::alloc::{smart_alloc, ut_buddy};
+use typenum::*;
+
+use ferros::bootstrap::UserImage;
+use ferros::cap::*;
+use ferros::userland::{
+    fault_or_message_channel, FaultOrMessage, RetypeForSetup, Sender, StandardProcess,
+};
+use ferros::vspace::*;
+
+/// Run a child process, tear it down, and then build a second
+/// process out of the very same untypeds, slots, stack, CNode and
+/// VSpace.
+#[ferros_test::ferros_test]
+pub fn process_teardown(
+    local_slots: LocalCNodeSlots<U32768>,
+    local_ut: LocalCap<Untyped<U20>>,
+    asid_pool: LocalCap<ASIDPool<U1>>,
+    local_mapped_region: MappedMemoryRegion<U17, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (child_cnode, child_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (first_source_slot, child_slots) = child_slots.alloc();
+        let (second_source_slot, _child_slots) = child_slots.alloc();
+        let (_first_fault_source, first_sender, first_handler) =
+            fault_or_message_channel(&root_cnode, ut, slots, first_source_slot, slots)?;
+        let (_second_fault_source, second_sender, second_handler) =
+            fault_or_message_channel(&root_cnode, ut, slots, second_source_slot, slots)?;
+
+        let (asid, _asid_pool) = asid_pool.alloc();
+
+        let root = retype(ut, slots)?;
+        let vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut vspace = VSpace::new(
+            root,
+            asid,
+            vspace_slots.weaken(),
+            vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let mut first_process = StandardProcess::new(
+            &mut vspace,
+            child_cnode,
+            local_mapped_region,
+            root_cnode,
+            proc_main as extern "C" fn(_) -> (),
+            ProcParams {
+                value: 42,
+                outcome_sender: first_sender,
+            },
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            None, // fault
+        )?;
+    });
+
+    first_process.start()?;
+    match first_handler.await_message()? {
+        FaultOrMessage::Message(true) => (),
+        _ => {
+            return Err(TopLevelError::TestAssertionFailure(
+                "First child process should have reported success",
+            ))
+        }
+    }
+
+    let parts = first_process
+        .destroy(&mut vspace, root_cnode)
+        .map_err(|(e, _process)| e)?;
+
+    let mut second_process = StandardProcess::new(
+        &mut vspace,
+        parts.cspace,
+        parts.parent_mapped_region,
+        root_cnode,
+        proc_main as extern "C" fn(_) -> (),
+        ProcParams {
+            value: 42,
+            outcome_sender: second_sender,
+        },
+        parts.ipc_buffer_ut,
+        parts.tcb_ut,
+        parts.slots,
+        tpa,
+        ThreadPriorities::default(),
+        None, // fault
+    )?;
+
+    second_process.start()?;
+    match second_handler.await_message()? {
+        FaultOrMessage::Message(true) => Ok(()),
+        _ => Err(TopLevelError::TestAssertionFailure(
+            "Second child process should have reported success",
+        )),
+    }
+}
+
+pub struct ProcParams<Role: CNodeRole> {
+    pub value: usize,
+    pub outcome_sender: Sender<bool, Role>,
+}
+
+impl RetypeForSetup for ProcParams<role::Local> {
+    type Output = ProcParams<role::Child>;
+}
+
+pub extern "C" fn proc_main(params: ProcParams<role::Local>) {
+    params
+        .outcome_sender
+        .blocking_send(&(params.value == 42))
+        .expect("Found value does not match expectations")
+}
@@ -0,0 +1,115 @@
+use ferros::alloc::{smart_alloc, ut_buddy};
+use typenum::*;
+
+use ferros::arch::fault_status::{Access, FaultKind};
+use ferros::arch::{self, fault::Fault};
+use ferros::bootstrap::UserImage;
+use ferros::cap::*;
+use ferros::userland::{CapRights, FaultSinkSetup, RetypeForSetup, StandardProcess};
+use ferros::vspace::*;
+
+use super::TopLevelError;
+
+type U33768 = Sum<U32768, U1000>;
+
+/// Map a page into a child read-write, take the write permission away
+/// in place, and check that the child can still read it but faults when
+/// it writes to it.
+#[ferros_test::ferros_test]
+pub fn region_protection(
+    local_slots: LocalCNodeSlots<U33768>,
+    local_ut: LocalCap<Untyped<U20>>,
+    asid_pool: LocalCap<ASIDPool<U1>>,
+    local_mapped_region: MappedMemoryRegion<U17, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (child_asid, _asid_pool) = asid_pool.alloc();
+        let child_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let child_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut child_vspace = VSpace::new(
+            retype(ut, slots)?,
+            child_asid,
+            child_vspace_slots.weaken(),
+            child_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let unmapped_page: UnmappedMemoryRegion<U12, _> = UnmappedMemoryRegion::new(ut, slots)?;
+        let mut page = child_vspace.map_region(
+            unmapped_page,
+            CapRights::RW,
+            arch::vm_attributes::PROGRAM_DATA,
+        )?;
+        child_vspace.protect_region(&mut page, CapRights::R, arch::vm_attributes::PROGRAM_DATA)?;
+        let page_vaddr = page.vaddr();
+
+        let (child_cnode, child_slots) = retype_cnode::<U12>(ut, slots)?;
+        let params = ProcParams { page_vaddr };
+
+        let setup = FaultSinkSetup::new(&root_cnode, ut, slots, slots)?;
+        let (child_slot_for_fault_source, _child_slots) = child_slots.alloc();
+        let fault_source =
+            setup.add_fault_source(&root_cnode, child_slot_for_fault_source, Badge::from(0))?;
+        let sink = setup.sink();
+
+        let mut child_process = StandardProcess::new(
+            &mut child_vspace,
+            child_cnode,
+            local_mapped_region,
+            root_cnode,
+            proc_main as extern "C" fn(_) -> (),
+            params,
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            Some(fault_source),
+        )?;
+    });
+
+    if page.rights() != CapRights::R {
+        return Err(TopLevelError::TestAssertionFailure(
+            "A protected region should report its new rights",
+        ));
+    }
+    child_process.start()?;
+
+    match sink.wait_for_fault() {
+        Fault::VMFault(ref fault)
+            if fault.address == page_vaddr
+                && fault.status().kind == FaultKind::Permission
+                && fault.status().access == Access::Write =>
+        {
+            Ok(())
+        }
+        _ => Err(TopLevelError::TestAssertionFailure(
+            "Writing to a region protected read-only should fault",
+        )),
+    }
+}
+
+pub struct ProcParams {
+    pub page_vaddr: usize,
+}
+
+impl RetypeForSetup for ProcParams {
+    type Output = ProcParams;
+}
+
+pub extern "C" fn proc_main(params: ProcParams) {
+    unsafe {
+        let x = params.page_vaddr as *mut usize;
+        let _ = core::ptr::read_volatile(x);
+        core::ptr::write_volatile(x, 42);
+    }
+
+    debug_println!("This is after the write to a read-only page, and should not be printed.");
+}
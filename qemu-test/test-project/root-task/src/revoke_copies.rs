@@ -0,0 +1,32 @@
+use typenum::*;
+
+use ferros::cap::{Endpoint, LocalCNode, LocalCNodeSlots, LocalCap, Untyped};
+use ferros::error::{KernelError, SeL4Error};
+use ferros::userland::CapRights;
+
+use super::TopLevelError;
+
+#[ferros_test::ferros_test]
+pub fn revoke_copies(
+    local_slots: LocalCNodeSlots<U4>,
+    ut: LocalCap<Untyped<U4>>,
+    root_cnode: &LocalCap<LocalCNode>,
+) -> Result<(), TopLevelError> {
+    let (endpoint_slot, local_slots) = local_slots.alloc();
+    let endpoint: LocalCap<Endpoint> = ut.retype(endpoint_slot)?;
+
+    let (copy_slot, local_slots) = local_slots.alloc();
+    let copied = endpoint.copy(root_cnode, copy_slot, CapRights::RWG)?;
+
+    // `copied` is only used to check that it's gone.
+    unsafe { endpoint.revoke(root_cnode) }?;
+
+    // The copy should be gone, so copying from its slot must fail.
+    let (probe_slot, _local_slots) = local_slots.alloc();
+    match copied.copy(root_cnode, probe_slot, CapRights::RWG) {
+        Err(SeL4Error::CNodeCopy(KernelError::FailedLookup)) => Ok(()),
+        _ => Err(TopLevelError::TestAssertionFailure(
+            "Copies of a revoked capability should no longer exist",
+        )),
+    }
+}
@@ -0,0 +1,131 @@
+//! Only builds against a kernel with the MCS scheduler, so it has its own
+//! test case: build with `TEST_CASE=sched_context` for the virt_mcs
+//! platform, which is configured with `KernelIsMCS`.
+
+use selfe_sys::*;
+
+use typenum::*;
+
+use ferros::alloc::{self, micro_alloc, smart_alloc};
+use ferros::arch;
+use ferros::arch::fault::Fault;
+use ferros::bootstrap::{root_cnode, BootInfo};
+use ferros::cap::{
+    retype, retype_cnode, Badge, LocalCNodeSlots, LocalCap, SchedContext, ThreadPriorities, Untyped,
+};
+use ferros::userland::{
+    CapRights, DefaultStackBitSize, FaultSinkSetup, RetypeForSetup, StandardProcess,
+};
+use ferros::vspace::*;
+
+use super::TopLevelError;
+
+const BUDGET_US: u64 = 1_000;
+const PERIOD_US: u64 = 10_000;
+const SCHED_CONTEXT_BADGE: usize = 0x5c;
+
+/// Run a spinning child on a scheduling context with less budget than
+/// period, and check that it gets a timeout fault once the budget is
+/// used up.
+pub fn run(raw_boot_info: &'static seL4_BootInfo) -> Result<(), TopLevelError> {
+    let (mut allocator, _device_allocator) = micro_alloc::bootstrap_allocators(&raw_boot_info)?;
+    let (root_cnode, local_slots) = root_cnode(&raw_boot_info);
+    let (root_vspace_slots, local_slots): (LocalCNodeSlots<U100>, _) = local_slots.alloc();
+    let BootInfo {
+        mut root_vspace,
+        asid_control,
+        user_image,
+        root_tcb,
+        mut sched_control,
+        ..
+    } = BootInfo::wrap(
+        &raw_boot_info,
+        allocator
+            .get_untyped::<U13>()
+            .expect("Initial untyped retrieval failure"),
+        root_vspace_slots,
+    );
+    let tpa = root_tcb.thread_priority_authority();
+    let uts = alloc::ut_buddy(
+        allocator
+            .get_untyped::<U21>()
+            .expect("initial alloc failure"),
+    );
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (asid_pool, _asid_control) = asid_control.allocate_asid_pool(ut, slots)?;
+        let (child_asid, _asid_pool) = asid_pool.alloc();
+
+        let vspace_slots: LocalCNodeSlots<arch::CodePageCount> = slots;
+        let vspace_ut: LocalCap<Untyped<U13>> = ut;
+        let mut child_vspace = VSpace::new(
+            retype(ut, slots)?,
+            child_asid,
+            vspace_slots.weaken(),
+            vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            &user_image,
+            &root_cnode,
+        )?;
+
+        let (child_cnode, _child_slots) = retype_cnode::<U12>(ut, slots)?;
+
+        let setup = FaultSinkSetup::new(&root_cnode, ut, slots, slots)?;
+        let timeout_source = setup.add_timeout_fault_source(&root_cnode, slots, Badge::from(0))?;
+        let sink = setup.sink();
+
+        let unmapped_stack: UnmappedMemoryRegion<DefaultStackBitSize, _> =
+            UnmappedMemoryRegion::new(ut, slots)?;
+        let stack =
+            root_vspace.map_region(unmapped_stack, CapRights::RW, arch::vm_attributes::DEFAULT)?;
+        let mut child_process = StandardProcess::new(
+            &mut child_vspace,
+            child_cnode,
+            stack,
+            &root_cnode,
+            proc_main as extern "C" fn(_) -> (),
+            ProcParams {},
+            ut,
+            ut,
+            slots,
+            &tpa,
+            ThreadPriorities::default(),
+            None,
+        )?;
+
+        let mut sched_context: LocalCap<SchedContext> = retype(ut, slots)?;
+    });
+
+    sched_control.configure_budget(
+        &mut sched_context,
+        BUDGET_US,
+        PERIOD_US,
+        0,
+        Badge::from(SCHED_CONTEXT_BADGE),
+    )?;
+    child_process.bind_sched_context(&mut sched_context)?;
+    child_process.set_timeout_fault_source(&timeout_source)?;
+    child_process.start()?;
+
+    match sink.wait_for_fault() {
+        Fault::Timeout(fault) if fault.data == SCHED_CONTEXT_BADGE && fault.consumed > 0 => {
+            debug_println!("sched_context test passed");
+            Ok(())
+        }
+        _ => Err(TopLevelError::TestAssertionFailure(
+            "The child should have a timeout fault when its budget runs out",
+        )),
+    }
+}
+
+pub struct ProcParams {}
+
+impl RetypeForSetup for ProcParams {
+    type Output = ProcParams;
+}
+
+pub extern "C" fn proc_main(_params: ProcParams) {
+    loop {
+        unsafe { core::ptr::read_volatile(&0usize) };
+    }
+}
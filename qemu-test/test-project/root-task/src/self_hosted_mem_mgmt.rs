@@ -82,6 +82,7 @@ pub fn self_hosted_mem_mgmt(
             cap_transfer_slots.weaken(),
             child_paging_slots.weaken(),
             tpa,
+            ThreadPriorities::default(),
             Some(fault_source),
         )?;
     });
@@ -0,0 +1,113 @@
+use super::TopLevelError;
+
+use ferros::alloc::{smart_alloc, ut_buddy};
+use typenum::*;
+
+use ferros::cap::*;
+use ferros::userland::{
+    fault_or_message_channel, CapRights, FaultOrMessage, RetypeForSetup, Sender, Thread,
+};
+use ferros::vspace::*;
+
+/// What the child tries to send while this thread is waiting for a
+/// message, while it isn't, and then sends blocking.
+const DELIVERED: usize = 1;
+const DROPPED: usize = 2;
+const BLOCKING: usize = 3;
+
+/// A child thread uses `try_send` once while this thread is waiting to
+/// receive, which should be delivered, and once while it isn't, which
+/// should be dropped without blocking the child.
+#[ferros_test::ferros_test]
+pub fn sender_try_send(
+    local_slots: LocalCNodeSlots<U32768>,
+    local_ut: LocalCap<Untyped<U20>>,
+    stack_mapped_region: MappedMemoryRegion<U17, shared_status::Exclusive>,
+    ipc_buffer_region: MappedMemoryRegion<U12, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+    vspace_paging_root: &LocalCap<ferros::arch::PagingRoot>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (child_cnode, child_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (child_fault_source_slot, child_slots) = child_slots.alloc();
+        let (notification_slot, child_slots) = child_slots.alloc();
+        let (thread_slots, _child_slots) = child_slots.alloc();
+        let (_fault_source, sender, handler) =
+            fault_or_message_channel(&root_cnode, ut, slots, child_fault_source_slot, slots)?;
+
+        let notification: LocalCap<Notification> = retype(ut, slots)?;
+        let child_notification =
+            notification.copy(root_cnode, notification_slot, CapRights::RWG)?;
+
+        let child = Thread::new(
+            vspace_paging_root,
+            child_cnode,
+            stack_mapped_region,
+            None, // stack guard
+            root_cnode,
+            proc_main,
+            ProcParams {
+                sender,
+                notification: child_notification,
+            },
+            ipc_buffer_region,
+            ut,
+            ut,
+            slots,
+            thread_slots,
+            tpa,
+            ThreadPriorities::default(),
+            None, // fault
+        )?;
+    });
+
+    let _child = child.start()?;
+
+    match handler.await_message()? {
+        FaultOrMessage::Message(DELIVERED) => (),
+        _ => {
+            return Err(TopLevelError::TestAssertionFailure(
+                "A message tried while a receiver is waiting should be delivered",
+            ))
+        }
+    }
+
+    // The child tries its second message while this thread waits on
+    // the notification instead of the endpoint.
+    notification.wait();
+
+    match handler.await_message()? {
+        FaultOrMessage::Message(BLOCKING) => Ok(()),
+        _ => Err(TopLevelError::TestAssertionFailure(
+            "A message tried while no receiver is waiting should be dropped",
+        )),
+    }
+}
+
+pub struct ProcParams<Role: CNodeRole> {
+    pub sender: Sender<usize, Role>,
+    pub notification: Cap<Notification, Role>,
+}
+
+impl RetypeForSetup for ProcParams<role::Local> {
+    type Output = ProcParams<role::Child>;
+}
+
+pub extern "C" fn proc_main(params: ProcParams<role::Local>) {
+    params
+        .sender
+        .try_send(&DELIVERED)
+        .expect("Could not try to send");
+    params
+        .sender
+        .try_send(&DROPPED)
+        .expect("Could not try to send");
+    params.notification.signal();
+    params
+        .sender
+        .blocking_send(&BLOCKING)
+        .expect("Could not send")
+}
@@ -102,6 +102,7 @@ pub fn shared_page_queue(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             Some(consumer_fault_source),
         )?;
         consumer_process.start()?;
@@ -117,6 +118,7 @@ pub fn shared_page_queue(
             ut,
             slots,
             tpa,
+            ThreadPriorities::default(),
             None, // fault handler
         )?;
         producer_process.start()?;
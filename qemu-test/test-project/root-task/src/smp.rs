@@ -0,0 +1,302 @@
+//! Only builds against a multicore kernel, so it has its own test case:
+//! build with `TEST_CASE=smp` for the virt platform, which is configured
+//! with four cores.
+
+use selfe_sys::*;
+
+use typenum::*;
+
+use ferros::alloc::{self, micro_alloc, smart_alloc};
+use ferros::arch;
+use ferros::bootstrap::{root_cnode, BootInfo};
+use ferros::cap::*;
+use ferros::userland::{
+    fault_or_message_channel, CapRights, Consumer1, FaultOrMessage, Producer, QueueFullError,
+    RetypeForSetup, Sender, StandardProcess,
+};
+use ferros::vspace::*;
+
+use super::TopLevelError;
+
+const CONSUMER_CORE: usize = 1;
+const PRODUCER_CORE: usize = 2;
+const SIGNALLER_CORE: usize = 3;
+const SIGNALLER_BADGE: usize = 0b100;
+
+/// Pin a queue consumer and its producer to two different cores, and
+/// have a process on a third core wake the root task, which stays on
+/// core 0.
+pub fn run(raw_boot_info: &'static seL4_BootInfo) -> Result<(), TopLevelError> {
+    let (mut allocator, _device_allocator) = micro_alloc::bootstrap_allocators(&raw_boot_info)?;
+    let (root_cnode, local_slots) = root_cnode(&raw_boot_info);
+    let (root_vspace_slots, local_slots): (LocalCNodeSlots<U100>, _) = local_slots.alloc();
+    let BootInfo {
+        mut root_vspace,
+        asid_control,
+        user_image,
+        root_tcb,
+        core_count,
+        ..
+    } = BootInfo::wrap(
+        &raw_boot_info,
+        allocator
+            .get_untyped::<U13>()
+            .expect("Initial untyped retrieval failure"),
+        root_vspace_slots,
+    );
+    let tpa = root_tcb.thread_priority_authority();
+    if core_count <= SIGNALLER_CORE {
+        return Err(TopLevelError::TestAssertionFailure(
+            "The kernel should be running on at least four cores",
+        ));
+    }
+    let uts = alloc::ut_buddy(
+        allocator
+            .get_untyped::<U22>()
+            .expect("initial alloc failure"),
+    );
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let scratch_ut: LocalCap<Untyped<U12>> = ut;
+        let reserved_for_scratch = root_vspace.reserve(scratch_ut.retype(slots)?)?;
+        let mut scratch: ScratchRegion = reserved_for_scratch.as_scratch(&root_vspace)?;
+
+        let (asid_pool, _asid_control) = asid_control.allocate_asid_pool(ut, slots)?;
+        let (consumer_asid, asid_pool) = asid_pool.alloc();
+        let (producer_asid, asid_pool) = asid_pool.alloc();
+        let (signaller_asid, _asid_pool) = asid_pool.alloc();
+
+        let consumer_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let consumer_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut consumer_vspace = VSpace::new(
+            retype(ut, slots)?,
+            consumer_asid,
+            consumer_vspace_slots.weaken(),
+            consumer_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            &user_image,
+            &root_cnode,
+        )?;
+        let producer_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let producer_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut producer_vspace = VSpace::new(
+            retype(ut, slots)?,
+            producer_asid,
+            producer_vspace_slots.weaken(),
+            producer_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            &user_image,
+            &root_cnode,
+        )?;
+        let signaller_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let signaller_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut signaller_vspace = VSpace::new(
+            retype(ut, slots)?,
+            signaller_asid,
+            signaller_vspace_slots.weaken(),
+            signaller_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            &user_image,
+            &root_cnode,
+        )?;
+
+        let (consumer_cnode, consumer_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (producer_cnode, producer_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (signaller_cnode, signaller_slots) = retype_cnode::<U12>(ut, slots)?;
+
+        let (slots_c, consumer_slots) = consumer_slots.alloc();
+        let (consumer, _consumer_token, producer_setup, _waker_setup) =
+            Consumer1::new::<U100, U12, _>(
+                ut,
+                ut,
+                &mut scratch,
+                &mut consumer_vspace,
+                &root_cnode,
+                slots,
+                slots,
+                slots,
+                slots_c,
+            )?;
+        let (consumer_sender_slot, _consumer_slots) = consumer_slots.alloc();
+        let (consumer_fault_source, outcome_sender, handler) =
+            fault_or_message_channel(&root_cnode, ut, slots, consumer_sender_slot, slots)?;
+        let consumer_params = ConsumerParams::<role::Child> {
+            consumer,
+            outcome_sender,
+        };
+
+        let (slots_p, _producer_slots) = producer_slots.alloc();
+        let producer = Producer::new(
+            &producer_setup,
+            slots_p,
+            &mut producer_vspace,
+            &root_cnode,
+            slots,
+        )?;
+        let producer_params = ProducerParams::<role::Child> { producer };
+
+        let notification: LocalCap<Notification> = retype(ut, slots)?;
+        let (signaller_slot, _signaller_slots) = signaller_slots.alloc();
+        let signaller_params = SignallerParams::<role::Child> {
+            notification: notification.mint(
+                &root_cnode,
+                signaller_slot,
+                CapRights::RWG,
+                Badge::from(SIGNALLER_BADGE),
+            )?,
+        };
+
+        let consumer_stack: UnmappedMemoryRegion<U17, _> = UnmappedMemoryRegion::new(ut, slots)?;
+        let consumer_stack =
+            root_vspace.map_region(consumer_stack, CapRights::RW, arch::vm_attributes::DEFAULT)?;
+        let producer_stack: UnmappedMemoryRegion<U17, _> = UnmappedMemoryRegion::new(ut, slots)?;
+        let producer_stack =
+            root_vspace.map_region(producer_stack, CapRights::RW, arch::vm_attributes::DEFAULT)?;
+        let signaller_stack: UnmappedMemoryRegion<U17, _> = UnmappedMemoryRegion::new(ut, slots)?;
+        let signaller_stack =
+            root_vspace.map_region(signaller_stack, CapRights::RW, arch::vm_attributes::DEFAULT)?;
+
+        let mut consumer_process = StandardProcess::new(
+            &mut consumer_vspace,
+            consumer_cnode,
+            consumer_stack,
+            &root_cnode,
+            consumer_run as extern "C" fn(_) -> (),
+            consumer_params,
+            ut,
+            ut,
+            slots,
+            &tpa,
+            ThreadPriorities::default(),
+            Some(consumer_fault_source),
+        )?;
+        let mut producer_process = StandardProcess::new(
+            &mut producer_vspace,
+            producer_cnode,
+            producer_stack,
+            &root_cnode,
+            producer_run as extern "C" fn(_) -> (),
+            producer_params,
+            ut,
+            ut,
+            slots,
+            &tpa,
+            ThreadPriorities::default(),
+            None, // fault handler
+        )?;
+        let mut signaller_process = StandardProcess::new(
+            &mut signaller_vspace,
+            signaller_cnode,
+            signaller_stack,
+            &root_cnode,
+            signaller_run as extern "C" fn(_) -> (),
+            signaller_params,
+            ut,
+            ut,
+            slots,
+            &tpa,
+            ThreadPriorities::default(),
+            None, // fault handler
+        )?;
+    });
+
+    consumer_process.set_affinity(CONSUMER_CORE)?;
+    producer_process.set_affinity(PRODUCER_CORE)?;
+    signaller_process.set_affinity(SIGNALLER_CORE)?;
+
+    consumer_process.start()?;
+    producer_process.start()?;
+    signaller_process.start()?;
+
+    if notification.wait() != Badge::from(SIGNALLER_BADGE) {
+        return Err(TopLevelError::TestAssertionFailure(
+            "The root task should be woken by the signaller on another core",
+        ));
+    }
+    match handler.await_message()? {
+        FaultOrMessage::Message(true) => {
+            debug_println!("smp test passed");
+            Ok(())
+        }
+        _ => Err(TopLevelError::TestAssertionFailure(
+            "The consumer should have received everything sent from another core",
+        )),
+    }
+}
+
+#[derive(Debug)]
+pub struct Xenon {
+    a: u64,
+}
+
+pub struct ConsumerParams<Role: CNodeRole> {
+    pub consumer: Consumer1<Role, Xenon>,
+    pub outcome_sender: Sender<bool, Role>,
+}
+
+impl RetypeForSetup for ConsumerParams<role::Local> {
+    type Output = ConsumerParams<role::Child>;
+}
+
+pub struct ProducerParams<Role: CNodeRole> {
+    pub producer: Producer<Role, Xenon>,
+}
+
+impl RetypeForSetup for ProducerParams<role::Local> {
+    type Output = ProducerParams<role::Child>;
+}
+
+pub struct SignallerParams<Role: CNodeRole> {
+    pub notification: Cap<Notification, Role>,
+}
+
+impl RetypeForSetup for SignallerParams<role::Local> {
+    type Output = SignallerParams<role::Child>;
+}
+
+/// The sum of 1 through 256, which only adds up if nothing sent from
+/// the producer's core goes missing.
+const EXPECTED_SUM: u64 = 256 * 257 / 2;
+
+pub extern "C" fn consumer_run(p: ConsumerParams<role::Local>) {
+    let ConsumerParams {
+        consumer,
+        outcome_sender,
+    } = p;
+    consumer.consume(
+        0,
+        |state| state,
+        |x, state| {
+            let fresh_state = x.a + state;
+            if fresh_state == EXPECTED_SUM {
+                outcome_sender
+                    .blocking_send(&true)
+                    .expect("Failed to send test outcome");
+            }
+            fresh_state
+        },
+    )
+}
+
+pub extern "C" fn producer_run(p: ProducerParams<role::Local>) {
+    for i in 1..=256 {
+        let mut x = Xenon { a: i };
+        // Unlike on a single core, the consumer is draining the queue at
+        // the same time, so retry until there's room.
+        loop {
+            match p.producer.send(x) {
+                Ok(_) => break,
+                Err(QueueFullError(rejected)) => {
+                    x = rejected;
+                    unsafe {
+                        seL4_Yield();
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub extern "C" fn signaller_run(p: SignallerParams<role::Local>) {
+    p.notification.signal();
+}
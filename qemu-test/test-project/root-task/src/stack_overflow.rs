@@ -0,0 +1,94 @@
+use ferros::alloc::{smart_alloc, ut_buddy};
+use typenum::*;
+
+use ferros::bootstrap::UserImage;
+use ferros::cap::*;
+use ferros::userland::{FaultClassification, FaultSinkSetup, RetypeForSetup, StandardProcess};
+use ferros::vspace::*;
+
+use super::TopLevelError;
+
+type U33768 = Sum<U32768, U1000>;
+
+/// Have a child recurse until it runs out of stack, and check that
+/// the fault it takes is classified as a stack overflow.
+#[ferros_test::ferros_test]
+pub fn stack_overflow(
+    local_slots: LocalCNodeSlots<U33768>,
+    local_ut: LocalCap<Untyped<U20>>,
+    asid_pool: LocalCap<ASIDPool<U1>>,
+    local_mapped_region: MappedMemoryRegion<U13, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (child_asid, _asid_pool) = asid_pool.alloc();
+        let child_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let child_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut child_vspace = VSpace::new(
+            retype(ut, slots)?,
+            child_asid,
+            child_vspace_slots.weaken(),
+            child_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let (child_cnode, child_slots) = retype_cnode::<U12>(ut, slots)?;
+        let params = ProcParams {};
+
+        let setup = FaultSinkSetup::new(&root_cnode, ut, slots, slots)?;
+        let (child_slot_for_fault_source, _child_slots) = child_slots.alloc();
+        let fault_source =
+            setup.add_fault_source(&root_cnode, child_slot_for_fault_source, Badge::from(0))?;
+        let sink = setup.sink();
+
+        let mut child_process = StandardProcess::new(
+            &mut child_vspace,
+            child_cnode,
+            local_mapped_region,
+            root_cnode,
+            proc_main as extern "C" fn(_) -> (),
+            params,
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            Some(fault_source),
+        )?;
+    });
+    child_process.start()?;
+
+    let fault = sink.wait_for_fault();
+    if fault.classify(child_process.stack_guard()) == FaultClassification::StackOverflow {
+        Ok(())
+    } else {
+        Err(TopLevelError::TestAssertionFailure(
+            "Running off the end of the stack should be classified as a stack overflow",
+        ))
+    }
+}
+
+pub struct ProcParams {}
+
+impl RetypeForSetup for ProcParams {
+    type Output = ProcParams;
+}
+
+/// Uses up a kilobyte of stack per call, and never stops.
+fn recurse(depth: usize) -> usize {
+    let frame = [depth as u8; 1024];
+    let below = recurse(depth + 1);
+    below + unsafe { core::ptr::read_volatile(&frame[depth % 1024]) } as usize
+}
+
+pub extern "C" fn proc_main(_params: ProcParams) {
+    let _ = recurse(0);
+
+    debug_println!("This is after the stack overflow, and should not be printed.");
+}
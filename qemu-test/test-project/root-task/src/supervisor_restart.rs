@@ -0,0 +1,274 @@
+use super::TopLevelError;
+
+use selfe_sys::*;
+use typenum::*;
+
+use ferros::alloc::{smart_alloc, ut_buddy};
+use ferros::bootstrap::UserImage;
+use ferros::cap::*;
+use ferros::userland::{
+    fault_or_message_channel, ChildPolicy, FaultOrMessage, FaultSource, Recovery, RetypeForSetup,
+    Sender, StandardProcess, StandardProcessParts, Supervised, Supervisor, SupervisorError,
+    SupervisorEvent,
+};
+use ferros::vspace::*;
+
+type U66536 = Sum<U65536, U1000>;
+
+/// Supervise two children that fault. The flaky child faults the first
+/// time it runs and reports success once restarted. The hopeless child
+/// faults every time, so the supervisor gives up on it once it reaches
+/// its fault limit.
+#[ferros_test::ferros_test]
+pub fn supervisor_restart(
+    local_slots: LocalCNodeSlots<U66536>,
+    local_ut: LocalCap<Untyped<U27>>,
+    asid_pool: LocalCap<ASIDPool<U2>>,
+    local_mapped_region: MappedMemoryRegion<U19, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (flaky_cnode, flaky_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (hopeless_cnode, hopeless_slots) = retype_cnode::<U12>(ut, slots)?;
+
+        let (outcome_slot, flaky_slots) = flaky_slots.alloc();
+        let (_outcome_fault_source, outcome_sender, outcome_handler) =
+            fault_or_message_channel(&root_cnode, ut, slots, outcome_slot, slots)?;
+        let (flaky_fault_source_slot, _flaky_slots) = flaky_slots.alloc();
+        let (hopeless_fault_source_slot, _hopeless_slots) = hopeless_slots.alloc();
+
+        let (flaky_asid, asid_pool) = asid_pool.alloc();
+        let (hopeless_asid, _asid_pool) = asid_pool.alloc();
+        let flaky_vspace = new_vspace(
+            flaky_asid,
+            retype(ut, slots)?,
+            slots,
+            ut,
+            user_image,
+            root_cnode,
+        )?;
+        let hopeless_vspace = new_vspace(
+            hopeless_asid,
+            retype(ut, slots)?,
+            slots,
+            ut,
+            user_image,
+            root_cnode,
+        )?;
+
+        let (u18_region, _spare_region) = local_mapped_region.split()?;
+        let (flaky_region, hopeless_region) = u18_region.split()?;
+
+        let flaky_ipc_buffer_ut: LocalCap<Untyped<U12>> = ut;
+        let flaky_tcb_ut: LocalCap<Untyped<<ThreadControlBlock as DirectRetype>::SizeBits>> = ut;
+        let flaky_process_slots: LocalCNodeSlots<U34> = slots;
+        let hopeless_ipc_buffer_ut: LocalCap<Untyped<U12>> = ut;
+        let hopeless_tcb_ut: LocalCap<Untyped<<ThreadControlBlock as DirectRetype>::SizeBits>> =
+            ut;
+        let hopeless_process_slots: LocalCNodeSlots<U34> = slots;
+
+        let supervisor_ut: LocalCap<Untyped<<Endpoint as DirectRetype>::SizeBits>> = ut;
+        let supervisor_slots: LocalCNodeSlots<U3> = slots;
+    });
+
+    let mut flaky = Child {
+        root_cnode,
+        tpa,
+        faulty_attempts: 1,
+        attempt: 0,
+        vspace: flaky_vspace,
+        parts: Some(StandardProcessParts {
+            cspace: flaky_cnode,
+            parent_mapped_region: flaky_region,
+            ipc_buffer_ut: flaky_ipc_buffer_ut,
+            tcb_ut: flaky_tcb_ut,
+            slots: flaky_process_slots,
+        }),
+        process: None,
+        outcome_sender: Some(outcome_sender),
+    };
+    let mut hopeless = Child {
+        root_cnode,
+        tpa,
+        faulty_attempts: 2,
+        attempt: 0,
+        vspace: hopeless_vspace,
+        parts: Some(StandardProcessParts {
+            cspace: hopeless_cnode,
+            parent_mapped_region: hopeless_region,
+            ipc_buffer_ut: hopeless_ipc_buffer_ut,
+            tcb_ut: hopeless_tcb_ut,
+            slots: hopeless_process_slots,
+        }),
+        process: None,
+        outcome_sender: None,
+    };
+
+    let (endpoint_slot, supervisor_slots) = supervisor_slots.alloc();
+    let (fault_sink_slot, reply_slot) = supervisor_slots.alloc();
+    let mut supervisor = Supervisor::new(
+        root_cnode,
+        supervisor_ut,
+        endpoint_slot,
+        fault_sink_slot,
+        reply_slot,
+    )?;
+
+    let flaky_id = supervisor.add_child(
+        root_cnode,
+        flaky_fault_source_slot,
+        &mut flaky,
+        ChildPolicy {
+            recovery: Recovery::Restart,
+            fault_limit: None,
+        },
+    )?;
+    let hopeless_id = supervisor.add_child(
+        root_cnode,
+        hopeless_fault_source_slot,
+        &mut hopeless,
+        ChildPolicy {
+            recovery: Recovery::Restart,
+            fault_limit: Some(2),
+        },
+    )?;
+
+    // The flaky child faults once and the hopeless child twice, in
+    // whatever order the scheduler happens to produce.
+    let mut flaky_restarts = 0;
+    let mut hopeless_restarts = 0;
+    let mut hopeless_given_up = false;
+    for _ in 0..3 {
+        match supervisor.handle_next_fault()? {
+            SupervisorEvent::Restarted(id) if id == flaky_id => flaky_restarts += 1,
+            SupervisorEvent::Restarted(id) if id == hopeless_id => hopeless_restarts += 1,
+            SupervisorEvent::GaveUp(id, _) if id == hopeless_id => hopeless_given_up = true,
+            _ => {
+                return Err(TopLevelError::TestAssertionFailure(
+                    "Unexpected supervisor event",
+                ))
+            }
+        }
+    }
+    if flaky_restarts != 1 || hopeless_restarts != 1 || !hopeless_given_up {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Each child should have been handled according to its policy",
+        ));
+    }
+    if supervisor.fault_count(flaky_id) != 1 || supervisor.fault_count(hopeless_id) != 2 {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Supervisor should have counted every fault",
+        ));
+    }
+
+    match outcome_handler.await_message()? {
+        FaultOrMessage::Message(true) => Ok(()),
+        _ => Err(TopLevelError::TestAssertionFailure(
+            "Restarted child should have reported success",
+        )),
+    }
+}
+
+fn new_vspace(
+    asid: LocalCap<UnassignedASID>,
+    root: LocalCap<ferros::arch::PagingRoot>,
+    slots: LocalCNodeSlots<U1024>,
+    ut: LocalCap<Untyped<U15>>,
+    user_image: &UserImage<role::Local>,
+    root_cnode: &LocalCap<LocalCNode>,
+) -> Result<VSpace, TopLevelError> {
+    Ok(VSpace::new(
+        root,
+        asid,
+        slots.weaken(),
+        ut.weaken(),
+        ProcessCodeImageConfig::ReadOnly,
+        user_image,
+        root_cnode,
+    )?)
+}
+
+/// A child process which faults on its first few attempts, and
+/// reports success on the attempt after that.
+struct Child<'a> {
+    root_cnode: &'a LocalCap<LocalCNode>,
+    tpa: &'a LocalCap<ThreadPriorityAuthority>,
+    faulty_attempts: usize,
+    attempt: usize,
+    vspace: VSpace,
+    parts: Option<StandardProcessParts<U17>>,
+    process: Option<StandardProcess<U17>>,
+    outcome_sender: Option<Sender<bool, role::Child>>,
+}
+
+impl<'a> Supervised for Child<'a> {
+    fn start(&mut self, fault_source: FaultSource<role::Child>) -> Result<(), SupervisorError> {
+        let parts = self.parts.take().expect("Child was started twice");
+        let fault = self.attempt < self.faulty_attempts;
+        let params = ProcParams {
+            fault,
+            outcome_sender: if fault {
+                None
+            } else {
+                self.outcome_sender.take()
+            },
+        };
+
+        let mut process = StandardProcess::new(
+            &mut self.vspace,
+            parts.cspace,
+            parts.parent_mapped_region,
+            self.root_cnode,
+            proc_main as extern "C" fn(_) -> (),
+            params,
+            parts.ipc_buffer_ut,
+            parts.tcb_ut,
+            parts.slots,
+            self.tpa,
+            ThreadPriorities::default(),
+            Some(fault_source),
+        )?;
+        process.start()?;
+
+        self.process = Some(process);
+        self.attempt += 1;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), SupervisorError> {
+        let process = self.process.take().expect("Child was stopped twice");
+        match process.destroy(&mut self.vspace, self.root_cnode) {
+            Ok(parts) => {
+                self.parts = Some(parts);
+                Ok(())
+            }
+            Err((e, process)) => {
+                self.process = Some(process);
+                Err(e.into())
+            }
+        }
+    }
+}
+
+pub struct ProcParams<Role: CNodeRole> {
+    pub fault: bool,
+    pub outcome_sender: Option<Sender<bool, Role>>,
+}
+
+impl RetypeForSetup for ProcParams<role::Local> {
+    type Output = ProcParams<role::Child>;
+}
+
+pub extern "C" fn proc_main(params: ProcParams<role::Local>) {
+    if params.fault {
+        // Sending to an empty slot causes a capability fault
+        unsafe { seL4_Send(314159, seL4_MessageInfo_new(0, 0, 0, 0)) }
+    }
+    if let Some(sender) = params.outcome_sender {
+        sender.blocking_send(&true).expect("Could not send outcome");
+    }
+}
@@ -0,0 +1,251 @@
+use super::TopLevelError;
+
+use typenum::*;
+
+use ferros::alloc::{smart_alloc, ut_buddy};
+use ferros::arch::{self, fault::Fault, PageBits, PageBytes};
+use ferros::bootstrap::UserImage;
+use ferros::cap::*;
+use ferros::userland::{
+    fault_or_message_channel, CapRights, ChildPolicy, FaultOrMessage, FaultSource, Recovery,
+    RetypeForSetup, Sender, StandardProcess, StandardProcessParts, Supervised, Supervisor,
+    SupervisorError, SupervisorEvent,
+};
+use ferros::vspace::*;
+
+type U33768 = Sum<U32768, U1000>;
+
+/// Supervise a child which writes to a page that isn't mapped yet. Its
+/// fix-up maps the page where the child faulted, so the faulted thread
+/// can be resumed rather than restarted, and goes on to read back what
+/// it wrote.
+#[ferros_test::ferros_test]
+pub fn supervisor_resume(
+    local_slots: LocalCNodeSlots<U33768>,
+    local_ut: LocalCap<Untyped<U20>>,
+    asid_pool: LocalCap<ASIDPool<U1>>,
+    local_mapped_region: MappedMemoryRegion<U17, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (child_cnode, child_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (outcome_slot, child_slots) = child_slots.alloc();
+        let (_outcome_fault_source, outcome_sender, outcome_handler) =
+            fault_or_message_channel(&root_cnode, ut, slots, outcome_slot, slots)?;
+        let (fault_source_slot, _child_slots) = child_slots.alloc();
+
+        let (child_asid, _asid_pool) = asid_pool.alloc();
+        let child_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let child_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut child_vspace = VSpace::new(
+            retype(ut, slots)?,
+            child_asid,
+            child_vspace_slots.weaken(),
+            child_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        // Map and unmap the page once, so that the paging structures
+        // it needs are in place by the time the child faults on it.
+        let page: UnmappedMemoryRegion<PageBits, shared_status::Exclusive> =
+            UnmappedMemoryRegion::new(ut, slots)?;
+        let mapped_page =
+            child_vspace.map_region(page, CapRights::RW, arch::vm_attributes::DEFAULT)?;
+        let missing_page_vaddr = mapped_page.vaddr();
+        let missing_page = child_vspace.unmap_region(mapped_page)?;
+
+        let ipc_buffer_ut: LocalCap<Untyped<U12>> = ut;
+        let tcb_ut: LocalCap<Untyped<<ThreadControlBlock as DirectRetype>::SizeBits>> = ut;
+        let process_slots: LocalCNodeSlots<U34> = slots;
+
+        let supervisor_ut: LocalCap<Untyped<<Endpoint as DirectRetype>::SizeBits>> = ut;
+        let supervisor_slots: LocalCNodeSlots<U3> = slots;
+    });
+
+    let mut child = Child {
+        root_cnode,
+        tpa,
+        starts: 0,
+        vspace: child_vspace,
+        parts: Some(StandardProcessParts {
+            cspace: child_cnode,
+            parent_mapped_region: local_mapped_region,
+            ipc_buffer_ut,
+            tcb_ut,
+            slots: process_slots,
+        }),
+        process: None,
+        missing_page_vaddr,
+        missing_page: Some(missing_page),
+        fixed_up_page: None,
+        outcome_sender: Some(outcome_sender),
+    };
+
+    let (endpoint_slot, supervisor_slots) = supervisor_slots.alloc();
+    let (fault_sink_slot, reply_slot) = supervisor_slots.alloc();
+    let mut supervisor = Supervisor::new(
+        root_cnode,
+        supervisor_ut,
+        endpoint_slot,
+        fault_sink_slot,
+        reply_slot,
+    )?;
+
+    let id = supervisor.add_child(
+        root_cnode,
+        fault_source_slot,
+        &mut child,
+        ChildPolicy {
+            recovery: Recovery::Resume,
+            fault_limit: None,
+        },
+    )?;
+
+    match supervisor.handle_next_fault()? {
+        SupervisorEvent::Resumed(resumed) if resumed == id => (),
+        _ => {
+            return Err(TopLevelError::TestAssertionFailure(
+                "A child that fixes up its fault should be resumed",
+            ))
+        }
+    }
+    if supervisor.fault_count(id) != 1 {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Supervisor should have counted the fault",
+        ));
+    }
+
+    match outcome_handler.await_message()? {
+        FaultOrMessage::Message(true) => (),
+        _ => {
+            return Err(TopLevelError::TestAssertionFailure(
+                "Resumed child should have read back what it wrote",
+            ))
+        }
+    }
+
+    drop(supervisor);
+    if child.starts != 1 {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Resumed child should not have been started again",
+        ));
+    }
+    Ok(())
+}
+
+/// A child process which expects a page to be mapped at
+/// `missing_page_vaddr`, and relies on its fix-up to map it there.
+struct Child<'a> {
+    root_cnode: &'a LocalCap<LocalCNode>,
+    tpa: &'a LocalCap<ThreadPriorityAuthority>,
+    starts: usize,
+    vspace: VSpace,
+    parts: Option<StandardProcessParts<U17>>,
+    process: Option<StandardProcess<U17>>,
+    missing_page_vaddr: usize,
+    missing_page: Option<UnmappedMemoryRegion<PageBits, shared_status::Exclusive>>,
+    fixed_up_page: Option<MappedMemoryRegion<PageBits, shared_status::Exclusive>>,
+    outcome_sender: Option<Sender<bool, role::Child>>,
+}
+
+impl<'a> Supervised for Child<'a> {
+    fn start(&mut self, fault_source: FaultSource<role::Child>) -> Result<(), SupervisorError> {
+        let parts = self.parts.take().expect("Child was started twice");
+        let params = ProcParams {
+            vaddr: self.missing_page_vaddr,
+            outcome_sender: self.outcome_sender.take(),
+        };
+
+        let mut process = StandardProcess::new(
+            &mut self.vspace,
+            parts.cspace,
+            parts.parent_mapped_region,
+            self.root_cnode,
+            proc_main as extern "C" fn(_) -> (),
+            params,
+            parts.ipc_buffer_ut,
+            parts.tcb_ut,
+            parts.slots,
+            self.tpa,
+            ThreadPriorities::default(),
+            Some(fault_source),
+        )?;
+        process.start()?;
+
+        self.process = Some(process);
+        self.starts += 1;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), SupervisorError> {
+        let process = self.process.take().expect("Child was stopped twice");
+        match process.destroy(&mut self.vspace, self.root_cnode) {
+            Ok(parts) => {
+                self.parts = Some(parts);
+                Ok(())
+            }
+            Err((e, process)) => {
+                self.process = Some(process);
+                Err(e.into())
+            }
+        }
+    }
+
+    fn fix_up(&mut self, fault: &Fault) -> bool {
+        let vaddr = match fault {
+            Fault::VMFault(f) => f.address & !(PageBytes::USIZE - 1),
+            _ => return false,
+        };
+        if vaddr != self.missing_page_vaddr {
+            return false;
+        }
+        let page = match self.missing_page.take() {
+            Some(page) => page,
+            None => return false,
+        };
+        match self.vspace.map_region_at_addr(
+            page,
+            vaddr,
+            CapRights::RW,
+            arch::vm_attributes::DEFAULT,
+        ) {
+            Ok(mapped) => {
+                self.fixed_up_page = Some(mapped);
+                true
+            }
+            Err((_, page)) => {
+                self.missing_page = page;
+                false
+            }
+        }
+    }
+}
+
+pub struct ProcParams<Role: CNodeRole> {
+    pub vaddr: usize,
+    pub outcome_sender: Option<Sender<bool, Role>>,
+}
+
+impl RetypeForSetup for ProcParams<role::Local> {
+    type Output = ProcParams<role::Child>;
+}
+
+pub extern "C" fn proc_main(params: ProcParams<role::Local>) {
+    let word = params.vaddr as *mut usize;
+    let read_back = unsafe {
+        // Faults, since nothing is mapped here until the fix-up
+        core::ptr::write_volatile(word, 0xf00d);
+        core::ptr::read_volatile(word)
+    };
+    if let Some(sender) = params.outcome_sender {
+        sender
+            .blocking_send(&(read_back == 0xf00d))
+            .expect("Could not send outcome");
+    }
+}
@@ -0,0 +1,225 @@
+use super::TopLevelError;
+
+use typenum::*;
+
+use ferros::alloc::{smart_alloc, ut_buddy};
+use ferros::arch::fault::Fault;
+use ferros::bootstrap::UserImage;
+use ferros::cap::*;
+use ferros::userland::{
+    fault_or_message_channel, ChildPolicy, FaultOrMessage, FaultSource, Recovery, RetypeForSetup,
+    Sender, StackGuardPageCount, StandardProcess, StandardProcessParts, Supervised, Supervisor,
+    SupervisorError, SupervisorEvent,
+};
+use ferros::vspace::*;
+
+type U33768 = Sum<U32768, U1000>;
+
+/// Supervise a child which runs off the end of its stack the first time
+/// it runs. Its policy is to resume, and its fix-up claims it can fix
+/// anything, but the supervisor should see the overflow through the
+/// child's stack guard and restart it instead.
+#[ferros_test::ferros_test]
+pub fn supervisor_stack_overflow(
+    local_slots: LocalCNodeSlots<U33768>,
+    local_ut: LocalCap<Untyped<U20>>,
+    asid_pool: LocalCap<ASIDPool<U1>>,
+    local_mapped_region: MappedMemoryRegion<U13, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (child_cnode, child_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (outcome_slot, child_slots) = child_slots.alloc();
+        let (_outcome_fault_source, outcome_sender, outcome_handler) =
+            fault_or_message_channel(&root_cnode, ut, slots, outcome_slot, slots)?;
+        let (fault_source_slot, _child_slots) = child_slots.alloc();
+
+        let (child_asid, _asid_pool) = asid_pool.alloc();
+        let child_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let child_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let child_vspace = VSpace::new(
+            retype(ut, slots)?,
+            child_asid,
+            child_vspace_slots.weaken(),
+            child_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let ipc_buffer_ut: LocalCap<Untyped<U12>> = ut;
+        let tcb_ut: LocalCap<Untyped<<ThreadControlBlock as DirectRetype>::SizeBits>> = ut;
+        let process_slots: LocalCNodeSlots<U4> = slots;
+
+        let supervisor_ut: LocalCap<Untyped<<Endpoint as DirectRetype>::SizeBits>> = ut;
+        let supervisor_slots: LocalCNodeSlots<U3> = slots;
+    });
+
+    let mut child = Child {
+        root_cnode,
+        tpa,
+        starts: 0,
+        fix_ups: 0,
+        vspace: child_vspace,
+        parts: Some(StandardProcessParts {
+            cspace: child_cnode,
+            parent_mapped_region: local_mapped_region,
+            ipc_buffer_ut,
+            tcb_ut,
+            slots: process_slots,
+        }),
+        process: None,
+        outcome_sender: Some(outcome_sender),
+    };
+
+    let (endpoint_slot, supervisor_slots) = supervisor_slots.alloc();
+    let (fault_sink_slot, reply_slot) = supervisor_slots.alloc();
+    let mut supervisor = Supervisor::new(
+        root_cnode,
+        supervisor_ut,
+        endpoint_slot,
+        fault_sink_slot,
+        reply_slot,
+    )?;
+
+    let id = supervisor.add_child(
+        root_cnode,
+        fault_source_slot,
+        &mut child,
+        ChildPolicy {
+            recovery: Recovery::Resume,
+            fault_limit: None,
+        },
+    )?;
+
+    match supervisor.handle_next_fault()? {
+        SupervisorEvent::Restarted(restarted) if restarted == id => (),
+        _ => {
+            return Err(TopLevelError::TestAssertionFailure(
+                "A child that overflows its stack should be restarted rather than resumed",
+            ))
+        }
+    }
+    if supervisor.stack_overflow_count(id) != 1 {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Supervisor should have recognised the fault as a stack overflow",
+        ));
+    }
+
+    match outcome_handler.await_message()? {
+        FaultOrMessage::Message(true) => (),
+        _ => {
+            return Err(TopLevelError::TestAssertionFailure(
+                "Restarted child should have reported success",
+            ))
+        }
+    }
+
+    drop(supervisor);
+    if child.fix_ups != 0 {
+        return Err(TopLevelError::TestAssertionFailure(
+            "A stack overflow should not be handed to the child's fix-up",
+        ));
+    }
+    Ok(())
+}
+
+/// A child process which recurses until it overflows its stack on its
+/// first start, and reports success on the next.
+struct Child<'a> {
+    root_cnode: &'a LocalCap<LocalCNode>,
+    tpa: &'a LocalCap<ThreadPriorityAuthority>,
+    starts: usize,
+    fix_ups: usize,
+    vspace: VSpace,
+    parts: Option<StandardProcessParts<U13>>,
+    process: Option<StandardProcess<U13>>,
+    outcome_sender: Option<Sender<bool, role::Child>>,
+}
+
+impl<'a> Supervised for Child<'a> {
+    fn start(&mut self, fault_source: FaultSource<role::Child>) -> Result<(), SupervisorError> {
+        let parts = self.parts.take().expect("Child was started twice");
+        let overflow = self.starts == 0;
+        let params = ProcParams {
+            overflow,
+            outcome_sender: if overflow {
+                None
+            } else {
+                self.outcome_sender.take()
+            },
+        };
+
+        let mut process = StandardProcess::new(
+            &mut self.vspace,
+            parts.cspace,
+            parts.parent_mapped_region,
+            self.root_cnode,
+            proc_main as extern "C" fn(_) -> (),
+            params,
+            parts.ipc_buffer_ut,
+            parts.tcb_ut,
+            parts.slots,
+            self.tpa,
+            ThreadPriorities::default(),
+            Some(fault_source),
+        )?;
+        process.start()?;
+
+        self.process = Some(process);
+        self.starts += 1;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), SupervisorError> {
+        let process = self.process.take().expect("Child was stopped twice");
+        match process.destroy(&mut self.vspace, self.root_cnode) {
+            Ok(parts) => {
+                self.parts = Some(parts);
+                Ok(())
+            }
+            Err((e, process)) => {
+                self.process = Some(process);
+                Err(e.into())
+            }
+        }
+    }
+
+    fn fix_up(&mut self, _fault: &Fault) -> bool {
+        self.fix_ups += 1;
+        true
+    }
+
+    fn stack_guard(&self) -> Option<&GuardRegion<StackGuardPageCount>> {
+        self.process.as_ref().map(|process| process.stack_guard())
+    }
+}
+
+pub struct ProcParams<Role: CNodeRole> {
+    pub overflow: bool,
+    pub outcome_sender: Option<Sender<bool, Role>>,
+}
+
+impl RetypeForSetup for ProcParams<role::Local> {
+    type Output = ProcParams<role::Child>;
+}
+
+/// Uses up a kilobyte of stack per call, and never stops.
+fn recurse(depth: usize) -> usize {
+    let frame = [depth as u8; 1024];
+    let below = recurse(depth + 1);
+    below + unsafe { core::ptr::read_volatile(&frame[depth % 1024]) } as usize
+}
+
+pub extern "C" fn proc_main(params: ProcParams<role::Local>) {
+    if params.overflow {
+        let _ = recurse(0);
+    }
+    if let Some(sender) = params.outcome_sender {
+        sender.blocking_send(&true).expect("Could not send outcome");
+    }
+}
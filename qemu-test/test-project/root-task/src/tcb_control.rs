@@ -0,0 +1,106 @@
+use typenum::*;
+
+use ferros::alloc::{smart_alloc, ut_buddy};
+use ferros::arch::fault::Fault;
+use ferros::bootstrap::UserImage;
+use ferros::cap::{
+    retype, retype_cnode, role, ASIDPool, Badge, LocalCNode, LocalCNodeSlots, LocalCap,
+    ThreadPriorities, ThreadPriorityAuthority, Untyped,
+};
+use ferros::userland::{FaultSinkSetup, RetypeForSetup, StandardProcess};
+use ferros::vspace::*;
+
+use super::TopLevelError;
+
+const REDIRECTED_FAULT_ADDRESS: usize = 0x7777_0000;
+
+/// Suspend a spinning child, point its program counter somewhere else,
+/// and check that it faults where it was sent once it's resumed.
+#[ferros_test::ferros_test]
+pub fn tcb_control(
+    local_slots: LocalCNodeSlots<U32768>,
+    local_ut: LocalCap<Untyped<U20>>,
+    asid_pool: LocalCap<ASIDPool<U1>>,
+    local_mapped_region: MappedMemoryRegion<U17, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (child_asid, _asid_pool) = asid_pool.alloc();
+        let child_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let child_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut child_vspace = VSpace::new(
+            retype(ut, slots)?,
+            child_asid,
+            child_vspace_slots.weaken(),
+            child_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let (child_cnode, child_slots) = retype_cnode::<U12>(ut, slots)?;
+        let params = ProcParams {};
+
+        let setup = FaultSinkSetup::new(&root_cnode, ut, slots, slots)?;
+        let (child_slot_for_fault_source, _child_slots) = child_slots.alloc();
+        let fault_source =
+            setup.add_fault_source(&root_cnode, child_slot_for_fault_source, Badge::from(0))?;
+        let sink = setup.sink();
+
+        let mut child_process = StandardProcess::new(
+            &mut child_vspace,
+            child_cnode,
+            local_mapped_region,
+            root_cnode,
+            proc_main as extern "C" fn(_) -> (),
+            params,
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            Some(fault_source),
+        )?;
+    });
+
+    child_process.start()?;
+    child_process.suspend()?;
+
+    let mut registers = child_process.read_registers()?;
+    registers.pc = redirected as usize;
+    child_process.write_registers(&registers, false)?;
+    if child_process.read_registers()?.pc != redirected as usize {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Written registers should read back",
+        ));
+    }
+    child_process.resume()?;
+
+    match sink.wait_for_fault() {
+        Fault::VMFault(fault) if fault.address == REDIRECTED_FAULT_ADDRESS => Ok(()),
+        _ => Err(TopLevelError::TestAssertionFailure(
+            "Resumed child should fault where its program counter was sent",
+        )),
+    }
+}
+
+pub struct ProcParams {}
+
+impl RetypeForSetup for ProcParams {
+    type Output = ProcParams;
+}
+
+pub extern "C" fn proc_main(_params: ProcParams) {
+    loop {
+        unsafe { core::ptr::read_volatile(&0usize) };
+    }
+}
+
+extern "C" fn redirected() -> ! {
+    unsafe { core::ptr::read_volatile(REDIRECTED_FAULT_ADDRESS as *const usize) };
+    unreachable!()
+}
@@ -0,0 +1,113 @@
+use super::TopLevelError;
+
+use ferros::alloc::{smart_alloc, ut_buddy};
+use typenum::*;
+
+use ferros::cap::*;
+use ferros::userland::{RetypeForSetup, Thread, ThreadParts};
+use ferros::vspace::*;
+
+/// Join a thread to get its return value back, then build a second
+/// thread out of the parts the first one was made from.
+#[ferros_test::ferros_test]
+pub fn thread_join(
+    local_slots: LocalCNodeSlots<U32>,
+    local_ut: LocalCap<Untyped<U16>>,
+    stack_mapped_region: MappedMemoryRegion<U17, shared_status::Exclusive>,
+    ipc_buffer_region: MappedMemoryRegion<U12, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+    vspace_paging_root: &LocalCap<ferros::arch::PagingRoot>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (child_cnode, child_slots) = retype_cnode::<U4>(ut, slots)?;
+        let (thread_slots, _child_slots) = child_slots.alloc();
+
+        let first = Thread::new(
+            vspace_paging_root,
+            child_cnode,
+            stack_mapped_region,
+            None, // stack guard
+            root_cnode,
+            sum_main,
+            SumParams { first: 1, count: 4 },
+            ipc_buffer_region,
+            ut,
+            ut,
+            slots,
+            thread_slots,
+            tpa,
+            ThreadPriorities::default(),
+            None, // fault
+        )?;
+    });
+
+    let (sums, parts) = first.start()?.join(root_cnode)?;
+    if sums != [1, 3, 6, 10] {
+        return Err(TopLevelError::TestAssertionFailure(
+            "The joined thread should hand back its return value",
+        ));
+    }
+
+    let ThreadParts {
+        cspace,
+        stack_region,
+        stack_guard,
+        ipc_buffer,
+        tcb_ut,
+        exit_notification_ut,
+        slots,
+        child_slots,
+    } = parts;
+    let second = Thread::new(
+        vspace_paging_root,
+        cspace,
+        stack_region,
+        stack_guard,
+        root_cnode,
+        sum_main,
+        SumParams {
+            first: 10,
+            count: 2,
+        },
+        ipc_buffer,
+        tcb_ut,
+        exit_notification_ut,
+        slots,
+        child_slots,
+        tpa,
+        ThreadPriorities::default(),
+        None, // fault
+    )?;
+
+    let (sums, _parts) = second.start()?.join(root_cnode)?;
+    if sums != [10, 21, 0, 0] {
+        return Err(TopLevelError::TestAssertionFailure(
+            "A thread built from reclaimed parts should run like the first",
+        ));
+    }
+    Ok(())
+}
+
+pub struct SumParams {
+    pub first: u64,
+    pub count: usize,
+}
+
+impl RetypeForSetup for SumParams {
+    type Output = SumParams;
+}
+
+/// Running totals of `count` consecutive numbers, which is too big to
+/// come back in a register.
+pub extern "C" fn sum_main(params: SumParams) -> [u64; 4] {
+    let mut sums = [0; 4];
+    let mut total = 0;
+    for (n, sum) in sums.iter_mut().take(params.count).enumerate() {
+        total += params.first + n as u64;
+        *sum = total;
+    }
+    sums
+}
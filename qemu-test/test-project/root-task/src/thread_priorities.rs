@@ -0,0 +1,137 @@
+use typenum::*;
+
+use ferros::alloc::{smart_alloc, ut_buddy};
+use ferros::arch::fault::Fault;
+use ferros::bootstrap::UserImage;
+use ferros::cap::{
+    retype, retype_cnode, role, ASIDPool, Badge, LocalCNode, LocalCNodeSlots, LocalCap, Priority,
+    ThreadControlBlock, ThreadPriorities, ThreadPriorityAuthority, Untyped,
+};
+use ferros::error::{KernelError, SeL4Error};
+use ferros::userland::{FaultSinkSetup, ProcessSetupError, RetypeForSetup, StandardProcess};
+use ferros::vspace::*;
+
+use super::TopLevelError;
+
+/// Cap a spare TCB's MCP and use it as a priority authority: priorities
+/// above the cap are turned away, and ones below it give a runnable
+/// process.
+#[ferros_test::ferros_test]
+pub fn thread_priorities(
+    local_slots: LocalCNodeSlots<U32768>,
+    local_ut: LocalCap<Untyped<U20>>,
+    asid_pool: LocalCap<ASIDPool<U1>>,
+    local_mapped_region: MappedMemoryRegion<U18, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+) -> Result<(), TopLevelError> {
+    let uts = ut_buddy(local_ut);
+    let limit = Priority::new(100).unwrap();
+    let over_limit = Priority::new(101).unwrap();
+
+    let (rejected_stack, stack) = local_mapped_region.split()?;
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let mut authority_tcb: LocalCap<ThreadControlBlock> = retype(ut, slots)?;
+        authority_tcb.set_max_controlled_priority(tpa, limit)?;
+        let limited = authority_tcb.downgrade_to_thread_priority_authority();
+        if limited.max_controlled_priority() != limit {
+            return Err(TopLevelError::TestAssertionFailure(
+                "The authority should remember the MCP it was given",
+            ));
+        }
+
+        let (child_asid, _asid_pool) = asid_pool.alloc();
+        let child_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let child_vspace_ut: LocalCap<Untyped<U15>> = ut;
+        let mut child_vspace = VSpace::new(
+            retype(ut, slots)?,
+            child_asid,
+            child_vspace_slots.weaken(),
+            child_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let (rejected_cnode, _rejected_slots) = retype_cnode::<U12>(ut, slots)?;
+        let rejected = StandardProcess::new(
+            &mut child_vspace,
+            rejected_cnode,
+            rejected_stack,
+            root_cnode,
+            proc_main as extern "C" fn(_) -> (),
+            ProcParams {},
+            ut,
+            ut,
+            slots,
+            &limited,
+            ThreadPriorities::new(over_limit, Priority::MIN),
+            None,
+        );
+        match rejected {
+            Err(ProcessSetupError::PrioritiesExceedAuthority) => (),
+            _ => {
+                return Err(TopLevelError::TestAssertionFailure(
+                    "A priority above the authority's MCP should be rejected",
+                ))
+            }
+        }
+
+        let (child_cnode, child_slots) = retype_cnode::<U12>(ut, slots)?;
+        let setup = FaultSinkSetup::new(&root_cnode, ut, slots, slots)?;
+        let (child_slot_for_fault_source, _child_slots) = child_slots.alloc();
+        let fault_source =
+            setup.add_fault_source(&root_cnode, child_slot_for_fault_source, Badge::from(0))?;
+        let sink = setup.sink();
+
+        let mut child_process = StandardProcess::new(
+            &mut child_vspace,
+            child_cnode,
+            stack,
+            root_cnode,
+            proc_main as extern "C" fn(_) -> (),
+            ProcParams {},
+            ut,
+            ut,
+            slots,
+            &limited,
+            ThreadPriorities::new(limit, Priority::new(50).unwrap()),
+            Some(fault_source),
+        )?;
+
+        // The kernel holds the authority to the same limit
+        let mut spare_tcb: LocalCap<ThreadControlBlock> = retype(ut, slots)?;
+    });
+
+    match spare_tcb.set_priority(&limited, over_limit) {
+        Err(SeL4Error::TCBSetPriority(KernelError::RangeError)) => (),
+        _ => {
+            return Err(TopLevelError::TestAssertionFailure(
+                "The kernel should refuse a priority above the authority's MCP",
+            ))
+        }
+    }
+
+    child_process.start()?;
+    match sink.wait_for_fault() {
+        Fault::VMFault(_) => Ok(()),
+        _ => Err(TopLevelError::TestAssertionFailure(
+            "The lower priority child should have run once the root task blocked",
+        )),
+    }
+}
+
+pub struct ProcParams {}
+
+impl RetypeForSetup for ProcParams {
+    type Output = ProcParams;
+}
+
+pub extern "C" fn proc_main(_params: ProcParams) {
+    unsafe {
+        let x: *const usize = 0x88888888usize as _;
+        core::ptr::read_volatile(x);
+    }
+}
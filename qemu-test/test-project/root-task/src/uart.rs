@@ -7,7 +7,8 @@ use ferros::alloc::{self, micro_alloc, smart_alloc};
 use ferros::arch;
 use ferros::bootstrap::{root_cnode, BootInfo};
 use ferros::cap::{
-    retype, retype_cnode, role, CNodeRole, LocalCNodeSlots, LocalCap, MaxIRQCount, Untyped,
+    retype, retype_cnode, role, CNodeRole, LocalCNodeSlots, LocalCap, MaxIRQCount,
+    ThreadPriorities, Untyped,
 };
 use ferros::userland::{
     CapRights, Consumer1, DefaultStackBitSize, InterruptConsumer, Producer, RetypeForSetup,
@@ -41,6 +42,7 @@ pub fn run(raw_boot_info: &'static seL4_BootInfo) -> Result<(), TopLevelError> {
             .expect("Initial untyped retrieval failure"),
         root_vspace_slots,
     );
+    let tpa = root_tcb.thread_priority_authority();
     let uts = alloc::ut_buddy(
         allocator
             .get_untyped::<U21>()
@@ -136,7 +138,8 @@ pub fn run(raw_boot_info: &'static seL4_BootInfo) -> Result<(), TopLevelError> {
             ut,
             ut,
             slots,
-            root_tcb.as_ref(),
+            &tpa,
+            ThreadPriorities::default(),
             None,
         )?;
 
@@ -78,7 +78,8 @@ pub fn weak_elf_process_runs<'a, 'b, 'c>(
             ut, // ipc_buffer_ut
             ut, // tcb_ut
             slots,
-            tpa,  // priority_authority
+            tpa, // priority_authority
+            ThreadPriorities::default(),
             None, // fault
         )?;
     });
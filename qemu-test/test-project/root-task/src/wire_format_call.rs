@@ -0,0 +1,158 @@
+use super::TopLevelError;
+use ferros::alloc::{smart_alloc, ut_buddy};
+use ferros::bootstrap::UserImage;
+use ferros::cap::{
+    retype, retype_cnode, role, ASIDPool, CNodeRole, LocalCNode, LocalCNodeSlots, LocalCap,
+    ThreadPriorities, ThreadPriorityAuthority, Untyped,
+};
+use ferros::userland::*;
+use ferros::vspace::*;
+use typenum::*;
+
+type U33768 = op!(U32768 + U1000);
+
+#[ferros_test::ferros_test]
+pub fn wire_format_call(
+    local_slots: LocalCNodeSlots<U33768>,
+    local_ut: LocalCap<Untyped<U20>>,
+    asid_pool: LocalCap<ASIDPool<U1>>,
+    local_mapped_region: MappedMemoryRegion<U17, shared_status::Exclusive>,
+    root_cnode: &LocalCap<LocalCNode>,
+    user_image: &UserImage<role::Local>,
+    tpa: &LocalCap<ThreadPriorityAuthority>,
+) -> Result<(), TopLevelError> {
+    // Malformed payloads are refused rather than decoded
+    if decode_from::<bool>(&[2]) != Err(WireError::InvalidValue)
+        || decode_from::<Option<u8>>(&[7, 0]) != Err(WireError::InvalidTag)
+        || decode_from::<u16>(&[1, 0]) != Err(WireError::TrailingBytes)
+        || decode_from::<u32>(&[0x80]) != Err(WireError::UnexpectedEnd)
+        || decode_from::<u8>(&[]) != Err(WireError::UnexpectedEnd)
+    {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Malformed payloads should fail to decode",
+        ));
+    }
+
+    // heapless strings round trip, and are refused if too long for
+    // the receiving type
+    let mut buf = [0u8; 16];
+    let greeting: heapless::String<8> = heapless::String::from("hello");
+    let round_trips = match encode_into(&greeting, &mut buf) {
+        Ok(len) => {
+            decode_from::<heapless::String<8>>(&buf[..len]) == Ok(greeting)
+                && decode_from::<heapless::String<4>>(&buf[..len]) == Err(WireError::InvalidValue)
+        }
+        Err(_) => false,
+    };
+    if !round_trips {
+        return Err(TopLevelError::TestAssertionFailure(
+            "heapless strings should round trip within their capacity",
+        ));
+    }
+
+    let uts = ut_buddy(local_ut);
+
+    smart_alloc!(|slots: local_slots, ut: uts| {
+        let (responder_asid, _asid_pool) = asid_pool.alloc();
+        let responder_root = retype(ut, slots)?;
+        let responder_vspace_slots: LocalCNodeSlots<U1024> = slots;
+        let responder_vspace_ut: LocalCap<Untyped<U15>> = ut;
+
+        let mut responder_vspace = VSpace::new(
+            responder_root,
+            responder_asid,
+            responder_vspace_slots.weaken(),
+            responder_vspace_ut.weaken(),
+            ProcessCodeImageConfig::ReadOnly,
+            user_image,
+            root_cnode,
+        )?;
+
+        let (responder_cnode, responder_slots) = retype_cnode::<U12>(ut, slots)?;
+        let (slots_r, _responder_slots) = responder_slots.alloc();
+        let (ipc_setup, responder) = call_channel(ut, &root_cnode, slots, slots_r)?;
+        let caller = ipc_setup.create_caller(slots)?;
+
+        let mut responder_process = StandardProcess::new(
+            &mut responder_vspace,
+            responder_cnode,
+            local_mapped_region,
+            &root_cnode,
+            responder_proc as extern "C" fn(_) -> (),
+            ResponderParams::<role::Child> { responder },
+            ut,
+            ut,
+            slots,
+            tpa,
+            ThreadPriorities::default(),
+            None, // fault
+        )?;
+        responder_process.start()?;
+    });
+
+    if caller.blocking_call_encoded(&Op::Add(40, 2))? != Some(42) {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Encoded call should have added its operands",
+        ));
+    }
+    if caller.blocking_call_encoded(&Op::Add(core::u32::MAX, 1))? != None {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Overflowing addition should have no result",
+        ));
+    }
+    if caller.blocking_call_encoded(&Op::Negate(-7))? != Some(7) {
+        return Err(TopLevelError::TestAssertionFailure(
+            "Encoded call should have negated its operand",
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum Op {
+    Add(u32, u32),
+    Negate(i64),
+}
+
+impl WireFormat for Op {
+    fn encode(&self, encoder: &mut Encoder) -> Result<(), WireError> {
+        match self {
+            Op::Add(a, b) => {
+                encoder.write_u8(0)?;
+                (*a, *b).encode(encoder)
+            }
+            Op::Negate(n) => {
+                encoder.write_u8(1)?;
+                n.encode(encoder)
+            }
+        }
+    }
+
+    fn decode(decoder: &mut Decoder) -> Result<Self, WireError> {
+        match decoder.read_u8()? {
+            0 => {
+                let (a, b) = <(u32, u32)>::decode(decoder)?;
+                Ok(Op::Add(a, b))
+            }
+            1 => Ok(Op::Negate(i64::decode(decoder)?)),
+            _ => Err(WireError::InvalidTag),
+        }
+    }
+}
+
+pub struct ResponderParams<Role: CNodeRole> {
+    pub responder: Responder<Op, Option<i64>, Role>,
+}
+
+impl RetypeForSetup for ResponderParams<role::Local> {
+    type Output = ResponderParams<role::Child>;
+}
+
+pub extern "C" fn responder_proc(p: ResponderParams<role::Local>) {
+    p.responder
+        .reply_recv_encoded(|op| match op {
+            Op::Add(a, b) => a.checked_add(b).map(i64::from),
+            Op::Negate(n) => n.checked_neg(),
+        })
+        .expect("reply_recv_encoded");
+}
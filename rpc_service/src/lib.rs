@@ -0,0 +1,368 @@
+extern crate proc_macro;
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenTree};
+use quote::quote;
+use std::fmt::{Display, Formatter};
+use syn::export::TokenStream2;
+use syn::parse_macro_input::parse as syn_parse;
+use syn::spanned::Spanned;
+use syn::{
+    ArgCaptured, Error as SynError, FnArg, Ident, ItemTrait, Pat, ReturnType, TraitItem,
+    TraitItemMethod, Type,
+};
+
+const EXPECTED_LAYOUT_MESSAGE: &str = r"rpc_service expects to be applied to a trait like:
+#[rpc_service]
+pub trait Storage {
+    fn get(&self, key: Key) -> Option<Value>;
+    fn put(&mut self, key: Key, value: Value);
+}
+
+Every item in the trait must be a non-generic method which takes
+`&self` or `&mut self` followed by arguments bound to plain identifiers.";
+
+/// Generate the plumbing for an IPC service from a trait describing
+/// its methods.
+///
+/// For a trait `Storage`, this emits, alongside the trait itself:
+///
+/// * `StorageRequest`, an enum with one variant per method holding
+///   that method's arguments, with a `dispatch` method which calls the
+///   corresponding method on a `Storage` implementation.
+/// * `StorageResponse`, an enum with one variant per method holding
+///   that method's return value.
+/// * `StorageClient<Role>`, wrapping a `Caller` with one method per
+///   RPC, each returning the method's return value or an `IPCError`.
+/// * `StorageServer<Role>`, wrapping a `Responder`, which serves
+///   requests by dispatching them to a `Storage` implementation.
+///
+/// Variants are named after their methods in CamelCase. Each
+/// comma-separated argument to the attribute is applied as an
+/// attribute to both enums, e.g.
+/// `#[rpc_service(derive(Debug, Clone), allow(clippy::large_enum_variant))]`.
+#[proc_macro_attribute]
+pub fn rpc_service(attr: TokenStream, item: TokenStream) -> TokenStream {
+    rpc_service_impl(TokenStream2::from(attr), TokenStream2::from(item))
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+fn rpc_service_impl(attr: TokenStream2, item: TokenStream2) -> Result<TokenStream2, Error> {
+    let service: ItemTrait = syn_parse(item.into())?;
+    if !service.generics.params.is_empty() || service.generics.where_clause.is_some() {
+        return Err(Error::GenericTrait {
+            span: service.generics.span(),
+        });
+    }
+    let methods = service
+        .items
+        .iter()
+        .map(parse_method)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(generate(&service, &methods, &enum_attrs(attr)))
+}
+
+/// The parts of a service method needed to generate its plumbing
+struct Method {
+    name: Ident,
+    variant: Ident,
+    arg_names: Vec<Ident>,
+    arg_types: Vec<Type>,
+    output: Type,
+}
+
+fn parse_method(item: &TraitItem) -> Result<Method, Error> {
+    let method: &TraitItemMethod = match item {
+        TraitItem::Method(m) => m,
+        _ => return Err(Error::NonMethodItem { span: item.span() }),
+    };
+    let sig = &method.sig;
+    if !sig.decl.generics.params.is_empty() || sig.decl.generics.where_clause.is_some() {
+        return Err(Error::GenericMethod {
+            span: sig.decl.generics.span(),
+        });
+    }
+
+    let mut inputs = sig.decl.inputs.iter();
+    match inputs.next() {
+        Some(FnArg::SelfRef(_)) => (),
+        _ => {
+            return Err(Error::InvalidReceiver {
+                method: sig.ident.to_string(),
+                span: sig.ident.span(),
+            })
+        }
+    }
+
+    let mut arg_names = Vec::new();
+    let mut arg_types = Vec::new();
+    for input in inputs {
+        match input {
+            FnArg::Captured(ArgCaptured {
+                pat: Pat::Ident(pat_ident),
+                ty,
+                ..
+            }) if pat_ident.by_ref.is_none() && pat_ident.subpat.is_none() => {
+                arg_names.push(pat_ident.ident.clone());
+                arg_types.push(ty.clone());
+            }
+            _ => return Err(Error::InvalidArgument { span: input.span() }),
+        }
+    }
+
+    let output = match &sig.decl.output {
+        ReturnType::Default => syn::parse_quote!(()),
+        ReturnType::Type(_, ty) => (**ty).clone(),
+    };
+
+    Ok(Method {
+        name: sig.ident.clone(),
+        variant: Ident::new(&camel_case(&sig.ident.to_string()), sig.ident.span()),
+        arg_names,
+        arg_types,
+        output,
+    })
+}
+
+fn generate(service: &ItemTrait, methods: &[Method], enum_attrs: &[TokenStream2]) -> TokenStream2 {
+    let vis = &service.vis;
+    let service_name = &service.ident;
+    let request = suffixed(service_name, "Request");
+    let response = suffixed(service_name, "Response");
+    let client = suffixed(service_name, "Client");
+    let server = suffixed(service_name, "Server");
+
+    let request_variants = methods.iter().map(|m| {
+        let variant = &m.variant;
+        let arg_types = &m.arg_types;
+        if arg_types.is_empty() {
+            quote!(#variant)
+        } else {
+            quote!(#variant(#(#arg_types),*))
+        }
+    });
+    let response_variants = methods.iter().map(|m| {
+        let variant = &m.variant;
+        let output = &m.output;
+        quote!(#variant(#output))
+    });
+    let dispatch_arms = methods.iter().map(|m| {
+        let variant = &m.variant;
+        let name = &m.name;
+        let arg_names = &m.arg_names;
+        if arg_names.is_empty() {
+            quote!(#request::#variant => #response::#variant(service.#name()))
+        } else {
+            quote! {
+                #request::#variant(#(#arg_names),*) =>
+                    #response::#variant(service.#name(#(#arg_names),*))
+            }
+        }
+    });
+    let client_methods = methods.iter().map(|m| {
+        let variant = &m.variant;
+        let name = &m.name;
+        let arg_names = &m.arg_names;
+        let arg_types = &m.arg_types;
+        let output = &m.output;
+        let request_value = if arg_names.is_empty() {
+            quote!(#request::#variant)
+        } else {
+            quote!(#request::#variant(#(#arg_names),*))
+        };
+        quote! {
+            pub fn #name(&self, #(#arg_names: #arg_types),*)
+                -> Result<#output, ferros::userland::IPCError> {
+                match self.caller.blocking_call(&#request_value)? {
+                    #response::#variant(output) => Ok(output),
+                    #[allow(unreachable_patterns)]
+                    _ => Err(ferros::userland::IPCError::UnexpectedResponse),
+                }
+            }
+        }
+    });
+
+    let client_impl = quote! {
+        #vis struct #client<Role: ferros::cap::CNodeRole> {
+            caller: ferros::userland::Caller<#request, #response, Role>,
+        }
+
+        impl<Role: ferros::cap::CNodeRole> #client<Role> {
+            pub fn new(caller: ferros::userland::Caller<#request, #response, Role>) -> Self {
+                #client { caller }
+            }
+
+            pub fn into_caller(self) -> ferros::userland::Caller<#request, #response, Role> {
+                self.caller
+            }
+        }
+
+        impl #client<ferros::cap::role::Local> {
+            #(#client_methods)*
+        }
+    };
+
+    let server_impl = quote! {
+        #vis struct #server<Role: ferros::cap::CNodeRole> {
+            responder: ferros::userland::Responder<#request, #response, Role>,
+        }
+
+        impl<Role: ferros::cap::CNodeRole> #server<Role> {
+            pub fn new(responder: ferros::userland::Responder<#request, #response, Role>) -> Self {
+                #server { responder }
+            }
+
+            pub fn into_responder(self) -> ferros::userland::Responder<#request, #response, Role> {
+                self.responder
+            }
+        }
+
+        impl #server<ferros::cap::role::Local> {
+            /// Serve requests with `service` forever.
+            pub fn serve<S: #service_name>(
+                self,
+                service: S,
+            ) -> Result<#response, ferros::userland::IPCError> {
+                self.responder
+                    .reply_recv_with_state(service, |request, mut service| {
+                        let response = request.dispatch(&mut service);
+                        (response, service)
+                    })
+            }
+
+            /// Serve a single request with `service`.
+            pub fn serve_once<S: #service_name>(
+                &self,
+                service: &mut S,
+            ) -> Result<(), ferros::userland::IPCError> {
+                self.responder
+                    .recv_reply_once(|request| request.dispatch(&mut *service))
+            }
+        }
+    };
+
+    quote! {
+        #service
+
+        #(#[#enum_attrs])*
+        #vis enum #request {
+            #(#request_variants),*
+        }
+
+        #(#[#enum_attrs])*
+        #vis enum #response {
+            #(#response_variants),*
+        }
+
+        impl #request {
+            /// Call the method this request names on `service`.
+            pub fn dispatch<S: #service_name>(self, service: &mut S) -> #response {
+                match self {
+                    #(#dispatch_arms),*
+                }
+            }
+        }
+
+        #client_impl
+
+        #server_impl
+    }
+}
+
+/// Split the attribute's arguments at each top-level comma
+fn enum_attrs(attr: TokenStream2) -> Vec<TokenStream2> {
+    let mut attrs = Vec::new();
+    let mut current = TokenStream2::new();
+    for token in attr {
+        match &token {
+            TokenTree::Punct(p) if p.as_char() == ',' => {
+                attrs.push(core::mem::replace(&mut current, TokenStream2::new()))
+            }
+            _ => current.extend(Some(token)),
+        }
+    }
+    if !current.is_empty() {
+        attrs.push(current);
+    }
+    attrs
+}
+
+fn suffixed(ident: &Ident, suffix: &str) -> Ident {
+    Ident::new(&format!("{}{}", ident, suffix), ident.span())
+}
+
+fn camel_case(snake: &str) -> String {
+    snake
+        .split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+enum Error {
+    GenericTrait { span: Span },
+    NonMethodItem { span: Span },
+    GenericMethod { span: Span },
+    InvalidReceiver { method: String, span: Span },
+    InvalidArgument { span: Span },
+    SynParse(SynError),
+}
+
+impl Error {
+    fn span(&self) -> Span {
+        match self {
+            Error::GenericTrait { span } => *span,
+            Error::NonMethodItem { span } => *span,
+            Error::GenericMethod { span } => *span,
+            Error::InvalidReceiver { method: _, span } => *span,
+            Error::InvalidArgument { span } => *span,
+            Error::SynParse(e) => e.span(),
+        }
+    }
+
+    pub(crate) fn to_compile_error(&self) -> TokenStream2 {
+        SynError::new(self.span(), self).to_compile_error()
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        let s = match self {
+            Error::GenericTrait { .. } => format!(
+                "{}\nbut the trait has generic parameters",
+                EXPECTED_LAYOUT_MESSAGE
+            ),
+            Error::NonMethodItem { .. } => format!(
+                "{}\nbut the trait contains an item which is not a method",
+                EXPECTED_LAYOUT_MESSAGE
+            ),
+            Error::GenericMethod { .. } => format!(
+                "{}\nbut a method has generic parameters",
+                EXPECTED_LAYOUT_MESSAGE
+            ),
+            Error::InvalidReceiver { method, .. } => format!(
+                "{}\nbut the method {} does not take `&self` or `&mut self`",
+                EXPECTED_LAYOUT_MESSAGE, method
+            ),
+            Error::InvalidArgument { .. } => format!(
+                "{}\nbut an argument is not bound to a plain identifier",
+                EXPECTED_LAYOUT_MESSAGE
+            ),
+            Error::SynParse(se) => se.to_compile_error().to_string(),
+        };
+        f.write_str(&s)
+    }
+}
+
+impl From<SynError> for Error {
+    fn from(se: SynError) -> Self {
+        Error::SynParse(se)
+    }
+}
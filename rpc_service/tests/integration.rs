@@ -0,0 +1,210 @@
+use rpc_service::rpc_service;
+
+use std::cell::RefCell;
+
+/// A stand-in for the parts of ferros the generated code refers to.
+/// Calls are delivered straight to a handler installed by the test
+/// rather than through an endpoint.
+mod ferros {
+    pub mod cap {
+        pub trait CNodeRole {}
+
+        pub mod role {
+            pub struct Local;
+            impl super::CNodeRole for Local {}
+        }
+    }
+
+    pub mod userland {
+        use super::cap::CNodeRole;
+        use core::marker::PhantomData;
+
+        #[derive(Debug, PartialEq)]
+        pub enum IPCError {
+            UnexpectedResponse,
+        }
+
+        pub struct Caller<Req, Rsp, Role: CNodeRole> {
+            pub handler: Box<dyn Fn(&Req) -> Rsp>,
+            pub _role: PhantomData<Role>,
+        }
+
+        impl<Req, Rsp, Role: CNodeRole> Caller<Req, Rsp, Role> {
+            pub fn blocking_call(&self, request: &Req) -> Result<Rsp, IPCError> {
+                Ok((self.handler)(request))
+            }
+        }
+
+        pub struct Responder<Req, Rsp, Role: CNodeRole> {
+            pub requests: std::cell::RefCell<Vec<Req>>,
+            pub responses: std::cell::RefCell<Vec<Rsp>>,
+            pub _role: PhantomData<Role>,
+        }
+
+        impl<Req, Rsp, Role: CNodeRole> Responder<Req, Rsp, Role> {
+            pub fn reply_recv_with_state<F, State>(
+                self,
+                initial_state: State,
+                mut f: F,
+            ) -> Result<Rsp, IPCError>
+            where
+                F: FnMut(Req, State) -> (Rsp, State),
+            {
+                let mut state = initial_state;
+                let mut last = None;
+                for request in self.requests.borrow_mut().drain(..) {
+                    let (response, next_state) = f(request, state);
+                    state = next_state;
+                    last = Some(response);
+                }
+                Ok(last.expect("no requests to serve"))
+            }
+
+            pub fn recv_reply_once<F>(&self, mut f: F) -> Result<(), IPCError>
+            where
+                F: FnMut(Req) -> Rsp,
+            {
+                let request = self.requests.borrow_mut().remove(0);
+                self.responses.borrow_mut().push(f(request));
+                Ok(())
+            }
+        }
+    }
+}
+
+use ferros::cap::role;
+use ferros::userland::{Caller, IPCError, Responder};
+
+#[rpc_service(derive(Debug, PartialEq), allow(dead_code))]
+pub trait Counter {
+    fn add(&mut self, amount: u32) -> u32;
+    fn current_value(&self) -> u32;
+    fn reset(&mut self);
+    fn add_pair(&mut self, first: u32, second: u32) -> Option<u32>;
+}
+
+#[derive(Default)]
+struct SimpleCounter {
+    value: u32,
+}
+
+impl Counter for SimpleCounter {
+    fn add(&mut self, amount: u32) -> u32 {
+        self.value += amount;
+        self.value
+    }
+
+    fn current_value(&self) -> u32 {
+        self.value
+    }
+
+    fn reset(&mut self) {
+        self.value = 0;
+    }
+
+    fn add_pair(&mut self, first: u32, second: u32) -> Option<u32> {
+        let sum = first.checked_add(second)?;
+        self.value = self.value.checked_add(sum)?;
+        Some(self.value)
+    }
+}
+
+#[test]
+fn dispatch_calls_the_named_method() {
+    let mut counter = SimpleCounter::default();
+    match CounterRequest::Add(3).dispatch(&mut counter) {
+        CounterResponse::Add(3) => (),
+        _ => panic!("Expected an Add response"),
+    }
+    match CounterRequest::AddPair(1, 2).dispatch(&mut counter) {
+        CounterResponse::AddPair(Some(6)) => (),
+        _ => panic!("Expected an AddPair response"),
+    }
+    match CounterRequest::CurrentValue.dispatch(&mut counter) {
+        CounterResponse::CurrentValue(6) => (),
+        _ => panic!("Expected a CurrentValue response"),
+    }
+    match CounterRequest::Reset.dispatch(&mut counter) {
+        CounterResponse::Reset(()) => (),
+        _ => panic!("Expected a Reset response"),
+    }
+    assert_eq!(0, counter.value);
+}
+
+#[test]
+fn enums_take_the_attribute_arguments() {
+    assert_eq!(CounterRequest::AddPair(1, 2), CounterRequest::AddPair(1, 2));
+    assert_eq!("Add(5)", format!("{:?}", CounterResponse::Add(5)));
+}
+
+#[test]
+fn client_returns_the_matching_response() {
+    let counter = RefCell::new(SimpleCounter::default());
+    let client = CounterClient::new(Caller {
+        handler: Box::new(move |request: &CounterRequest| {
+            let request = match request {
+                CounterRequest::Add(amount) => CounterRequest::Add(*amount),
+                CounterRequest::CurrentValue => CounterRequest::CurrentValue,
+                CounterRequest::Reset => CounterRequest::Reset,
+                CounterRequest::AddPair(a, b) => CounterRequest::AddPair(*a, *b),
+            };
+            request.dispatch(&mut *counter.borrow_mut())
+        }),
+        _role: Default::default(),
+    });
+
+    assert_eq!(Ok(2), client.add(2));
+    assert_eq!(Ok(Some(7)), client.add_pair(2, 3));
+    assert_eq!(Ok(None), client.add_pair(u32::MAX, 1));
+    assert_eq!(Ok(7), client.current_value());
+    assert_eq!(Ok(()), client.reset());
+    assert_eq!(Ok(0), client.current_value());
+}
+
+#[test]
+fn client_rejects_a_mismatched_response() {
+    let client: CounterClient<role::Local> = CounterClient::new(Caller {
+        handler: Box::new(|_: &CounterRequest| CounterResponse::Reset(())),
+        _role: Default::default(),
+    });
+    assert_eq!(Err(IPCError::UnexpectedResponse), client.add(1));
+}
+
+#[test]
+fn server_serves_requests_with_state() {
+    let server: CounterServer<role::Local> = CounterServer::new(Responder {
+        requests: RefCell::new(vec![
+            CounterRequest::Add(4),
+            CounterRequest::AddPair(1, 1),
+            CounterRequest::CurrentValue,
+        ]),
+        responses: RefCell::new(Vec::new()),
+        _role: Default::default(),
+    });
+    match server.serve(SimpleCounter::default()) {
+        Ok(CounterResponse::CurrentValue(6)) => (),
+        _ => panic!("Expected the final response to carry the accumulated value"),
+    }
+}
+
+#[test]
+fn server_serves_a_single_request() {
+    let server: CounterServer<role::Local> = CounterServer::new(Responder {
+        requests: RefCell::new(vec![CounterRequest::Add(5), CounterRequest::Reset]),
+        responses: RefCell::new(Vec::new()),
+        _role: Default::default(),
+    });
+    let mut counter = SimpleCounter::default();
+
+    server.serve_once(&mut counter).unwrap();
+    assert_eq!(5, counter.value);
+    server.serve_once(&mut counter).unwrap();
+    assert_eq!(0, counter.value);
+
+    let responder = server.into_responder();
+    let responses = responder.responses.into_inner();
+    match responses.as_slice() {
+        [CounterResponse::Add(5), CounterResponse::Reset(())] => (),
+        _ => panic!("Expected an Add response followed by a Reset response"),
+    }
+}
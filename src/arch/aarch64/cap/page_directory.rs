@@ -7,7 +7,7 @@ use crate::error::{ErrorExt, KernelError, SeL4Error};
 use crate::userland::CapRights;
 use crate::vspace::{MappingError, Maps};
 
-use super::super::{PageIndexBits, PagingRoot};
+use super::super::{PageBits, PageIndexBits, PageTableIndexBits, PagingRoot};
 
 const PD_MASK: usize = !((1 << PageIndexBits::USIZE) - 1);
 
@@ -15,6 +15,8 @@ const PD_MASK: usize = !((1 << PageIndexBits::USIZE) - 1);
 pub struct PageDirectory {}
 
 impl Maps<PageTable> for PageDirectory {
+    const GRANULE_SIZE_BITS: usize = PageBits::USIZE + PageTableIndexBits::USIZE;
+
     fn map_granule(
         &mut self,
         table: &LocalCap<PageTable>,
@@ -7,7 +7,10 @@ use crate::error::{ErrorExt, SeL4Error};
 use crate::userland::CapRights;
 use crate::vspace::{MappingError, Maps};
 
-use super::super::{PageDirIndexBits, PageIndexBits, PageTableIndexBits, PagingRoot};
+use super::super::{
+    PageBits, PageDirIndexBits, PageIndexBits, PageTableIndexBits, PageUpperDirIndexBits,
+    PagingRoot,
+};
 use super::PageUpperDirectory;
 
 const GD_MASK: usize =
@@ -17,6 +20,11 @@ const GD_MASK: usize =
 pub struct PageGlobalDirectory {}
 
 impl Maps<PageUpperDirectory> for PageGlobalDirectory {
+    const GRANULE_SIZE_BITS: usize = PageBits::USIZE
+        + PageTableIndexBits::USIZE
+        + PageDirIndexBits::USIZE
+        + PageUpperDirIndexBits::USIZE;
+
     fn map_granule(
         &mut self,
         upper_dir: &LocalCap<PageUpperDirectory>,
@@ -7,7 +7,7 @@ use crate::error::{ErrorExt, KernelError, SeL4Error};
 use crate::userland::CapRights;
 use crate::vspace::{MappingError, Maps};
 
-use super::super::{PageIndexBits, PageTableIndexBits, PagingRoot};
+use super::super::{PageBits, PageDirIndexBits, PageIndexBits, PageTableIndexBits, PagingRoot};
 use super::PageDirectory;
 
 const UD_MASK: usize = !((1 << (PageIndexBits::USIZE + PageTableIndexBits::USIZE)) - 1);
@@ -16,6 +16,9 @@ const UD_MASK: usize = !((1 << (PageIndexBits::USIZE + PageTableIndexBits::USIZE
 pub struct PageUpperDirectory {}
 
 impl Maps<PageDirectory> for PageUpperDirectory {
+    const GRANULE_SIZE_BITS: usize =
+        PageBits::USIZE + PageTableIndexBits::USIZE + PageDirIndexBits::USIZE;
+
     fn map_granule(
         &mut self,
         dir: &LocalCap<PageDirectory>,
@@ -1,5 +1,9 @@
+use crate::arch::fault_status::{abort_status_from_iss, AbortStatus, ExceptionSyndrome};
 use crate::cap::Badge;
+#[cfg(HardwareDebugAPI)]
+use crate::cap::BreakpointType;
 use crate::userland::MessageInfo;
+use core::fmt;
 use sel_claw::*;
 
 #[derive(Debug)]
@@ -66,6 +70,31 @@ pub struct VCPUFault {
     pub hyp_syndrome_register: usize,
 }
 
+/// A breakpoint, watchpoint or single step set through the thread's
+/// `ThreadControlBlock`, or a breakpoint instruction in its code.
+#[cfg(HardwareDebugAPI)]
+#[derive(Debug)]
+pub struct DebugException {
+    pub sender: Badge,
+    pub program_counter: usize,
+    pub reason: BreakpointType,
+    /// The data address which triggered a watchpoint
+    pub trigger_address: usize,
+    pub breakpoint_number: usize,
+}
+
+/// A thread used up the budget of its `SchedContext` while it had a
+/// timeout fault handler.
+#[cfg(KernelIsMCS)]
+#[derive(Debug)]
+pub struct TimeoutFault {
+    pub sender: Badge,
+    /// The badge the scheduling context was configured with
+    pub data: usize,
+    /// Microseconds of budget consumed since this was last reported
+    pub consumed: u64,
+}
+
 #[derive(Debug)]
 pub enum Fault {
     VMFault(VMFault),
@@ -78,6 +107,10 @@ pub enum Fault {
     VGICMaintenanceFault(VGICMaintenanceFault),
     #[cfg(KernelArmHypervisorSupport)]
     VCPUFault(VCPUFault),
+    #[cfg(HardwareDebugAPI)]
+    DebugException(DebugException),
+    #[cfg(KernelIsMCS)]
+    Timeout(TimeoutFault),
 }
 
 impl Fault {
@@ -93,6 +126,97 @@ impl Fault {
             Fault::VGICMaintenanceFault(f) => f.sender,
             #[cfg(KernelArmHypervisorSupport)]
             Fault::VCPUFault(f) => f.sender,
+            #[cfg(HardwareDebugAPI)]
+            Fault::DebugException(f) => f.sender,
+            #[cfg(KernelIsMCS)]
+            Fault::Timeout(f) => f.sender,
+        }
+    }
+}
+
+impl VMFault {
+    /// On AArch64 the kernel reports the ESR as the fault status
+    pub fn syndrome(&self) -> ExceptionSyndrome {
+        ExceptionSyndrome::new(self.fault_status_register)
+    }
+
+    pub fn status(&self) -> AbortStatus {
+        abort_status_from_iss(self.syndrome().iss, self.is_instruction_fault)
+    }
+}
+
+impl fmt::Display for VMFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at {:#x} (pc {:#x})",
+            self.status(),
+            self.address,
+            self.program_counter
+        )
+    }
+}
+
+impl UserException {
+    /// On AArch64 the kernel reports the ESR as the exception number
+    pub fn syndrome(&self) -> ExceptionSyndrome {
+        ExceptionSyndrome::new(self.number)
+    }
+}
+
+impl fmt::Display for UserException {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (pc {:#x})", self.syndrome(), self.program_counter)
+    }
+}
+
+#[cfg(KernelArmHypervisorSupport)]
+impl VCPUFault {
+    pub fn syndrome(&self) -> ExceptionSyndrome {
+        ExceptionSyndrome::new(self.hyp_syndrome_register)
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::VMFault(fault) => write!(f, "VM fault: {}", fault),
+            Fault::UnknownSyscall(fault) => write!(
+                f,
+                "unknown syscall {} (pc {:#x})",
+                fault.syscall, fault.program_counter
+            ),
+            Fault::UserException(fault) => write!(f, "user exception: {}", fault),
+            Fault::NullFault(_) => f.write_str("null fault"),
+            Fault::CapFault(fault) => write!(
+                f,
+                "cap fault at {:#x}{}",
+                fault.cap_address,
+                if fault.in_receive_phase {
+                    " in receive phase"
+                } else {
+                    ""
+                }
+            ),
+            Fault::UnidentifiedFault(_) => f.write_str("unidentified fault"),
+            #[cfg(KernelArmHypervisorSupport)]
+            Fault::VGICMaintenanceFault(fault) => {
+                write!(f, "VGIC maintenance fault, index {}", fault.index)
+            }
+            #[cfg(KernelArmHypervisorSupport)]
+            Fault::VCPUFault(fault) => write!(f, "VCPU fault: {}", fault.syndrome()),
+            #[cfg(HardwareDebugAPI)]
+            Fault::DebugException(fault) => write!(
+                f,
+                "debug exception: {:?}, breakpoint {} (pc {:#x})",
+                fault.reason, fault.breakpoint_number, fault.program_counter
+            ),
+            #[cfg(KernelIsMCS)]
+            Fault::Timeout(fault) => write!(
+                f,
+                "timeout fault, {}us consumed (data {:#x})",
+                fault.consumed, fault.data
+            ),
         }
     }
 }
@@ -110,6 +234,10 @@ impl From<(MessageInfo, Badge)> for Fault {
         const VGIC_MAINTENANCE_FAULT: usize = seL4_Fault_tag_seL4_Fault_VGICMaintenance as usize;
         #[cfg(KernelArmHypervisorSupport)]
         const VCPU_FAULT: usize = seL4_Fault_tag_seL4_Fault_VCPUFault as usize;
+        #[cfg(HardwareDebugAPI)]
+        const DEBUG_EXCEPTION: usize = seL4_Fault_tag_seL4_Fault_DebugException as usize;
+        #[cfg(KernelIsMCS)]
+        const TIMEOUT: usize = seL4_Fault_tag_seL4_Fault_Timeout as usize;
         match info.label() {
             NULL_FAULT => Fault::NullFault(NullFault { sender }),
             VM_FAULT => Fault::VMFault(VMFault {
@@ -158,6 +286,25 @@ impl From<(MessageInfo, Badge)> for Fault {
                 sender,
                 hyp_syndrome_register: buffer.msg[seL4_VCPUFault_HSR as usize],
             }),
+            #[cfg(HardwareDebugAPI)]
+            DEBUG_EXCEPTION => match BreakpointType::from_sel4(
+                buffer.msg[seL4_DebugException_Msg_seL4_DebugException_ExceptionReason as usize],
+            ) {
+                Some(reason) => Fault::DebugException(DebugException {
+                    sender,
+                    program_counter: buffer.msg[seL4_DebugException_Msg_seL4_DebugException_FaultIP as usize],
+                    reason,
+                    trigger_address: buffer.msg[seL4_DebugException_Msg_seL4_DebugException_TriggerAddress as usize],
+                    breakpoint_number: buffer.msg[seL4_DebugException_Msg_seL4_DebugException_BreakpointNumber as usize],
+                }),
+                None => Fault::UnidentifiedFault(UnidentifiedFault { sender }),
+            },
+            #[cfg(KernelIsMCS)]
+            TIMEOUT => Fault::Timeout(TimeoutFault {
+                sender,
+                data: buffer.msg[seL4_Timeout_Msg_seL4_Timeout_Data as usize],
+                consumed: buffer.msg[seL4_Timeout_Msg_seL4_Timeout_Consumed as usize] as u64,
+            }),
             _ => Fault::UnidentifiedFault(UnidentifiedFault { sender }),
         }
     }
@@ -30,6 +30,8 @@ pub type ASIDPoolCount = op!(U1 << ASIDHighBits);
 pub type ASIDPoolSize = op!(U1 << ASIDLowBits);
 pub type TCBBits = U11;
 pub type NotificationBits = U5;
+// Big enough for a scheduling context with no extra refills
+pub type MinSchedContextBits = U8;
 
 // The paging structures are layed out as follows:
 // L0: PageGlobalDirectory
@@ -149,8 +151,8 @@ pub mod vm_attributes {
     pub const PROGRAM_DATA: VMAttributes = PAGE_CACHEABLE | PARITY_ENABLED | EXECUTE_NEVER;
 }
 
-pub(crate) unsafe fn flush_page(cptr: usize) -> Result<(), SeL4Error> {
-    sel_claw::seL4_ARM_Page_CleanInvalidate_Data(cptr, 0x0000, PageBytes::USIZE)
+pub(crate) unsafe fn flush_page<GranuleBits: Unsigned>(cptr: usize) -> Result<(), SeL4Error> {
+    sel_claw::seL4_ARM_Page_CleanInvalidate_Data(cptr, 0x0000, 1 << GranuleBits::USIZE)
         .as_result()
         .map_err(SeL4Error::PageCleanInvalidateData)?;
 
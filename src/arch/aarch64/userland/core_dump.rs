@@ -0,0 +1,49 @@
+use sel_claw::*;
+
+/// EM_AARCH64
+pub(crate) const ELF_MACHINE: u16 = 183;
+
+/// The number of registers in an AArch64 `elf_gregset_t`
+pub(crate) const ELF_NGREG: usize = 34;
+
+/// Lay the registers out as a Linux `elf_gregset_t` (i.e. `struct
+/// user_pt_regs`), which is where debuggers look for them in a core
+/// file's NT_PRSTATUS note.
+pub(crate) fn elf_gregset(registers: &seL4_UserContext) -> [usize; ELF_NGREG] {
+    [
+        registers.x0,
+        registers.x1,
+        registers.x2,
+        registers.x3,
+        registers.x4,
+        registers.x5,
+        registers.x6,
+        registers.x7,
+        registers.x8,
+        registers.x9,
+        registers.x10,
+        registers.x11,
+        registers.x12,
+        registers.x13,
+        registers.x14,
+        registers.x15,
+        registers.x16,
+        registers.x17,
+        registers.x18,
+        registers.x19,
+        registers.x20,
+        registers.x21,
+        registers.x22,
+        registers.x23,
+        registers.x24,
+        registers.x25,
+        registers.x26,
+        registers.x27,
+        registers.x28,
+        registers.x29,
+        registers.x30,
+        registers.sp,
+        registers.pc,
+        registers.spsr,
+    ]
+}
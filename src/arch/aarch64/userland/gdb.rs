@@ -0,0 +1,149 @@
+use arrayvec::ArrayVec;
+use sel_claw::*;
+
+/// The core registers, in the order GDB numbers them for AArch64
+pub(crate) const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>aarch64</architecture>
+  <feature name="org.gnu.gdb.aarch64.core">
+    <reg name="x0" bitsize="64"/>
+    <reg name="x1" bitsize="64"/>
+    <reg name="x2" bitsize="64"/>
+    <reg name="x3" bitsize="64"/>
+    <reg name="x4" bitsize="64"/>
+    <reg name="x5" bitsize="64"/>
+    <reg name="x6" bitsize="64"/>
+    <reg name="x7" bitsize="64"/>
+    <reg name="x8" bitsize="64"/>
+    <reg name="x9" bitsize="64"/>
+    <reg name="x10" bitsize="64"/>
+    <reg name="x11" bitsize="64"/>
+    <reg name="x12" bitsize="64"/>
+    <reg name="x13" bitsize="64"/>
+    <reg name="x14" bitsize="64"/>
+    <reg name="x15" bitsize="64"/>
+    <reg name="x16" bitsize="64"/>
+    <reg name="x17" bitsize="64"/>
+    <reg name="x18" bitsize="64"/>
+    <reg name="x19" bitsize="64"/>
+    <reg name="x20" bitsize="64"/>
+    <reg name="x21" bitsize="64"/>
+    <reg name="x22" bitsize="64"/>
+    <reg name="x23" bitsize="64"/>
+    <reg name="x24" bitsize="64"/>
+    <reg name="x25" bitsize="64"/>
+    <reg name="x26" bitsize="64"/>
+    <reg name="x27" bitsize="64"/>
+    <reg name="x28" bitsize="64"/>
+    <reg name="x29" bitsize="64"/>
+    <reg name="x30" bitsize="64"/>
+    <reg name="sp" bitsize="64" type="data_ptr"/>
+    <reg name="pc" bitsize="64" type="code_ptr"/>
+    <reg name="cpsr" bitsize="32"/>
+  </feature>
+</target>
+"#;
+
+/// x0-x30, sp, pc and cpsr
+pub(crate) const GDB_REGISTER_COUNT: usize = 34;
+
+/// `brk #0`, which the thread faults on. Its length is the breakpoint
+/// kind GDB asks for.
+pub(crate) const SOFTWARE_BREAKPOINT: [u8; 4] = [0x00, 0x00, 0x20, 0xd4];
+
+/// The size in bytes of one of GDB's registers. Only cpsr is narrower
+/// than a word.
+pub(crate) fn gdb_register_size(number: usize) -> usize {
+    if number == 33 {
+        4
+    } else {
+        8
+    }
+}
+
+/// The register GDB knows by `number`, if there is one.
+pub(crate) fn gdb_register(registers: &mut seL4_UserContext, number: usize) -> Option<&mut usize> {
+    Some(match number {
+        0 => &mut registers.x0,
+        1 => &mut registers.x1,
+        2 => &mut registers.x2,
+        3 => &mut registers.x3,
+        4 => &mut registers.x4,
+        5 => &mut registers.x5,
+        6 => &mut registers.x6,
+        7 => &mut registers.x7,
+        8 => &mut registers.x8,
+        9 => &mut registers.x9,
+        10 => &mut registers.x10,
+        11 => &mut registers.x11,
+        12 => &mut registers.x12,
+        13 => &mut registers.x13,
+        14 => &mut registers.x14,
+        15 => &mut registers.x15,
+        16 => &mut registers.x16,
+        17 => &mut registers.x17,
+        18 => &mut registers.x18,
+        19 => &mut registers.x19,
+        20 => &mut registers.x20,
+        21 => &mut registers.x21,
+        22 => &mut registers.x22,
+        23 => &mut registers.x23,
+        24 => &mut registers.x24,
+        25 => &mut registers.x25,
+        26 => &mut registers.x26,
+        27 => &mut registers.x27,
+        28 => &mut registers.x28,
+        29 => &mut registers.x29,
+        30 => &mut registers.x30,
+        31 => &mut registers.sp,
+        32 => &mut registers.pc,
+        33 => &mut registers.spsr,
+        _ => return None,
+    })
+}
+
+/// Where the thread can go after running `instruction`, the one at its
+/// program counter, so that it can be stepped over by planting
+/// breakpoints there. `None` if that can't be told from the instruction.
+// Only needed without the debug registers, which can step by themselves
+#[cfg_attr(HardwareDebugAPI, allow(dead_code))]
+pub(crate) fn next_instructions(
+    instruction: u32,
+    registers: &seL4_UserContext,
+) -> Option<ArrayVec<[usize; 2]>> {
+    let pc = registers.pc;
+    // The offset in the `bits` bit field at `shift`, in instructions
+    let target = |bits: u32, shift: u32| {
+        let field = ((instruction >> shift) << (32 - bits)) as i32 >> (32 - bits);
+        pc.wrapping_add((field as isize * 4) as usize)
+    };
+    let mut next = ArrayVec::new();
+    if instruction & 0x7c00_0000 == 0x1400_0000 {
+        // b, bl
+        next.push(target(26, 0));
+    } else if instruction & 0xff00_0010 == 0x5400_0000 || instruction & 0x7e00_0000 == 0x3400_0000 {
+        // b.cond, cbz, cbnz
+        next.push(pc + 4);
+        next.push(target(19, 5));
+    } else if instruction & 0x7e00_0000 == 0x3600_0000 {
+        // tbz, tbnz
+        next.push(pc + 4);
+        next.push(target(14, 5));
+    } else if instruction & 0xff9f_fc1f == 0xd61f_0000 {
+        // br, blr, ret
+        let mut registers = *registers;
+        let register = (instruction >> 5) & 0x1f;
+        if register == 31 {
+            return None;
+        }
+        next.push(*gdb_register(&mut registers, register as usize)?);
+    } else if instruction & 0xfe00_0000 == 0xd600_0000 {
+        // The other branches to a register, e.g. with pointer
+        // authentication
+        return None;
+    } else {
+        next.push(pc + 4);
+    }
+    Some(next)
+}
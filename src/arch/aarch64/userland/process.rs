@@ -1,6 +1,8 @@
 use core::mem;
 use core::ptr;
 
+use crate::userland::FrameStart;
+
 use sel_claw::*;
 
 /// Set up the target registers and stack to pass the parameter.
@@ -90,6 +92,15 @@ pub(crate) fn set_thread_link_register(
     registers.x30 = (post_return_fn as *const fn() -> !) as usize;
 }
 
+impl<'a> From<&'a seL4_UserContext> for FrameStart {
+    fn from(registers: &'a seL4_UserContext) -> Self {
+        FrameStart {
+            program_counter: registers.pc,
+            frame_pointer: registers.x29,
+        }
+    }
+}
+
 #[doc(hidden)]
 #[allow(dead_code)]
 #[cfg(feature = "test_support")]
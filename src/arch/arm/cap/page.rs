@@ -2,10 +2,13 @@ use selfe_sys::*;
 
 use crate::cap::{page_state, DirectRetype, LocalCap, Page, PageState, PhantomCap};
 use crate::error::{ErrorExt, SeL4Error};
-use crate::typenum::Unsigned;
+use crate::typenum::{GrEq, IsGreaterOrEqual, Unsigned};
 use crate::userland::CapRights;
+use crate::vspace::{LargestGranule, PickGranule};
 
-impl<T: PageState> LocalCap<Page<T>> {
+use super::super::{LargePageBits, PageBits, SectionBits, SuperSectionBits};
+
+impl<T: PageState, GranuleBits: Unsigned> LocalCap<Page<T, GranuleBits>> {
     pub(crate) fn paddr(&self) -> Result<usize, SeL4Error> {
         let res = unsafe { seL4_ARM_Page_GetAddress(self.cptr) };
         match (res.error as seL4_Error).as_result() {
@@ -15,7 +18,7 @@ impl<T: PageState> LocalCap<Page<T>> {
     }
 }
 
-impl LocalCap<Page<page_state::Unmapped>> {
+impl<GranuleBits: Unsigned> LocalCap<Page<page_state::Unmapped, GranuleBits>> {
     pub(crate) unsafe fn unchecked_page_map(
         &self,
         addr: usize,
@@ -35,17 +38,53 @@ impl LocalCap<Page<page_state::Unmapped>> {
     }
 }
 
-impl LocalCap<Page<page_state::Mapped>> {
+impl<GranuleBits: Unsigned> LocalCap<Page<page_state::Mapped, GranuleBits>> {
+    /// Map this page again at the same address with new rights and
+    /// attributes. Whatever was written through a writable mapping is
+    /// cleaned out to memory first, and the instruction cache is brought
+    /// up to date with it, so the page can be executed afterwards.
+    pub(crate) fn remap(
+        &self,
+        root: &mut LocalCap<crate::arch::PagingRoot>,
+        rights: CapRights,
+        vm_attributes: seL4_ARM_VMAttributes,
+    ) -> Result<(), SeL4Error> {
+        if self.rights().is_writable() {
+            self.unify_instructions()?;
+        }
+
+        unsafe {
+            seL4_ARM_Page_Map(
+                self.cptr,
+                root.cptr,
+                self.vaddr(),
+                seL4_CapRights_t::from(rights),
+                vm_attributes,
+            )
+        }
+        .as_result()
+        .map_err(SeL4Error::PageMap)
+    }
+
+    /// Clean whatever was written to the page out to memory, and bring
+    /// the instruction cache up to date with it.
+    pub(crate) fn unify_instructions(&self) -> Result<(), SeL4Error> {
+        unsafe { seL4_ARM_Page_CleanInvalidate_Data(self.cptr, 0x0000, 1 << GranuleBits::USIZE) }
+            .as_result()
+            .map_err(SeL4Error::PageCleanInvalidateData)?;
+        unsafe { seL4_ARM_Page_Unify_Instruction(self.cptr, 0x0000, 1 << GranuleBits::USIZE) }
+            .as_result()
+            .map_err(SeL4Error::PageUnifyInstruction)
+    }
+
     /// Keeping this non-public in order to restrict mapping operations to
     /// owners of a VSpace-related object
-    pub(crate) fn unmap(self) -> Result<LocalCap<Page<page_state::Unmapped>>, SeL4Error> {
+    pub(crate) fn unmap(
+        self,
+    ) -> Result<LocalCap<Page<page_state::Unmapped, GranuleBits>>, SeL4Error> {
         if self.rights().is_writable() {
             unsafe {
-                seL4_ARM_Page_CleanInvalidate_Data(
-                    self.cptr,
-                    0x0000,
-                    super::super::PageBytes::USIZE,
-                )
+                seL4_ARM_Page_CleanInvalidate_Data(self.cptr, 0x0000, 1 << GranuleBits::USIZE)
             }
             .as_result()
             .map_err(SeL4Error::PageCleanInvalidateData)?;
@@ -56,6 +95,7 @@ impl LocalCap<Page<page_state::Mapped>> {
                 cptr: self.cptr,
                 cap_data: Page {
                     state: page_state::Unmapped {},
+                    _granule: core::marker::PhantomData,
                 },
                 _role: core::marker::PhantomData,
             }),
@@ -71,10 +111,58 @@ impl DirectRetype for Page<page_state::Unmapped> {
     }
 }
 
-impl PhantomCap for Page<page_state::Unmapped> {
+impl DirectRetype for Page<page_state::Unmapped, super::super::LargePageBits> {
+    type SizeBits = super::super::LargePageBits;
+    fn sel4_type_id() -> usize {
+        _object_seL4_ARM_LargePageObject as usize
+    }
+}
+
+impl DirectRetype for Page<page_state::Unmapped, super::super::SectionBits> {
+    type SizeBits = super::super::SectionBits;
+    fn sel4_type_id() -> usize {
+        _object_seL4_ARM_SectionObject as usize
+    }
+}
+
+impl DirectRetype for Page<page_state::Unmapped, super::super::SuperSectionBits> {
+    type SizeBits = super::super::SuperSectionBits;
+    fn sel4_type_id() -> usize {
+        _object_seL4_ARM_SuperSectionObject as usize
+    }
+}
+
+type LargeOrSmallPageBits<SizeBits> =
+    <GrEq<SizeBits, LargePageBits> as PickGranule<LargePageBits, PageBits>>::Output;
+type SectionOrPageBits<SizeBits> = <GrEq<SizeBits, SectionBits> as PickGranule<
+    SectionBits,
+    LargeOrSmallPageBits<SizeBits>,
+>>::Output;
+type LargestFrameBits<SizeBits> = <GrEq<SizeBits, SuperSectionBits> as PickGranule<
+    SuperSectionBits,
+    SectionOrPageBits<SizeBits>,
+>>::Output;
+
+/// Supersections where they fit, then sections, then large pages, then
+/// pages.
+impl<SizeBits: Unsigned> LargestGranule for SizeBits
+where
+    SizeBits: IsGreaterOrEqual<LargePageBits>,
+    SizeBits: IsGreaterOrEqual<SectionBits>,
+    SizeBits: IsGreaterOrEqual<SuperSectionBits>,
+    GrEq<SizeBits, LargePageBits>: PickGranule<LargePageBits, PageBits>,
+    GrEq<SizeBits, SectionBits>: PickGranule<SectionBits, LargeOrSmallPageBits<SizeBits>>,
+    GrEq<SizeBits, SuperSectionBits>: PickGranule<SuperSectionBits, SectionOrPageBits<SizeBits>>,
+    LargestFrameBits<SizeBits>: Unsigned,
+{
+    type Bits = LargestFrameBits<SizeBits>;
+}
+
+impl<GranuleBits: Unsigned> PhantomCap for Page<page_state::Unmapped, GranuleBits> {
     fn phantom_instance() -> Self {
         Page {
             state: page_state::Unmapped {},
+            _granule: core::marker::PhantomData,
         }
     }
 }
@@ -17,6 +17,8 @@ const PD_MASK: usize =
 pub struct PageDirectory {}
 
 impl Maps<PageTable> for PageDirectory {
+    const GRANULE_SIZE_BITS: usize = PageIndexBits::USIZE + PageTableIndexBits::USIZE;
+
     fn map_granule(
         &mut self,
         table: &LocalCap<PageTable>,
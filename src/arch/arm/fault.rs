@@ -1,5 +1,11 @@
+#[cfg(KernelArmHypervisorSupport)]
+use crate::arch::fault_status::{abort_status_from_iss, ExceptionSyndrome};
+use crate::arch::fault_status::{AbortStatus, Access, FaultKind};
 use crate::cap::Badge;
+#[cfg(HardwareDebugAPI)]
+use crate::cap::BreakpointType;
 use crate::userland::MessageInfo;
+use core::fmt;
 use selfe_sys::*;
 
 #[derive(Debug)]
@@ -66,6 +72,31 @@ pub struct VCPUFault {
     pub hyp_syndrome_register: usize,
 }
 
+/// A breakpoint, watchpoint or single step set through the thread's
+/// `ThreadControlBlock`, or a breakpoint instruction in its code.
+#[cfg(HardwareDebugAPI)]
+#[derive(Debug)]
+pub struct DebugException {
+    pub sender: Badge,
+    pub program_counter: usize,
+    pub reason: BreakpointType,
+    /// The data address which triggered a watchpoint
+    pub trigger_address: usize,
+    pub breakpoint_number: usize,
+}
+
+/// A thread used up the budget of its `SchedContext` while it had a
+/// timeout fault handler.
+#[cfg(KernelIsMCS)]
+#[derive(Debug)]
+pub struct TimeoutFault {
+    pub sender: Badge,
+    /// The badge the scheduling context was configured with
+    pub data: usize,
+    /// Microseconds of budget consumed since this was last reported
+    pub consumed: u64,
+}
+
 #[derive(Debug)]
 pub enum Fault {
     VMFault(VMFault),
@@ -78,6 +109,10 @@ pub enum Fault {
     VGICMaintenanceFault(VGICMaintenanceFault),
     #[cfg(KernelArmHypervisorSupport)]
     VCPUFault(VCPUFault),
+    #[cfg(HardwareDebugAPI)]
+    DebugException(DebugException),
+    #[cfg(KernelIsMCS)]
+    Timeout(TimeoutFault),
 }
 
 impl Fault {
@@ -93,6 +128,152 @@ impl Fault {
             Fault::VGICMaintenanceFault(f) => f.sender,
             #[cfg(KernelArmHypervisorSupport)]
             Fault::VCPUFault(f) => f.sender,
+            #[cfg(HardwareDebugAPI)]
+            Fault::DebugException(f) => f.sender,
+            #[cfg(KernelIsMCS)]
+            Fault::Timeout(f) => f.sender,
+        }
+    }
+}
+
+impl VMFault {
+    /// With hypervisor support the kernel reports the syndrome bits of the
+    /// HSR as the fault status.
+    #[cfg(KernelArmHypervisorSupport)]
+    pub fn status(&self) -> AbortStatus {
+        abort_status_from_iss(
+            ExceptionSyndrome::new(self.fault_status_register).iss,
+            self.is_instruction_fault,
+        )
+    }
+
+    /// Otherwise it's the DFSR, or the IFSR for an instruction fault.
+    #[cfg(not(KernelArmHypervisorSupport))]
+    pub fn status(&self) -> AbortStatus {
+        let fsr = self.fault_status_register;
+        let access = if self.is_instruction_fault {
+            Access::InstructionFetch
+        } else if (fsr >> 13) & 1 == 1 {
+            Access::CacheMaintenance
+        } else if (fsr >> 11) & 1 == 1 {
+            Access::Write
+        } else {
+            Access::Read
+        };
+
+        // The LPAE bit says the long-descriptor translation table format
+        // is in use, which moves the status code to bits [5:0].
+        if (fsr >> 9) & 1 == 1 {
+            return AbortStatus::from_long_descriptor_code((fsr & 0b11_1111) as u8, access);
+        }
+        short_descriptor_status((((fsr >> 6) & 0b1_0000) | (fsr & 0b1111)) as u8, access)
+    }
+}
+
+/// Decode the 5-bit FS field of a DFSR or IFSR in the short-descriptor
+/// format. FS[4] is bit 10 of the register and FS[3:0] are bits [3:0].
+#[cfg(not(KernelArmHypervisorSupport))]
+fn short_descriptor_status(fs: u8, access: Access) -> AbortStatus {
+    let (kind, level, on_table_walk) = match fs {
+        0b00001 => (FaultKind::Alignment, None, false),
+        0b00010 => (FaultKind::Debug, None, false),
+        0b00011 => (FaultKind::AccessFlag, Some(1), false),
+        0b00100 => (FaultKind::InstructionCacheMaintenance, None, false),
+        0b00101 => (FaultKind::Translation, Some(1), false),
+        0b00110 => (FaultKind::AccessFlag, Some(2), false),
+        0b00111 => (FaultKind::Translation, Some(2), false),
+        0b01000 => (FaultKind::SynchronousExternal, None, false),
+        0b01001 => (FaultKind::Domain, Some(1), false),
+        0b01011 => (FaultKind::Domain, Some(2), false),
+        0b01100 => (FaultKind::SynchronousExternal, Some(1), true),
+        0b01101 => (FaultKind::Permission, Some(1), false),
+        0b01110 => (FaultKind::SynchronousExternal, Some(2), true),
+        0b01111 => (FaultKind::Permission, Some(2), false),
+        0b10000 => (FaultKind::TLBConflict, None, false),
+        0b10100 => (FaultKind::Lockdown, None, false),
+        0b10110 => (FaultKind::AsynchronousExternal, None, false),
+        0b11000 => (FaultKind::AsynchronousParity, None, false),
+        0b11001 => (FaultKind::SynchronousParity, None, false),
+        0b11010 => (FaultKind::CoprocessorAbort, None, false),
+        0b11100 => (FaultKind::SynchronousParity, Some(1), true),
+        0b11110 => (FaultKind::SynchronousParity, Some(2), true),
+        other => (FaultKind::Unknown(other), None, false),
+    };
+    AbortStatus {
+        kind,
+        level,
+        on_table_walk,
+        access,
+    }
+}
+
+impl fmt::Display for VMFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at {:#x} (pc {:#x})",
+            self.status(),
+            self.address,
+            self.program_counter
+        )
+    }
+}
+
+// The kernel only raises user exceptions for undefined instructions on
+// 32-bit ARM, and always reports them with number and code 0.
+impl fmt::Display for UserException {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "undefined instruction (pc {:#x})", self.program_counter)
+    }
+}
+
+#[cfg(KernelArmHypervisorSupport)]
+impl VCPUFault {
+    pub fn syndrome(&self) -> ExceptionSyndrome {
+        ExceptionSyndrome::new(self.hyp_syndrome_register)
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::VMFault(fault) => write!(f, "VM fault: {}", fault),
+            Fault::UnknownSyscall(fault) => write!(
+                f,
+                "unknown syscall {} (pc {:#x})",
+                fault.syscall, fault.program_counter
+            ),
+            Fault::UserException(fault) => write!(f, "user exception: {}", fault),
+            Fault::NullFault(_) => f.write_str("null fault"),
+            Fault::CapFault(fault) => write!(
+                f,
+                "cap fault at {:#x}{}",
+                fault.cap_address,
+                if fault.in_receive_phase {
+                    " in receive phase"
+                } else {
+                    ""
+                }
+            ),
+            Fault::UnidentifiedFault(_) => f.write_str("unidentified fault"),
+            #[cfg(KernelArmHypervisorSupport)]
+            Fault::VGICMaintenanceFault(fault) => {
+                write!(f, "VGIC maintenance fault, index {}", fault.index)
+            }
+            #[cfg(KernelArmHypervisorSupport)]
+            Fault::VCPUFault(fault) => write!(f, "VCPU fault: {}", fault.syndrome()),
+            #[cfg(HardwareDebugAPI)]
+            Fault::DebugException(fault) => write!(
+                f,
+                "debug exception: {:?}, breakpoint {} (pc {:#x})",
+                fault.reason, fault.breakpoint_number, fault.program_counter
+            ),
+            #[cfg(KernelIsMCS)]
+            Fault::Timeout(fault) => write!(
+                f,
+                "timeout fault, {}us consumed (data {:#x})",
+                fault.consumed, fault.data
+            ),
         }
     }
 }
@@ -110,6 +291,10 @@ impl From<(MessageInfo, Badge)> for Fault {
         const VGIC_MAINTENANCE_FAULT: usize = seL4_Fault_tag_seL4_Fault_VGICMaintenance as usize;
         #[cfg(KernelArmHypervisorSupport)]
         const VCPU_FAULT: usize = seL4_Fault_tag_seL4_Fault_VCPUFault as usize;
+        #[cfg(HardwareDebugAPI)]
+        const DEBUG_EXCEPTION: usize = seL4_Fault_tag_seL4_Fault_DebugException as usize;
+        #[cfg(KernelIsMCS)]
+        const TIMEOUT: usize = seL4_Fault_tag_seL4_Fault_Timeout as usize;
         match info.label() {
             NULL_FAULT => Fault::NullFault(NullFault { sender }),
             VM_FAULT => Fault::VMFault(VMFault {
@@ -158,6 +343,26 @@ impl From<(MessageInfo, Badge)> for Fault {
                 sender,
                 hyp_syndrome_register: buffer.msg[seL4_VCPUFault_HSR as usize],
             }),
+            #[cfg(HardwareDebugAPI)]
+            DEBUG_EXCEPTION => match BreakpointType::from_sel4(
+                buffer.msg[seL4_DebugException_Msg_seL4_DebugException_ExceptionReason as usize],
+            ) {
+                Some(reason) => Fault::DebugException(DebugException {
+                    sender,
+                    program_counter: buffer.msg[seL4_DebugException_Msg_seL4_DebugException_FaultIP as usize],
+                    reason,
+                    trigger_address: buffer.msg[seL4_DebugException_Msg_seL4_DebugException_TriggerAddress as usize],
+                    breakpoint_number: buffer.msg[seL4_DebugException_Msg_seL4_DebugException_BreakpointNumber as usize],
+                }),
+                None => Fault::UnidentifiedFault(UnidentifiedFault { sender }),
+            },
+            #[cfg(KernelIsMCS)]
+            TIMEOUT => Fault::Timeout(TimeoutFault {
+                sender,
+                data: buffer.msg[seL4_Timeout_Msg_seL4_Timeout_Data as usize],
+                consumed: (buffer.msg[seL4_Timeout_Msg_seL4_Timeout_Consumed_HighBits as usize] as u64) << 32
+                    | buffer.msg[seL4_Timeout_Msg_seL4_Timeout_Consumed_LowBits as usize] as u64,
+            }),
             _ => Fault::UnidentifiedFault(UnidentifiedFault { sender }),
         }
     }
@@ -25,6 +25,8 @@ pub type ASIDPoolCount = op!((U1 << ASIDHighBits) - U1);
 pub type ASIDPoolSize = op!(U1 << ASIDLowBits);
 pub type TCBBits = U10;
 pub type NotificationBits = U4;
+// Big enough for a scheduling context with no extra refills
+pub type MinSchedContextBits = U8;
 
 #[cfg(KernelHypervisorSupport)]
 mod hyp_dependent_constants {
@@ -139,8 +141,8 @@ pub mod vm_attributes {
     pub const PROGRAM_DATA: VMAttributes = PAGE_CACHEABLE | PARITY_ENABLED | EXECUTE_NEVER;
 }
 
-pub(crate) unsafe fn flush_page(cptr: usize) -> Result<(), SeL4Error> {
-    selfe_sys::seL4_ARM_Page_CleanInvalidate_Data(cptr, 0x0000, PageBytes::USIZE)
+pub(crate) unsafe fn flush_page<GranuleBits: Unsigned>(cptr: usize) -> Result<(), SeL4Error> {
+    selfe_sys::seL4_ARM_Page_CleanInvalidate_Data(cptr, 0x0000, 1 << GranuleBits::USIZE)
         .as_result()
         .map_err(SeL4Error::PageCleanInvalidateData)?;
 
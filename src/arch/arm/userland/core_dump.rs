@@ -0,0 +1,34 @@
+use selfe_sys::*;
+
+/// EM_ARM
+pub(crate) const ELF_MACHINE: u16 = 40;
+
+/// The number of registers in an ARM `elf_gregset_t`
+pub(crate) const ELF_NGREG: usize = 18;
+
+/// Lay the registers out as a Linux `elf_gregset_t` (i.e. `struct
+/// pt_regs`), which is where debuggers look for them in a core file's
+/// NT_PRSTATUS note.
+pub(crate) fn elf_gregset(registers: &seL4_UserContext) -> [usize; ELF_NGREG] {
+    [
+        registers.r0,
+        registers.r1,
+        registers.r2,
+        registers.r3,
+        registers.r4,
+        registers.r5,
+        registers.r6,
+        registers.r7,
+        registers.r8,
+        registers.r9,
+        registers.r10,
+        registers.r11,
+        registers.r12,
+        registers.sp,
+        registers.r14,
+        registers.pc,
+        registers.cpsr,
+        // ORIG_r0, which only means anything to Linux's syscall restarting
+        registers.r0,
+    ]
+}
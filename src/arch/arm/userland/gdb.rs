@@ -0,0 +1,117 @@
+use arrayvec::ArrayVec;
+use selfe_sys::*;
+
+/// The core registers, in the order GDB numbers them for ARM
+pub(crate) const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>arm</architecture>
+  <feature name="org.gnu.gdb.arm.core">
+    <reg name="r0" bitsize="32"/>
+    <reg name="r1" bitsize="32"/>
+    <reg name="r2" bitsize="32"/>
+    <reg name="r3" bitsize="32"/>
+    <reg name="r4" bitsize="32"/>
+    <reg name="r5" bitsize="32"/>
+    <reg name="r6" bitsize="32"/>
+    <reg name="r7" bitsize="32"/>
+    <reg name="r8" bitsize="32"/>
+    <reg name="r9" bitsize="32"/>
+    <reg name="r10" bitsize="32"/>
+    <reg name="r11" bitsize="32"/>
+    <reg name="r12" bitsize="32"/>
+    <reg name="sp" bitsize="32" type="data_ptr"/>
+    <reg name="lr" bitsize="32"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+    <reg name="cpsr" bitsize="32"/>
+  </feature>
+</target>
+"#;
+
+/// r0-r12, sp, lr, pc and cpsr
+pub(crate) const GDB_REGISTER_COUNT: usize = 17;
+
+/// `bkpt #0` in ARM state, which the thread faults on. Its length is the breakpoint
+/// kind GDB asks for.
+pub(crate) const SOFTWARE_BREAKPOINT: [u8; 4] = [0x70, 0x00, 0x20, 0xe1];
+
+pub(crate) fn gdb_register_size(_number: usize) -> usize {
+    4
+}
+
+/// The register GDB knows by `number`, if there is one.
+pub(crate) fn gdb_register(registers: &mut seL4_UserContext, number: usize) -> Option<&mut usize> {
+    Some(match number {
+        0 => &mut registers.r0,
+        1 => &mut registers.r1,
+        2 => &mut registers.r2,
+        3 => &mut registers.r3,
+        4 => &mut registers.r4,
+        5 => &mut registers.r5,
+        6 => &mut registers.r6,
+        7 => &mut registers.r7,
+        8 => &mut registers.r8,
+        9 => &mut registers.r9,
+        10 => &mut registers.r10,
+        11 => &mut registers.r11,
+        12 => &mut registers.r12,
+        13 => &mut registers.sp,
+        14 => &mut registers.r14,
+        15 => &mut registers.pc,
+        16 => &mut registers.cpsr,
+        _ => return None,
+    })
+}
+
+/// Where the thread can go after running `instruction`, the one at its
+/// program counter, so that it can be stepped over by planting
+/// breakpoints there. `None` if that can't be told from the
+/// instruction, which includes anything in Thumb state.
+// Only needed without the debug registers, which can step by themselves
+#[cfg_attr(HardwareDebugAPI, allow(dead_code))]
+pub(crate) fn next_instructions(
+    instruction: u32,
+    registers: &seL4_UserContext,
+) -> Option<ArrayVec<[usize; 2]>> {
+    const CPSR_THUMB: usize = 1 << 5;
+    if registers.cpsr & CPSR_THUMB != 0 {
+        return None;
+    }
+    let pc = registers.pc;
+    let mut next = ArrayVec::new();
+    // A branch that's only taken on some condition may fall through
+    if instruction >> 28 != 0xe {
+        next.push(pc + 4);
+    }
+    if instruction >> 28 == 0xf {
+        // Unconditional instructions, of which only blx switches to
+        // Thumb state and the rest carry on with the next
+        if instruction & 0xfe00_0000 == 0xfa00_0000 {
+            return None;
+        }
+    } else if instruction & 0x0e00_0000 == 0x0a00_0000 {
+        // b, bl, whose offset is from two instructions on
+        let offset = ((instruction << 8) as i32 >> 8) as isize * 4;
+        next.push((pc + 8).wrapping_add(offset as usize));
+    } else if instruction & 0x0fff_ffd0 == 0x012f_ff10 {
+        // bx, blx
+        let mut registers = *registers;
+        let target = match instruction & 0xf {
+            15 => pc + 8,
+            register => *gdb_register(&mut registers, register as usize)?,
+        };
+        if target & 1 != 0 {
+            return None;
+        }
+        next.push(target);
+    } else if (instruction & 0x0800_0000 == 0 && (instruction >> 12) & 0xf == 15)
+        || instruction & 0x0e10_8000 == 0x0810_8000
+    {
+        // Something else that writes the pc, e.g. a `pop` or a `mov`
+        // into it
+        return None;
+    } else if instruction >> 28 == 0xe {
+        next.push(pc + 4);
+    }
+    Some(next)
+}
@@ -1 +1,3 @@
+pub(crate) mod core_dump;
+pub(crate) mod gdb;
 pub mod process;
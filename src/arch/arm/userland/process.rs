@@ -2,6 +2,8 @@ use core::cmp;
 use core::mem::{self, size_of};
 use core::ptr;
 
+use crate::userland::FrameStart;
+
 use selfe_sys::*;
 
 /// Set up the target registers and stack to pass the parameter. See
@@ -111,6 +113,15 @@ pub(crate) fn set_thread_link_register(
     registers.r14 = (post_return_fn as *const fn() -> !) as usize;
 }
 
+impl<'a> From<&'a seL4_UserContext> for FrameStart {
+    fn from(registers: &'a seL4_UserContext) -> Self {
+        FrameStart {
+            program_counter: registers.pc,
+            frame_pointer: registers.r11,
+        }
+    }
+}
+
 #[doc(hidden)]
 #[allow(dead_code)]
 #[cfg(feature = "test_support")]
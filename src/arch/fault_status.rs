@@ -0,0 +1,327 @@
+//! Decoding for the ARM fault status and exception syndrome registers that
+//! the kernel hands us as raw words in fault messages.
+//!
+//! Both the AArch64 ESR and the ARMv7 HSR use the exception syndrome
+//! layout decoded by `ExceptionSyndrome`. The fault status codes found in
+//! their abort syndromes use the same long-descriptor encoding as an
+//! ARMv7 DFSR/IFSR with LPAE enabled, so the code that turns them into an
+//! `AbortStatus` lives here for both architectures.
+use core::fmt;
+
+/// What kind of abort a fault status code describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    AddressSize,
+    Translation,
+    AccessFlag,
+    Permission,
+    Domain,
+    Alignment,
+    Debug,
+    SynchronousExternal,
+    SynchronousParity,
+    AsynchronousExternal,
+    AsynchronousParity,
+    TagCheck,
+    TLBConflict,
+    UnsupportedAtomicUpdate,
+    InstructionCacheMaintenance,
+    Lockdown,
+    CoprocessorAbort,
+    /// A fault status code we don't know how to interpret
+    Unknown(u8),
+}
+
+impl fmt::Display for FaultKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FaultKind::AddressSize => f.write_str("address size fault"),
+            FaultKind::Translation => f.write_str("translation fault"),
+            FaultKind::AccessFlag => f.write_str("access flag fault"),
+            FaultKind::Permission => f.write_str("permission fault"),
+            FaultKind::Domain => f.write_str("domain fault"),
+            FaultKind::Alignment => f.write_str("alignment fault"),
+            FaultKind::Debug => f.write_str("debug event"),
+            FaultKind::SynchronousExternal => f.write_str("synchronous external abort"),
+            FaultKind::SynchronousParity => f.write_str("synchronous parity error"),
+            FaultKind::AsynchronousExternal => f.write_str("asynchronous external abort"),
+            FaultKind::AsynchronousParity => f.write_str("asynchronous parity error"),
+            FaultKind::TagCheck => f.write_str("tag check fault"),
+            FaultKind::TLBConflict => f.write_str("TLB conflict abort"),
+            FaultKind::UnsupportedAtomicUpdate => {
+                f.write_str("unsupported atomic hardware update fault")
+            }
+            FaultKind::InstructionCacheMaintenance => {
+                f.write_str("instruction cache maintenance fault")
+            }
+            FaultKind::Lockdown => f.write_str("lockdown abort"),
+            FaultKind::CoprocessorAbort => f.write_str("coprocessor abort"),
+            FaultKind::Unknown(code) => write!(f, "unknown fault status {:#04x}", code),
+        }
+    }
+}
+
+/// The kind of access that was being made when an abort happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    InstructionFetch,
+    /// A cache maintenance or address translation instruction. The
+    /// hardware reports these as writes, so they get their own variant.
+    CacheMaintenance,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Access::Read => f.write_str("read"),
+            Access::Write => f.write_str("write"),
+            Access::InstructionFetch => f.write_str("instruction fetch"),
+            Access::CacheMaintenance => f.write_str("cache maintenance"),
+        }
+    }
+}
+
+/// A decoded fault status: what went wrong, where in the translation
+/// table walk it went wrong, and what sort of access triggered it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AbortStatus {
+    pub kind: FaultKind,
+    /// The translation table level the fault was taken at, for the kinds
+    /// of fault that report one.
+    pub level: Option<u8>,
+    /// Whether the abort happened while walking the translation tables
+    /// rather than while making the access itself.
+    pub on_table_walk: bool,
+    pub access: Access,
+}
+
+impl AbortStatus {
+    fn new(kind: FaultKind, level: Option<u8>, access: Access) -> AbortStatus {
+        AbortStatus {
+            kind,
+            level,
+            on_table_walk: false,
+            access,
+        }
+    }
+
+    fn on_table_walk(kind: FaultKind, level: u8, access: Access) -> AbortStatus {
+        AbortStatus {
+            kind,
+            level: Some(level),
+            on_table_walk: true,
+            access,
+        }
+    }
+
+    /// Decode a 6-bit fault status code in the long-descriptor format used
+    /// by the AArch64 ESR, the ARMv7 HSR, and an ARMv7 DFSR/IFSR when LPAE
+    /// is enabled.
+    pub(crate) fn from_long_descriptor_code(code: u8, access: Access) -> AbortStatus {
+        let code = code & 0b11_1111;
+        let level = code & 0b11;
+        match code >> 2 {
+            0b0000 => return AbortStatus::new(FaultKind::AddressSize, Some(level), access),
+            0b0001 => return AbortStatus::new(FaultKind::Translation, Some(level), access),
+            0b0010 => return AbortStatus::new(FaultKind::AccessFlag, Some(level), access),
+            0b0011 => return AbortStatus::new(FaultKind::Permission, Some(level), access),
+            0b0101 => {
+                return AbortStatus::on_table_walk(FaultKind::SynchronousExternal, level, access)
+            }
+            0b0111 => {
+                return AbortStatus::on_table_walk(FaultKind::SynchronousParity, level, access)
+            }
+            _ => (),
+        }
+        let kind = match code {
+            0b01_0000 => FaultKind::SynchronousExternal,
+            0b01_0001 => FaultKind::TagCheck,
+            0b01_1000 => FaultKind::SynchronousParity,
+            0b10_0001 => FaultKind::Alignment,
+            0b10_0010 => FaultKind::Debug,
+            0b11_0000 => FaultKind::TLBConflict,
+            0b11_0001 => FaultKind::UnsupportedAtomicUpdate,
+            0b11_0100 => FaultKind::Lockdown,
+            0b11_1101 => return AbortStatus::new(FaultKind::Domain, Some(1), access),
+            0b11_1110 => return AbortStatus::new(FaultKind::Domain, Some(2), access),
+            _ => FaultKind::Unknown(code),
+        };
+        AbortStatus::new(kind, None, access)
+    }
+}
+
+impl fmt::Display for AbortStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if self.on_table_walk {
+            f.write_str(" on translation table walk")?;
+        }
+        if let Some(level) = self.level {
+            write!(f, ", level {}", level)?;
+        }
+        write!(f, ", {}", self.access)
+    }
+}
+
+/// The reason an exception was taken, from the EC field of an exception
+/// syndrome.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionClass {
+    Unknown,
+    WaitForInterruptOrEvent,
+    CoprocessorAccess,
+    FloatingPointAccess,
+    IllegalExecutionState,
+    SupervisorCall,
+    HypervisorCall,
+    SecureMonitorCall,
+    SystemRegisterAccess,
+    SVEAccess,
+    InstructionAbort {
+        from_lower_level: bool,
+    },
+    PCAlignment,
+    DataAbort {
+        from_lower_level: bool,
+    },
+    SPAlignment,
+    FloatingPointException,
+    SError,
+    Breakpoint,
+    SoftwareStep,
+    Watchpoint,
+    BreakpointInstruction,
+    /// An exception class we don't know how to interpret
+    Other(u8),
+}
+
+impl From<u8> for ExceptionClass {
+    fn from(ec: u8) -> Self {
+        match ec {
+            0x00 => ExceptionClass::Unknown,
+            0x01 => ExceptionClass::WaitForInterruptOrEvent,
+            0x03..=0x06 | 0x0C => ExceptionClass::CoprocessorAccess,
+            0x07 => ExceptionClass::FloatingPointAccess,
+            0x0E => ExceptionClass::IllegalExecutionState,
+            0x11 | 0x15 => ExceptionClass::SupervisorCall,
+            0x12 | 0x16 => ExceptionClass::HypervisorCall,
+            0x13 | 0x17 => ExceptionClass::SecureMonitorCall,
+            0x18 => ExceptionClass::SystemRegisterAccess,
+            0x19 => ExceptionClass::SVEAccess,
+            0x20 => ExceptionClass::InstructionAbort {
+                from_lower_level: true,
+            },
+            0x21 => ExceptionClass::InstructionAbort {
+                from_lower_level: false,
+            },
+            0x22 => ExceptionClass::PCAlignment,
+            0x24 => ExceptionClass::DataAbort {
+                from_lower_level: true,
+            },
+            0x25 => ExceptionClass::DataAbort {
+                from_lower_level: false,
+            },
+            0x26 => ExceptionClass::SPAlignment,
+            0x28 | 0x2C => ExceptionClass::FloatingPointException,
+            0x2F => ExceptionClass::SError,
+            0x30 | 0x31 => ExceptionClass::Breakpoint,
+            0x32 | 0x33 => ExceptionClass::SoftwareStep,
+            0x34 | 0x35 => ExceptionClass::Watchpoint,
+            0x38 | 0x3C => ExceptionClass::BreakpointInstruction,
+            other => ExceptionClass::Other(other),
+        }
+    }
+}
+
+impl fmt::Display for ExceptionClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExceptionClass::Unknown => f.write_str("unknown reason"),
+            ExceptionClass::WaitForInterruptOrEvent => f.write_str("trapped WFI/WFE"),
+            ExceptionClass::CoprocessorAccess => f.write_str("trapped coprocessor access"),
+            ExceptionClass::FloatingPointAccess => {
+                f.write_str("trapped SIMD/floating-point access")
+            }
+            ExceptionClass::IllegalExecutionState => f.write_str("illegal execution state"),
+            ExceptionClass::SupervisorCall => f.write_str("supervisor call"),
+            ExceptionClass::HypervisorCall => f.write_str("hypervisor call"),
+            ExceptionClass::SecureMonitorCall => f.write_str("secure monitor call"),
+            ExceptionClass::SystemRegisterAccess => f.write_str("trapped system register access"),
+            ExceptionClass::SVEAccess => f.write_str("trapped SVE access"),
+            ExceptionClass::InstructionAbort { .. } => f.write_str("instruction abort"),
+            ExceptionClass::PCAlignment => f.write_str("PC alignment fault"),
+            ExceptionClass::DataAbort { .. } => f.write_str("data abort"),
+            ExceptionClass::SPAlignment => f.write_str("SP alignment fault"),
+            ExceptionClass::FloatingPointException => f.write_str("floating-point exception"),
+            ExceptionClass::SError => f.write_str("SError interrupt"),
+            ExceptionClass::Breakpoint => f.write_str("breakpoint"),
+            ExceptionClass::SoftwareStep => f.write_str("software step"),
+            ExceptionClass::Watchpoint => f.write_str("watchpoint"),
+            ExceptionClass::BreakpointInstruction => f.write_str("breakpoint instruction"),
+            ExceptionClass::Other(ec) => write!(f, "exception class {:#04x}", ec),
+        }
+    }
+}
+
+/// A decoded exception syndrome, as found in the AArch64 ESR or the ARMv7
+/// HSR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExceptionSyndrome {
+    pub class: ExceptionClass,
+    /// Whether the trapped instruction was 32 bits long, as opposed to a
+    /// 16-bit Thumb instruction.
+    pub is_32_bit_instruction: bool,
+    /// The class-specific syndrome bits
+    pub iss: u32,
+}
+
+impl ExceptionSyndrome {
+    pub fn new(syndrome: usize) -> ExceptionSyndrome {
+        ExceptionSyndrome {
+            class: ExceptionClass::from(((syndrome >> 26) & 0b11_1111) as u8),
+            is_32_bit_instruction: (syndrome >> 25) & 1 == 1,
+            iss: (syndrome & 0x1FF_FFFF) as u32,
+        }
+    }
+
+    /// Decode the fault status of an instruction or data abort. Returns
+    /// `None` for any other class of exception.
+    pub fn abort_status(&self) -> Option<AbortStatus> {
+        match self.class {
+            ExceptionClass::InstructionAbort { .. } => Some(abort_status_from_iss(self.iss, true)),
+            ExceptionClass::DataAbort { .. } => Some(abort_status_from_iss(self.iss, false)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ExceptionSyndrome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.abort_status() {
+            Some(status) => write!(f, "{}: {}", self.class, status),
+            None => write!(f, "{} (syndrome {:#x})", self.class, self.iss),
+        }
+    }
+}
+
+/// Decode the syndrome bits of an instruction or data abort: the status
+/// code lives in bits [5:0], WnR in bit 6, S1PTW in bit 7 and CM in bit 8.
+pub(crate) fn abort_status_from_iss(iss: u32, is_instruction_fault: bool) -> AbortStatus {
+    let access = if is_instruction_fault {
+        Access::InstructionFetch
+    } else if (iss >> 8) & 1 == 1 {
+        Access::CacheMaintenance
+    } else if (iss >> 6) & 1 == 1 {
+        Access::Write
+    } else {
+        Access::Read
+    };
+    let mut status = AbortStatus::from_long_descriptor_code((iss & 0b11_1111) as u8, access);
+    // A stage 2 fault taken while the stage 1 tables were being walked
+    if (iss >> 7) & 1 == 1 {
+        status.on_table_walk = true;
+    }
+    status
+}
@@ -1,3 +1,5 @@
+pub mod fault_status;
+
 #[cfg(any(target_arch = "arm", target_arch = "aarch32"))]
 mod arm;
 #[cfg(any(target_arch = "arm", target_arch = "aarch32"))]
@@ -30,6 +32,11 @@ pub(crate) unsafe fn to_sel4_word(n: usize) -> u32 {
     n as u32
 }
 
+/// The full register set of a thread, as read and written through its
+/// `ThreadControlBlock`. Which registers it holds depends on the
+/// architecture.
+pub type UserContext = sel_claw::seL4_UserContext;
+
 #[cfg(target_pointer_width = "64")]
 pub type CNodeSlotBits = typenum::U5;
 #[cfg(target_pointer_width = "32")]
@@ -6,10 +6,12 @@ use typenum::operator_aliases::Diff;
 use typenum::*;
 
 use crate::arch::*;
+#[cfg(KernelIsMCS)]
+use crate::cap::SchedControl;
 use crate::cap::{
     page_state, role, ASIDControl, AssignedASID, CNode, CNodeRole, CNodeSlots, Cap, IRQControl,
-    InternalASID, LocalCNode, LocalCNodeSlots, LocalCap, MaxIRQCount, Page, ThreadControlBlock,
-    Untyped,
+    InternalASID, LocalCNode, LocalCNodeSlots, LocalCap, MaxIRQCount, Page, Priority,
+    ThreadControlBlock, Untyped,
 };
 use crate::error::SeL4Error;
 use crate::pow::Pow;
@@ -70,6 +72,12 @@ pub struct BootInfo<ASIDControlFreePools: Unsigned> {
     pub asid_control: LocalCap<ASIDControl<ASIDControlFreePools>>,
     pub irq_control: LocalCap<IRQControl>,
     pub user_image: UserImage<role::Local>,
+    /// How many cores the kernel is running on
+    pub core_count: usize,
+    /// Configures scheduling contexts on the core the root task
+    /// started on
+    #[cfg(KernelIsMCS)]
+    pub sched_control: LocalCap<SchedControl>,
 
     #[allow(dead_code)]
     neither_send_nor_sync: NeitherSendNorSync,
@@ -122,7 +130,14 @@ impl BootInfo<op!(ASIDPoolCount - U1)> {
                 },
                 root_vspace_ut.weaken(),
             ),
-            root_tcb: Cap::wrap_cptr(seL4_CapInitThreadTCB as usize),
+            // The kernel gives the root task the highest MCP there is
+            root_tcb: Cap {
+                cptr: seL4_CapInitThreadTCB as usize,
+                cap_data: ThreadControlBlock {
+                    max_controlled_priority: Priority::MAX,
+                },
+                _role: PhantomData,
+            },
             asid_control,
             irq_control: Cap {
                 cptr: seL4_CapIRQControl as usize,
@@ -132,6 +147,9 @@ impl BootInfo<op!(ASIDPoolCount - U1)> {
                 _role: PhantomData,
             },
             user_image,
+            core_count: bootinfo.numNodes,
+            #[cfg(KernelIsMCS)]
+            sched_control: Cap::wrap_cptr(bootinfo.schedcontrol.start),
             neither_send_nor_sync: Default::default(),
         }
     }
@@ -167,6 +185,7 @@ impl UserImage<role::Local> {
                         // We don't actually know how each page is mapped; assume the worst.
                         rights: CapRights::RW,
                     },
+                    _granule: PhantomData,
                 },
                 _role: PhantomData,
             })
@@ -11,6 +11,8 @@ use typenum::*;
 /// source's execution. After that, it destroys itself, and gives you back the
 /// cnode slot where it was living. Or you can just destroy it, to get the cnode
 /// slot back.
+///
+/// Only the classic scheduler saves reply capabilities this way.
 pub struct FaultReplyEndpoint {
     original_slot_cptr: usize,
 }
@@ -67,4 +69,20 @@ impl LocalCap<FaultReplyEndpoint> {
         // send, so we should be able to reuse its slot.
         self.to_slot()
     }
+
+    /// Delete the reply capability without resuming the faulted thread, and
+    /// return the slot it was in.
+    pub fn destroy(self) -> Result<LocalCNodeSlot, SeL4Error> {
+        unsafe {
+            seL4_CNode_Delete(
+                self.cap_data.original_slot_cptr, // _service
+                self.cptr,                        // index
+                arch::WordSize::U8,               // depth
+            )
+        }
+        .as_result()
+        .map_err(SeL4Error::CNodeDelete)?;
+
+        Ok(self.to_slot())
+    }
 }
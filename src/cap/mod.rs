@@ -12,12 +12,15 @@ mod asid_pool;
 mod badge;
 mod cnode;
 mod endpoint;
+#[cfg(not(KernelIsMCS))]
 mod fault_reply_endpoint;
 mod irq_control;
 pub mod irq_handler;
 mod notification;
 mod page;
 mod page_table;
+#[cfg(KernelIsMCS)]
+mod sched_context;
 mod tcb;
 mod untyped;
 
@@ -27,12 +30,15 @@ pub use asid_pool::*;
 pub use badge::*;
 pub use cnode::*;
 pub use endpoint::*;
+#[cfg(not(KernelIsMCS))]
 pub use fault_reply_endpoint::*;
 pub use irq_control::*;
 pub use irq_handler::*;
 pub use notification::*;
 pub use page::*;
 pub use page_table::*;
+#[cfg(KernelIsMCS)]
+pub use sched_context::*;
 pub use tcb::*;
 pub use untyped::*;
 
@@ -58,6 +64,12 @@ pub mod role {
 pub trait DirectRetype {
     type SizeBits: Unsigned;
     fn sel4_type_id() -> usize;
+
+    /// The `size_bits` to retype with, which the kernel only looks at
+    /// for objects whose size is chosen at retype time.
+    fn sel4_size_bits() -> usize {
+        0
+    }
 }
 
 /// Marker trait for CapType implementing structs to indicate that
@@ -496,6 +508,27 @@ impl<Role: CNodeRole, CT: CapType> Cap<CT, Role> {
         })
     }
 
+    /// Revoke every capability derived from this one, be it a copy,
+    /// a mint or, for an `Untyped`, an object retyped from it, which
+    /// leaves the untyped ready to be retyped again. The capability
+    /// itself remains valid.
+    ///
+    /// The caller must ensure that no handle to one of the derived
+    /// capabilities is used afterwards, since it names an empty slot.
+    pub unsafe fn revoke(&self, cnode: &LocalCap<CNode<Role>>) -> Result<(), SeL4Error> {
+        unsafe {
+            seL4_CNode_Revoke(
+                // Since cnode is restricted to CSpace Local Root, the cptr must
+                // actually be the slot index
+                cnode.cptr,          // _service
+                self.cptr,           // index
+                seL4_WordBits as u8, // depth
+            )
+        }
+        .as_result()
+        .map_err(SeL4Error::CNodeRevoke)
+    }
+
     /// Delete a capability
     pub fn delete(self, parent_cnode: &LocalCap<LocalCNode>) -> Result<(), SeL4Error>
     where
@@ -527,6 +560,7 @@ mod private {
     impl SealedCapType for ThreadControlBlock {}
     impl SealedCapType for ThreadPriorityAuthority {}
     impl SealedCapType for Endpoint {}
+    #[cfg(not(KernelIsMCS))]
     impl SealedCapType for FaultReplyEndpoint {}
     impl SealedCapType for Notification {}
     impl<FreeSlots: Unsigned> SealedCapType for ASIDPool<FreeSlots> {}
@@ -536,6 +570,10 @@ mod private {
     {
     }
     impl<State: PageState> SealedCapType for Page<State> {}
+    #[cfg(KernelIsMCS)]
+    impl SealedCapType for SchedContext {}
+    #[cfg(KernelIsMCS)]
+    impl SealedCapType for SchedControl {}
 
     /*
     Cross Arch things:
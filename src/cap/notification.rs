@@ -44,4 +44,22 @@ impl LocalCap<Notification> {
         };
         Badge::from(sender_badge)
     }
+
+    /// Non-blocking check for a pending signal. Returns the accumulated
+    /// badge and clears it, or `None` if nothing was signaled.
+    ///
+    /// N.B. A signal sent through an unbadged capability carries a
+    /// badge of zero and so can't be told apart from no signal at all.
+    /// Mint badged copies for senders whose signals should be polled.
+    pub fn poll(&self) -> Option<Badge> {
+        let mut sender_badge: usize = 0;
+        unsafe {
+            seL4_Poll(self.cptr, &mut sender_badge as *mut usize);
+        };
+        if sender_badge == 0 {
+            None
+        } else {
+            Some(Badge::from(sender_badge))
+        }
+    }
 }
@@ -1,13 +1,19 @@
-use crate::arch::PageBytes;
+use core::marker::PhantomData;
+
+use crate::arch::PageBits;
 use crate::cap::{
     CNodeRole, Cap, CapRangeDataReconstruction, CapType, CopyAliasable, InternalASID, Movable,
 };
 use crate::userland::CapRights;
 use typenum::Unsigned;
 
+/// A frame of memory. `GranuleBits` is the size of the frame, which is
+/// a single page by default; larger granules are mapped as large pages,
+/// sections or blocks, depending on the architecture.
 #[derive(Clone, Debug)]
-pub struct Page<State: PageState> {
+pub struct Page<State: PageState, GranuleBits: Unsigned = PageBits> {
     pub(crate) state: State,
+    pub(crate) _granule: PhantomData<GranuleBits>,
 }
 
 pub trait PageState:
@@ -43,34 +49,42 @@ pub mod page_state {
         }
     }
 }
-impl<State: PageState> CapType for Page<State> {}
+impl<State: PageState, GranuleBits: Unsigned> CapType for Page<State, GranuleBits> {}
 
-impl<State: PageState> CopyAliasable for Page<State> {
-    type CopyOutput = Page<page_state::Unmapped>;
+impl<State: PageState, GranuleBits: Unsigned> CopyAliasable for Page<State, GranuleBits> {
+    type CopyOutput = Page<page_state::Unmapped, GranuleBits>;
 }
-impl<State: PageState> Movable for Page<State> {}
+impl<State: PageState, GranuleBits: Unsigned> Movable for Page<State, GranuleBits> {}
 
-impl<'a, State: PageState> From<&'a Page<State>> for Page<page_state::Unmapped> {
-    fn from(_val: &'a Page<State>) -> Self {
+impl<'a, State: PageState, GranuleBits: Unsigned> From<&'a Page<State, GranuleBits>>
+    for Page<page_state::Unmapped, GranuleBits>
+{
+    fn from(_val: &'a Page<State, GranuleBits>) -> Self {
         Page {
             state: page_state::Unmapped {},
+            _granule: PhantomData,
         }
     }
 }
 
-impl<State: PageState> CapRangeDataReconstruction for Page<State> {
+impl<State: PageState, GranuleBits: Unsigned> CapRangeDataReconstruction
+    for Page<State, GranuleBits>
+{
     fn reconstruct(index: usize, seed_cap_data: &Self) -> Self {
         Page {
             state: seed_cap_data
                 .state
-                .offset_by(index * PageBytes::USIZE)
+                .offset_by(index << GranuleBits::USIZE)
                 // TODO - consider making reconstruct fallible
                 .expect("Earlier checks confirm the memory fits into available space"),
+            _granule: PhantomData,
         }
     }
 }
 
-impl<CapRole: CNodeRole> Cap<Page<page_state::Mapped>, CapRole> {
+impl<CapRole: CNodeRole, GranuleBits: Unsigned>
+    Cap<Page<page_state::Mapped, GranuleBits>, CapRole>
+{
     pub fn vaddr(&self) -> usize {
         self.cap_data.state.vaddr
     }
@@ -1,6 +1,8 @@
 use sel_claw::*;
 
-use crate::arch::PagingRoot;
+use typenum::Unsigned;
+
+use crate::arch::{PageBits, PagingRoot};
 use crate::cap::{page_state, CapType, LocalCap, Page, PhantomCap};
 use crate::error::{KernelError, SeL4Error};
 use crate::userland::CapRights;
@@ -16,6 +18,8 @@ impl PhantomCap for PageTable {
     }
 }
 impl Maps<Page<page_state::Unmapped>> for PageTable {
+    const GRANULE_SIZE_BITS: usize = PageBits::USIZE;
+
     fn map_granule(
         &mut self,
         page: &LocalCap<Page<page_state::Unmapped>>,
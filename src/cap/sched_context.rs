@@ -0,0 +1,117 @@
+use sel_claw::*;
+use typenum::*;
+
+use crate::arch::MinSchedContextBits;
+use crate::cap::{
+    Badge, CapType, DirectRetype, LocalCap, Notification, PhantomCap, ThreadControlBlock,
+};
+use crate::error::{ErrorExt, SeL4Error};
+
+/// A budget of CPU time which is refilled every period. Under the MCS
+/// scheduler a thread only runs while it's bound to a scheduling
+/// context that has budget left.
+#[derive(Debug)]
+pub struct SchedContext {}
+
+impl CapType for SchedContext {}
+
+impl PhantomCap for SchedContext {
+    fn phantom_instance() -> Self {
+        Self {}
+    }
+}
+
+/// Retyping makes the smallest size of scheduling context, which has
+/// room for few, if any, extra refills.
+impl DirectRetype for SchedContext {
+    type SizeBits = MinSchedContextBits;
+    fn sel4_type_id() -> usize {
+        api_object_seL4_SchedContextObject as usize
+    }
+
+    fn sel4_size_bits() -> usize {
+        MinSchedContextBits::USIZE
+    }
+}
+
+/// The authority to configure the budget and period of scheduling
+/// contexts. There is one of these for each core.
+#[derive(Debug)]
+pub struct SchedControl {}
+
+impl CapType for SchedControl {}
+
+impl PhantomCap for SchedControl {
+    fn phantom_instance() -> Self {
+        Self {}
+    }
+}
+
+impl LocalCap<SchedControl> {
+    /// Give a scheduling context `budget_us` microseconds of CPU time
+    /// every `period_us` microseconds. A budget equal to the period
+    /// makes for a round-robin thread which is never throttled.
+    ///
+    /// `badge` is reported in the `Timeout` faults of threads that run
+    /// out of budget on this context.
+    pub fn configure_budget(
+        &mut self,
+        sched_context: &mut LocalCap<SchedContext>,
+        budget_us: u64,
+        period_us: u64,
+        extra_refills: usize,
+        badge: Badge,
+    ) -> Result<(), SeL4Error> {
+        unsafe {
+            seL4_SchedControl_Configure(
+                self.cptr,
+                sched_context.cptr,
+                budget_us,     // budget
+                period_us,     // period
+                extra_refills, // extra_refills
+                badge.into(),  // badge
+            )
+        }
+        .as_result()
+        .map_err(SeL4Error::SchedControlConfigure)
+    }
+}
+
+impl LocalCap<SchedContext> {
+    /// Let a thread run on this scheduling context. A context can only
+    /// be bound to one thread at a time.
+    pub fn bind(&mut self, tcb: &LocalCap<ThreadControlBlock>) -> Result<(), SeL4Error> {
+        unsafe { seL4_SchedContext_Bind(self.cptr, tcb.cptr) }
+            .as_result()
+            .map_err(SeL4Error::SchedContextBind)
+    }
+
+    /// Bind this scheduling context to a notification, so that passive
+    /// threads waiting on it borrow the context while they handle a
+    /// signal.
+    pub fn bind_notification(
+        &mut self,
+        notification: &LocalCap<Notification>,
+    ) -> Result<(), SeL4Error> {
+        unsafe { seL4_SchedContext_Bind(self.cptr, notification.cptr) }
+            .as_result()
+            .map_err(SeL4Error::SchedContextBind)
+    }
+
+    /// Unbind this scheduling context from whatever it is bound to. A
+    /// thread left without one stops running.
+    pub fn unbind(&mut self) -> Result<(), SeL4Error> {
+        unsafe { seL4_SchedContext_Unbind(self.cptr) }
+            .as_result()
+            .map_err(SeL4Error::SchedContextUnbind)
+    }
+
+    /// The microseconds of budget used up since this was last asked.
+    pub fn consumed(&mut self) -> Result<u64, SeL4Error> {
+        let result = unsafe { seL4_SchedContext_Consumed(self.cptr) };
+        (result.error as seL4_Error)
+            .as_result()
+            .map_err(SeL4Error::SchedContextConsumed)?;
+        Ok(result.consumed)
+    }
+}
@@ -1,19 +1,30 @@
+use core::marker::PhantomData;
+
 use sel_claw::*;
 
+use crate::arch::UserContext;
 use crate::cap::{
-    page_state, role, CapType, ChildCNode, CopyAliasable, DirectRetype, LocalCap, Page, PhantomCap,
+    page_state, role, CNodeRole, Cap, CapType, ChildCNode, CopyAliasable, DirectRetype, LocalCap,
+    Page, PhantomCap,
 };
 use crate::error::{ErrorExt, SeL4Error};
-use crate::userland::FaultSource;
+use crate::userland::{FaultSource, FaultSourceRole};
 
 #[derive(Debug)]
-pub struct ThreadControlBlock {}
+pub struct ThreadControlBlock {
+    /// The highest priority this thread may give to any thread,
+    /// itself included
+    pub(crate) max_controlled_priority: Priority,
+}
 
 impl CapType for ThreadControlBlock {}
 
+/// A fresh TCB has a maximum controlled priority of 0
 impl PhantomCap for ThreadControlBlock {
     fn phantom_instance() -> Self {
-        Self {}
+        Self {
+            max_controlled_priority: Priority::MIN,
+        }
     }
 }
 
@@ -28,8 +39,10 @@ impl CopyAliasable for ThreadControlBlock {
     type CopyOutput = Self;
 }
 impl<'a> From<&'a ThreadControlBlock> for ThreadControlBlock {
-    fn from(_val: &'a ThreadControlBlock) -> Self {
-        PhantomCap::phantom_instance()
+    fn from(val: &'a ThreadControlBlock) -> Self {
+        ThreadControlBlock {
+            max_controlled_priority: val.max_controlled_priority,
+        }
     }
 }
 
@@ -37,13 +50,17 @@ impl<'a> From<&'a ThreadControlBlock> for ThreadControlBlock {
 /// that is only intended for use in establishing
 /// the priority of child threads
 #[derive(Debug)]
-pub struct ThreadPriorityAuthority {}
+pub struct ThreadPriorityAuthority {
+    max_controlled_priority: Priority,
+}
 
 impl CapType for ThreadPriorityAuthority {}
 
 impl PhantomCap for ThreadPriorityAuthority {
     fn phantom_instance() -> Self {
-        Self {}
+        Self {
+            max_controlled_priority: Priority::MIN,
+        }
     }
 }
 
@@ -51,26 +68,101 @@ impl CopyAliasable for ThreadPriorityAuthority {
     type CopyOutput = Self;
 }
 impl<'a> From<&'a ThreadPriorityAuthority> for ThreadPriorityAuthority {
-    fn from(_val: &'a ThreadPriorityAuthority) -> Self {
-        PhantomCap::phantom_instance()
+    fn from(val: &'a ThreadPriorityAuthority) -> Self {
+        ThreadPriorityAuthority {
+            max_controlled_priority: val.max_controlled_priority,
+        }
+    }
+}
+
+impl<Role: CNodeRole> Cap<ThreadPriorityAuthority, Role> {
+    /// The highest priority or maximum controlled priority that can be
+    /// given out using this authority.
+    pub fn max_controlled_priority(&self) -> Priority {
+        self.cap_data.max_controlled_priority
+    }
+}
+
+/// A thread priority, from 0 up to the kernel's maximum of
+/// `seL4_MaxPrio`. Higher priority threads always run in preference to
+/// lower ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Priority(u8);
+
+impl Priority {
+    pub const MIN: Priority = Priority(0);
+    pub const MAX: Priority = Priority(seL4_MaxPrio as u8);
+
+    /// A priority of `value`, if the kernel supports one that high.
+    pub fn new(value: u8) -> Option<Priority> {
+        if value as u32 <= seL4_MaxPrio {
+            Some(Priority(value))
+        } else {
+            None
+        }
+    }
+
+    pub fn value(self) -> u8 {
+        self.0
     }
 }
 
-impl AsRef<LocalCap<ThreadPriorityAuthority>> for LocalCap<ThreadControlBlock> {
-    fn as_ref(&self) -> &LocalCap<ThreadPriorityAuthority> {
-        unsafe { core::mem::transmute(self) }
+/// The priorities a new thread is created with: the one it runs at, and
+/// its maximum controlled priority (MCP), which bounds the priorities it
+/// can hand out when its TCB is used as a `ThreadPriorityAuthority`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadPriorities {
+    pub priority: Priority,
+    pub max_controlled_priority: Priority,
+}
+
+impl ThreadPriorities {
+    pub fn new(priority: Priority, max_controlled_priority: Priority) -> Self {
+        ThreadPriorities {
+            priority,
+            max_controlled_priority,
+        }
+    }
+
+    /// Whether both priorities are within what the authority may give
+    /// out. The kernel would reject them otherwise.
+    pub fn allowed_by(&self, authority: &LocalCap<ThreadPriorityAuthority>) -> bool {
+        let limit = authority.max_controlled_priority();
+        self.priority <= limit && self.max_controlled_priority <= limit
+    }
+}
+
+/// The highest priority, and no authority over the priority of other
+/// threads.
+impl Default for ThreadPriorities {
+    fn default() -> Self {
+        ThreadPriorities::new(Priority::MAX, Priority::MIN)
     }
 }
 
 impl LocalCap<ThreadControlBlock> {
     pub fn downgrade_to_thread_priority_authority(self) -> LocalCap<ThreadPriorityAuthority> {
-        unsafe { core::mem::transmute(self) }
+        self.thread_priority_authority()
+    }
+
+    /// This TCB's authority over the priorities of other threads, for
+    /// when the TCB itself is still needed, e.g. the root task's. The
+    /// authority names the same capability, so it's only good for as
+    /// long as the TCB is.
+    pub fn thread_priority_authority(&self) -> LocalCap<ThreadPriorityAuthority> {
+        Cap {
+            cptr: self.cptr,
+            cap_data: ThreadPriorityAuthority {
+                max_controlled_priority: self.cap_data.max_controlled_priority,
+            },
+            _role: PhantomData,
+        }
     }
 
     pub fn configure(
         &mut self,
         cspace_root: LocalCap<ChildCNode>,
-        fault_source: Option<FaultSource<role::Child>>,
+        fault_source: Option<FaultSource<FaultSourceRole>>,
         virtual_address_space_root: &LocalCap<crate::arch::PagingRoot>, // vspace_root,
         ipc_buffer: Option<LocalCap<Page<page_state::Mapped>>>,
     ) -> Result<(), SeL4Error> {
@@ -90,7 +182,8 @@ impl LocalCap<ThreadControlBlock> {
             (seL4_CapNull as usize, 0)
         };
 
-        unsafe {
+        #[cfg(not(KernelIsMCS))]
+        let result = unsafe {
             seL4_TCB_Configure(
                 self.cptr,
                 fault_source.map_or(seL4_CapNull as usize, |source| source.endpoint.cptr), // fault_ep.cptr,
@@ -101,19 +194,335 @@ impl LocalCap<ThreadControlBlock> {
                 buffer_vaddr,          // buffer address
                 buffer_cap,            // bufferFrame capability
             )
+        };
+        #[cfg(KernelIsMCS)]
+        let result = unsafe {
+            seL4_TCB_Configure(
+                self.cptr,
+                cspace_root.cptr,
+                cspace_root_data,
+                virtual_address_space_root.cptr,
+                seL4_NilData as usize, // vspace_root_data, always 0, reserved by kernel?
+                buffer_vaddr,          // buffer address
+                buffer_cap,            // bufferFrame capability
+            )
+        };
+        result.as_result().map_err(SeL4Error::TCBConfigure)?;
+
+        // The MCS kernel only takes a fault endpoint along with the
+        // thread's CSpace and VSpace, so those are set again with it.
+        #[cfg(KernelIsMCS)]
+        {
+            if let Some(fault_source) = fault_source {
+                unsafe {
+                    seL4_TCB_SetSpace(
+                        self.cptr,
+                        fault_source.endpoint.cptr,
+                        cspace_root.cptr,
+                        cspace_root_data,
+                        virtual_address_space_root.cptr,
+                        seL4_NilData as usize, // vspace_root_data
+                    )
+                }
+                .as_result()
+                .map_err(SeL4Error::TCBSetSpace)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop the thread from running until it's resumed. A suspended
+    /// thread keeps its registers, and any IPC it was blocked in is
+    /// cancelled.
+    pub fn suspend(&mut self) -> Result<(), SeL4Error> {
+        unsafe { seL4_TCB_Suspend(self.cptr) }
+            .as_result()
+            .map_err(SeL4Error::TCBSuspend)
+    }
+
+    /// Let a new or suspended thread run again.
+    pub fn resume(&mut self) -> Result<(), SeL4Error> {
+        unsafe { seL4_TCB_Resume(self.cptr) }
+            .as_result()
+            .map_err(SeL4Error::TCBResume)
+    }
+
+    /// Read the thread's full register set.
+    pub fn read_registers(&self) -> Result<UserContext, SeL4Error> {
+        let mut registers: UserContext = unsafe { core::mem::zeroed() };
+        unsafe {
+            seL4_TCB_ReadRegisters(
+                self.cptr,
+                0, // suspend_source
+                0, // arch_flags
+                // all the regs
+                core::mem::size_of::<UserContext>() / core::mem::size_of::<usize>(),
+                &mut registers,
+            )
+        }
+        .as_result()
+        .map_err(SeL4Error::TCBReadRegisters)?;
+        Ok(registers)
+    }
+
+    /// Overwrite the thread's full register set, and optionally resume
+    /// it afterwards.
+    pub fn write_registers(
+        &mut self,
+        registers: &UserContext,
+        resume: bool,
+    ) -> Result<(), SeL4Error> {
+        let mut registers = *registers;
+        unsafe {
+            seL4_TCB_WriteRegisters(
+                self.cptr,
+                resume as u8, // resume_target
+                0,            // arch_flags
+                // all the regs
+                core::mem::size_of::<UserContext>() / core::mem::size_of::<usize>(),
+                &mut registers,
+            )
         }
         .as_result()
-        .map_err(SeL4Error::TCBConfigure)
+        .map_err(SeL4Error::TCBWriteRegisters)
     }
 
-    /// Set this TCB's priority.
+    /// Set this TCB's priority. The kernel refuses priorities above the
+    /// authority's maximum controlled priority.
     pub fn set_priority(
         &mut self,
         tpa: &LocalCap<ThreadPriorityAuthority>,
-        prio: usize,
+        prio: Priority,
     ) -> Result<(), SeL4Error> {
-        unsafe { seL4_TCB_SetPriority(self.cptr, tpa.cptr, prio) }
+        unsafe { seL4_TCB_SetPriority(self.cptr, tpa.cptr, prio.value() as usize) }
             .as_result()
             .map_err(SeL4Error::TCBSetPriority)
     }
+
+    /// Set this TCB's maximum controlled priority, which is checked
+    /// against the authority's own just like `set_priority`.
+    pub fn set_max_controlled_priority(
+        &mut self,
+        tpa: &LocalCap<ThreadPriorityAuthority>,
+        mcp: Priority,
+    ) -> Result<(), SeL4Error> {
+        unsafe { seL4_TCB_SetMCPriority(self.cptr, tpa.cptr, mcp.value() as usize) }
+            .as_result()
+            .map_err(SeL4Error::TCBSetMCPriority)?;
+        self.cap_data.max_controlled_priority = mcp;
+        Ok(())
+    }
+
+    /// Set both of this TCB's priorities.
+    pub fn set_priorities(
+        &mut self,
+        tpa: &LocalCap<ThreadPriorityAuthority>,
+        priorities: ThreadPriorities,
+    ) -> Result<(), SeL4Error> {
+        self.set_max_controlled_priority(tpa, priorities.max_controlled_priority)?;
+        self.set_priority(tpa, priorities.priority)
+    }
+}
+
+/// Scheduling context management, for kernels built with the MCS
+/// scheduler.
+#[cfg(KernelIsMCS)]
+impl LocalCap<ThreadControlBlock> {
+    /// Have `Timeout` faults, raised when the thread runs out of budget,
+    /// sent to the given fault source.
+    pub fn set_timeout_fault_source(
+        &mut self,
+        fault_source: &FaultSource<role::Local>,
+    ) -> Result<(), SeL4Error> {
+        unsafe { seL4_TCB_SetTimeoutEndpoint(self.cptr, fault_source.endpoint.cptr) }
+            .as_result()
+            .map_err(SeL4Error::TCBSetTimeoutEndpoint)
+    }
+}
+
+/// Placement on a core, for multicore kernels. Under the MCS scheduler
+/// a thread runs on the core of its scheduling context instead.
+#[cfg(all(KernelEnableSMPSupport, not(KernelIsMCS)))]
+impl LocalCap<ThreadControlBlock> {
+    /// Run the thread on the given core, counting from 0. The kernel
+    /// rejects cores past `BootInfo::core_count`.
+    pub fn set_affinity(&mut self, core: usize) -> Result<(), SeL4Error> {
+        unsafe { seL4_TCB_SetAffinity(self.cptr, core) }
+            .as_result()
+            .map_err(SeL4Error::TCBSetAffinity)
+    }
+}
+
+/// What a hardware breakpoint triggers on. This is also the reason given
+/// in a `DebugException` fault.
+#[cfg(HardwareDebugAPI)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakpointType {
+    /// A watchpoint on a range of data addresses
+    Data,
+    /// Execution of the instruction at an address
+    Instruction,
+    /// The completion of a single-stepping run
+    SingleStep,
+    /// A breakpoint instruction in the thread's own code
+    SoftwareBreakRequest,
+}
+
+#[cfg(HardwareDebugAPI)]
+impl BreakpointType {
+    pub(crate) fn from_sel4(value: usize) -> Option<Self> {
+        match value as u32 {
+            seL4_BreakpointType_seL4_DataBreakpoint => Some(BreakpointType::Data),
+            seL4_BreakpointType_seL4_InstructionBreakpoint => Some(BreakpointType::Instruction),
+            seL4_BreakpointType_seL4_SingleStep => Some(BreakpointType::SingleStep),
+            seL4_BreakpointType_seL4_SoftwareBreakRequest => {
+                Some(BreakpointType::SoftwareBreakRequest)
+            }
+            _ => None,
+        }
+    }
+
+    fn to_sel4(self) -> usize {
+        (match self {
+            BreakpointType::Data => seL4_BreakpointType_seL4_DataBreakpoint,
+            BreakpointType::Instruction => seL4_BreakpointType_seL4_InstructionBreakpoint,
+            BreakpointType::SingleStep => seL4_BreakpointType_seL4_SingleStep,
+            BreakpointType::SoftwareBreakRequest => seL4_BreakpointType_seL4_SoftwareBreakRequest,
+        }) as usize
+    }
+}
+
+/// The kinds of access a data breakpoint triggers on.
+#[cfg(HardwareDebugAPI)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakpointAccess {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+#[cfg(HardwareDebugAPI)]
+impl BreakpointAccess {
+    fn from_sel4(value: usize) -> Option<Self> {
+        match value as u32 {
+            seL4_BreakpointAccess_seL4_BreakOnRead => Some(BreakpointAccess::Read),
+            seL4_BreakpointAccess_seL4_BreakOnWrite => Some(BreakpointAccess::Write),
+            seL4_BreakpointAccess_seL4_BreakOnReadWrite => Some(BreakpointAccess::ReadWrite),
+            _ => None,
+        }
+    }
+
+    fn to_sel4(self) -> usize {
+        (match self {
+            BreakpointAccess::Read => seL4_BreakpointAccess_seL4_BreakOnRead,
+            BreakpointAccess::Write => seL4_BreakpointAccess_seL4_BreakOnWrite,
+            BreakpointAccess::ReadWrite => seL4_BreakpointAccess_seL4_BreakOnReadWrite,
+        }) as usize
+    }
+}
+
+/// A hardware breakpoint or watchpoint, as programmed into one of the
+/// thread's debug registers.
+#[cfg(HardwareDebugAPI)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub vaddr: usize,
+    pub kind: BreakpointType,
+    /// The size in bytes of the watched range. Always 0 for instruction
+    /// breakpoints.
+    pub size: usize,
+    pub access: BreakpointAccess,
+}
+
+#[cfg(HardwareDebugAPI)]
+impl Breakpoint {
+    /// Break when the thread is about to execute the instruction at
+    /// `vaddr`.
+    pub fn instruction(vaddr: usize) -> Self {
+        Breakpoint {
+            vaddr,
+            kind: BreakpointType::Instruction,
+            size: 0,
+            access: BreakpointAccess::Read,
+        }
+    }
+
+    /// Break when the thread accesses any of the `size` bytes at `vaddr`.
+    pub fn data(vaddr: usize, size: usize, access: BreakpointAccess) -> Self {
+        Breakpoint {
+            vaddr,
+            kind: BreakpointType::Data,
+            size,
+            access,
+        }
+    }
+}
+
+/// Hardware debugging, which is only available when the kernel is built
+/// with its hardware debug API. Breakpoints are numbered by the debug
+/// register they occupy; when one is hit, the thread raises a
+/// `DebugException` fault to its fault handler.
+#[cfg(HardwareDebugAPI)]
+impl LocalCap<ThreadControlBlock> {
+    pub fn set_breakpoint(&mut self, number: u16, breakpoint: Breakpoint) -> Result<(), SeL4Error> {
+        unsafe {
+            seL4_TCB_SetBreakpoint(
+                self.cptr,
+                number,                      // bp_num
+                breakpoint.vaddr,            // vaddr
+                breakpoint.kind.to_sel4(),   // type
+                breakpoint.size,             // size
+                breakpoint.access.to_sel4(), // rw
+            )
+        }
+        .as_result()
+        .map_err(SeL4Error::TCBSetBreakpoint)
+    }
+
+    /// The breakpoint in the given debug register, if it's enabled.
+    pub fn get_breakpoint(&self, number: u16) -> Result<Option<Breakpoint>, SeL4Error> {
+        let result = unsafe { seL4_TCB_GetBreakpoint(self.cptr, number) };
+        (result.error as seL4_Error)
+            .as_result()
+            .map_err(SeL4Error::TCBGetBreakpoint)?;
+        if result.is_enabled == 0 {
+            return Ok(None);
+        }
+        match (
+            BreakpointType::from_sel4(result.type_),
+            BreakpointAccess::from_sel4(result.rw),
+        ) {
+            (Some(kind), Some(access)) => Ok(Some(Breakpoint {
+                vaddr: result.vaddr,
+                kind,
+                size: result.size,
+                access,
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn unset_breakpoint(&mut self, number: u16) -> Result<(), SeL4Error> {
+        unsafe { seL4_TCB_UnsetBreakpoint(self.cptr, number) }
+            .as_result()
+            .map_err(SeL4Error::TCBUnsetBreakpoint)
+    }
+
+    /// Make the thread raise a `DebugException` after every
+    /// `num_instructions` instructions it executes, or stop stepping
+    /// when `num_instructions` is 0. On some platforms stepping uses up
+    /// a breakpoint register; the result says whether the one given in
+    /// `number` was taken for it.
+    pub fn configure_single_stepping(
+        &mut self,
+        number: u16,
+        num_instructions: usize,
+    ) -> Result<bool, SeL4Error> {
+        let result =
+            unsafe { seL4_TCB_ConfigureSingleStepping(self.cptr, number, num_instructions) };
+        (result.error as seL4_Error)
+            .as_result()
+            .map_err(SeL4Error::TCBConfigureSingleStepping)?;
+        Ok(result.bp_was_consumed != 0)
+    }
 }
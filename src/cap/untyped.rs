@@ -16,7 +16,7 @@ use crate::cap::{
 };
 use crate::error::{ErrorExt, KernelError, SeL4Error};
 use crate::pow::{Pow, _Pow};
-use crate::vspace::NumPages;
+use crate::vspace::{NumGranules, NumPages};
 
 // The seL4 kernel's maximum amount of retypes per system call is configurable
 // in the sel4.toml, particularly by the KernelRetypeFanOutLimit property.
@@ -131,14 +131,14 @@ impl<Kind: MemoryKind> LocalCap<WUntyped<Kind>> {
             .map_err(RetypeError::CNodeSlotsError)?;
         unsafe {
             seL4_Untyped_Retype(
-                self.cptr,                  // _service
-                Page::sel4_type_id(),       // type
-                0,                          // size_bits
-                dest_slots.cptr,            // root
-                0,                          // index
-                0,                          // depth
-                dest_slots.cap_data.offset, // offset
-                num_pages,                  // num_objects
+                self.cptr,                                    // _service
+                Page::<page_state::Unmapped>::sel4_type_id(), // type
+                0,                                            // size_bits
+                dest_slots.cptr,                              // root
+                0,                                            // index
+                0,                                            // depth
+                dest_slots.cap_data.offset,                   // offset
+                num_pages,                                    // num_objects
             )
             .as_result()
             .map_err(SeL4Error::UntypedRetype)?;
@@ -148,6 +148,7 @@ impl<Kind: MemoryKind> LocalCap<WUntyped<Kind>> {
             dest_slots.cap_data.offset,
             Page {
                 state: page_state::Unmapped,
+                _granule: PhantomData,
                 /* TODO - kind piping
                  *memory_kind: self.cap_data.kind, */
             },
@@ -170,7 +171,7 @@ impl LocalCap<WUntyped<memory_kind::General>> {
             seL4_Untyped_Retype(
                 self.cptr,            // _service
                 D::sel4_type_id(),    // type
-                0,                    // size_bits
+                D::sel4_size_bits(),  // size_bits
                 slots.cptr,           // root
                 0,                    // index
                 0,                    // depth
@@ -346,15 +347,7 @@ impl<BitSize: Unsigned, Kind: MemoryKind> LocalCap<Untyped<BitSize, Kind>> {
         });
 
         // Clean up any child/derived capabilities that may have been created.
-        unsafe {
-            seL4_CNode_Revoke(
-                parent_cnode.cptr,   // _service
-                self.cptr,           // index
-                seL4_WordBits as u8, // depth
-            )
-        }
-        .as_result()
-        .map_err(SeL4Error::CNodeRevoke)?;
+        unsafe { self.revoke(parent_cnode) }?;
         Ok(r)
     }
 
@@ -505,18 +498,44 @@ impl<BitSize: Unsigned, Kind: MemoryKind> LocalCap<Untyped<BitSize, Kind>> {
         Pow<<BitSize as Sub<PageBits>>::Output>: Unsigned,
         Pow<<BitSize as Sub<PageBits>>::Output>:
             IsLessOrEqual<KernelRetypeFanOutLimit, Output = True>,
+    {
+        self.retype_granules(dest_slots)
+    }
+
+    /// Like `retype_pages`, but carves the memory into frames of
+    /// `1 << GranuleBits` bytes, e.g. large pages or sections.
+    pub fn retype_granules<GranuleBits: Unsigned, CRole: CNodeRole>(
+        self,
+        dest_slots: CNodeSlots<NumGranules<BitSize, GranuleBits>, CRole>,
+    ) -> Result<
+        CapRange<
+            Page<page_state::Unmapped, GranuleBits>,
+            role::Local,
+            NumGranules<BitSize, GranuleBits>,
+        >,
+        SeL4Error,
+    >
+    where
+        Page<page_state::Unmapped, GranuleBits>: DirectRetype,
+        BitSize: IsGreaterOrEqual<GranuleBits>,
+        BitSize: Sub<GranuleBits>,
+        <BitSize as Sub<GranuleBits>>::Output: Unsigned,
+        <BitSize as Sub<GranuleBits>>::Output: _Pow,
+        Pow<<BitSize as Sub<GranuleBits>>::Output>: Unsigned,
+        Pow<<BitSize as Sub<GranuleBits>>::Output>:
+            IsLessOrEqual<KernelRetypeFanOutLimit, Output = True>,
     {
         let (dest_cptr, dest_offset, _) = dest_slots.elim();
         unsafe {
             seL4_Untyped_Retype(
-                self.cptr,                               // _service
-                Page::sel4_type_id(),                    // type
-                0,                                       // size_bits
-                dest_cptr,                               // root
-                0,                                       // index
-                0,                                       // depth
-                dest_offset,                             // offset
-                1 << (BitSize::USIZE - PageBits::USIZE), // num_objects
+                self.cptr,                                                 // _service
+                Page::<page_state::Unmapped, GranuleBits>::sel4_type_id(), // type
+                0,                                                         // size_bits
+                dest_cptr,                                                 // root
+                0,                                                         // index
+                0,                                                         // depth
+                dest_offset,                                               // offset
+                1 << (BitSize::USIZE - GranuleBits::USIZE),                // num_objects
             )
             .as_result()
             .map_err(SeL4Error::UntypedRetype)?;
@@ -526,6 +545,7 @@ impl<BitSize: Unsigned, Kind: MemoryKind> LocalCap<Untyped<BitSize, Kind>> {
             dest_offset,
             Page {
                 state: page_state::Unmapped,
+                _granule: PhantomData,
                 /* TODO - implement kind piping
                  *memory_kind: self.cap_data.kind, */
             },
@@ -587,14 +607,14 @@ impl<BitSize: Unsigned> LocalCap<Untyped<BitSize, memory_kind::General>> {
 
         unsafe {
             seL4_Untyped_Retype(
-                self.cptr,                     // _service
-                TargetCapType::sel4_type_id(), // type
-                0,                             // size_bits
-                dest_cptr,                     // root
-                0,                             // index
-                0,                             // depth
-                dest_offset,                   // offset
-                1,                             // num_objects
+                self.cptr,                       // _service
+                TargetCapType::sel4_type_id(),   // type
+                TargetCapType::sel4_size_bits(), // size_bits
+                dest_cptr,                       // root
+                0,                               // index
+                0,                               // depth
+                dest_offset,                     // offset
+                1,                               // num_objects
             )
         }
         .as_result()
@@ -635,6 +655,7 @@ impl<BitSize: Unsigned> LocalCap<Untyped<BitSize, memory_kind::General>> {
                 self.cptr,
                 Count::USIZE,
                 TargetCapType::sel4_type_id(),
+                TargetCapType::sel4_size_bits(),
                 dest_cptr,
                 dest_offset,
             )?;
@@ -646,13 +667,14 @@ impl<BitSize: Unsigned> LocalCap<Untyped<BitSize, memory_kind::General>> {
         self_cptr: usize,
         count: usize,
         type_id: usize,
+        size_bits: usize,
         dest_cptr: usize,
         dest_offset: usize,
     ) -> Result<(), SeL4Error> {
         seL4_Untyped_Retype(
             self_cptr,   // _service
             type_id,     // type
-            0,           // size_bits
+            size_bits,   // size_bits
             dest_cptr,   // root
             0,           // index
             0,           // depth
@@ -765,14 +787,14 @@ impl LocalCap<Untyped<PageBits, memory_kind::Device>> {
 
         unsafe {
             seL4_Untyped_Retype(
-                self.cptr,            // _service
-                Page::sel4_type_id(), // type
-                0,                    // size_bits
-                dest_cptr,            // root
-                0,                    // index
-                0,                    // depth
-                dest_offset,          // offset
-                1,                    // num_objects
+                self.cptr,                                    // _service
+                Page::<page_state::Unmapped>::sel4_type_id(), // type
+                0,                                            // size_bits
+                dest_cptr,                                    // root
+                0,                                            // index
+                0,                                            // depth
+                dest_offset,                                  // offset
+                1,                                            // num_objects
             )
         }
         .as_result()
@@ -782,6 +804,7 @@ impl LocalCap<Untyped<PageBits, memory_kind::Device>> {
             cptr: dest_offset,
             cap_data: Page {
                 state: page_state::Unmapped,
+                _granule: PhantomData,
                 /* TODO - reinstate kind piping
                  *memory_kind: self.cap_data.kind, */
             },
@@ -16,7 +16,9 @@ pub enum SeL4Error {
     TCBWriteRegisters(KernelError),
     TCBReadRegisters(KernelError),
     TCBSetPriority(KernelError),
+    TCBSetMCPriority(KernelError),
     TCBResume(KernelError),
+    TCBSuspend(KernelError),
     CNodeMutate(KernelError),
     CNodeMove(KernelError),
     CNodeDelete(KernelError),
@@ -31,6 +33,18 @@ pub enum SeL4Error {
     VCPUWriteRegisters(KernelError),
     VCPUBindTcb(KernelError),
     TCBBindNotification(KernelError),
+    TCBSetBreakpoint(KernelError),
+    TCBGetBreakpoint(KernelError),
+    TCBUnsetBreakpoint(KernelError),
+    TCBConfigureSingleStepping(KernelError),
+    TCBSetTimeoutEndpoint(KernelError),
+    TCBSetSpace(KernelError),
+    SchedControlConfigure(KernelError),
+    SchedContextBind(KernelError),
+    SchedContextUnbind(KernelError),
+    SchedContextConsumed(KernelError),
+    TCBSetAffinity(KernelError),
+    PageUnifyInstruction(KernelError),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
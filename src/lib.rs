@@ -15,6 +15,7 @@ extern crate sel_claw;
 extern crate typenum;
 
 extern crate cross_queue;
+extern crate rpc_service;
 extern crate smart_alloc;
 
 #[macro_use]
@@ -0,0 +1,193 @@
+//! Stack backtraces of faulted child processes.
+//!
+//! The backtrace is found by walking the chain of frame records that
+//! the child's code leaves on its stack. Each record is a pair of words:
+//! the caller's frame pointer, followed by the return address into the
+//! caller. This is the layout used on both AArch64 (x29/x30) and 32-bit
+//! ARM (r11/lr), but the records are only there if the child was built
+//! with frame pointers, e.g. with `-C force-frame-pointers=yes`.
+use core::fmt;
+use core::mem::size_of;
+
+use arrayvec::ArrayVec;
+
+/// The most frames a `Backtrace` will record.
+pub const MAX_BACKTRACE_DEPTH: usize = 32;
+
+/// Read access to some part of a child's address space.
+pub trait ChildMemory {
+    /// Read the word at `vaddr` in the child's address space, or `None`
+    /// if that word is not accessible.
+    fn read_word(&self, vaddr: usize) -> Option<usize>;
+}
+
+/// Memory mapped into the local address space which holds the memory
+/// mapped at `child_vaddr` in the child's address space, e.g. a region
+/// shared with the child, or one that's temporarily mapped through a
+/// `ScratchRegion`.
+pub struct MappedChildMemory<'a> {
+    local: &'a [u8],
+    child_vaddr: usize,
+}
+
+impl<'a> MappedChildMemory<'a> {
+    pub fn new(local: &'a [u8], child_vaddr: usize) -> Self {
+        MappedChildMemory { local, child_vaddr }
+    }
+
+    pub fn child_vaddr(&self) -> usize {
+        self.child_vaddr
+    }
+
+    pub fn as_slice(&self) -> &'a [u8] {
+        self.local
+    }
+}
+
+impl<'a> ChildMemory for MappedChildMemory<'a> {
+    fn read_word(&self, vaddr: usize) -> Option<usize> {
+        if vaddr % size_of::<usize>() != 0 {
+            return None;
+        }
+        let offset = vaddr.checked_sub(self.child_vaddr)?;
+        let word = self
+            .local
+            .get(offset..offset.checked_add(size_of::<usize>())?)?;
+        Some(unsafe { core::ptr::read_volatile(word.as_ptr() as *const usize) })
+    }
+}
+
+/// Where a backtrace starts: the faulting instruction and the frame
+/// pointer at the time of the fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStart {
+    pub program_counter: usize,
+    pub frame_pointer: usize,
+}
+
+/// A bounded list of code addresses in a child, starting with the
+/// program counter and followed by the return address of each frame
+/// found on the stack, innermost first.
+#[derive(Debug)]
+pub struct Backtrace {
+    addresses: ArrayVec<[usize; MAX_BACKTRACE_DEPTH]>,
+    truncated: bool,
+}
+
+impl Backtrace {
+    /// Walk the frame record chain starting at `start`. The walk stops at
+    /// a null frame pointer or return address, at a record which can't be
+    /// read from `memory`, at a record which isn't further up the stack
+    /// than the one before it, or once `MAX_BACKTRACE_DEPTH` addresses
+    /// have been found.
+    pub fn walk<M: ChildMemory>(start: FrameStart, memory: &M) -> Backtrace {
+        let mut addresses = ArrayVec::new();
+        addresses.push(start.program_counter);
+
+        let mut frame_pointer = start.frame_pointer;
+        let mut truncated = false;
+        while frame_pointer != 0 {
+            let (caller_frame_pointer, return_address) = match (
+                memory.read_word(frame_pointer),
+                memory.read_word(frame_pointer.wrapping_add(size_of::<usize>())),
+            ) {
+                (Some(fp), Some(ra)) => (fp, ra),
+                _ => break,
+            };
+            if return_address == 0 {
+                break;
+            }
+            if addresses.try_push(return_address).is_err() {
+                truncated = true;
+                break;
+            }
+            // The stack grows down, so callers' frames are at higher
+            // addresses. Anything else means the chain is corrupt.
+            if caller_frame_pointer != 0 && caller_frame_pointer <= frame_pointer {
+                break;
+            }
+            frame_pointer = caller_frame_pointer;
+        }
+
+        Backtrace {
+            addresses,
+            truncated,
+        }
+    }
+
+    /// The program counter, followed by the return address of each frame.
+    pub fn addresses(&self) -> &[usize] {
+        &self.addresses
+    }
+
+    /// Whether the walk stopped because it ran out of room, rather than
+    /// because it reached the end of the chain.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Pair this backtrace with a symbol table (such as
+    /// `ElfProc::SYMBOLS`) for display.
+    pub fn symbolize<'a>(&'a self, symbols: &'a [Symbol]) -> SymbolizedBacktrace<'a> {
+        SymbolizedBacktrace {
+            backtrace: self,
+            symbols,
+        }
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.symbolize(&[]).fmt(f)
+    }
+}
+
+/// A function in a child's ELF image, as embedded by `ferros-build`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol {
+    pub address: usize,
+    pub size: usize,
+    pub name: &'static str,
+}
+
+/// Find the function containing `address` in a table of symbols that
+/// is sorted by address, along with the offset of `address` into it.
+pub fn lookup_symbol(symbols: &[Symbol], address: usize) -> Option<(&Symbol, usize)> {
+    let index = match symbols.binary_search_by_key(&address, |s| s.address) {
+        Ok(index) => index,
+        Err(0) => return None,
+        Err(index) => index - 1,
+    };
+    let symbol = &symbols[index];
+    let offset = address - symbol.address;
+    if offset < symbol.size || (symbol.size == 0 && offset == 0) {
+        Some((symbol, offset))
+    } else {
+        None
+    }
+}
+
+/// A backtrace which displays the function each address falls in.
+pub struct SymbolizedBacktrace<'a> {
+    backtrace: &'a Backtrace,
+    symbols: &'a [Symbol],
+}
+
+impl<'a> fmt::Display for SymbolizedBacktrace<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (n, address) in self.backtrace.addresses.iter().enumerate() {
+            write!(f, "{:>3}: {:#x}", n, address)?;
+            // Return addresses point after the call, which may be past the
+            // end of the calling function, so look up the call itself.
+            let lookup_address = if n == 0 { *address } else { address - 1 };
+            if let Some((symbol, _)) = lookup_symbol(self.symbols, lookup_address) {
+                write!(f, " {}+{:#x}", symbol.name, address - symbol.address)?;
+            }
+            writeln!(f)?;
+        }
+        if self.backtrace.truncated {
+            writeln!(f, "     ...")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,483 @@
+//! Core dumps of faulted child processes, in the ELF core format that
+//! `gdb` and friends understand.
+//!
+//! A core file holds a single NT_PRSTATUS note with the thread's
+//! registers, followed by one PT_LOAD segment for each region of the
+//! child's memory that was added to the dump. Read-only memory such as
+//! the code image isn't needed: the debugger reads it from the original
+//! binary, e.g. with `gdb path/to/elf-process core`.
+//!
+//! Memory the parent has mapped locally is copied straight out of its
+//! slice. Anything else is added as one of the child's `Mapping`s and
+//! read through a `MemoryWindow` onto the child's VSpace while the dump
+//! is written. A `MemoryWindow` only reaches single pages, so mappings
+//! made of larger frames are listed in the dump without their contents.
+use core::fmt;
+use core::mem::size_of;
+
+use arrayvec::ArrayVec;
+use sel_claw::*;
+use typenum::Unsigned;
+
+use crate::arch::fault::Fault;
+use crate::arch::userland::core_dump::{elf_gregset, ELF_MACHINE, ELF_NGREG};
+use crate::arch::PageBytes;
+use crate::cap::InternalASID;
+use crate::debug::DebugOutHandle;
+use crate::error::SeL4Error;
+use crate::userland::MappedChildMemory;
+use crate::vspace::{Mapping, MemoryWindow, VSpace, VSpaceError, MAX_RECORDED_MAPPINGS};
+
+/// The most memory regions a `CoreDump` can hold: enough for every
+/// mapping a `VSpace` keeps a record of, along with a few regions added
+/// with `add_memory`.
+pub const MAX_CORE_DUMP_SEGMENTS: usize = 40;
+
+const _: () = assert!(MAX_CORE_DUMP_SEGMENTS > MAX_RECORDED_MAPPINGS);
+
+#[derive(Debug)]
+pub enum CoreDumpError {
+    TooManySegments,
+    /// The output ran out of room before the whole dump was written
+    OutputTooSmall,
+    OutputFailed,
+    /// Some of the memory in the dump can only be read through a
+    /// `MemoryWindow` onto the child's VSpace, and none was given.
+    MemoryWindowRequired,
+    SeL4Error(SeL4Error),
+    VSpaceError(VSpaceError),
+}
+
+impl From<SeL4Error> for CoreDumpError {
+    fn from(s: SeL4Error) -> Self {
+        CoreDumpError::SeL4Error(s)
+    }
+}
+
+impl From<VSpaceError> for CoreDumpError {
+    fn from(e: VSpaceError) -> Self {
+        CoreDumpError::VSpaceError(e)
+    }
+}
+
+/// Somewhere to put a core dump.
+pub trait CoreDumpWriter {
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), CoreDumpError>;
+}
+
+/// Writes a core dump into a byte slice, e.g. the contents of a
+/// `MappedMemoryRegion`.
+pub struct SliceCoreDumpWriter<'a> {
+    buffer: &'a mut [u8],
+    position: usize,
+}
+
+impl<'a> SliceCoreDumpWriter<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        SliceCoreDumpWriter {
+            buffer,
+            position: 0,
+        }
+    }
+
+    /// How many bytes have been written so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl<'a> CoreDumpWriter for SliceCoreDumpWriter<'a> {
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), CoreDumpError> {
+        let end = self.position + bytes.len();
+        if end > self.buffer.len() {
+            return Err(CoreDumpError::OutputTooSmall);
+        }
+        self.buffer[self.position..end].copy_from_slice(bytes);
+        self.position = end;
+        Ok(())
+    }
+}
+
+/// How many bytes of the core file go on each line of hex output.
+const HEX_BYTES_PER_LINE: usize = 32;
+
+/// Streams a core dump as lines of hex, by default over the kernel's
+/// debug UART. Each line starts with `coredump:`, so the core file can
+/// be picked out of a log on the host with:
+///
+/// ```text
+/// grep -o 'coredump:[0-9a-f]*' log | cut -d: -f2 | xxd -r -p > core
+/// ```
+pub struct HexCoreDumpWriter<W: fmt::Write = DebugOutHandle> {
+    out: W,
+    line_length: usize,
+}
+
+impl HexCoreDumpWriter<DebugOutHandle> {
+    pub fn debug_uart() -> Self {
+        HexCoreDumpWriter::new(DebugOutHandle)
+    }
+}
+
+impl<W: fmt::Write> HexCoreDumpWriter<W> {
+    pub fn new(out: W) -> Self {
+        HexCoreDumpWriter {
+            out,
+            line_length: 0,
+        }
+    }
+
+    /// End the last line of output.
+    pub fn finish(mut self) -> Result<W, CoreDumpError> {
+        if self.line_length != 0 {
+            self.out
+                .write_str("\n")
+                .map_err(|_| CoreDumpError::OutputFailed)?;
+        }
+        Ok(self.out)
+    }
+}
+
+impl<W: fmt::Write> CoreDumpWriter for HexCoreDumpWriter<W> {
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), CoreDumpError> {
+        for byte in bytes {
+            if self.line_length == 0 {
+                self.out
+                    .write_str("coredump:")
+                    .map_err(|_| CoreDumpError::OutputFailed)?;
+            }
+            write!(self.out, "{:02x}", byte).map_err(|_| CoreDumpError::OutputFailed)?;
+            self.line_length += 1;
+            if self.line_length == HEX_BYTES_PER_LINE {
+                self.out
+                    .write_str("\n")
+                    .map_err(|_| CoreDumpError::OutputFailed)?;
+                self.line_length = 0;
+            }
+        }
+        Ok(())
+    }
+}
+
+const WORD: usize = size_of::<usize>();
+
+#[cfg(target_pointer_width = "64")]
+const ELF_CLASS: u8 = 2; // ELFCLASS64
+#[cfg(target_pointer_width = "32")]
+const ELF_CLASS: u8 = 1; // ELFCLASS32
+
+const ELF_HEADER_SIZE: usize = 16 + 2 + 2 + 4 + 3 * WORD + 4 + 6 * 2;
+const PROGRAM_HEADER_SIZE: usize = 2 * 4 + 6 * WORD;
+#[cfg(target_pointer_width = "64")]
+const SECTION_HEADER_SIZE: usize = 64;
+#[cfg(target_pointer_width = "32")]
+const SECTION_HEADER_SIZE: usize = 40;
+
+const ET_CORE: u16 = 4;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+
+// The layout of the Linux `struct elf_prstatus`: a signal info struct,
+// the current signal, two signal masks, four pids and four timevals,
+// followed by the registers and `pr_fpvalid`.
+const PRSTATUS_CURSIG_OFFSET: usize = 12;
+const PRSTATUS_PID_OFFSET: usize = 16 + 2 * WORD;
+const PRSTATUS_REGISTERS_OFFSET: usize = PRSTATUS_PID_OFFSET + 4 * 4 + 4 * 2 * WORD;
+const PRSTATUS_SIZE: usize =
+    (PRSTATUS_REGISTERS_OFFSET + ELF_NGREG * WORD + 4 + WORD - 1) / WORD * WORD;
+
+/// The note's name, "CORE", null terminated and padded to 4 bytes
+const NOTE_NAME: &[u8; 8] = b"CORE\0\0\0\0";
+const NOTE_SIZE: usize = 3 * 4 + NOTE_NAME.len() + PRSTATUS_SIZE;
+
+const SIGILL: u16 = 4;
+const SIGTRAP: u16 = 5;
+const SIGSEGV: u16 = 11;
+const SIGSYS: u16 = 31;
+
+/// A region of memory to include in a core dump.
+enum Segment<'a> {
+    /// Memory the parent can see, along with where the child sees it
+    Local {
+        child_vaddr: usize,
+        contents: &'a [u8],
+    },
+    /// Memory which is only mapped in the child
+    Mapped(Mapping),
+}
+
+impl<'a> Segment<'a> {
+    fn child_vaddr(&self) -> usize {
+        match self {
+            Segment::Local { child_vaddr, .. } => *child_vaddr,
+            Segment::Mapped(mapping) => mapping.vaddr(),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Segment::Local { contents, .. } => contents.len(),
+            Segment::Mapped(mapping) => mapping.size_bytes(),
+        }
+    }
+
+    /// How much of the segment is in the core file. A `MemoryWindow`
+    /// only maps a page at a time, so the contents of mappings made of
+    /// larger frames are left out, and the debugger reports them as
+    /// unavailable.
+    fn file_size(&self) -> usize {
+        match self {
+            Segment::Mapped(mapping) if !mapping.is_page_granular() => 0,
+            _ => self.size(),
+        }
+    }
+
+    fn flags(&self) -> u32 {
+        match self {
+            Segment::Local { .. } => PF_R | PF_W,
+            Segment::Mapped(mapping) if mapping.rights().is_writable() => PF_R | PF_W,
+            Segment::Mapped(_) => PF_R,
+        }
+    }
+}
+
+/// A snapshot of a stopped child process: its registers, along with
+/// whichever regions of its memory have been added.
+pub struct CoreDump<'a> {
+    registers: seL4_UserContext,
+    signal: u16,
+    segments: ArrayVec<[Segment<'a>; MAX_CORE_DUMP_SEGMENTS]>,
+    /// The VSpace the `Segment::Mapped`s are in, if there are any
+    vspace_asid: Option<InternalASID>,
+}
+
+impl<'a> CoreDump<'a> {
+    /// Start a core dump of a thread with the given registers. If it's
+    /// being dumped because of a fault, passing the fault along lets the
+    /// debugger report it as the signal Linux would have sent.
+    pub fn new(registers: seL4_UserContext, fault: Option<&Fault>) -> Self {
+        let signal = match fault {
+            Some(Fault::VMFault(_)) => SIGSEGV,
+            Some(Fault::UserException(_)) => SIGILL,
+            Some(Fault::UnknownSyscall(_)) | Some(Fault::CapFault(_)) => SIGSYS,
+            Some(_) => SIGTRAP,
+            None => 0,
+        };
+        CoreDump {
+            registers,
+            signal,
+            segments: ArrayVec::new(),
+            vspace_asid: None,
+        }
+    }
+
+    /// Include a region of the child's memory in the dump.
+    pub fn add_memory(&mut self, memory: MappedChildMemory<'a>) -> Result<(), CoreDumpError> {
+        self.segments
+            .try_push(Segment::Local {
+                child_vaddr: memory.child_vaddr(),
+                contents: memory.as_slice(),
+            })
+            .map_err(|_| CoreDumpError::TooManySegments)
+    }
+
+    /// Include memory that's only mapped in the child, as recorded by
+    /// its VSpace. Its contents are read when the dump is written, so
+    /// `write_to` then needs a `MemoryWindow` onto `vspace`.
+    pub fn add_mapping(&mut self, vspace: &VSpace, mapping: &Mapping) -> Result<(), CoreDumpError> {
+        if self.vspace_asid.map_or(false, |asid| asid != vspace.asid()) {
+            return Err(CoreDumpError::VSpaceError(VSpaceError::ASIDMismatch));
+        }
+        self.segments
+            .try_push(Segment::Mapped(*mapping))
+            .map_err(|_| CoreDumpError::TooManySegments)?;
+        self.vspace_asid = Some(vspace.asid());
+        Ok(())
+    }
+
+    /// The size of the core file, in bytes.
+    pub fn size(&self) -> usize {
+        self.contents_offset() + self.segments.iter().map(Segment::file_size).sum::<usize>()
+    }
+
+    fn contents_offset(&self) -> usize {
+        ELF_HEADER_SIZE + (1 + self.segments.len()) * PROGRAM_HEADER_SIZE + NOTE_SIZE
+    }
+
+    /// Write out the core file. `window` is only needed if mappings
+    /// were added with `add_mapping`, and has to be onto their VSpace.
+    pub fn write_to<W: CoreDumpWriter>(
+        &self,
+        writer: &mut W,
+        mut window: Option<&mut MemoryWindow>,
+    ) -> Result<(), CoreDumpError> {
+        if let Some(asid) = self.vspace_asid {
+            match window {
+                Some(ref w) if w.asid() == asid => (),
+                _ => return Err(CoreDumpError::MemoryWindowRequired),
+            }
+        }
+        self.write_elf_header(writer)?;
+
+        let note_offset = ELF_HEADER_SIZE + (1 + self.segments.len()) * PROGRAM_HEADER_SIZE;
+        write_program_header(writer, PT_NOTE, 0, note_offset, 0, NOTE_SIZE, NOTE_SIZE, 4)?;
+        let mut offset = self.contents_offset();
+        for segment in self.segments.iter() {
+            write_program_header(
+                writer,
+                PT_LOAD,
+                segment.flags(),
+                offset,
+                segment.child_vaddr(),
+                segment.file_size(),
+                segment.size(),
+                PageBytes::USIZE,
+            )?;
+            offset += segment.file_size();
+        }
+
+        self.write_note(writer)?;
+        for segment in self.segments.iter() {
+            match (segment, window.as_mut()) {
+                (Segment::Local { contents, .. }, _) => writer.write_all(contents)?,
+                (Segment::Mapped(mapping), _) if !mapping.is_page_granular() => (),
+                (Segment::Mapped(mapping), Some(window)) => {
+                    let mut page = [0u8; PageBytes::USIZE];
+                    let mut vaddr = mapping.vaddr();
+                    while vaddr < mapping.vaddr() + mapping.size_bytes() {
+                        window.read(vaddr, &mut page)?;
+                        writer.write_all(&page)?;
+                        vaddr += PageBytes::USIZE;
+                    }
+                }
+                (Segment::Mapped(_), None) => return Err(CoreDumpError::MemoryWindowRequired),
+            }
+        }
+        Ok(())
+    }
+
+    fn write_elf_header<W: CoreDumpWriter>(&self, writer: &mut W) -> Result<(), CoreDumpError> {
+        let mut header = [0u8; ELF_HEADER_SIZE];
+        header[..7].copy_from_slice(&[
+            0x7f, b'E', b'L', b'F', ELF_CLASS, 1, // little endian
+            1, // EV_CURRENT
+        ]);
+        let mut fields = Fields::new(&mut header[16..]);
+        fields.put_u16(ET_CORE);
+        fields.put_u16(ELF_MACHINE);
+        fields.put_u32(1); // e_version
+        fields.put_word(0); // e_entry
+        fields.put_word(ELF_HEADER_SIZE); // e_phoff
+        fields.put_word(0); // e_shoff
+        fields.put_u32(0); // e_flags
+        fields.put_u16(ELF_HEADER_SIZE as u16);
+        fields.put_u16(PROGRAM_HEADER_SIZE as u16);
+        fields.put_u16(1 + self.segments.len() as u16); // e_phnum
+        fields.put_u16(SECTION_HEADER_SIZE as u16);
+        fields.put_u16(0); // e_shnum
+        fields.put_u16(0); // e_shstrndx
+        writer.write_all(&header)
+    }
+
+    fn write_note<W: CoreDumpWriter>(&self, writer: &mut W) -> Result<(), CoreDumpError> {
+        let mut note_header = [0u8; 12];
+        let mut fields = Fields::new(&mut note_header);
+        fields.put_u32(5); // "CORE" and its terminator
+        fields.put_u32(PRSTATUS_SIZE as u32);
+        fields.put_u32(NT_PRSTATUS);
+        writer.write_all(&note_header)?;
+        writer.write_all(NOTE_NAME)?;
+
+        let mut prstatus = [0u8; PRSTATUS_SIZE];
+        Fields::new(&mut prstatus[PRSTATUS_CURSIG_OFFSET..]).put_u16(self.signal);
+        Fields::new(&mut prstatus[PRSTATUS_PID_OFFSET..]).put_u32(1);
+        let mut registers = Fields::new(&mut prstatus[PRSTATUS_REGISTERS_OFFSET..]);
+        for register in elf_gregset(&self.registers).iter() {
+            registers.put_word(*register);
+        }
+        writer.write_all(&prstatus)
+    }
+}
+
+#[cfg(target_pointer_width = "64")]
+fn write_program_header<W: CoreDumpWriter>(
+    writer: &mut W,
+    kind: u32,
+    flags: u32,
+    offset: usize,
+    vaddr: usize,
+    file_size: usize,
+    memory_size: usize,
+    align: usize,
+) -> Result<(), CoreDumpError> {
+    let mut header = [0u8; PROGRAM_HEADER_SIZE];
+    let mut fields = Fields::new(&mut header);
+    fields.put_u32(kind);
+    fields.put_u32(flags);
+    fields.put_word(offset);
+    fields.put_word(vaddr);
+    fields.put_word(0); // p_paddr
+    fields.put_word(file_size); // p_filesz
+    fields.put_word(memory_size); // p_memsz
+    fields.put_word(align);
+    writer.write_all(&header)
+}
+
+#[cfg(target_pointer_width = "32")]
+fn write_program_header<W: CoreDumpWriter>(
+    writer: &mut W,
+    kind: u32,
+    flags: u32,
+    offset: usize,
+    vaddr: usize,
+    file_size: usize,
+    memory_size: usize,
+    align: usize,
+) -> Result<(), CoreDumpError> {
+    let mut header = [0u8; PROGRAM_HEADER_SIZE];
+    let mut fields = Fields::new(&mut header);
+    fields.put_u32(kind);
+    fields.put_word(offset);
+    fields.put_word(vaddr);
+    fields.put_word(0); // p_paddr
+    fields.put_word(file_size); // p_filesz
+    fields.put_word(memory_size); // p_memsz
+    fields.put_u32(flags);
+    fields.put_word(align);
+    writer.write_all(&header)
+}
+
+/// Fills in consecutive little endian fields of a header.
+struct Fields<'b> {
+    buffer: &'b mut [u8],
+    position: usize,
+}
+
+impl<'b> Fields<'b> {
+    fn new(buffer: &'b mut [u8]) -> Self {
+        Fields {
+            buffer,
+            position: 0,
+        }
+    }
+
+    fn put(&mut self, bytes: &[u8]) {
+        self.buffer[self.position..self.position + bytes.len()].copy_from_slice(bytes);
+        self.position += bytes.len();
+    }
+
+    fn put_u16(&mut self, value: u16) {
+        self.put(&value.to_le_bytes())
+    }
+
+    fn put_u32(&mut self, value: u32) {
+        self.put(&value.to_le_bytes())
+    }
+
+    fn put_word(&mut self, value: usize) {
+        self.put(&value.to_le_bytes())
+    }
+}
@@ -1,14 +1,32 @@
+//! Fault endpoints, and the supervision of children through them.
+//!
+//! A faulted thread is resumed through the reply capability the kernel
+//! leaves with whoever received its fault, which the MCS kernel
+//! replaces with reply objects. Those aren't supported here yet, so
+//! resuming faulted threads is unsupported on MCS: `Supervisor` and
+//! `FaultReplyEndpoint` are only built for the classic scheduler.
+//! Faults can still be set up and received there.
+
 use core::marker::PhantomData;
 
+#[cfg(not(KernelIsMCS))]
+use arrayvec::ArrayVec;
 use sel_claw::*;
+use typenum::{IsGreaterOrEqual, True, Unsigned, U1};
 
 use crate::arch::fault::Fault;
+#[cfg(not(KernelIsMCS))]
+use crate::cap::FaultReplyEndpoint;
 use crate::cap::{
     role, Badge, CNodeRole, CNodeSlot, Cap, ChildCNodeSlot, DirectRetype, Endpoint, LocalCNode,
     LocalCNodeSlot, LocalCap, Untyped,
 };
 use crate::error::SeL4Error;
-use crate::userland::{type_length_in_words, CapRights, IPCBuffer, IPCError, MessageInfo, Sender};
+use crate::userland::{
+    type_length_in_words, CapRights, IPCBuffer, IPCError, MessageInfo, ProcessSetupError, Sender,
+    StackGuardPageCount,
+};
+use crate::vspace::{GuardRegion, VSpaceError};
 
 #[derive(Debug)]
 pub enum FaultManagementError {
@@ -57,23 +75,45 @@ impl<SinkRole: CNodeRole> FaultSinkSetup<SinkRole> {
         })
     }
 
+    /// Mint a source for the sink with the given badge, for a thread to
+    /// be configured with. See `FaultSourceRole` for which CSpace it
+    /// has to live in.
     pub fn add_fault_source(
         &self,
         local_cnode: &LocalCap<LocalCNode>,
-        fault_source_slot: ChildCNodeSlot,
+        fault_source_slot: CNodeSlot<FaultSourceRole>,
         badge: Badge,
-    ) -> Result<FaultSource<role::Child>, FaultManagementError> {
-        if fault_source_slot.cptr == self.sink_cspace_local_cptr {
-            return Err(FaultManagementError::SelfFaultHandlingForbidden);
+    ) -> Result<FaultSource<FaultSourceRole>, FaultManagementError> {
+        // Under the MCS scheduler a local fault source says nothing
+        // about which process's threads it will be given to.
+        #[cfg(not(KernelIsMCS))]
+        {
+            if fault_source_slot.cptr == self.sink_cspace_local_cptr {
+                return Err(FaultManagementError::SelfFaultHandlingForbidden);
+            }
         }
 
-        let child_endpoint_fault_source =
+        let endpoint =
             self.local_endpoint
                 .mint_new(local_cnode, fault_source_slot, CapRights::RWG, badge)?;
 
-        Ok(FaultSource {
-            endpoint: child_endpoint_fault_source,
-        })
+        Ok(FaultSource { endpoint })
+    }
+
+    /// Set up a source for `Timeout` faults, for use with
+    /// `set_timeout_fault_source`. Like any other fault source under
+    /// the MCS scheduler, it stays local.
+    #[cfg(KernelIsMCS)]
+    pub fn add_timeout_fault_source(
+        &self,
+        local_cnode: &LocalCap<LocalCNode>,
+        fault_source_slot: LocalCNodeSlot,
+        badge: Badge,
+    ) -> Result<FaultSource<role::Local>, FaultManagementError> {
+        let endpoint =
+            self.local_endpoint
+                .mint_new(local_cnode, fault_source_slot, CapRights::RWG, badge)?;
+        Ok(FaultSource { endpoint })
     }
 
     pub fn sink(self) -> FaultSink<SinkRole> {
@@ -95,11 +135,29 @@ pub fn setup_fault_endpoint_pair(
     fault_source_slot: ChildCNodeSlot,
     fault_sink_slot: ChildCNodeSlot,
 ) -> Result<(FaultSource<role::Child>, FaultSink<role::Child>), FaultManagementError> {
+    if fault_source_slot.cptr == fault_sink_slot.cptr {
+        return Err(FaultManagementError::SelfFaultHandlingForbidden);
+    }
     let setup = FaultSinkSetup::new(local_cnode, untyped, endpoint_slot, fault_sink_slot)?;
-    let fault_source = setup.add_fault_source(local_cnode, fault_source_slot, Badge::from(0))?;
-    Ok((fault_source, setup.sink()))
+    let endpoint = setup.local_endpoint.mint_new(
+        local_cnode,
+        fault_source_slot,
+        CapRights::RWG,
+        Badge::from(0),
+    )?;
+    Ok((FaultSource { endpoint }, setup.sink()))
 }
 
+/// Where the fault source a thread is configured with has to live. The
+/// classic kernel looks a thread's fault endpoint up in the thread's own
+/// CSpace each time it faults, so there it goes in the child's CNode.
+/// The MCS kernel copies it out of the CSpace of whoever configures the
+/// thread instead, so there it stays local.
+#[cfg(not(KernelIsMCS))]
+pub type FaultSourceRole = role::Child;
+#[cfg(KernelIsMCS)]
+pub type FaultSourceRole = role::Local;
+
 /// The side of a fault endpoint that sends fault messages
 #[derive(Debug)]
 pub struct FaultSource<Role: CNodeRole> {
@@ -113,11 +171,342 @@ pub struct FaultSink<Role: CNodeRole> {
 }
 
 impl FaultSink<role::Local> {
+    /// The kernel keeps the faulted thread's reply capability until this
+    /// thread's next receive, for `FaultReplyEndpoint` to save.
+    #[cfg(not(KernelIsMCS))]
     pub fn wait_for_fault(&self) -> Fault {
         let mut sender: usize = 0;
         let info = unsafe { seL4_Recv(self.endpoint.cptr, &mut sender as *mut usize) }.into();
         (info, Badge::from(sender)).into()
     }
+
+    /// Under the MCS scheduler there is no reply object to receive into,
+    /// so the kernel leaves the faulted thread inactive instead of
+    /// waiting on a reply. Resuming its TCB gets it going again.
+    #[cfg(KernelIsMCS)]
+    pub fn wait_for_fault(&self) -> Fault {
+        let mut sender: usize = 0;
+        let info = unsafe { seL4_Wait(self.endpoint.cptr, &mut sender as *mut usize) }.into();
+        (info, Badge::from(sender)).into()
+    }
+}
+
+/// What a fault means for the thread that took it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultClassification {
+    /// A data access in the guard pages below the thread's stack,
+    /// i.e. the thread ran off the end of its stack.
+    StackOverflow,
+    Other,
+}
+
+impl Fault {
+    /// Classify a fault from a thread whose stack sits above
+    /// `stack_guard`, as given by e.g. `StandardProcess::stack_guard`.
+    pub fn classify<PageCount: Unsigned>(
+        &self,
+        stack_guard: &GuardRegion<PageCount>,
+    ) -> FaultClassification
+    where
+        PageCount: IsGreaterOrEqual<U1, Output = True>,
+    {
+        match self {
+            Fault::VMFault(fault)
+                if !fault.is_instruction_fault && stack_guard.contains(fault.address) =>
+            {
+                FaultClassification::StackOverflow
+            }
+            _ => FaultClassification::Other,
+        }
+    }
+}
+
+/// The most children a single `Supervisor` can watch over
+#[cfg(not(KernelIsMCS))]
+pub const MAX_SUPERVISED_CHILDREN: usize = 16;
+
+#[derive(Debug)]
+pub enum SupervisorError {
+    TooManyChildren,
+    FaultManagementError(FaultManagementError),
+    ProcessSetupError(ProcessSetupError),
+    VSpaceError(VSpaceError),
+    SeL4Error(SeL4Error),
+}
+
+impl From<FaultManagementError> for SupervisorError {
+    fn from(e: FaultManagementError) -> Self {
+        SupervisorError::FaultManagementError(e)
+    }
+}
+
+impl From<ProcessSetupError> for SupervisorError {
+    fn from(e: ProcessSetupError) -> Self {
+        SupervisorError::ProcessSetupError(e)
+    }
+}
+
+impl From<VSpaceError> for SupervisorError {
+    fn from(e: VSpaceError) -> Self {
+        SupervisorError::VSpaceError(e)
+    }
+}
+
+impl From<SeL4Error> for SupervisorError {
+    fn from(e: SeL4Error) -> Self {
+        SupervisorError::SeL4Error(e)
+    }
+}
+
+/// A child process which a `Supervisor` can start, stop and start
+/// again.
+///
+/// Implementations own whatever resources the process is built from,
+/// and are expected to get them back from `stop` (e.g. with
+/// `StandardProcess::destroy`) so that `start` can be called again.
+pub trait Supervised {
+    /// Create the process and start it running, with its faults
+    /// routed to `fault_source`.
+    fn start(&mut self, fault_source: FaultSource<role::Child>) -> Result<(), SupervisorError>;
+
+    /// Stop the process and reclaim the resources `start` used.
+    fn stop(&mut self) -> Result<(), SupervisorError>;
+
+    /// Repair whatever caused `fault` so that the faulted thread can
+    /// carry on from where it stopped. Returning false means the fault
+    /// can't be repaired, and the child is restarted instead.
+    fn fix_up(&mut self, _fault: &Fault) -> bool {
+        false
+    }
+
+    /// The guard below the running process's stack, e.g. from
+    /// `StandardProcess::stack_guard`, which lets the supervisor tell
+    /// a stack overflow apart from other faults.
+    fn stack_guard(&self) -> Option<&GuardRegion<StackGuardPageCount>> {
+        None
+    }
+}
+
+/// What a `Supervisor` does when a child faults
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// Have the child fix up the fault, then resume the faulted
+    /// thread. Falls back to `Restart` if the fault can't be fixed up,
+    /// or if it's a stack overflow, which resuming would only repeat.
+    Resume,
+    /// Stop the child and start it again from scratch.
+    Restart,
+    /// Leave the faulted thread blocked and hand the fault to whoever
+    /// is running the supervisor.
+    Escalate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChildPolicy {
+    pub recovery: Recovery,
+    /// Once a child has faulted this many times it is stopped for
+    /// good rather than recovered. `None` means there is no limit.
+    pub fault_limit: Option<usize>,
+}
+
+/// Identifies one of a `Supervisor`'s children
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChildId(usize);
+
+impl ChildId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+
+    #[cfg(not(KernelIsMCS))]
+    fn badge(self) -> Badge {
+        // Badge 0 is reserved for unbadged senders
+        Badge::from(self.0 + 1)
+    }
+}
+
+/// The outcome of a `Supervisor` handling a fault
+#[derive(Debug)]
+pub enum SupervisorEvent {
+    Resumed(ChildId),
+    Restarted(ChildId),
+    Escalated(ChildId, Fault),
+    /// The child reached its fault limit and has been stopped.
+    GaveUp(ChildId, Fault),
+    /// A fault arrived with a badge that doesn't belong to any child.
+    UnknownSender(Fault),
+}
+
+#[cfg(not(KernelIsMCS))]
+struct SupervisedChild<'a> {
+    child: &'a mut dyn Supervised,
+    policy: ChildPolicy,
+    fault_source_cptr: usize,
+    faults: usize,
+    stack_overflows: usize,
+    running: bool,
+}
+
+/// Watches over a set of child processes, receiving their faults via
+/// badged `FaultSource`s and recovering from them according to each
+/// child's `ChildPolicy`.
+///
+/// Escalated faults are handed back to whoever is running the
+/// supervisor. A supervisor that runs in a supervised process can
+/// pass them further up the tree by giving up itself, leaving its own
+/// supervisor to decide what to do.
+///
+/// Resuming a child relies on saving its reply capability, which the
+/// MCS kernel doesn't offer, so this is only there for the classic
+/// scheduler.
+#[cfg(not(KernelIsMCS))]
+pub struct Supervisor<'a> {
+    setup: FaultSinkSetup<role::Local>,
+    sink: FaultSink<role::Local>,
+    reply_slot: LocalCNodeSlot,
+    children: ArrayVec<[SupervisedChild<'a>; MAX_SUPERVISED_CHILDREN]>,
+}
+
+#[cfg(not(KernelIsMCS))]
+impl<'a> Supervisor<'a> {
+    pub fn new(
+        local_cnode: &LocalCap<LocalCNode>,
+        untyped: LocalCap<Untyped<<Endpoint as DirectRetype>::SizeBits>>,
+        endpoint_slot: LocalCNodeSlot,
+        fault_sink_slot: LocalCNodeSlot,
+        reply_slot: LocalCNodeSlot,
+    ) -> Result<Self, SeL4Error> {
+        let setup = FaultSinkSetup::new(local_cnode, untyped, endpoint_slot, fault_sink_slot)?;
+        let sink = FaultSink {
+            endpoint: Cap {
+                cptr: setup.sink_endpoint.cptr,
+                _role: PhantomData,
+                cap_data: Endpoint {},
+            },
+        };
+        Ok(Supervisor {
+            setup,
+            sink,
+            reply_slot,
+            children: ArrayVec::new(),
+        })
+    }
+
+    /// Start `child`, with its faults routed to this supervisor by way
+    /// of a fault source minted into `fault_source_slot`.
+    pub fn add_child(
+        &mut self,
+        local_cnode: &LocalCap<LocalCNode>,
+        fault_source_slot: ChildCNodeSlot,
+        child: &'a mut dyn Supervised,
+        policy: ChildPolicy,
+    ) -> Result<ChildId, SupervisorError> {
+        if self.children.is_full() {
+            return Err(SupervisorError::TooManyChildren);
+        }
+        let id = ChildId(self.children.len());
+        let fault_source =
+            self.setup
+                .add_fault_source(local_cnode, fault_source_slot, id.badge())?;
+        let fault_source_cptr = fault_source.endpoint.cptr;
+
+        child.start(fault_source)?;
+        self.children.push(SupervisedChild {
+            child,
+            policy,
+            fault_source_cptr,
+            faults: 0,
+            stack_overflows: 0,
+            running: true,
+        });
+        Ok(id)
+    }
+
+    /// The number of faults received from the given child so far
+    pub fn fault_count(&self, id: ChildId) -> usize {
+        self.children[id.0].faults
+    }
+
+    /// How many of the given child's faults were stack overflows, as
+    /// told by its `Supervised::stack_guard`
+    pub fn stack_overflow_count(&self, id: ChildId) -> usize {
+        self.children[id.0].stack_overflows
+    }
+
+    /// Wait for a child to fault and recover from it according to the
+    /// child's policy.
+    pub fn handle_next_fault(&mut self) -> Result<SupervisorEvent, SupervisorError> {
+        let fault = self.sink.wait_for_fault();
+        let index = usize::from(fault.sender()).wrapping_sub(1);
+        if index >= self.children.len() {
+            return Ok(SupervisorEvent::UnknownSender(fault));
+        }
+        let id = ChildId(index);
+        let entry = &mut self.children[index];
+        entry.faults += 1;
+        let classification = entry
+            .child
+            .stack_guard()
+            .map_or(FaultClassification::Other, |guard| fault.classify(guard));
+        if classification == FaultClassification::StackOverflow {
+            entry.stack_overflows += 1;
+        }
+
+        if let Some(limit) = entry.policy.fault_limit {
+            if entry.faults >= limit {
+                entry.child.stop()?;
+                entry.running = false;
+                return Ok(SupervisorEvent::GaveUp(id, fault));
+            }
+        }
+
+        let fault_source = FaultSource {
+            endpoint: Cap {
+                cptr: entry.fault_source_cptr,
+                _role: PhantomData,
+                cap_data: Endpoint {},
+            },
+        };
+        match entry.policy.recovery {
+            Recovery::Escalate => Ok(SupervisorEvent::Escalated(id, fault)),
+            Recovery::Restart => {
+                entry.child.stop()?;
+                entry.child.start(fault_source)?;
+                Ok(SupervisorEvent::Restarted(id))
+            }
+            Recovery::Resume => {
+                // The reply capability has to be saved before the
+                // child's fix-up gets a chance to make any other IPC
+                // calls from this thread.
+                let reply_slot: LocalCNodeSlot =
+                    Cap::internal_new(self.reply_slot.cptr, self.reply_slot.cap_data.offset);
+                let reply = LocalCap::<FaultReplyEndpoint>::save_caller_and_create(reply_slot)?;
+                if classification != FaultClassification::StackOverflow
+                    && entry.child.fix_up(&fault)
+                {
+                    reply.resume_faulted_thread();
+                    Ok(SupervisorEvent::Resumed(id))
+                } else {
+                    entry.child.stop()?;
+                    reply.destroy()?;
+                    entry.child.start(fault_source)?;
+                    Ok(SupervisorEvent::Restarted(id))
+                }
+            }
+        }
+    }
+
+    /// Handle faults until one is escalated, one arrives from an
+    /// unknown sender, or every child has been given up on.
+    pub fn supervise(&mut self) -> Result<SupervisorEvent, SupervisorError> {
+        loop {
+            match self.handle_next_fault()? {
+                SupervisorEvent::Resumed(_) | SupervisorEvent::Restarted(_) => (),
+                SupervisorEvent::GaveUp(..) if self.children.iter().any(|c| c.running) => (),
+                event => return Ok(event),
+            }
+        }
+    }
 }
 
 pub fn fault_or_message_channel<Msg: Sized, HandlerRole: CNodeRole>(
@@ -192,8 +581,13 @@ impl<Msg: Sized> FaultOrMessageHandler<Msg, role::Local> {
         let ipc_buffer: IPCBuffer<Msg, ()> = unsafe { IPCBuffer::unchecked_new() };
         let mut sender: usize = 0;
         // Do a regular receive to seed our initial value
+        #[cfg(not(KernelIsMCS))]
         let msg_info: MessageInfo =
             unsafe { seL4_Recv(self.endpoint.cptr, &mut sender as *mut usize) }.into();
+        // Nothing is ever replied to, so there's no need for a reply object
+        #[cfg(KernelIsMCS)]
+        let msg_info: MessageInfo =
+            unsafe { seL4_Wait(self.endpoint.cptr, &mut sender as *mut usize) }.into();
 
         let badge = Badge::from(sender);
         if msg_info.has_null_fault_label() {
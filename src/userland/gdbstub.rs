@@ -0,0 +1,996 @@
+//! A stub for the GDB Remote Serial Protocol, for debugging a child
+//! process.
+//!
+//! The stub talks to GDB over a `GdbConnection`, usually a UART: e.g.
+//! `target remote /dev/ttyUSB0` on hardware, or `target remote
+//! localhost:8888` under qemu with `-serial tcp::8888,server`. The
+//! debugged process must send its faults to a `FaultSink` that only the
+//! stub reads from, which is how the stub learns that the process has
+//! stopped.
+//!
+//! The stub reaches the process's memory through a `MemoryWindow`,
+//! which maps its frames into a scratch region one page at a time. It
+//! is best run as a service process of its own, with `serve`, so that
+//! it can wait on the process's faults while the rest of the system
+//! gets on with its work; `GdbStub` can also be driven directly by the
+//! process's parent.
+//!
+//! Software breakpoints are written over the process's code, so they
+//! need it to have a writable copy of its code image (see
+//! `ProcessCodeImageConfig::ReadWritable`). Hardware breakpoints and
+//! watchpoints use the debug registers, and are only available when the
+//! kernel is built with `HardwareDebugAPI`. Without it, GDB is told
+//! they aren't supported.
+//!
+//! Single stepping uses the debug registers too if there are any, and
+//! otherwise plants breakpoints wherever the instruction can go next.
+//! Continuing from a software breakpoint steps over the instruction it
+//! replaced first, so that the process doesn't hit it again straight
+//! away. Without the debug registers, an instruction whose destination
+//! can't be told from the instruction alone, such as anything in Thumb
+//! state, can't be stepped, and GDB gets an error instead.
+//!
+//! Once a process is continued it runs until it next faults; GDB can't
+//! interrupt it.
+use core::fmt::{self, Write};
+use core::marker::PhantomData;
+
+use arrayvec::ArrayVec;
+
+use crate::arch::fault::Fault;
+#[cfg(not(HardwareDebugAPI))]
+use crate::arch::userland::gdb::next_instructions;
+use crate::arch::userland::gdb::{
+    gdb_register, gdb_register_size, GDB_REGISTER_COUNT, SOFTWARE_BREAKPOINT, TARGET_XML,
+};
+use crate::arch::{PageBits, UserContext};
+use crate::cap::{
+    page_state, role, CNodeRole, CNodeSlotsData, Cap, InternalASID, LocalCNode, LocalCap, Page,
+    ThreadControlBlock, Untyped, WCNodeSlotsData, WeakCapRange, WeakCopyError,
+};
+#[cfg(HardwareDebugAPI)]
+use crate::cap::{Breakpoint, BreakpointAccess, BreakpointType};
+use crate::error::SeL4Error;
+use crate::userland::{FaultSink, RetypeForSetup};
+use crate::vspace::{Mapping, MemoryWindow, VSpace, VSpaceError, MAX_RECORDED_MAPPINGS};
+use typenum::U2;
+
+/// The largest packet the stub will send or receive.
+pub const MAX_GDB_PACKET_SIZE: usize = 1024;
+
+/// The most breakpoints and watchpoints of each sort, software and
+/// hardware, a `GdbStub` will set at once.
+pub const MAX_GDB_BREAKPOINTS: usize = 16;
+
+// GDB's own signal numbers, which it uses on every target
+const GDB_SIGNAL_ILL: u8 = 4;
+const GDB_SIGNAL_TRAP: u8 = 5;
+const GDB_SIGNAL_SEGV: u8 = 11;
+const GDB_SIGNAL_SYS: u8 = 12;
+
+#[derive(Debug)]
+pub enum GdbStubError {
+    SeL4Error(SeL4Error),
+    VSpaceError(VSpaceError),
+}
+
+impl From<SeL4Error> for GdbStubError {
+    fn from(s: SeL4Error) -> Self {
+        GdbStubError::SeL4Error(s)
+    }
+}
+
+impl From<VSpaceError> for GdbStubError {
+    fn from(e: VSpaceError) -> Self {
+        GdbStubError::VSpaceError(e)
+    }
+}
+
+/// The byte stream between the stub and GDB.
+pub trait GdbConnection {
+    /// Wait for the next byte from GDB.
+    fn read_byte(&mut self) -> u8;
+
+    fn write_byte(&mut self, byte: u8);
+}
+
+/// A thread that can be debugged through a `GdbStub`.
+pub trait DebugTarget {
+    fn suspend(&mut self) -> Result<(), SeL4Error>;
+
+    /// Let the thread run again after it was suspended or faulted.
+    fn resume(&mut self) -> Result<(), SeL4Error>;
+
+    fn read_registers(&self) -> Result<UserContext, SeL4Error>;
+
+    fn write_registers(&mut self, registers: &UserContext) -> Result<(), SeL4Error>;
+
+    #[cfg(HardwareDebugAPI)]
+    fn set_breakpoint(&mut self, number: u16, breakpoint: Breakpoint) -> Result<(), SeL4Error>;
+
+    #[cfg(HardwareDebugAPI)]
+    fn unset_breakpoint(&mut self, number: u16) -> Result<(), SeL4Error>;
+
+    #[cfg(HardwareDebugAPI)]
+    fn configure_single_stepping(
+        &mut self,
+        number: u16,
+        num_instructions: usize,
+    ) -> Result<bool, SeL4Error>;
+}
+
+/// A bare thread, e.g. one handed to a `serve`ing stub.
+impl DebugTarget for LocalCap<ThreadControlBlock> {
+    fn suspend(&mut self) -> Result<(), SeL4Error> {
+        LocalCap::<ThreadControlBlock>::suspend(self)
+    }
+
+    fn resume(&mut self) -> Result<(), SeL4Error> {
+        LocalCap::<ThreadControlBlock>::resume(self)
+    }
+
+    fn read_registers(&self) -> Result<UserContext, SeL4Error> {
+        LocalCap::<ThreadControlBlock>::read_registers(self)
+    }
+
+    fn write_registers(&mut self, registers: &UserContext) -> Result<(), SeL4Error> {
+        LocalCap::<ThreadControlBlock>::write_registers(self, registers, false)
+    }
+
+    #[cfg(HardwareDebugAPI)]
+    fn set_breakpoint(&mut self, number: u16, breakpoint: Breakpoint) -> Result<(), SeL4Error> {
+        LocalCap::<ThreadControlBlock>::set_breakpoint(self, number, breakpoint)
+    }
+
+    #[cfg(HardwareDebugAPI)]
+    fn unset_breakpoint(&mut self, number: u16) -> Result<(), SeL4Error> {
+        LocalCap::<ThreadControlBlock>::unset_breakpoint(self, number)
+    }
+
+    #[cfg(HardwareDebugAPI)]
+    fn configure_single_stepping(
+        &mut self,
+        number: u16,
+        num_instructions: usize,
+    ) -> Result<bool, SeL4Error> {
+        LocalCap::<ThreadControlBlock>::configure_single_stepping(self, number, num_instructions)
+    }
+}
+
+/// How a debugging session ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GdbSessionEnd {
+    /// GDB detached, and the process was left running.
+    Detached,
+    /// GDB killed the process, which was left suspended.
+    Killed,
+}
+
+type Packet = ArrayVec<[u8; MAX_GDB_PACKET_SIZE]>;
+
+/// A packet being built up to send back to GDB.
+struct Reply {
+    data: Packet,
+}
+
+impl Reply {
+    fn push_str(&mut self, s: &str) {
+        let _ = self.write_str(s);
+    }
+
+    fn hex_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            let _ = write!(self, "{:02x}", byte);
+        }
+    }
+
+    /// Registers go over the wire in the target's byte order.
+    fn hex_register(&mut self, value: usize, size: usize) {
+        self.hex_bytes(&value.to_le_bytes()[..size]);
+    }
+}
+
+impl fmt::Write for Reply {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.data.try_push(byte).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
+/// A breakpoint instruction written over the debugged process's code,
+/// and the bytes it replaced.
+struct SoftwareBreakpoint {
+    vaddr: usize,
+    replaced: [u8; SOFTWARE_BREAKPOINT.len()],
+}
+
+/// How running a process for a single instruction went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    /// The process ran the instruction and stopped at the next one.
+    Stepped,
+    /// The process stopped on some other fault along the way.
+    Faulted,
+    /// There's no way to step the instruction at the program counter.
+    Unsupported,
+}
+
+/// Serves GDB's requests about a single process.
+pub struct GdbStub<'a, C: GdbConnection, T: DebugTarget> {
+    connection: C,
+    target: &'a mut T,
+    sink: &'a FaultSink<role::Local>,
+    memory: MemoryWindow<'a>,
+    stop_signal: u8,
+    software_breakpoints: ArrayVec<[SoftwareBreakpoint; MAX_GDB_BREAKPOINTS]>,
+    #[cfg(HardwareDebugAPI)]
+    breakpoints: ArrayVec<[(u16, Breakpoint); MAX_GDB_BREAKPOINTS]>,
+}
+
+impl<'a, C: GdbConnection, T: DebugTarget> GdbStub<'a, C, T> {
+    /// `sink` must be where `target` sends its faults, and `memory` a
+    /// window onto the VSpace it runs in.
+    pub fn new(
+        connection: C,
+        target: &'a mut T,
+        sink: &'a FaultSink<role::Local>,
+        memory: MemoryWindow<'a>,
+    ) -> Self {
+        GdbStub {
+            connection,
+            target,
+            sink,
+            memory,
+            stop_signal: GDB_SIGNAL_TRAP,
+            software_breakpoints: ArrayVec::new(),
+            #[cfg(HardwareDebugAPI)]
+            breakpoints: ArrayVec::new(),
+        }
+    }
+
+    /// Serve GDB until it detaches or kills the process. `stopped_by`
+    /// is the fault the process is blocked on, if it has faulted;
+    /// otherwise it's suspended first.
+    pub fn run(&mut self, stopped_by: Option<&Fault>) -> Result<GdbSessionEnd, GdbStubError> {
+        match stopped_by {
+            Some(fault) => self.stop_signal = self.stop_signal(fault)?,
+            None => {
+                self.target.suspend()?;
+                self.stop_signal = GDB_SIGNAL_TRAP;
+            }
+        }
+
+        loop {
+            let packet = self.receive_packet();
+            let mut reply = Reply {
+                data: ArrayVec::new(),
+            };
+            let end = self.handle_packet(&packet, &mut reply)?;
+            // A kill request is the one that gets no reply
+            if end != Some(GdbSessionEnd::Killed) {
+                self.send_packet(&reply.data);
+            }
+            if let Some(end) = end {
+                return Ok(end);
+            }
+        }
+    }
+
+    fn handle_packet(
+        &mut self,
+        packet: &[u8],
+        reply: &mut Reply,
+    ) -> Result<Option<GdbSessionEnd>, GdbStubError> {
+        let (command, args) = match packet.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(None),
+        };
+        match command {
+            b'?' => self.stop_reply(reply),
+            b'g' => {
+                let mut registers = self.target.read_registers()?;
+                for number in 0..GDB_REGISTER_COUNT {
+                    if let Some(value) = gdb_register(&mut registers, number) {
+                        reply.hex_register(*value, gdb_register_size(number));
+                    }
+                }
+            }
+            b'G' => {
+                let mut registers = self.target.read_registers()?;
+                let mut values = args;
+                for number in 0..GDB_REGISTER_COUNT {
+                    let digits = 2 * gdb_register_size(number);
+                    if values.len() < digits {
+                        break;
+                    }
+                    let (value, rest) = values.split_at(digits);
+                    if let (Some(register), Some(value)) =
+                        (gdb_register(&mut registers, number), parse_hex_le(value))
+                    {
+                        *register = value;
+                    }
+                    values = rest;
+                }
+                self.target.write_registers(&registers)?;
+                reply.push_str("OK");
+            }
+            b'p' => {
+                let mut registers = self.target.read_registers()?;
+                match parse_hex(args).and_then(|number| {
+                    gdb_register(&mut registers, number)
+                        .map(|value| (*value, gdb_register_size(number)))
+                }) {
+                    Some((value, size)) => reply.hex_register(value, size),
+                    None => reply.push_str("E00"),
+                }
+            }
+            b'P' => {
+                let mut registers = self.target.read_registers()?;
+                let assignment = split_once(args, b'=')
+                    .and_then(|(number, value)| Some((parse_hex(number)?, parse_hex_le(value)?)));
+                match assignment.and_then(|(number, value)| {
+                    gdb_register(&mut registers, number).map(|register| *register = value)
+                }) {
+                    Some(()) => {
+                        self.target.write_registers(&registers)?;
+                        reply.push_str("OK");
+                    }
+                    None => reply.push_str("E00"),
+                }
+            }
+            b'm' => {
+                let mut buffer = [0u8; MAX_GDB_PACKET_SIZE / 2];
+                match split_once(args, b',')
+                    .and_then(|(addr, len)| Some((parse_hex(addr)?, parse_hex(len)?)))
+                {
+                    Some((vaddr, len)) => {
+                        // Every byte takes two hex digits
+                        let buffer = &mut buffer[..len.min(MAX_GDB_PACKET_SIZE / 2)];
+                        match self.read_memory(vaddr, buffer) {
+                            Ok(()) => reply.hex_bytes(buffer),
+                            Err(_) => reply.push_str("E14"),
+                        }
+                    }
+                    None => reply.push_str("E00"),
+                }
+            }
+            b'M' => {
+                let mut buffer = [0u8; MAX_GDB_PACKET_SIZE / 2];
+                let request = split_once(args, b':').and_then(|(range, data)| {
+                    let (addr, len) = split_once(range, b',')?;
+                    Some((parse_hex(addr)?, parse_hex(len)?, data))
+                });
+                match request {
+                    Some((vaddr, len, data)) if len <= buffer.len() && data.len() == 2 * len => {
+                        let buffer = &mut buffer[..len];
+                        for (byte, digits) in buffer.iter_mut().zip(data.chunks(2)) {
+                            *byte = parse_hex(digits).unwrap_or(0) as u8;
+                        }
+                        match self.write_memory(vaddr, buffer) {
+                            Ok(()) => reply.push_str("OK"),
+                            Err(_) => reply.push_str("E14"),
+                        }
+                    }
+                    _ => reply.push_str("E00"),
+                }
+            }
+            b'c' => {
+                self.jump_to(args)?;
+                let step = if self.breakpoint_at_pc()? {
+                    self.step_instruction()?
+                } else {
+                    Step::Stepped
+                };
+                match step {
+                    Step::Stepped => {
+                        self.continue_until_fault()?;
+                        self.stop_reply(reply);
+                    }
+                    Step::Faulted => self.stop_reply(reply),
+                    Step::Unsupported => reply.push_str("E00"),
+                }
+            }
+            b's' => {
+                self.jump_to(args)?;
+                match self.step_instruction()? {
+                    Step::Unsupported => reply.push_str("E00"),
+                    _ => self.stop_reply(reply),
+                }
+            }
+            b'Z' | b'z' => self.change_breakpoint(command == b'Z', args, reply)?,
+            b'D' => {
+                self.clear_breakpoints()?;
+                self.target.resume()?;
+                reply.push_str("OK");
+                return Ok(Some(GdbSessionEnd::Detached));
+            }
+            b'k' => {
+                self.clear_breakpoints()?;
+                self.target.suspend()?;
+                return Ok(Some(GdbSessionEnd::Killed));
+            }
+            // There's only the one thread
+            b'H' | b'T' => reply.push_str("OK"),
+            b'q' => self.query(args, reply),
+            // Anything else isn't supported
+            _ => (),
+        }
+        Ok(None)
+    }
+
+    fn query(&mut self, args: &[u8], reply: &mut Reply) {
+        const FEATURES: &[u8] = b"Xfer:features:read:target.xml:";
+        if args.starts_with(b"Supported") {
+            let _ = write!(
+                reply,
+                "PacketSize={:x};qXfer:features:read+",
+                MAX_GDB_PACKET_SIZE
+            );
+        } else if args.starts_with(FEATURES) {
+            let range = split_once(&args[FEATURES.len()..], b',')
+                .and_then(|(offset, len)| Some((parse_hex(offset)?, parse_hex(len)?)));
+            match range {
+                Some((offset, len)) => {
+                    let xml = TARGET_XML.as_bytes();
+                    let start = offset.min(xml.len());
+                    // Leave room for the 'm' or 'l'
+                    let end = start + len.min(xml.len() - start).min(MAX_GDB_PACKET_SIZE - 1);
+                    reply.push_str(if end == xml.len() { "l" } else { "m" });
+                    for byte in &xml[start..end] {
+                        let _ = reply.data.try_push(*byte);
+                    }
+                }
+                None => reply.push_str("E00"),
+            }
+        } else if args == b"Attached" {
+            reply.push_str("1");
+        } else if args == b"C" {
+            reply.push_str("QC1");
+        } else if args == b"fThreadInfo" {
+            reply.push_str("m1");
+        } else if args == b"sThreadInfo" {
+            reply.push_str("l");
+        }
+    }
+
+    fn stop_reply(&self, reply: &mut Reply) {
+        let _ = write!(reply, "S{:02x}", self.stop_signal);
+    }
+
+    /// Move the program counter to the address given with a continue or
+    /// step request, if there is one.
+    fn jump_to(&mut self, address: &[u8]) -> Result<(), SeL4Error> {
+        if let Some(pc) = parse_hex(address) {
+            let mut registers = self.target.read_registers()?;
+            registers.pc = pc;
+            self.target.write_registers(&registers)?;
+        }
+        Ok(())
+    }
+
+    fn continue_until_fault(&mut self) -> Result<(), SeL4Error> {
+        let fault = self.resume_until_fault()?;
+        self.stop_signal = self.stop_signal(&fault)?;
+        Ok(())
+    }
+
+    fn resume_until_fault(&mut self) -> Result<Fault, SeL4Error> {
+        self.target.resume()?;
+        Ok(self.sink.wait_for_fault())
+    }
+
+    /// The signal GDB should report for the process having stopped on
+    /// `fault`. Whatever the kernel made of a software breakpoint, it's
+    /// a trap as far as GDB is concerned.
+    fn stop_signal(&self, fault: &Fault) -> Result<u8, SeL4Error> {
+        if self.breakpoint_at_pc()? {
+            return Ok(GDB_SIGNAL_TRAP);
+        }
+        Ok(stop_signal(fault))
+    }
+
+    /// Whether the process is stopped at one of the software
+    /// breakpoints.
+    fn breakpoint_at_pc(&self) -> Result<bool, SeL4Error> {
+        let pc = self.target.read_registers()?.pc;
+        Ok(self.software_breakpoints.iter().any(|b| b.vaddr == pc))
+    }
+
+    /// Read the process's memory as GDB expects to see it, without the
+    /// breakpoint instructions written over it.
+    fn read_memory(&mut self, vaddr: usize, buffer: &mut [u8]) -> Result<(), VSpaceError> {
+        self.memory.read(vaddr, buffer)?;
+        for breakpoint in &self.software_breakpoints {
+            for (offset, byte) in overlap(vaddr, buffer.len(), breakpoint) {
+                buffer[offset] = breakpoint.replaced[byte];
+            }
+        }
+        Ok(())
+    }
+
+    /// Write into the process's memory, keeping any breakpoints in the
+    /// way in place but replacing what they'll put back when removed.
+    fn write_memory(&mut self, vaddr: usize, data: &mut [u8]) -> Result<(), VSpaceError> {
+        for breakpoint in &mut self.software_breakpoints {
+            for (offset, byte) in overlap(vaddr, data.len(), breakpoint) {
+                breakpoint.replaced[byte] = data[offset];
+                data[offset] = SOFTWARE_BREAKPOINT[byte];
+            }
+        }
+        self.memory.write(vaddr, data)
+    }
+
+    /// Run the process for a single instruction, with any software
+    /// breakpoint at the program counter taken out of its way.
+    fn step_instruction(&mut self) -> Result<Step, GdbStubError> {
+        let registers = self.target.read_registers()?;
+        let lifted = self
+            .software_breakpoints
+            .iter()
+            .find(|b| b.vaddr == registers.pc)
+            .map(|b| b.replaced);
+        if let Some(replaced) = lifted {
+            self.memory.write_instructions(registers.pc, &replaced)?;
+        }
+        let step = self.run_one_instruction(&registers);
+        if lifted.is_some() {
+            self.memory
+                .write_instructions(registers.pc, &SOFTWARE_BREAKPOINT)?;
+        }
+        let step = step?;
+        if step == Step::Stepped {
+            self.stop_signal = GDB_SIGNAL_TRAP;
+        }
+        Ok(step)
+    }
+
+    #[cfg(HardwareDebugAPI)]
+    fn run_one_instruction(&mut self, _registers: &UserContext) -> Result<Step, GdbStubError> {
+        let number = match self.free_breakpoint_numbers().next() {
+            Some(number) => number,
+            None => return Ok(Step::Unsupported),
+        };
+        self.target.configure_single_stepping(number, 1)?;
+        let fault = self.resume_until_fault()?;
+        self.target.configure_single_stepping(number, 0)?;
+        match fault {
+            Fault::DebugException(ref e) if e.reason == BreakpointType::SingleStep => {
+                Ok(Step::Stepped)
+            }
+            fault => {
+                self.stop_signal = stop_signal(&fault);
+                Ok(Step::Faulted)
+            }
+        }
+    }
+
+    /// Without the debug registers, a breakpoint is planted at each
+    /// place the instruction can go next, and taken out again once the
+    /// process stops.
+    #[cfg(not(HardwareDebugAPI))]
+    fn run_one_instruction(&mut self, registers: &UserContext) -> Result<Step, GdbStubError> {
+        let mut instruction = [0u8; 4];
+        self.read_memory(registers.pc, &mut instruction)?;
+        let next = match next_instructions(u32::from_le_bytes(instruction), registers) {
+            Some(next) => next,
+            None => return Ok(Step::Unsupported),
+        };
+
+        let mut planted: ArrayVec<[SoftwareBreakpoint; 2]> = ArrayVec::new();
+        let mut plantable = true;
+        for &vaddr in next.iter() {
+            // A branch to itself never gets anywhere else
+            if vaddr == registers.pc
+                || self
+                    .software_breakpoints
+                    .iter()
+                    .chain(planted.iter())
+                    .any(|b| b.vaddr == vaddr)
+            {
+                continue;
+            }
+            let mut replaced = [0u8; SOFTWARE_BREAKPOINT.len()];
+            let written = self
+                .memory
+                .read(vaddr, &mut replaced)
+                .and_then(|_| self.memory.write_instructions(vaddr, &SOFTWARE_BREAKPOINT));
+            if written.is_err() {
+                plantable = false;
+                break;
+            }
+            planted.push(SoftwareBreakpoint { vaddr, replaced });
+        }
+
+        let fault = if plantable {
+            Some(self.resume_until_fault())
+        } else {
+            None
+        };
+        for breakpoint in planted.iter() {
+            self.memory
+                .write_instructions(breakpoint.vaddr, &breakpoint.replaced)?;
+        }
+        let fault = match fault {
+            Some(fault) => fault?,
+            None => return Ok(Step::Unsupported),
+        };
+        if next.contains(&self.target.read_registers()?.pc) {
+            Ok(Step::Stepped)
+        } else {
+            self.stop_signal = stop_signal(&fault);
+            Ok(Step::Faulted)
+        }
+    }
+
+    /// Handle a `Z` (insert) or `z` (remove) request, whose arguments
+    /// are `type,addr,kind`.
+    fn change_breakpoint(
+        &mut self,
+        insert: bool,
+        args: &[u8],
+        reply: &mut Reply,
+    ) -> Result<(), GdbStubError> {
+        let request = split_once(args, b',').and_then(|(kind, rest)| {
+            let (addr, size) = split_once(rest, b',')?;
+            Some((kind, parse_hex(addr)?, parse_hex(size)?))
+        });
+        match request {
+            Some((b"0", vaddr, size)) => {
+                match self.change_software_breakpoint(insert, vaddr, size) {
+                    Ok(true) => reply.push_str("OK"),
+                    Ok(false) => reply.push_str("E00"),
+                    Err(_) => reply.push_str("E14"),
+                }
+            }
+            Some((kind, vaddr, size)) => {
+                self.change_hardware_breakpoint(insert, kind, vaddr, size, reply)?
+            }
+            None => reply.push_str("E00"),
+        }
+        Ok(())
+    }
+
+    /// Write a breakpoint instruction over the code at `vaddr`, or put
+    /// back what it replaced. Returns whether there was anything to do.
+    fn change_software_breakpoint(
+        &mut self,
+        insert: bool,
+        vaddr: usize,
+        size: usize,
+    ) -> Result<bool, VSpaceError> {
+        let index = self
+            .software_breakpoints
+            .iter()
+            .position(|b| b.vaddr == vaddr);
+        match (insert, index) {
+            // GDB may insert a breakpoint that's already there
+            (true, Some(_)) => Ok(true),
+            (true, None) => {
+                if size != SOFTWARE_BREAKPOINT.len() || self.software_breakpoints.is_full() {
+                    return Ok(false);
+                }
+                let mut replaced = [0u8; SOFTWARE_BREAKPOINT.len()];
+                self.memory.read(vaddr, &mut replaced)?;
+                self.memory
+                    .write_instructions(vaddr, &SOFTWARE_BREAKPOINT)?;
+                self.software_breakpoints
+                    .push(SoftwareBreakpoint { vaddr, replaced });
+                Ok(true)
+            }
+            (false, Some(index)) => {
+                let breakpoint = self.software_breakpoints.remove(index);
+                self.memory
+                    .write_instructions(breakpoint.vaddr, &breakpoint.replaced)?;
+                Ok(true)
+            }
+            (false, None) => Ok(false),
+        }
+    }
+
+    #[cfg(not(HardwareDebugAPI))]
+    fn change_hardware_breakpoint(
+        &mut self,
+        _insert: bool,
+        _kind: &[u8],
+        _vaddr: usize,
+        _size: usize,
+        _reply: &mut Reply,
+    ) -> Result<(), GdbStubError> {
+        Ok(())
+    }
+
+    #[cfg(HardwareDebugAPI)]
+    fn change_hardware_breakpoint(
+        &mut self,
+        insert: bool,
+        kind: &[u8],
+        vaddr: usize,
+        size: usize,
+        reply: &mut Reply,
+    ) -> Result<(), GdbStubError> {
+        let breakpoint = match kind {
+            b"1" => Breakpoint::instruction(vaddr),
+            b"2" => Breakpoint::data(vaddr, size, BreakpointAccess::Write),
+            b"3" => Breakpoint::data(vaddr, size, BreakpointAccess::Read),
+            b"4" => Breakpoint::data(vaddr, size, BreakpointAccess::ReadWrite),
+            // An empty reply says this kind isn't supported
+            _ => return Ok(()),
+        };
+
+        if insert {
+            let mut inserted = false;
+            if !self.breakpoints.is_full() {
+                // Not every debug register can hold every kind of
+                // breakpoint, so try each free one in turn
+                let mut numbers = self.free_breakpoint_numbers();
+                while let Some(number) = numbers.next() {
+                    if self.target.set_breakpoint(number, breakpoint).is_ok() {
+                        self.breakpoints.push((number, breakpoint));
+                        inserted = true;
+                        break;
+                    }
+                }
+            }
+            reply.push_str(if inserted { "OK" } else { "E00" });
+        } else {
+            let index = self.breakpoints.iter().position(|(_, set)| {
+                set.vaddr == breakpoint.vaddr
+                    && (set.kind == BreakpointType::Instruction)
+                        == (breakpoint.kind == BreakpointType::Instruction)
+            });
+            match index {
+                Some(index) => {
+                    let (number, _) = self.breakpoints.remove(index);
+                    self.target.unset_breakpoint(number)?;
+                    reply.push_str("OK");
+                }
+                None => reply.push_str("E00"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Debug register numbers that no breakpoint is using.
+    #[cfg(HardwareDebugAPI)]
+    fn free_breakpoint_numbers(&self) -> impl Iterator<Item = u16> {
+        let used: ArrayVec<[u16; MAX_GDB_BREAKPOINTS]> =
+            self.breakpoints.iter().map(|(number, _)| *number).collect();
+        (0..MAX_GDB_BREAKPOINTS as u16).filter(move |number| !used.contains(number))
+    }
+
+    /// Take out every breakpoint, so the process can run on without
+    /// the stub.
+    fn clear_breakpoints(&mut self) -> Result<(), GdbStubError> {
+        while let Some(breakpoint) = self.software_breakpoints.pop() {
+            self.memory
+                .write_instructions(breakpoint.vaddr, &breakpoint.replaced)?;
+        }
+        #[cfg(HardwareDebugAPI)]
+        while let Some((number, _)) = self.breakpoints.pop() {
+            self.target.unset_breakpoint(number)?;
+        }
+        Ok(())
+    }
+
+    /// Wait for a well-formed packet from GDB, acknowledging it, and
+    /// return its contents.
+    fn receive_packet(&mut self) -> Packet {
+        loop {
+            // Skip anything outside a packet, such as acknowledgements
+            // and interrupt requests
+            while self.connection.read_byte() != b'$' {}
+
+            let mut packet = Packet::new();
+            let mut checksum = 0u8;
+            let mut overflowed = false;
+            loop {
+                let byte = self.connection.read_byte();
+                if byte == b'#' {
+                    break;
+                }
+                checksum = checksum.wrapping_add(byte);
+                overflowed |= packet.try_push(byte).is_err();
+            }
+            let expected = [self.connection.read_byte(), self.connection.read_byte()];
+
+            if !overflowed && parse_hex(&expected) == Some(checksum as usize) {
+                self.connection.write_byte(b'+');
+                return packet;
+            }
+            self.connection.write_byte(b'-');
+        }
+    }
+
+    /// Send a packet, resending it until GDB acknowledges it.
+    fn send_packet(&mut self, data: &[u8]) {
+        loop {
+            self.connection.write_byte(b'$');
+            let mut checksum = 0u8;
+            for &byte in data {
+                let escaped: &[u8] = match byte {
+                    b'#' | b'$' | b'}' | b'*' => &[b'}', byte ^ 0x20],
+                    _ => &[byte],
+                };
+                for &byte in escaped {
+                    checksum = checksum.wrapping_add(byte);
+                    self.connection.write_byte(byte);
+                }
+            }
+            self.connection.write_byte(b'#');
+            for &byte in hex_digits(checksum).iter() {
+                self.connection.write_byte(byte);
+            }
+
+            loop {
+                match self.connection.read_byte() {
+                    b'+' => return,
+                    b'-' => break,
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+/// The memory of the process a `serve`ing stub debugs: a copy of its
+/// VSpace's mappings, made of copies of the frame caps behind them in
+/// the stub's own CSpace.
+pub struct DebuggeeMemory<Role: CNodeRole> {
+    mappings: ArrayVec<[Mapping; MAX_RECORDED_MAPPINGS]>,
+    asid: InternalASID,
+    _role: PhantomData<Role>,
+}
+
+impl DebuggeeMemory<role::Child> {
+    /// Copy the caps to every frame `vspace` has mapped into `slots`,
+    /// which are in the CSpace of the stub's process. This is only
+    /// possible while all of `vspace`'s mappings fit in its record, and
+    /// only covers what is mapped by the time it's called.
+    pub fn new(
+        vspace: &VSpace,
+        parent_cnode: &LocalCap<LocalCNode>,
+        slots: &mut LocalCap<WCNodeSlotsData<role::Child>>,
+    ) -> Result<Self, VSpaceError> {
+        let mut mappings = ArrayVec::new();
+        for mapping in vspace.mappings()?.iter() {
+            let frames: WeakCapRange<Page<page_state::Unmapped>, role::Local> = WeakCapRange::new(
+                mapping.start_cptr(),
+                Page {
+                    state: page_state::Unmapped {},
+                    _granule: PhantomData,
+                },
+                mapping.frame_count(),
+            );
+            let copies =
+                frames
+                    .copy(parent_cnode, slots, mapping.rights())
+                    .map_err(|e| match e {
+                        WeakCopyError::NotEnoughSlots => VSpaceError::InsufficientCNodeSlots,
+                        WeakCopyError::SeL4Error(e) => VSpaceError::SeL4Error(e),
+                    })?;
+            // The record holds as many mappings as this does
+            mappings.push(mapping.with_start_cptr(copies.start_cptr));
+        }
+        Ok(DebuggeeMemory {
+            mappings,
+            asid: vspace.asid(),
+            _role: PhantomData,
+        })
+    }
+}
+
+/// What a process running `serve` needs from whoever sets it up.
+pub struct GdbStubParams<Role: CNodeRole> {
+    /// A copy of the debugged process's TCB. The process should be set
+    /// up but not yet started: the stub starts it when GDB first
+    /// continues.
+    pub debuggee: Cap<ThreadControlBlock, Role>,
+    /// Where the debugged process sends its faults
+    pub sink: FaultSink<Role>,
+    pub memory: DebuggeeMemory<Role>,
+    /// For the page given up to reserve the stub's scratch region
+    pub scratch_ut: Cap<Untyped<PageBits>, Role>,
+    /// One slot for that page, and one for each frame cap while it's
+    /// mapped into the scratch region
+    pub scratch_slots: Cap<CNodeSlotsData<U2, Role>, Role>,
+}
+
+impl RetypeForSetup for GdbStubParams<role::Local> {
+    type Output = GdbStubParams<role::Child>;
+}
+
+/// Run a stub as a process of its own, e.g. from the entry point of a
+/// `SelfHostedProcess`. GDB is served first while the debugged process
+/// has yet to start, and then every time it faults, until GDB kills it.
+pub fn serve<C: GdbConnection>(
+    mut vspace: VSpace,
+    params: GdbStubParams<role::Local>,
+    connection: C,
+) -> Result<(), GdbStubError> {
+    let GdbStubParams {
+        mut debuggee,
+        sink,
+        memory,
+        scratch_ut,
+        scratch_slots,
+    } = params;
+    let (page_slot, window_slot) = scratch_slots.alloc();
+    let page = scratch_ut.retype(page_slot)?;
+    let mut scratch = vspace.reserve(page)?.as_scratch(&vspace)?;
+    let window = MemoryWindow::over(&memory.mappings, memory.asid, &mut scratch, window_slot);
+
+    let mut stub = GdbStub::new(connection, &mut debuggee, &sink, window);
+    let mut stopped_by = None;
+    loop {
+        if stub.run(stopped_by.as_ref())? == GdbSessionEnd::Killed {
+            return Ok(());
+        }
+        stopped_by = Some(sink.wait_for_fault());
+    }
+}
+
+/// The signal GDB should report for a process stopped by `fault`.
+fn stop_signal(fault: &Fault) -> u8 {
+    match fault {
+        Fault::VMFault(_) => GDB_SIGNAL_SEGV,
+        Fault::UserException(_) => GDB_SIGNAL_ILL,
+        Fault::UnknownSyscall(_) | Fault::CapFault(_) => GDB_SIGNAL_SYS,
+        _ => GDB_SIGNAL_TRAP,
+    }
+}
+
+/// Where the `len` bytes at `vaddr` overlap `breakpoint`'s, as pairs
+/// of offsets into each.
+fn overlap(
+    vaddr: usize,
+    len: usize,
+    breakpoint: &SoftwareBreakpoint,
+) -> impl Iterator<Item = (usize, usize)> {
+    let breakpoint_vaddr = breakpoint.vaddr;
+    (0..SOFTWARE_BREAKPOINT.len()).filter_map(move |byte| {
+        let offset = (breakpoint_vaddr + byte).checked_sub(vaddr)?;
+        if offset < len {
+            Some((offset, byte))
+        } else {
+            None
+        }
+    })
+}
+
+fn hex_digits(byte: u8) -> [u8; 2] {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    [DIGITS[(byte >> 4) as usize], DIGITS[(byte & 0xf) as usize]]
+}
+
+fn split_once(bytes: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let index = bytes.iter().position(|b| *b == separator)?;
+    Some((&bytes[..index], &bytes[index + 1..]))
+}
+
+/// Parse a big-endian hex number, as used for addresses and lengths.
+fn parse_hex(digits: &[u8]) -> Option<usize> {
+    if digits.is_empty() || digits.len() > 2 * core::mem::size_of::<usize>() {
+        return None;
+    }
+    digits.iter().try_fold(0usize, |value, digit| {
+        let digit = (*digit as char).to_digit(16)?;
+        Some(value << 4 | digit as usize)
+    })
+}
+
+/// Parse a value sent as little-endian hex bytes, as used for
+/// registers.
+fn parse_hex_le(digits: &[u8]) -> Option<usize> {
+    if digits.is_empty()
+        || digits.len() % 2 != 0
+        || digits.len() > 2 * core::mem::size_of::<usize>()
+    {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .rev()
+        .try_fold(0usize, |value, byte| Some(value << 8 | parse_hex(byte)?))
+}
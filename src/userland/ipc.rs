@@ -1,18 +1,32 @@
+//! Synchronous calls between processes, and the shared buffers and
+//! chunking behind them.
+//!
+//! Responders answer through the reply capability the kernel leaves
+//! with the thread that received a call. The MCS kernel replaces those
+//! with reply objects, which aren't supported here yet, so IPC servers
+//! are unsupported on MCS: `Responder`'s serving loops and
+//! `PendingReply` are only built for the classic scheduler. Callers,
+//! along with everything else in this module, work on both.
+
 use core::marker::PhantomData;
+use core::mem::MaybeUninit;
 
 use sel_claw::*;
 
 use crate::arch;
+#[cfg(not(KernelIsMCS))]
+use crate::cap::CNodeSlotsData;
 use crate::cap::{
-    role, Badge, CNode, CNodeRole, CNodeSlot, Cap, DirectRetype, Endpoint, LocalCNode,
-    LocalCNodeSlot, LocalCNodeSlots, LocalCap, Notification, Untyped,
+    role, Badge, CNode, CNodeRole, CNodeSlot, CNodeSlots, Cap, CapType, DirectRetype, Endpoint,
+    LocalCNode, LocalCNodeSlot, LocalCNodeSlots, LocalCap, Notification, PhantomCap, Untyped,
 };
-use crate::error::SeL4Error;
+use crate::error::{ErrorExt, SeL4Error};
 use crate::userland::multi_consumer::WakerSetup;
 use crate::userland::shared_memory_ipc::WAKER_BADGE;
+use crate::userland::wire::{decode_from, encode_into, WireError, WireFormat};
 use crate::userland::CapRights;
 use crate::vspace::VSpaceError;
-use typenum::U2;
+use typenum::{Unsigned, U2};
 
 #[derive(Debug)]
 pub enum IPCError {
@@ -20,6 +34,20 @@ pub enum IPCError {
     ResponseSizeTooBig,
     ResponseSizeMismatch,
     RequestSizeMismatch,
+    /// A request or response too large for a single message was
+    /// only partially transferred.
+    ChunkedTransferAborted,
+    /// The request could not be encoded, or the responder could not
+    /// decode it.
+    MalformedRequest(WireError),
+    /// The response could not be encoded, or the caller could not
+    /// decode it.
+    MalformedResponse(WireError),
+    /// The response was for a different request than the one sent.
+    UnexpectedResponse,
+    /// A message which should have carried a capability arrived
+    /// without one.
+    CapNotTransferred,
     SeL4Error(SeL4Error),
     VSpaceError(VSpaceError),
 }
@@ -46,13 +74,15 @@ pub struct IpcSetup<'a, Req, Rsp> {
 /// Fastpath call channel -> given some memory capacity, a local cnode, and a
 /// target responder cnode, create an endpoint locally, copy it to the responder
 /// process cnode, and return an IpcSetup to allow connecting callers.
+///
+/// `Req` and `Rsp` may be larger than the IPC buffer, in which case
+/// they are transferred in chunks; see `Caller::blocking_call`.
 pub fn call_channel<Req: Send + Sync, Rsp: Send + Sync, ResponderRole: CNodeRole>(
     untyped: LocalCap<Untyped<<Endpoint as DirectRetype>::SizeBits>>,
     local_cnode: &LocalCap<LocalCNode>,
     local_slot: LocalCNodeSlot,
     responder_slot: CNodeSlot<ResponderRole>,
 ) -> Result<(IpcSetup<Req, Rsp>, Responder<Req, Rsp, ResponderRole>), IPCError> {
-    let _ = IPCBuffer::<Req, Rsp>::new()?; // Check buffer fits Req and Rsp
     let local_endpoint: LocalCap<Endpoint> = untyped.retype(local_slot)?;
     let responder_endpoint = local_endpoint.copy(local_cnode, responder_slot, CapRights::RW)?;
 
@@ -87,7 +117,6 @@ pub fn call_channel_with_waker<Req: Send + Sync, Rsp: Send + Sync, ResponderRole
     ),
     IPCError,
 > {
-    let _ = IPCBuffer::<Req, Rsp>::new()?; // Check buffer fits Req and Rsp
     let (local_slot, local_slots) = local_slots.alloc();
     let local_endpoint: LocalCap<Endpoint> = untyped.retype(local_slot)?;
     let responder_endpoint = local_endpoint.copy(local_cnode, responder_slot, CapRights::RW)?;
@@ -131,6 +160,43 @@ impl<'a, Req, Rsp> IpcSetup<'a, Req, Rsp> {
             _rsp: PhantomData,
         })
     }
+
+    /// Create a caller whose requests are tagged with `badge`, so that
+    /// a responder using `reply_recv_with_badge` can tell it apart
+    /// from other callers.
+    ///
+    /// N.B. A zero badge is indistinguishable from an unbadged caller.
+    pub fn create_badged_caller<Role: CNodeRole>(
+        &self,
+        caller_slot: CNodeSlot<Role>,
+        badge: Badge,
+    ) -> Result<Caller<Req, Rsp, Role>, IPCError> {
+        let caller_endpoint =
+            self.endpoint
+                .mint(self.endpoint_cnode, caller_slot, CapRights::RWG, badge)?;
+
+        Ok(Caller {
+            endpoint: caller_endpoint,
+            _req: PhantomData,
+            _rsp: PhantomData,
+        })
+    }
+
+    /// Create one badged caller per slot, with badges counting up from
+    /// 1 in slot order.
+    pub fn create_badged_callers<'s, Count: Unsigned, Role: CNodeRole>(
+        &'s self,
+        caller_slots: CNodeSlots<Count, Role>,
+    ) -> impl Iterator<Item = Result<Caller<Req, Rsp, Role>, IPCError>> + 's
+    where
+        Count: 's,
+        Role: 's,
+    {
+        caller_slots
+            .iter()
+            .enumerate()
+            .map(move |(index, slot)| self.create_badged_caller(slot, Badge::from(index + 1)))
+    }
 }
 
 #[derive(Debug)]
@@ -215,12 +281,79 @@ impl<'a, Req: Sized, Rsp: Sized> IPCBuffer<'a, Req, Rsp> {
         unsafe { self.unchecked_copy_from_buffer() }
     }
 
+    #[cfg(not(KernelIsMCS))]
     fn copy_rsp_into_buffer(&mut self, response: &Rsp) {
         unsafe { self.unchecked_copy_into_buffer(response) }
     }
     fn copy_rsp_from_buffer(&mut self) -> Rsp {
         unsafe { self.unchecked_copy_from_buffer() }
     }
+
+    /// Copy chunk `index` of the `total` bytes at `src` into the
+    /// buffer, returning its length in words.
+    unsafe fn copy_chunk_into_buffer(
+        &mut self,
+        src: *const u8,
+        total: usize,
+        index: usize,
+    ) -> usize {
+        let capacity = core::mem::size_of_val(&self.buffer.msg);
+        let start = index * capacity;
+        let length = core::cmp::min(capacity, total - start);
+        core::ptr::copy_nonoverlapping(
+            src.add(start),
+            self.buffer.msg.as_mut_ptr() as *mut u8,
+            length,
+        );
+        bytes_to_words(length)
+    }
+
+    /// Copy chunk `index` of `total` bytes from the buffer into the
+    /// corresponding part of `dest`.
+    unsafe fn copy_chunk_from_buffer(&self, dest: *mut u8, total: usize, index: usize) {
+        let capacity = core::mem::size_of_val(&self.buffer.msg);
+        let start = index * capacity;
+        let length = core::cmp::min(capacity, total - start);
+        core::ptr::copy_nonoverlapping(
+            self.buffer.msg.as_ptr() as *const u8,
+            dest.add(start),
+            length,
+        );
+    }
+
+    /// The message words following the leading length word of an
+    /// encoded message, viewed as bytes.
+    fn encoded_payload_bytes(&mut self) -> &mut [u8] {
+        let words = &mut self.buffer.msg[1..];
+        unsafe {
+            core::slice::from_raw_parts_mut(
+                words.as_mut_ptr() as *mut u8,
+                core::mem::size_of_val(words),
+            )
+        }
+    }
+
+    /// Encode `data` as a length word followed by its encoded bytes,
+    /// returning the length of the whole message in words.
+    fn encode_into_buffer<T: WireFormat>(&mut self, data: &T) -> Result<usize, WireError> {
+        let byte_length = encode_into(data, self.encoded_payload_bytes())?;
+        self.buffer.msg[0] = byte_length as seL4_Word;
+        Ok(1 + bytes_to_words(byte_length))
+    }
+
+    /// Decode a message of `length_words` written by `encode_into_buffer`,
+    /// without trusting either length.
+    fn decode_from_buffer<T: WireFormat>(&mut self, length_words: usize) -> Result<T, WireError> {
+        if length_words == 0 {
+            return Err(WireError::UnexpectedEnd);
+        }
+        let byte_length = self.buffer.msg[0] as usize;
+        let payload = self.encoded_payload_bytes();
+        if byte_length > payload.len() || bytes_to_words(byte_length) != length_words - 1 {
+            return Err(WireError::UnexpectedEnd);
+        }
+        decode_from(&payload[..byte_length])
+    }
 }
 
 #[inline]
@@ -228,8 +361,581 @@ fn unchecked_raw_ipc_buffer<'a>() -> &'a mut seL4_IPCBuffer {
     unsafe { &mut *seL4_GetIPCBuffer() }
 }
 
+/// The number of message registers passed by the `seL4_*WithMRs`
+/// syscall wrappers. On ARM this is also the longest message the
+/// kernel's IPC fastpath will take.
+const MESSAGE_REGISTER_COUNT: usize = 4;
+
+/// Local copies of the message registers, for messages short enough
+/// to bypass the IPC buffer entirely.
+struct MessageRegisters {
+    words: [seL4_Word; MESSAGE_REGISTER_COUNT],
+}
+
+impl MessageRegisters {
+    /// Do both a request and its response fit in the message registers?
+    fn fit<Req, Rsp>() -> bool {
+        type_length_in_words::<Req>() <= MESSAGE_REGISTER_COUNT
+            && type_length_in_words::<Rsp>() <= MESSAGE_REGISTER_COUNT
+    }
+
+    fn new() -> Self {
+        MessageRegisters {
+            words: [0; MESSAGE_REGISTER_COUNT],
+        }
+    }
+
+    /// Use only when `T` is known to fit in the message registers
+    unsafe fn store<T: Sized>(&mut self, data: &T) {
+        core::ptr::copy_nonoverlapping(
+            data as *const T as *const u8,
+            self.words.as_mut_ptr() as *mut u8,
+            core::mem::size_of::<T>(),
+        );
+    }
+
+    /// Use only when `T` is known to fit in the message registers
+    unsafe fn load<T: Sized>(&self) -> T {
+        let mut data = core::mem::zeroed();
+        core::ptr::copy_nonoverlapping(
+            self.words.as_ptr() as *const u8,
+            &mut data as *mut T as *mut u8,
+            core::mem::size_of::<T>(),
+        );
+        data
+    }
+}
+
+/// Moves requests and responses between this thread and the kernel.
+///
+/// When both `Req` and `Rsp` fit in the message registers they are
+/// passed directly in registers, so that a round trip never touches
+/// the IPC buffer. When either is too large for the IPC buffer, it is
+/// split into chunks which are sent one per round trip. Otherwise
+/// everything goes through the IPC buffer.
+enum Transport<'a, Req: Sized, Rsp: Sized> {
+    Registers(MessageRegisters, PhantomData<(Req, Rsp)>),
+    Buffer(IPCBuffer<'a, Req, Rsp>),
+    Chunked(Chunks<'a, Req, Rsp>),
+}
+
+impl<'a, Req: Sized, Rsp: Sized> Transport<'a, Req, Rsp> {
+    /// Use only when all possible prior paths have conclusively
+    /// checked sizing constraints, as for `IPCBuffer::unchecked_new`
+    unsafe fn unchecked_new() -> Self {
+        if Chunks::<Req, Rsp>::needed() {
+            Transport::Chunked(Chunks::unchecked_new())
+        } else if MessageRegisters::fit::<Req, Rsp>() {
+            Transport::Registers(MessageRegisters::new(), PhantomData)
+        } else {
+            Transport::Buffer(IPCBuffer::unchecked_new())
+        }
+    }
+
+    /// Send a request and wait for its response
+    fn call(&mut self, endpoint: usize, request: &Req) -> Result<Rsp, IPCError> {
+        let info = type_length_message_info::<Req>();
+        let msg_info: MessageInfo = match self {
+            Transport::Registers(mrs, _) => unsafe {
+                mrs.store(request);
+                let [m0, m1, m2, m3] = &mut mrs.words;
+                seL4_CallWithMRs(endpoint, info, m0, m1, m2, m3)
+            },
+            Transport::Buffer(ipc_buffer) => unsafe {
+                ipc_buffer.copy_req_into_buffer(request);
+                seL4_Call(endpoint, info)
+            },
+            Transport::Chunked(chunks) => return chunks.call(endpoint, request),
+        }
+        .into();
+        if msg_info.length_words() != type_length_in_words::<Rsp>() {
+            return Err(IPCError::ResponseSizeMismatch);
+        }
+        Ok(self.response())
+    }
+
+    /// The response most recently received
+    fn response(&mut self) -> Rsp {
+        match self {
+            Transport::Registers(mrs, _) => unsafe { mrs.load() },
+            Transport::Buffer(ipc_buffer) => ipc_buffer.copy_rsp_from_buffer(),
+            // Chunked responses are returned directly from `call`
+            Transport::Chunked(_) => unreachable!(),
+        }
+    }
+}
+
+/// The responder's side, which answers through the implicit reply
+/// capability of the classic scheduler.
+#[cfg(not(KernelIsMCS))]
+impl<'a, Req: Sized, Rsp: Sized> Transport<'a, Req, Rsp> {
+    fn recv(&mut self, endpoint: usize, sender_badge: &mut usize) -> MessageInfo {
+        match self {
+            Transport::Registers(mrs, _) => unsafe {
+                let [m0, m1, m2, m3] = &mut mrs.words;
+                seL4_RecvWithMRs(endpoint, sender_badge as *mut usize, m0, m1, m2, m3).into()
+            },
+            Transport::Buffer(_) => {
+                unsafe { seL4_Recv(endpoint, sender_badge as *mut usize) }.into()
+            }
+            Transport::Chunked(chunks) => {
+                if let Some(msg_info) = chunks.take_notifications(sender_badge) {
+                    return msg_info;
+                }
+                let msg_info = unsafe { seL4_Recv(endpoint, sender_badge as *mut usize) }.into();
+                chunks.serve(endpoint, sender_badge, msg_info)
+            }
+        }
+    }
+
+    /// Treat messages with nonzero badges as notifications, which are
+    /// never part of a chunked transfer.
+    fn with_notification_badges(mut self) -> Self {
+        if let Transport::Chunked(chunks) = &mut self {
+            chunks.notification_badges = true;
+        }
+        self
+    }
+
+    /// Has a whole request arrived with `msg_info`, ready to be
+    /// retrieved with `request`?
+    fn has_request(&self, msg_info: &MessageInfo) -> bool {
+        match self {
+            Transport::Chunked(chunks) => chunks.has_request(),
+            _ => msg_info.length_words() == type_length_in_words::<Req>(),
+        }
+    }
+
+    fn reply(&mut self, endpoint: usize, response: &Rsp, sender_badge: &mut usize) {
+        let info = type_length_message_info::<Rsp>();
+        match self {
+            Transport::Registers(mrs, _) => unsafe {
+                mrs.store(response);
+                let [m0, m1, m2, m3] = &mut mrs.words;
+                seL4_ReplyWithMRs(info, m0, m1, m2, m3)
+            },
+            Transport::Buffer(ipc_buffer) => unsafe {
+                ipc_buffer.copy_rsp_into_buffer(response);
+                seL4_Reply(info)
+            },
+            Transport::Chunked(chunks) => chunks.reply(endpoint, response, sender_badge),
+        }
+    }
+
+    fn reply_recv(
+        &mut self,
+        endpoint: usize,
+        response: &Rsp,
+        sender_badge: &mut usize,
+    ) -> MessageInfo {
+        let info = type_length_message_info::<Rsp>();
+        match self {
+            Transport::Registers(mrs, _) => unsafe {
+                mrs.store(response);
+                let [m0, m1, m2, m3] = &mut mrs.words;
+                seL4_ReplyRecvWithMRs(endpoint, info, sender_badge as *mut usize, m0, m1, m2, m3)
+                    .into()
+            },
+            Transport::Buffer(ipc_buffer) => unsafe {
+                ipc_buffer.copy_rsp_into_buffer(response);
+                seL4_ReplyRecv(endpoint, info, sender_badge as *mut usize).into()
+            },
+            Transport::Chunked(chunks) => chunks.reply_recv(endpoint, response, sender_badge),
+        }
+    }
+
+    /// The request most recently received
+    fn request(&mut self) -> Req {
+        match self {
+            Transport::Registers(mrs, _) => unsafe { mrs.load() },
+            Transport::Buffer(ipc_buffer) => ipc_buffer.copy_req_from_buffer(),
+            Transport::Chunked(chunks) => chunks.request(),
+        }
+    }
+}
+
+// Labels of the messages making up a chunked transfer. Every message
+// from the caller is a call, and the responder's reply tells the
+// caller what to do next.
+//
+// Caller to responder: request chunk `i` is labeled `i + 1`, and the
+// caller asks for response chunk `j` with an empty message labeled
+// `CHUNK_PULL | j`. A request small enough to send whole is labeled 0.
+//
+// Responder to caller: a reply labeled 0 carries response data, while
+// the others carry no data.
+const CHUNK_PULL: usize = 1 << 19;
+/// Send the next request chunk
+const CHUNK_ACK: usize = 1;
+/// The responder is busy with another caller's transfer; try again
+const CHUNK_BUSY: usize = 2;
+/// The message was not the expected next step of the transfer, which
+/// has been abandoned
+const CHUNK_ABORT: usize = 3;
+
+/// What the responder should do with an incoming message
+#[cfg(not(KernelIsMCS))]
+enum ChunkStep {
+    /// Hand the message to the responder loop
+    Deliver,
+    /// Reply and wait for the next message
+    Reply(seL4_MessageInfo_t),
+}
+
+// Only responders, which the MCS scheduler doesn't have, track progress
+#[cfg_attr(KernelIsMCS, allow(dead_code))]
+#[derive(Clone, Copy)]
+enum ChunkState {
+    Idle,
+    /// Request chunks before `next` have arrived from `badge`
+    ReceivingRequest {
+        badge: usize,
+        next: usize,
+    },
+    /// A whole request from `badge` has arrived and awaits a response
+    RequestComplete {
+        badge: usize,
+    },
+    /// Response chunks before `next` have been sent to `badge`
+    SendingResponse {
+        badge: usize,
+        next: usize,
+    },
+}
+
+/// Reassembly buffers and progress for requests and responses too
+/// large to fit in the IPC buffer, which are split into chunks that
+/// each take one round trip.
+///
+/// A responder works on one chunked transfer at a time, and tells
+/// other callers to retry until it is done. Callers are told apart by
+/// their badges where they have them. Unbadged callers can only be told
+/// apart by where their messages fall in a transfer, so any request
+/// that starts while another transfer is under way is told to retry,
+/// whoever it's from. `reply_recv_with_notification` takes nonzero
+/// badges to be notifications, so its callers have to be unbadged.
+#[cfg_attr(KernelIsMCS, allow(dead_code))]
+struct Chunks<'a, Req: Sized, Rsp: Sized> {
+    ipc_buffer: IPCBuffer<'a, Req, Rsp>,
+    request: MaybeUninit<Req>,
+    response: MaybeUninit<Rsp>,
+    state: ChunkState,
+    /// Whether messages with nonzero badges are notifications, to be
+    /// handed to the responder loop whatever the transfer's state
+    notification_badges: bool,
+    /// The badges of notifications which arrived while `reply` was
+    /// sending a chunked response, combined as the kernel combines
+    /// signals
+    notifications: usize,
+}
+
+impl<'a, Req: Sized, Rsp: Sized> Chunks<'a, Req, Rsp> {
+    /// Is either `Req` or `Rsp` too large for a single message?
+    fn needed() -> bool {
+        chunk_count::<Req>() > 1 || chunk_count::<Rsp>() > 1
+    }
+
+    unsafe fn unchecked_new() -> Self {
+        Chunks {
+            ipc_buffer: IPCBuffer::unchecked_new(),
+            request: MaybeUninit::uninit(),
+            response: MaybeUninit::uninit(),
+            state: ChunkState::Idle,
+            notification_badges: false,
+            notifications: 0,
+        }
+    }
+
+    fn call(&mut self, endpoint: usize, request: &Req) -> Result<Rsp, IPCError> {
+        let request_chunks = chunk_count::<Req>();
+        let mut index = 0;
+        let mut msg_info = loop {
+            let length = unsafe {
+                self.ipc_buffer.copy_chunk_into_buffer(
+                    request as *const Req as *const u8,
+                    core::mem::size_of::<Req>(),
+                    index,
+                )
+            };
+            let label = if request_chunks > 1 { index + 1 } else { 0 };
+            let msg_info: MessageInfo =
+                unsafe { seL4_Call(endpoint, chunk_message_info(label, length)) }.into();
+            match msg_info.label() {
+                CHUNK_BUSY => unsafe { seL4_Yield() },
+                CHUNK_ACK if index + 1 < request_chunks => index += 1,
+                CHUNK_ABORT => return Err(IPCError::ChunkedTransferAborted),
+                _ => break msg_info,
+            }
+        };
+
+        let mut response: MaybeUninit<Rsp> = MaybeUninit::uninit();
+        for index in 0..chunk_count::<Rsp>() {
+            if index > 0 {
+                msg_info =
+                    unsafe { seL4_Call(endpoint, chunk_message_info(CHUNK_PULL | index, 0)) }
+                        .into();
+            }
+            if msg_info.label() == CHUNK_ABORT {
+                return Err(IPCError::ChunkedTransferAborted);
+            }
+            if msg_info.label() != 0
+                || msg_info.length_words() != chunk_length_in_words::<Rsp>(index)
+            {
+                return Err(IPCError::ResponseSizeMismatch);
+            }
+            unsafe {
+                self.ipc_buffer.copy_chunk_from_buffer(
+                    response.as_mut_ptr() as *mut u8,
+                    core::mem::size_of::<Rsp>(),
+                    index,
+                )
+            };
+        }
+        Ok(unsafe { response.assume_init() })
+    }
+}
+
+/// The responder's side, which answers through the implicit reply
+/// capability of the classic scheduler.
+#[cfg(not(KernelIsMCS))]
+impl<'a, Req: Sized, Rsp: Sized> Chunks<'a, Req, Rsp> {
+    /// Handle chunk protocol messages until one arrives which the
+    /// responder loop must see, either because it completes a request
+    /// or because it is not part of a chunked transfer at all.
+    fn serve(
+        &mut self,
+        endpoint: usize,
+        sender_badge: &mut usize,
+        mut msg_info: MessageInfo,
+    ) -> MessageInfo {
+        loop {
+            match self.step(*sender_badge, &msg_info) {
+                ChunkStep::Deliver => return msg_info,
+                ChunkStep::Reply(reply_info) => {
+                    msg_info =
+                        unsafe { seL4_ReplyRecv(endpoint, reply_info, sender_badge as *mut usize) }
+                            .into()
+                }
+            }
+        }
+    }
+
+    fn step(&mut self, badge: usize, msg_info: &MessageInfo) -> ChunkStep {
+        if self.notification_badges && badge != 0 {
+            return ChunkStep::Deliver;
+        }
+        let label = msg_info.label();
+        let length_words = msg_info.length_words();
+        match self.state {
+            ChunkState::Idle | ChunkState::RequestComplete { .. } => {
+                if label == 0 {
+                    if chunk_count::<Req>() == 1 && length_words == type_length_in_words::<Req>() {
+                        self.receive_request_chunk(0);
+                        self.state = ChunkState::RequestComplete { badge };
+                    }
+                    ChunkStep::Deliver
+                } else if label == 1
+                    && chunk_count::<Req>() > 1
+                    && length_words == chunk_length_in_words::<Req>(0)
+                {
+                    self.receive_request_chunk(0);
+                    self.state = ChunkState::ReceivingRequest { badge, next: 1 };
+                    ChunkStep::Reply(chunk_message_info(CHUNK_ACK, 0))
+                } else {
+                    ChunkStep::Reply(chunk_message_info(CHUNK_ABORT, 0))
+                }
+            }
+            ChunkState::ReceivingRequest {
+                badge: transfer_badge,
+                next,
+            } => {
+                // A whole request, the start of another, or any message
+                // from another caller has to wait for this transfer to
+                // finish
+                if label == 0 || label == 1 || badge != transfer_badge {
+                    ChunkStep::Reply(chunk_message_info(CHUNK_BUSY, 0))
+                } else if label == next + 1 && length_words == chunk_length_in_words::<Req>(next) {
+                    self.receive_request_chunk(next);
+                    if next + 1 == chunk_count::<Req>() {
+                        self.state = ChunkState::RequestComplete { badge };
+                        ChunkStep::Deliver
+                    } else {
+                        self.state = ChunkState::ReceivingRequest {
+                            badge,
+                            next: next + 1,
+                        };
+                        ChunkStep::Reply(chunk_message_info(CHUNK_ACK, 0))
+                    }
+                } else {
+                    self.state = ChunkState::Idle;
+                    ChunkStep::Reply(chunk_message_info(CHUNK_ABORT, 0))
+                }
+            }
+            ChunkState::SendingResponse {
+                badge: transfer_badge,
+                next,
+            } => {
+                // A whole request, the start of another, or any message
+                // from another caller has to wait for this transfer to
+                // finish
+                if label == 0 || label == 1 || badge != transfer_badge {
+                    ChunkStep::Reply(chunk_message_info(CHUNK_BUSY, 0))
+                } else if label == CHUNK_PULL | next && length_words == 0 {
+                    ChunkStep::Reply(self.send_response_chunk(badge, next))
+                } else {
+                    self.state = ChunkState::Idle;
+                    ChunkStep::Reply(chunk_message_info(CHUNK_ABORT, 0))
+                }
+            }
+        }
+    }
+
+    /// Hand over the notifications kept by `reply` as though they had
+    /// just been received, if there are any.
+    fn take_notifications(&mut self, sender_badge: &mut usize) -> Option<MessageInfo> {
+        if self.notifications == 0 {
+            return None;
+        }
+        *sender_badge = core::mem::replace(&mut self.notifications, 0);
+        Some(chunk_message_info(0, 0).into())
+    }
+
+    fn has_request(&self) -> bool {
+        match self.state {
+            ChunkState::RequestComplete { .. } => true,
+            _ => false,
+        }
+    }
+
+    fn request(&mut self) -> Req {
+        unsafe { core::ptr::read(self.request.as_ptr()) }
+    }
+
+    fn receive_request_chunk(&mut self, index: usize) {
+        unsafe {
+            self.ipc_buffer.copy_chunk_from_buffer(
+                self.request.as_mut_ptr() as *mut u8,
+                core::mem::size_of::<Req>(),
+                index,
+            )
+        }
+    }
+
+    /// Hold on to `response` and put its first chunk in the IPC buffer,
+    /// returning the message info with which to reply.
+    fn start_response(&mut self, response: &Rsp) -> seL4_MessageInfo_t {
+        let badge = match self.state {
+            ChunkState::RequestComplete { badge } => badge,
+            _ => 0,
+        };
+        unsafe {
+            core::ptr::copy_nonoverlapping(response as *const Rsp, self.response.as_mut_ptr(), 1)
+        };
+        self.send_response_chunk(badge, 0)
+    }
+
+    /// Put response chunk `index` in the IPC buffer, returning the
+    /// message info with which to reply.
+    fn send_response_chunk(&mut self, badge: usize, index: usize) -> seL4_MessageInfo_t {
+        let length = unsafe {
+            self.ipc_buffer.copy_chunk_into_buffer(
+                self.response.as_ptr() as *const u8,
+                core::mem::size_of::<Rsp>(),
+                index,
+            )
+        };
+        self.state = if index + 1 < chunk_count::<Rsp>() {
+            ChunkState::SendingResponse {
+                badge,
+                next: index + 1,
+            }
+        } else {
+            ChunkState::Idle
+        };
+        chunk_message_info(0, length)
+    }
+
+    fn reply(&mut self, endpoint: usize, response: &Rsp, sender_badge: &mut usize) {
+        let mut reply_info = self.start_response(response);
+        // Serve the rest of the response before returning, keeping any
+        // notifications which arrive in the meantime for the next
+        // `recv`.
+        loop {
+            if let ChunkState::Idle = self.state {
+                unsafe { seL4_Reply(reply_info) };
+                return;
+            }
+            let mut msg_info: MessageInfo =
+                unsafe { seL4_ReplyRecv(endpoint, reply_info, sender_badge as *mut usize) }.into();
+            reply_info = loop {
+                match self.step(*sender_badge, &msg_info) {
+                    ChunkStep::Reply(info) => break info,
+                    // Mid-transfer, only notifications are delivered
+                    ChunkStep::Deliver => {
+                        self.notifications |= *sender_badge;
+                        msg_info =
+                            unsafe { seL4_Recv(endpoint, sender_badge as *mut usize) }.into();
+                    }
+                }
+            };
+        }
+    }
+
+    fn reply_recv(
+        &mut self,
+        endpoint: usize,
+        response: &Rsp,
+        sender_badge: &mut usize,
+    ) -> MessageInfo {
+        let reply_info = self.start_response(response);
+        if let Some(msg_info) = self.take_notifications(sender_badge) {
+            unsafe { seL4_Reply(reply_info) };
+            return msg_info;
+        }
+        let msg_info =
+            unsafe { seL4_ReplyRecv(endpoint, reply_info, sender_badge as *mut usize) }.into();
+        self.serve(endpoint, sender_badge, msg_info)
+    }
+}
+
+/// The number of messages it takes to send a `T`
+fn chunk_count<T>() -> usize {
+    let capacity = IPCBuffer::<(), ()>::max_size();
+    let size = core::mem::size_of::<T>();
+    if size <= capacity {
+        1
+    } else {
+        (size + capacity - 1) / capacity
+    }
+}
+
+/// The length in words of chunk `index` of a `T`
+fn chunk_length_in_words<T>(index: usize) -> usize {
+    let capacity = IPCBuffer::<(), ()>::max_size();
+    let size = core::mem::size_of::<T>();
+    let start = index * capacity;
+    if start >= size {
+        return 0;
+    }
+    bytes_to_words(core::cmp::min(capacity, size - start))
+}
+
+fn chunk_message_info(label: usize, length_words: usize) -> seL4_MessageInfo_t {
+    unsafe {
+        seL4_MessageInfo_new(
+            arch::to_sel4_word(label),        // label,
+            0,                                // capsUnwrapped,
+            0,                                // extraCaps,
+            arch::to_sel4_word(length_words), // length in words!
+        )
+    }
+}
+
 pub(crate) fn type_length_in_words<T>() -> usize {
-    let t_bytes = core::mem::size_of::<T>();
+    bytes_to_words(core::mem::size_of::<T>())
+}
+
+fn bytes_to_words(t_bytes: usize) -> usize {
     let usize_bytes = core::mem::size_of::<usize>();
     if t_bytes == 0 {
         return 0;
@@ -247,16 +953,303 @@ pub(crate) fn type_length_in_words<T>() -> usize {
 }
 
 fn type_length_message_info<T>() -> seL4_MessageInfo_t {
+    type_length_message_info_with_caps::<T>(0)
+}
+
+fn type_length_message_info_with_caps<T>(extra_caps: usize) -> seL4_MessageInfo_t {
     unsafe {
         seL4_MessageInfo_new(
             0,                                               // label,
             0,                                               // capsUnwrapped,
-            0,                                               // extraCaps,
+            arch::to_sel4_word(extra_caps),                  // extraCaps,
             arch::to_sel4_word(type_length_in_words::<T>()), // length in words!
         )
     }
 }
 
+/// Message info for an encoded message of `length_words`.
+fn encoded_message_info(length_words: usize) -> seL4_MessageInfo_t {
+    unsafe {
+        seL4_MessageInfo_new(
+            0,                                // label,
+            0,                                // capsUnwrapped,
+            0,                                // extraCaps,
+            arch::to_sel4_word(length_words), // length in words!
+        )
+    }
+}
+
+/// Message info for an empty reply whose label tells the caller why
+/// its encoded request was refused.
+#[cfg(not(KernelIsMCS))]
+fn rejection_message_info(error: WireError) -> seL4_MessageInfo_t {
+    unsafe {
+        seL4_MessageInfo_new(
+            arch::to_sel4_word(error.to_code()), // label,
+            0,                                   // capsUnwrapped,
+            0,                                   // extraCaps,
+            0,                                   // length in words!
+        )
+    }
+}
+
+/// Designate the slot into which the kernel should place the next
+/// capability received over IPC by this thread.
+fn set_cap_receive_slot(slot: &LocalCNodeSlot) {
+    unsafe {
+        seL4_SetCapReceivePath(
+            slot.cptr,              // receiveCNode
+            slot.cap_data.offset,   // receiveIndex
+            seL4_WordBits as usize, // receiveDepth
+        )
+    }
+}
+
+/// Clear the receive path so that no later message may write a
+/// capability into a slot that has since been handed out.
+fn clear_cap_receive_slot() {
+    unsafe { seL4_SetCapReceivePath(seL4_CapNull as usize, 0, 0) }
+}
+
+/// A value backed by a single capability, which can travel alongside
+/// a message on a channel whose messages are `WithCap`. The kernel
+/// delivers at most one capability per message, into a slot that the
+/// receiver sets aside for it.
+pub trait Transferable: Sized {
+    /// Where a received value's capability lands: a slot, or `()` for
+    /// a value without one.
+    type ReceiveSlot;
+
+    /// The local cptr of the capability to send, if there is one.
+    fn cptr(&self) -> Option<usize>;
+
+    /// The slot to receive the capability into, if there is one.
+    fn receive_slot(slot: &Self::ReceiveSlot) -> Option<&LocalCNodeSlot>;
+
+    /// Rebuild the value from the capability which has arrived in
+    /// `slot`.
+    ///
+    /// The capability must be of the type the value expects, which a
+    /// channel typed with `WithCap` ensures for its own messages.
+    unsafe fn received(slot: Self::ReceiveSlot) -> Self;
+}
+
+/// No capability at all, for the side of a call which doesn't
+/// transfer one.
+impl Transferable for () {
+    type ReceiveSlot = ();
+
+    fn cptr(&self) -> Option<usize> {
+        None
+    }
+
+    fn receive_slot(_slot: &()) -> Option<&LocalCNodeSlot> {
+        None
+    }
+
+    unsafe fn received(_slot: ()) -> Self {}
+}
+
+impl<CT: CapType + PhantomCap> Transferable for LocalCap<CT> {
+    type ReceiveSlot = LocalCNodeSlot;
+
+    fn cptr(&self) -> Option<usize> {
+        Some(self.cptr)
+    }
+
+    fn receive_slot(slot: &LocalCNodeSlot) -> Option<&LocalCNodeSlot> {
+        Some(slot)
+    }
+
+    unsafe fn received(slot: LocalCNodeSlot) -> Self {
+        let (_, offset, _) = slot.elim();
+        Cap::wrap_cptr(offset)
+    }
+}
+
+/// A message on a call channel which carries a `P` alongside its `T`
+/// contents, so that the channel's type says what is transferred in
+/// each direction, e.g.
+///
+///     call_channel::<WithCap<Req, LocalCap<Notification>>, WithCap<Rsp>>(...)
+///
+/// `WithCap<T>` carries no capability, for calls where only one side
+/// transfers one.
+pub struct WithCap<T, P: Transferable = ()> {
+    _contents: PhantomData<T>,
+    _payload: PhantomData<P>,
+}
+
+/// A call or receive which failed, handing back the slot that was set
+/// aside for the capability, empty.
+#[derive(Debug)]
+pub struct CapTransferError<S> {
+    pub error: IPCError,
+    pub receive_slot: S,
+}
+
+/// Claim the capability that `msg_info` says arrived in `receive_slot`.
+/// If the message is unusable because of `error`, or didn't carry the
+/// capability, the slot is emptied and handed back instead.
+unsafe fn claim_received<P: Transferable>(
+    msg_info: &MessageInfo,
+    receive_slot: P::ReceiveSlot,
+    error: Option<IPCError>,
+) -> Result<P, CapTransferError<P::ReceiveSlot>> {
+    let expects_cap = P::receive_slot(&receive_slot).is_some();
+    let error = match error {
+        Some(error) => error,
+        None if expects_cap && !msg_info.has_received_cap() => IPCError::CapNotTransferred,
+        None => return Ok(P::received(receive_slot)),
+    };
+    if let Some(slot) = P::receive_slot(&receive_slot) {
+        if msg_info.has_received_cap() {
+            if let Err(e) = seL4_CNode_Delete(
+                slot.cptr,            // _service
+                slot.cap_data.offset, // index
+                seL4_WordBits as u8,  // depth
+            )
+            .as_result()
+            {
+                return Err(CapTransferError {
+                    error: IPCError::SeL4Error(SeL4Error::CNodeDelete(e)),
+                    receive_slot,
+                });
+            }
+        }
+    }
+    Err(CapTransferError {
+        error,
+        receive_slot,
+    })
+}
+
+impl<Req, ReqP: Transferable, Rsp, RspP: Transferable>
+    Caller<WithCap<Req, ReqP>, WithCap<Rsp, RspP>, role::Local>
+{
+    /// Make a call which transfers `payload`'s capability to the
+    /// responder, and receives the one sent back with the response
+    /// into `receive_slot`.
+    ///
+    /// The responder gets a copy of the capability with the same
+    /// rights, and this caller retains the original. If the call
+    /// fails, `receive_slot` is handed back empty.
+    pub fn blocking_call_with_cap(
+        &self,
+        request: &Req,
+        payload: &ReqP,
+        receive_slot: RspP::ReceiveSlot,
+    ) -> Result<(Rsp, RspP), CapTransferError<RspP::ReceiveSlot>> {
+        // Capabilities can't be transferred in chunks, so both messages
+        // must fit in the buffer
+        let mut ipc_buffer: IPCBuffer<Req, Rsp> = match IPCBuffer::new() {
+            Ok(ipc_buffer) => ipc_buffer,
+            Err(error) => {
+                return Err(CapTransferError {
+                    error,
+                    receive_slot,
+                })
+            }
+        };
+        if let Some(slot) = RspP::receive_slot(&receive_slot) {
+            set_cap_receive_slot(slot);
+        }
+        let msg_info: MessageInfo = unsafe {
+            ipc_buffer.copy_req_into_buffer(request);
+            let extra_caps = match payload.cptr() {
+                Some(cptr) => {
+                    seL4_SetCap(0, cptr);
+                    1
+                }
+                None => 0,
+            };
+            seL4_Call(
+                self.endpoint.cptr,
+                type_length_message_info_with_caps::<Req>(extra_caps),
+            )
+        }
+        .into();
+        clear_cap_receive_slot();
+
+        let error = if msg_info.length_words() != type_length_in_words::<Rsp>() {
+            Some(IPCError::ResponseSizeMismatch)
+        } else {
+            None
+        };
+        let received = unsafe { claim_received(&msg_info, receive_slot, error)? };
+        Ok((ipc_buffer.copy_rsp_from_buffer(), received))
+    }
+}
+
+#[cfg(not(KernelIsMCS))]
+impl<Req, ReqP: Transferable, Rsp, RspP: Transferable>
+    Responder<WithCap<Req, ReqP>, WithCap<Rsp, RspP>, role::Local>
+{
+    /// Like `recv_reply_once`, but the request's capability is received
+    /// into `receive_slot`, and the payload the handler returns is
+    /// transferred along with the response.
+    ///
+    /// The caller gets a copy of the payload's capability, and the
+    /// payload itself is handed back once the reply has been sent. If
+    /// the request can't be accepted, e.g. because it arrived without
+    /// its capability, it's dropped without a reply and `receive_slot`
+    /// is handed back empty.
+    pub fn recv_reply_once_with_cap<F>(
+        &self,
+        receive_slot: ReqP::ReceiveSlot,
+        f: F,
+    ) -> Result<RspP, CapTransferError<ReqP::ReceiveSlot>>
+    where
+        F: FnOnce(Req, ReqP) -> (Rsp, RspP),
+    {
+        // Capabilities can't be transferred in chunks, so both messages
+        // must fit in the buffer
+        let mut ipc_buffer: IPCBuffer<Req, Rsp> = match IPCBuffer::new() {
+            Ok(ipc_buffer) => ipc_buffer,
+            Err(error) => {
+                return Err(CapTransferError {
+                    error,
+                    receive_slot,
+                })
+            }
+        };
+        let mut sender_badge: usize = 0;
+        if let Some(slot) = ReqP::receive_slot(&receive_slot) {
+            set_cap_receive_slot(slot);
+        }
+        let msg_info: MessageInfo =
+            unsafe { seL4_Recv(self.endpoint.cptr, &mut sender_badge as *mut usize) }.into();
+        clear_cap_receive_slot();
+
+        let request_length_in_words = type_length_in_words::<Req>();
+        let error = if msg_info.length_words() != request_length_in_words {
+            // See `recv_reply_once` for why a wrong-sized message is
+            // dropped without a reply.
+            debug_println!("Request size incoming ({} words) does not match static size expectation ({} words).",
+                msg_info.length_words(), request_length_in_words);
+            Some(IPCError::RequestSizeMismatch)
+        } else {
+            None
+        };
+        let received = unsafe { claim_received(&msg_info, receive_slot, error)? };
+
+        let (response, payload) = f(ipc_buffer.copy_req_from_buffer(), received);
+        unsafe {
+            ipc_buffer.copy_rsp_into_buffer(&response);
+            let extra_caps = match payload.cptr() {
+                Some(cptr) => {
+                    seL4_SetCap(0, cptr);
+                    1
+                }
+                None => 0,
+            };
+            seL4_Reply(type_length_message_info_with_caps::<Rsp>(extra_caps));
+        }
+
+        Ok(payload)
+    }
+}
+
 pub struct MessageInfo {
     inner: seL4_MessageInfo_t,
 }
@@ -281,6 +1274,32 @@ impl MessageInfo {
         }
     }
 
+    /// The number of capabilities that the kernel transferred
+    /// along with this message.
+    pub(crate) fn extra_caps(&self) -> usize {
+        unsafe {
+            seL4_MessageInfo_ptr_get_extraCaps(
+                &self.inner as *const seL4_MessageInfo_t as *mut seL4_MessageInfo_t,
+            ) as usize
+        }
+    }
+
+    /// Bitmask of the transferred capabilities that were unwrapped
+    /// into badges rather than placed into the receive slot.
+    pub(crate) fn caps_unwrapped(&self) -> usize {
+        unsafe {
+            seL4_MessageInfo_ptr_get_capsUnwrapped(
+                &self.inner as *const seL4_MessageInfo_t as *mut seL4_MessageInfo_t,
+            ) as usize
+        }
+    }
+
+    /// Did a single capability arrive in the receive slot, rather
+    /// than not at all or unwrapped into a badge?
+    pub(crate) fn has_received_cap(&self) -> bool {
+        self.extra_caps() == 1 && self.caps_unwrapped() & 1 == 0
+    }
+
     /// Does this message info have the label tag
     /// that indicates that no fault has occurred?
     pub(crate) fn has_null_fault_label(&self) -> bool {
@@ -312,19 +1331,41 @@ impl<Req, Rsp> Caller<Req, Rsp, role::Local> {
 }
 
 impl<Req, Rsp> Caller<Req, Rsp, role::Local> {
+    /// Send a request and wait for its response.
+    ///
+    /// When both `Req` and `Rsp` fit in the message registers, neither
+    /// is copied through the IPC buffer. When either is too large for
+    /// the IPC buffer, it is sent in chunks over several round trips.
     pub fn blocking_call(&self, request: &Req) -> Result<Rsp, IPCError> {
-        // Can safely use unchecked_new because we check sizing during the creation of
-        // Caller
-        let mut ipc_buffer = unsafe { IPCBuffer::unchecked_new() };
-        let msg_info: MessageInfo = unsafe {
-            ipc_buffer.copy_req_into_buffer(request);
-            seL4_Call(self.endpoint.cptr, type_length_message_info::<Req>())
-        }
-        .into();
-        if msg_info.length_words() != type_length_in_words::<Rsp>() {
-            return Err(IPCError::ResponseSizeMismatch);
+        // Can safely use unchecked_new because Transport handles messages of any size
+        let mut transport = unsafe { Transport::unchecked_new() };
+        transport.call(self.endpoint.cptr, request)
+    }
+}
+
+impl<Req: WireFormat, Rsp: WireFormat> Caller<Req, Rsp, role::Local> {
+    /// Like `blocking_call`, but the request and response are sent
+    /// in their `WireFormat` encoding, to be handled by one of the
+    /// responder's `*_encoded` methods.
+    ///
+    /// Only the words occupied by the encoding are transferred, and a
+    /// response which fails to decode is reported rather than
+    /// reinterpreted.
+    pub fn blocking_call_encoded(&self, request: &Req) -> Result<Rsp, IPCError> {
+        // Can safely use unchecked_new because encodings are bounded by the buffer
+        // rather than by the sizes of Req and Rsp
+        let mut ipc_buffer: IPCBuffer<Req, Rsp> = unsafe { IPCBuffer::unchecked_new() };
+        let length_words = ipc_buffer
+            .encode_into_buffer(request)
+            .map_err(IPCError::MalformedRequest)?;
+        let msg_info: MessageInfo =
+            unsafe { seL4_Call(self.endpoint.cptr, encoded_message_info(length_words)) }.into();
+        if let Some(error) = WireError::from_code(msg_info.label()) {
+            return Err(IPCError::MalformedRequest(error));
         }
-        Ok(ipc_buffer.copy_rsp_from_buffer())
+        ipc_buffer
+            .decode_from_buffer(msg_info.length_words())
+            .map_err(IPCError::MalformedResponse)
     }
 }
 
@@ -351,7 +1392,12 @@ impl<Req, Rsp> Responder<Req, Rsp, role::Local> {
             _role: PhantomData,
         }
     }
+}
 
+/// Responders answer through the implicit reply capability of the
+/// classic scheduler, so there are none under the MCS scheduler.
+#[cfg(not(KernelIsMCS))]
+impl<Req, Rsp> Responder<Req, Rsp, role::Local> {
     pub fn reply_recv<F>(self, mut f: F) -> Result<Rsp, IPCError>
     where
         F: FnMut(Req) -> Rsp,
@@ -380,13 +1426,11 @@ impl<Req, Rsp> Responder<Req, Rsp, role::Local> {
         F: FnMut(Req, State) -> (Rsp, State),
         G: FnMut(usize, State) -> State,
     {
-        // Can safely use unchecked_new because we check sizing during the creation of
-        // Responder
-        let mut ipc_buffer = unsafe { IPCBuffer::unchecked_new() };
+        // Can safely use unchecked_new because Transport handles messages of any size
+        let mut transport = unsafe { Transport::unchecked_new() }.with_notification_badges();
         let mut sender_badge: usize = 0;
         // Do a regular receive to seed our initial value
-        let mut msg_info: MessageInfo =
-            unsafe { seL4_Recv(self.endpoint.cptr, &mut sender_badge as *mut usize) }.into();
+        let mut msg_info = transport.recv(self.endpoint.cptr, &mut sender_badge);
 
         let request_length_in_words = type_length_in_words::<Req>();
         let mut response;
@@ -394,10 +1438,10 @@ impl<Req, Rsp> Responder<Req, Rsp, role::Local> {
         loop {
             // if the badge is zero, it's a regular IPC
             if sender_badge == 0 {
-                if msg_info.length_words() != request_length_in_words {
+                if !transport.has_request(&msg_info) {
                     // A wrong-sized message length is an indication of unforeseen or
-                    // misunderstood kernel operations. Using the checks established in
-                    // the creation of Caller/Responder sets should prevent the creation
+                    // misunderstood kernel operations. A Caller and Responder made as a
+                    // pair share their Req and Rsp types, which should prevent the creation
                     // of wrong-sized messages through their expected paths.
                     //
                     // Not knowing what this incoming message is, we drop it and spin-fail the loop.
@@ -408,47 +1452,86 @@ impl<Req, Rsp> Responder<Req, Rsp, role::Local> {
                 msg_info.length_words(), request_length_in_words);
                     continue;
                 }
-                let out = f(ipc_buffer.copy_req_from_buffer(), state);
+                let out = f(transport.request(), state);
                 response = out.0;
                 state = out.1;
 
-                ipc_buffer.copy_rsp_into_buffer(&response);
-                msg_info = unsafe {
-                    seL4_ReplyRecv(
-                        self.endpoint.cptr,
-                        type_length_message_info::<Rsp>(),
-                        &mut sender_badge as *mut usize,
-                    )
-                }
-                .into();
+                msg_info = transport.reply_recv(self.endpoint.cptr, &response, &mut sender_badge);
             } else {
                 // nonzero badges are from a notification
                 state = g(sender_badge, state);
 
-                msg_info =
-                    unsafe { seL4_Recv(self.endpoint.cptr, &mut sender_badge as *mut usize) }
-                        .into();
+                msg_info = transport.recv(self.endpoint.cptr, &mut sender_badge);
             }
         }
     }
 
+    /// Like `reply_recv`, but the handler is also given the badge of
+    /// the calling client, as set by `IpcSetup::create_badged_caller`.
+    pub fn reply_recv_with_badge<F>(self, mut f: F) -> Result<Rsp, IPCError>
+    where
+        F: FnMut(Badge, Req) -> Rsp,
+    {
+        self.reply_recv_with_badge_and_state((), move |badge, req, state| (f(badge, req), state))
+    }
+
+    /// Like `reply_recv_with_state`, but the handler is also given the
+    /// badge of the calling client, allowing it to keep per-client
+    /// state or refuse particular clients.
+    ///
+    /// Since nonzero badges identify clients here, this responder can't
+    /// also service a bound notification the way
+    /// `reply_recv_with_notification` does.
+    pub fn reply_recv_with_badge_and_state<F, State>(
+        self,
+        initial_state: State,
+        mut f: F,
+    ) -> Result<Rsp, IPCError>
+    where
+        F: FnMut(Badge, Req, State) -> (Rsp, State),
+    {
+        // Can safely use unchecked_new because Transport handles messages of any size
+        let mut transport = unsafe { Transport::unchecked_new() };
+        let mut sender_badge: usize = 0;
+        // Do a regular receive to seed our initial value
+        let mut msg_info = transport.recv(self.endpoint.cptr, &mut sender_badge);
+
+        let request_length_in_words = type_length_in_words::<Req>();
+        let mut response;
+        let mut state = initial_state;
+        loop {
+            if !transport.has_request(&msg_info) {
+                // See `reply_recv_with_notification` for why a wrong-sized message
+                // is dropped without a reply. Rather than spinning on the stale
+                // message, wait for the next one.
+                debug_println!("Request size incoming ({} words) does not match static size expectation ({} words).",
+                msg_info.length_words(), request_length_in_words);
+                msg_info = transport.recv(self.endpoint.cptr, &mut sender_badge);
+                continue;
+            }
+            let out = f(Badge::from(sender_badge), transport.request(), state);
+            response = out.0;
+            state = out.1;
+
+            msg_info = transport.reply_recv(self.endpoint.cptr, &response, &mut sender_badge);
+        }
+    }
+
     pub fn recv_reply_once<F>(&self, mut f: F) -> Result<(), IPCError>
     where
         F: FnMut(Req) -> Rsp,
     {
-        // Can safely use unchecked_new because we check sizing during the creation of
-        // Responder
-        let mut ipc_buffer = unsafe { IPCBuffer::unchecked_new() };
+        // Can safely use unchecked_new because Transport handles messages of any size
+        let mut transport = unsafe { Transport::unchecked_new() };
         let mut sender_badge: usize = 0;
         // Do a regular receive to seed our initial value
-        let msg_info: MessageInfo =
-            unsafe { seL4_Recv(self.endpoint.cptr, &mut sender_badge as *mut usize) }.into();
+        let msg_info = transport.recv(self.endpoint.cptr, &mut sender_badge);
 
         let request_length_in_words = type_length_in_words::<Req>();
-        if msg_info.length_words() != request_length_in_words {
+        if !transport.has_request(&msg_info) {
             // A wrong-sized message length is an indication of unforeseen or
-            // misunderstood kernel operations. Using the checks established in
-            // the creation of Caller/Responder sets should prevent the creation
+            // misunderstood kernel operations. A Caller and Responder made as a
+            // pair share their Req and Rsp types, which should prevent the creation
             // of wrong-sized messages through their expected paths.
             //
             // Not knowing what this incoming message is, we drop it and spin-fail the loop.
@@ -460,14 +1543,247 @@ impl<Req, Rsp> Responder<Req, Rsp, role::Local> {
             return Err(IPCError::RequestSizeMismatch);
         }
 
-        let response = f(ipc_buffer.copy_req_from_buffer());
-        ipc_buffer.copy_rsp_into_buffer(&response);
+        let response = f(transport.request());
+        transport.reply(self.endpoint.cptr, &response, &mut sender_badge);
+
+        Ok(())
+    }
+
+    /// Receive a single request without replying to it.
+    ///
+    /// The caller's reply capability is saved into `reply_slot` and
+    /// handed back as a `PendingReply`, which may be held onto while
+    /// this responder goes on to receive requests from other clients.
+    /// The caller stays blocked until `PendingReply::reply` is used.
+    ///
+    /// If no request is received, `reply_slot` is handed back along
+    /// with the error.
+    pub fn recv_deferred(
+        &self,
+        reply_slot: LocalCNodeSlot,
+    ) -> Result<(Req, PendingReply<Rsp>), (IPCError, LocalCNodeSlot)> {
+        // Deferred replies aren't sent in chunks, so both messages must
+        // fit in the buffer
+        let ipc_buffer: IPCBuffer<Req, Rsp> = match IPCBuffer::new() {
+            Ok(ipc_buffer) => ipc_buffer,
+            Err(e) => return Err((e, reply_slot)),
+        };
+        let mut sender_badge: usize = 0;
+        let msg_info: MessageInfo =
+            unsafe { seL4_Recv(self.endpoint.cptr, &mut sender_badge as *mut usize) }.into();
+
+        let request_length_in_words = type_length_in_words::<Req>();
+        if msg_info.length_words() != request_length_in_words {
+            // See `recv_reply_once` for why a wrong-sized message is
+            // dropped without a reply.
+            debug_println!("Request size incoming ({} words) does not match static size expectation ({} words).",
+                msg_info.length_words(), request_length_in_words);
+            return Err((IPCError::RequestSizeMismatch, reply_slot));
+        }
+
+        // Copy the request out before anything else gets a chance to
+        // clobber the IPC buffer.
+        let request = ipc_buffer.copy_req_from_buffer();
+
+        if let Err(e) = unsafe {
+            seL4_CNode_SaveCaller(
+                reply_slot.cptr,            // _service
+                reply_slot.cap_data.offset, // index
+                arch::WordSize::U8,         // depth
+            )
+        }
+        .as_result()
+        {
+            return Err((SeL4Error::CNodeSaveCaller(e).into(), reply_slot));
+        }
+
+        let (slot_cptr, offset, _) = reply_slot.elim();
+        Ok((
+            request,
+            PendingReply {
+                reply_cptr: offset,
+                slot_cptr,
+                badge: Badge::from(sender_badge),
+                _rsp: PhantomData,
+            },
+        ))
+    }
+}
+
+/// A reply owed to a caller whose request was received with
+/// `Responder::recv_deferred`.
+///
+/// The reply capability lives in a slot of the responder's CNode.
+/// Replying consumes it, resumes the caller with the response, and
+/// gives the slot back. Dropping it instead leaves the caller blocked
+/// and the slot occupied, so one that won't be replied to should be
+/// given up with `PendingReply::abandon`.
+#[cfg(not(KernelIsMCS))]
+#[derive(Debug)]
+#[must_use = "the caller stays blocked until it is replied to"]
+pub struct PendingReply<Rsp: Sized> {
+    reply_cptr: usize,
+    slot_cptr: usize,
+    badge: Badge,
+    _rsp: PhantomData<Rsp>,
+}
+
+#[cfg(not(KernelIsMCS))]
+impl<Rsp: Sized> PendingReply<Rsp> {
+    /// The badge of the caller awaiting this reply.
+    pub fn badge(&self) -> Badge {
+        self.badge
+    }
+
+    /// Send `response` to the waiting caller and return the slot the
+    /// reply capability occupied.
+    pub fn reply(self, response: &Rsp) -> LocalCNodeSlot {
+        // Can safely use unchecked_new because the response size was
+        // checked by the `recv_deferred` which created this
+        let mut ipc_buffer: IPCBuffer<(), Rsp> = unsafe { IPCBuffer::unchecked_new() };
+        ipc_buffer.copy_rsp_into_buffer(response);
+
+        unsafe {
+            seL4_Send(self.reply_cptr, type_length_message_info::<Rsp>());
+        }
+
+        // The kernel invalidates a reply capability once it has been
+        // used, leaving the slot empty.
+        self.to_slot()
+    }
 
+    /// Delete the reply capability without replying, and return the
+    /// slot it was in. The caller is never resumed, so this is for
+    /// callers which are about to be torn down.
+    pub fn abandon(self) -> Result<LocalCNodeSlot, SeL4Error> {
         unsafe {
-            seL4_Reply(type_length_message_info::<Rsp>());
+            seL4_CNode_Delete(
+                self.slot_cptr,     // _service
+                self.reply_cptr,    // index
+                arch::WordSize::U8, // depth
+            )
         }
+        .as_result()
+        .map_err(SeL4Error::CNodeDelete)?;
 
-        Ok(())
+        Ok(self.to_slot())
+    }
+
+    fn to_slot(self) -> LocalCNodeSlot {
+        Cap {
+            cptr: self.slot_cptr,
+            _role: PhantomData,
+            cap_data: CNodeSlotsData {
+                offset: self.reply_cptr,
+                _role: PhantomData,
+                _size: PhantomData,
+            },
+        }
+    }
+}
+
+#[cfg(not(KernelIsMCS))]
+impl<Req: WireFormat, Rsp: WireFormat> Responder<Req, Rsp, role::Local> {
+    /// Like `reply_recv`, but serving a `Caller` which uses
+    /// `blocking_call_encoded`.
+    pub fn reply_recv_encoded<F>(self, mut f: F) -> Result<Rsp, IPCError>
+    where
+        F: FnMut(Req) -> Rsp,
+    {
+        self.reply_recv_encoded_with_state((), move |req, state| (f(req), state))
+    }
+
+    /// Like `reply_recv_with_state`, but serving a `Caller` which uses
+    /// `blocking_call_encoded`.
+    ///
+    /// A request which fails to decode is not passed to the handler.
+    /// Instead the caller is sent an empty reply labeled with the
+    /// decoding error, which it reports as `IPCError::MalformedRequest`.
+    pub fn reply_recv_encoded_with_state<F, State>(
+        self,
+        initial_state: State,
+        mut f: F,
+    ) -> Result<Rsp, IPCError>
+    where
+        F: FnMut(Req, State) -> (Rsp, State),
+    {
+        // Can safely use unchecked_new because encodings are bounded by the buffer
+        // rather than by the sizes of Req and Rsp
+        let mut ipc_buffer: IPCBuffer<Req, Rsp> = unsafe { IPCBuffer::unchecked_new() };
+        let mut sender_badge: usize = 0;
+        // Do a regular receive to seed our initial value
+        let mut msg_info: MessageInfo =
+            unsafe { seL4_Recv(self.endpoint.cptr, &mut sender_badge as *mut usize) }.into();
+
+        let mut state = initial_state;
+        loop {
+            let reply_info = match ipc_buffer.decode_from_buffer(msg_info.length_words()) {
+                Ok(request) => {
+                    let out = f(request, state);
+                    state = out.1;
+                    encode_response(&mut ipc_buffer, &out.0)
+                }
+                Err(error) => {
+                    debug_println!("Rejecting malformed request: {:?}", error);
+                    rejection_message_info(error)
+                }
+            };
+            msg_info = unsafe {
+                seL4_ReplyRecv(
+                    self.endpoint.cptr,
+                    reply_info,
+                    &mut sender_badge as *mut usize,
+                )
+            }
+            .into();
+        }
+    }
+
+    /// Like `recv_reply_once`, but serving a `Caller` which uses
+    /// `blocking_call_encoded`.
+    ///
+    /// A request which fails to decode is rejected as described in
+    /// `reply_recv_encoded_with_state`, and the error is also returned.
+    pub fn recv_reply_once_encoded<F>(&self, f: F) -> Result<(), IPCError>
+    where
+        F: FnOnce(Req) -> Rsp,
+    {
+        // Can safely use unchecked_new because encodings are bounded by the buffer
+        // rather than by the sizes of Req and Rsp
+        let mut ipc_buffer: IPCBuffer<Req, Rsp> = unsafe { IPCBuffer::unchecked_new() };
+        let mut sender_badge: usize = 0;
+        let msg_info: MessageInfo =
+            unsafe { seL4_Recv(self.endpoint.cptr, &mut sender_badge as *mut usize) }.into();
+
+        match ipc_buffer.decode_from_buffer(msg_info.length_words()) {
+            Ok(request) => {
+                let response = f(request);
+                let reply_info = encode_response(&mut ipc_buffer, &response);
+                unsafe { seL4_Reply(reply_info) };
+                Ok(())
+            }
+            Err(error) => {
+                unsafe { seL4_Reply(rejection_message_info(error)) };
+                Err(IPCError::MalformedRequest(error))
+            }
+        }
+    }
+}
+
+/// Encode a response into the IPC buffer. A response which can't be
+/// encoded is replaced by an empty message, which the caller will
+/// fail to decode and report as `IPCError::MalformedResponse`.
+#[cfg(not(KernelIsMCS))]
+fn encode_response<Req, Rsp: WireFormat>(
+    ipc_buffer: &mut IPCBuffer<Req, Rsp>,
+    response: &Rsp,
+) -> seL4_MessageInfo_t {
+    match ipc_buffer.encode_into_buffer(response) {
+        Ok(length_words) => encoded_message_info(length_words),
+        Err(error) => {
+            debug_println!("Could not encode response: {:?}", error);
+            encoded_message_info(0)
+        }
     }
 }
 
@@ -488,6 +1804,22 @@ impl<Msg: Sized> Sender<Msg, role::Local> {
         }
         Ok(())
     }
+
+    /// Send `message` only if a receiver is already waiting on the
+    /// endpoint, never blocking this thread.
+    ///
+    /// The kernel gives no indication of whether the message was
+    /// delivered; if no receiver was waiting it is silently dropped.
+    pub fn try_send(&self, message: &Msg) -> Result<(), IPCError> {
+        // Using unchecked_new is acceptable here because we check the message size
+        // constraints during the construction of Sender + FaultOrMessageHandler
+        let mut ipc_buffer: IPCBuffer<Msg, ()> = unsafe { IPCBuffer::unchecked_new() };
+        ipc_buffer.copy_req_into_buffer(message);
+        unsafe {
+            seL4_NBSend(self.endpoint.cptr, type_length_message_info::<Msg>());
+        }
+        Ok(())
+    }
 }
 
 impl<Msg: Sized, Role: CNodeRole> Sender<Msg, Role> {
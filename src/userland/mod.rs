@@ -1,15 +1,29 @@
+mod backtrace;
+mod core_dump;
 mod fault;
+mod gdbstub;
 mod ipc;
 mod irq;
 mod multi_consumer;
+#[cfg(not(KernelIsMCS))]
+mod pager;
 pub(crate) mod process;
 mod rights;
 mod shared_memory_ipc;
+mod wire;
 
+pub use crate::userland::backtrace::*;
+pub use crate::userland::core_dump::*;
 pub use crate::userland::fault::*;
+pub use crate::userland::gdbstub::*;
 pub use crate::userland::ipc::*;
 pub use crate::userland::irq::*;
 pub use crate::userland::multi_consumer::*;
+#[cfg(not(KernelIsMCS))]
+pub use crate::userland::pager::*;
 pub use crate::userland::process::*;
 pub use crate::userland::rights::*;
 pub use crate::userland::shared_memory_ipc::*;
+pub use crate::userland::wire::*;
+
+pub use crate::rpc_service::rpc_service;
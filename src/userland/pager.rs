@@ -0,0 +1,636 @@
+//! Demand paging of children's address spaces.
+//!
+//! A `LazyRegion` claims address space in a child's `VSpace` without
+//! backing any of it. Once the region is handed to a `Pager`, which
+//! receives the child's faults, each page is retyped and mapped the
+//! first time the child touches it, so big but sparsely used heaps and
+//! buffers only cost the memory that actually gets used.
+//!
+//! The same `Pager` also looks after copy-on-write regions, which are
+//! mapped read-only into every address space that shares them. When a
+//! child writes to one of their pages, the pager copies the page into
+//! fresh memory and maps the copy in its place, writable. Many children
+//! can share the same initialized data that way, such as that of an
+//! `ElfDataTemplate`, and only pay for the pages each of them changes.
+//!
+//! A pager can look after several children's address spaces at once,
+//! told apart by the badges of the fault sources their faults come
+//! through.
+//!
+//! Faulted threads are resumed through their saved reply capabilities,
+//! which the MCS kernel doesn't offer, so this is only built for the
+//! classic scheduler.
+
+use core::marker::PhantomData;
+use core::ops::Sub;
+
+use arrayvec::ArrayVec;
+use typenum::*;
+
+use crate::alloc::ut_buddy::{UTBuddyError, WUTBuddy};
+use crate::arch::fault::Fault;
+use crate::arch::fault_status::{Access, FaultKind};
+use crate::arch::{self, PageBits, PageBytes};
+use crate::cap::{
+    page_state, role, Badge, Cap, FaultReplyEndpoint, InternalASID, LocalCNodeSlot, LocalCap, Page,
+    RetypeError, WCNodeSlots,
+};
+use crate::error::SeL4Error;
+use crate::pow::{Pow, _Pow};
+use crate::userland::{CapRights, FaultSink};
+use crate::vspace::{
+    shared_status, ElfDataMapping, ElfDataTemplate, MappedMemoryRegion, ReservedRegion,
+    ScratchRegion, UnmappedMemoryRegion, VSpace, VSpaceError,
+};
+
+/// The most address spaces a single `Pager` can look after
+pub const MAX_PAGED_VSPACES: usize = 8;
+
+/// The most lazy and copy-on-write regions a single `Pager` can look
+/// after
+pub const MAX_PAGED_REGIONS: usize = 16;
+
+/// The most pages a single `Pager` can back or copy
+pub const MAX_PAGED_IN_PAGES: usize = 64;
+
+/// `PageCount` pages of address space in a child's `VSpace` which are
+/// only backed by memory once the child touches them.
+pub struct LazyRegion<PageCount: Unsigned> {
+    reserved: ReservedRegion<PageCount>,
+    rights: CapRights,
+    vm_attributes: arch::VMAttributes,
+}
+
+impl<PageCount: Unsigned> LazyRegion<PageCount>
+where
+    PageCount: IsGreaterOrEqual<U1, Output = True>,
+{
+    /// Reserve the region in `vspace`. Its pages will be mapped with
+    /// the given rights and attributes as they are faulted in.
+    pub fn new(
+        vspace: &mut VSpace,
+        rights: CapRights,
+        vm_attributes: arch::VMAttributes,
+    ) -> Result<Self, VSpaceError> {
+        Ok(LazyRegion {
+            reserved: vspace.reserve_unbacked()?,
+            rights,
+            vm_attributes,
+        })
+    }
+
+    /// The address of the region's first page
+    pub fn vaddr(&self) -> usize {
+        self.reserved.vaddr()
+    }
+
+    pub fn size(&self) -> usize {
+        self.reserved.size()
+    }
+}
+
+/// One of the address spaces a pager looks after, and the badge its
+/// faults come with
+struct PagedVSpace<'a> {
+    badge: Badge,
+    vspace: &'a mut VSpace,
+}
+
+/// A lazy or copy-on-write region, as far as the pager needs to know
+/// it
+struct PagedRange {
+    /// Which of the pager's address spaces the region is in
+    vspace: usize,
+    vaddr: usize,
+    size: usize,
+    rights: CapRights,
+    vm_attributes: arch::VMAttributes,
+    backing: Backing,
+}
+
+enum Backing {
+    /// Nothing is mapped until the child touches it.
+    Lazy,
+    /// Every page starts out as a read-only mapping of a copy of one of
+    /// the template's frames. The caps for those copies start at
+    /// `frames_cptr`, and the template's own start at `template_cptr`.
+    CopyOnWrite {
+        frames_cptr: usize,
+        template_cptr: usize,
+    },
+}
+
+#[derive(Debug)]
+pub enum PagerError {
+    TooManyVSpaces,
+    /// Another of the pager's address spaces already has this badge.
+    BadgeInUse,
+    TooManyRegions,
+    /// The pager has already backed or copied as many pages as it can
+    /// keep track of.
+    TooManyPages,
+    /// The reply from a `PagerEvent::Failed` hasn't been dealt with
+    /// yet, and its slot is where the next fault's reply goes.
+    ReplyOutstanding,
+    /// The region was reserved or mapped in a VSpace the pager hasn't
+    /// been given.
+    ASIDMismatch,
+    /// Copy-on-write regions can only be added once the pager has a
+    /// scratch region to copy pages through.
+    NoScratchRegion,
+    UTBuddyError(UTBuddyError),
+    RetypeError(RetypeError),
+    VSpaceError(VSpaceError),
+    SeL4Error(SeL4Error),
+}
+
+impl From<UTBuddyError> for PagerError {
+    fn from(e: UTBuddyError) -> Self {
+        PagerError::UTBuddyError(e)
+    }
+}
+
+impl From<RetypeError> for PagerError {
+    fn from(e: RetypeError) -> Self {
+        PagerError::RetypeError(e)
+    }
+}
+
+impl From<VSpaceError> for PagerError {
+    fn from(e: VSpaceError) -> Self {
+        PagerError::VSpaceError(e)
+    }
+}
+
+impl From<SeL4Error> for PagerError {
+    fn from(e: SeL4Error) -> Self {
+        PagerError::SeL4Error(e)
+    }
+}
+
+/// The outcome of a `Pager` handling a fault
+#[derive(Debug)]
+pub enum PagerEvent {
+    /// The page at this address was backed, and the faulted thread
+    /// resumed.
+    PagedIn(usize),
+    /// The page at this address was written to for the first time, and
+    /// replaced with a private copy before the faulted thread resumed.
+    CopiedOnWrite(usize),
+    /// A fault from an address space the pager doesn't look after, or
+    /// outside of any region it looks after, or one which backing or
+    /// copying a page wouldn't fix. The faulted thread is left blocked.
+    Unhandled(Fault),
+    /// Backing or copying a page for this fault went wrong. The faulted
+    /// thread is left blocked, and it's up to the caller to resume it
+    /// or destroy the reply, then hand the slot back with
+    /// `Pager::restore_reply_slot`.
+    Failed {
+        fault: Fault,
+        error: PagerError,
+        reply: LocalCap<FaultReplyEndpoint>,
+    },
+}
+
+/// What the pager does about a fault
+enum Resolution {
+    Back,
+    Copy {
+        frame_cptr: usize,
+        template_cptr: usize,
+    },
+}
+
+/// Backs the pages of lazy regions in its children's `VSpace`s as they
+/// fault on them, and copies the pages of copy-on-write regions as they
+/// write to them, with memory from its own `WUTBuddy`.
+pub struct Pager<'a> {
+    vspaces: ArrayVec<[PagedVSpace<'a>; MAX_PAGED_VSPACES]>,
+    sink: FaultSink<role::Local>,
+    untyped: WUTBuddy,
+    slots: WCNodeSlots,
+    reply_slot: Option<LocalCNodeSlot>,
+    scratch: Option<&'a mut ScratchRegion>,
+    regions: ArrayVec<[PagedRange; MAX_PAGED_REGIONS]>,
+    pages: ArrayVec<[MappedMemoryRegion<PageBits, shared_status::Exclusive>; MAX_PAGED_IN_PAGES]>,
+    /// A zeroed page retyped for a fault that couldn't be mapped, kept
+    /// for the next one rather than retyping another
+    spare_page: Option<LocalCap<Page<page_state::Unmapped>>>,
+}
+
+/// What a `Pager` was built from, along with every page it backed or
+/// copied, handed back by `Pager::into_parts`.
+pub struct PagerParts {
+    pub sink: FaultSink<role::Local>,
+    pub untyped: WUTBuddy,
+    pub slots: WCNodeSlots,
+    /// Missing if the reply from a `PagerEvent::Failed` still has it
+    pub reply_slot: Option<LocalCNodeSlot>,
+    pub pages:
+        ArrayVec<[MappedMemoryRegion<PageBits, shared_status::Exclusive>; MAX_PAGED_IN_PAGES]>,
+    /// A page retyped for a fault that couldn't be mapped, if one was
+    /// never used after that
+    pub spare_page: Option<LocalCap<Page<page_state::Unmapped>>>,
+}
+
+impl<'a> Pager<'a> {
+    /// Make a pager for children whose threads send their faults to
+    /// `sink`. Pages are retyped out of `untyped` into `slots`.
+    pub fn new(
+        sink: FaultSink<role::Local>,
+        untyped: WUTBuddy,
+        slots: WCNodeSlots,
+        reply_slot: LocalCNodeSlot,
+    ) -> Self {
+        Pager {
+            vspaces: ArrayVec::new(),
+            sink,
+            untyped,
+            slots,
+            reply_slot: Some(reply_slot),
+            scratch: None,
+            regions: ArrayVec::new(),
+            pages: ArrayVec::new(),
+            spare_page: None,
+        }
+    }
+
+    /// Give back the slot of the reply from a `PagerEvent::Failed`,
+    /// once it has been used, so the pager can handle faults again.
+    pub fn restore_reply_slot(&mut self, slot: LocalCNodeSlot) {
+        self.reply_slot = Some(slot);
+    }
+
+    /// Stop paging, and hand back the pager's resources along with the
+    /// pages it mapped.
+    pub fn into_parts(self) -> PagerParts {
+        PagerParts {
+            sink: self.sink,
+            untyped: self.untyped,
+            slots: self.slots,
+            reply_slot: self.reply_slot,
+            pages: self.pages,
+            spare_page: self.spare_page,
+        }
+    }
+
+    /// Give the pager a region of the local address space to map fresh
+    /// pages into while it fills them with copies. It's needed before
+    /// any copy-on-write region can be added.
+    pub fn with_scratch(self, scratch: &'a mut ScratchRegion) -> Self {
+        Pager {
+            scratch: Some(scratch),
+            ..self
+        }
+    }
+
+    /// Look after `vspace`, whose threads' fault sources were minted
+    /// with `badge`. Faults with a badge the pager hasn't been given are
+    /// left alone.
+    pub fn add_vspace(&mut self, vspace: &'a mut VSpace, badge: Badge) -> Result<(), PagerError> {
+        if self.vspaces.iter().any(|v| v.badge == badge) {
+            return Err(PagerError::BadgeInUse);
+        }
+        self.vspaces
+            .try_push(PagedVSpace { badge, vspace })
+            .map_err(|_| PagerError::TooManyVSpaces)
+    }
+
+    /// Start backing `region` on demand.
+    pub fn add_region<PageCount: Unsigned>(
+        &mut self,
+        region: LazyRegion<PageCount>,
+    ) -> Result<(), PagerError>
+    where
+        PageCount: IsGreaterOrEqual<U1, Output = True>,
+    {
+        let vspace = self.vspace_index(region.reserved.asid())?;
+        if self.regions.is_full() {
+            return Err(PagerError::TooManyRegions);
+        }
+        self.regions.push(PagedRange {
+            vspace,
+            vaddr: region.vaddr(),
+            size: region.size(),
+            rights: region.rights,
+            vm_attributes: region.vm_attributes,
+            backing: Backing::Lazy,
+        });
+        Ok(())
+    }
+
+    /// Start copying the pages of `region`, which was mapped into one
+    /// of the pager's `VSpace`s from `template`, when they're written
+    /// to. The copies are filled from the template's frames, and mapped
+    /// read-write with `vm_attributes`.
+    ///
+    /// The mapped region's caps must stay where they are for as long as
+    /// the pager is around, since it unmaps them page by page.
+    pub fn add_copy_on_write_region<SizeBits: Unsigned>(
+        &mut self,
+        region: &MappedMemoryRegion<SizeBits, shared_status::CopyOnWrite>,
+        template: &'a UnmappedMemoryRegion<SizeBits, shared_status::CopyOnWrite>,
+        vm_attributes: arch::VMAttributes,
+    ) -> Result<(), PagerError>
+    where
+        SizeBits: IsGreaterOrEqual<PageBits>,
+        SizeBits: Sub<PageBits>,
+        <SizeBits as Sub<PageBits>>::Output: Unsigned,
+        <SizeBits as Sub<PageBits>>::Output: _Pow,
+        Pow<<SizeBits as Sub<PageBits>>::Output>: Unsigned,
+    {
+        let vspace = self.vspace_index(region.asid())?;
+        if self.scratch.is_none() {
+            return Err(PagerError::NoScratchRegion);
+        }
+        if self.regions.is_full() {
+            return Err(PagerError::TooManyRegions);
+        }
+        self.regions.push(PagedRange {
+            vspace,
+            vaddr: region.vaddr(),
+            size: region.size_bytes(),
+            rights: CapRights::RW,
+            vm_attributes,
+            backing: Backing::CopyOnWrite {
+                frames_cptr: region.start_cptr(),
+                template_cptr: template.start_cptr(),
+            },
+        });
+        Ok(())
+    }
+
+    /// Start copying the pages of an ELF image's writable segments, as
+    /// mapped into one of the pager's `VSpace`s by
+    /// `VSpace::new_from_elf_copy_on_write`, when they're written to.
+    pub fn add_elf_data(
+        &mut self,
+        mapping: &ElfDataMapping,
+        template: &'a ElfDataTemplate,
+    ) -> Result<(), PagerError> {
+        let vspace = self.vspace_index(mapping.asid())?;
+        if self.scratch.is_none() {
+            return Err(PagerError::NoScratchRegion);
+        }
+        if self.regions.len() + template.segments().len() > self.regions.capacity() {
+            return Err(PagerError::TooManyRegions);
+        }
+        for segment in template.segments() {
+            self.regions.push(PagedRange {
+                vspace,
+                vaddr: segment.vaddr,
+                size: segment.size,
+                rights: CapRights::RW,
+                vm_attributes: segment.vm_attributes,
+                backing: Backing::CopyOnWrite {
+                    frames_cptr: mapping.frames_cptr() + segment.first_page,
+                    template_cptr: template.pages_cptr() + segment.first_page,
+                },
+            });
+        }
+        Ok(())
+    }
+
+    /// Wait for a fault, and if it's the first touch of a page in one
+    /// of the lazy regions or the first write to a page in one of the
+    /// copy-on-write regions, back or copy that page and resume the
+    /// thread.
+    pub fn handle_next_fault(&mut self) -> Result<PagerEvent, PagerError> {
+        if self.reply_slot.is_none() {
+            return Err(PagerError::ReplyOutstanding);
+        }
+        let fault = self.sink.wait_for_fault();
+        let (vspace, vaddr, rights, vm_attributes, resolution) = match self.find_range(&fault) {
+            Some(found) => found,
+            None => return Ok(PagerEvent::Unhandled(fault)),
+        };
+
+        // Save the reply capability before anything else gets a chance
+        // to overwrite it.
+        let reply_slot = self
+            .reply_slot
+            .take()
+            .expect("The reply slot was checked before waiting");
+        let reply = LocalCap::<FaultReplyEndpoint>::save_caller_and_create(reply_slot)?;
+        let (result, event) = match resolution {
+            Resolution::Back => (
+                self.back_page(vspace, vaddr, rights, vm_attributes),
+                PagerEvent::PagedIn(vaddr),
+            ),
+            Resolution::Copy {
+                frame_cptr,
+                template_cptr,
+            } => (
+                self.copy_page(vspace, vaddr, frame_cptr, template_cptr, vm_attributes),
+                PagerEvent::CopiedOnWrite(vaddr),
+            ),
+        };
+        match result {
+            Ok(()) => {
+                self.reply_slot = Some(reply.resume_faulted_thread());
+                Ok(event)
+            }
+            Err(error) => Ok(PagerEvent::Failed {
+                fault,
+                error,
+                reply,
+            }),
+        }
+    }
+
+    /// Handle faults until one comes along that the pager can't deal
+    /// with, or fails to, and hand back what happened to that one.
+    pub fn run(&mut self) -> Result<PagerEvent, PagerError> {
+        loop {
+            match self.handle_next_fault()? {
+                PagerEvent::PagedIn(_) | PagerEvent::CopiedOnWrite(_) => (),
+                event => return Ok(event),
+            }
+        }
+    }
+
+    /// Which of the pager's address spaces a region was reserved or
+    /// mapped in
+    fn vspace_index(&self, asid: InternalASID) -> Result<usize, PagerError> {
+        self.vspaces
+            .iter()
+            .position(|v| v.vspace.asid() == asid)
+            .ok_or(PagerError::ASIDMismatch)
+    }
+
+    /// The address space and page to back or copy for a fault, along
+    /// with how to map it. The address space is picked by the fault's
+    /// badge, so one child's faults are never resolved in another's.
+    /// Only translation faults are of interest in a lazy region, since
+    /// any other fault there is on a page that's already backed. In a
+    /// copy-on-write region it's writes that fail on permission,
+    /// because a page which has already been copied is writable.
+    fn find_range(
+        &self,
+        fault: &Fault,
+    ) -> Option<(usize, usize, CapRights, arch::VMAttributes, Resolution)> {
+        let (address, status) = match fault {
+            Fault::VMFault(f) => (f.address, f.status()),
+            _ => return None,
+        };
+        let vspace = self
+            .vspaces
+            .iter()
+            .position(|v| v.badge == fault.sender())?;
+        let range = self
+            .regions
+            .iter()
+            .find(|r| r.vspace == vspace && address >= r.vaddr && address - r.vaddr < r.size)?;
+        let vaddr = address & !(PageBytes::USIZE - 1);
+        let resolution = match range.backing {
+            Backing::Lazy if status.kind == FaultKind::Translation => Resolution::Back,
+            Backing::CopyOnWrite {
+                frames_cptr,
+                template_cptr,
+            } if status.kind == FaultKind::Permission && status.access == Access::Write => {
+                let index = (vaddr - range.vaddr) / PageBytes::USIZE;
+                Resolution::Copy {
+                    frame_cptr: frames_cptr + index,
+                    template_cptr: template_cptr + index,
+                }
+            }
+            _ => return None,
+        };
+        Some((vspace, vaddr, range.rights, range.vm_attributes, resolution))
+    }
+
+    fn back_page(
+        &mut self,
+        vspace: usize,
+        vaddr: usize,
+        rights: CapRights,
+        vm_attributes: arch::VMAttributes,
+    ) -> Result<(), PagerError> {
+        if self.pages.is_full() {
+            return Err(PagerError::TooManyPages);
+        }
+        let page = self.fresh_page()?;
+        match self.vspaces[vspace]
+            .vspace
+            .map_region_at_addr(page.to_region(), vaddr, rights, vm_attributes)
+        {
+            Ok(mapped) => {
+                self.pages.push(mapped);
+                Ok(())
+            }
+            Err((e, unmapped)) => {
+                // The page was never written, so it can go as it is.
+                self.spare_page = unmapped.map(|r| r.to_page());
+                Err(e.into())
+            }
+        }
+    }
+
+    /// A zeroed page, either the one left over from a failed fault or
+    /// a new one retyped out of the pager's untyped
+    fn fresh_page(&mut self) -> Result<LocalCap<Page<page_state::Unmapped>>, PagerError> {
+        if let Some(page) = self.spare_page.take() {
+            return Ok(page);
+        }
+        let ut = self.untyped.alloc(&mut self.slots, PageBits::U8)?;
+        Ok(ut.retype(&mut self.slots)?)
+    }
+
+    /// Fill a fresh page from the template's frame at `template_cptr`,
+    /// then swap it in for the read-only mapping of the frame at
+    /// `frame_cptr`, which sits at `vaddr` in the given address space.
+    /// If the copy can't be mapped, the frame is mapped back.
+    fn copy_page(
+        &mut self,
+        vspace: usize,
+        vaddr: usize,
+        frame_cptr: usize,
+        template_cptr: usize,
+        vm_attributes: arch::VMAttributes,
+    ) -> Result<(), PagerError> {
+        if self.pages.is_full() {
+            return Err(PagerError::TooManyPages);
+        }
+        let page = self.fresh_page()?;
+        let template: LocalCap<Page<page_state::Unmapped>> = Cap {
+            cptr: template_cptr,
+            cap_data: Page {
+                state: page_state::Unmapped {},
+                _granule: PhantomData,
+            },
+            _role: PhantomData,
+        };
+        let copied = match self.scratch.as_mut() {
+            Some(scratch) => scratch.copy_page(&template, &page).map_err(PagerError::from),
+            None => Err(PagerError::NoScratchRegion),
+        };
+        if let Err(e) = copied {
+            self.keep_dirty_page(page);
+            return Err(e);
+        }
+
+        // The shared frame's cap is the child's own copy, made when the
+        // region was mapped, so only its mapping goes.
+        let shared: LocalCap<Page<page_state::Mapped>> = Cap {
+            cptr: frame_cptr,
+            cap_data: Page {
+                state: page_state::Mapped {
+                    vaddr,
+                    asid: self.vspaces[vspace].vspace.asid(),
+                    rights: CapRights::R,
+                },
+                _granule: PhantomData,
+            },
+            _role: PhantomData,
+        };
+        let shared = match shared.unmap() {
+            Ok(shared) => shared,
+            Err(e) => {
+                self.keep_dirty_page(page);
+                return Err(e.into());
+            }
+        };
+        match self.vspaces[vspace].vspace.map_region_at_addr(
+            page.to_region(),
+            vaddr,
+            CapRights::RW,
+            vm_attributes,
+        ) {
+            Ok(mapped) => {
+                self.pages.push(mapped);
+                Ok(())
+            }
+            Err((e, unmapped)) => {
+                if let Some(unmapped) = unmapped {
+                    self.keep_dirty_page(unmapped.to_page());
+                }
+                // Put the shared frame back, so the child is left as it
+                // was and its next write faults the same way.
+                self.vspaces[vspace]
+                    .vspace
+                    .map_region_at_addr(shared.to_region(), vaddr, CapRights::R, vm_attributes)
+                    .map_err(|(e, _)| e)?;
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Zero a page that a copy was written into, and keep it for the
+    /// next fault. If it can't be zeroed, it's dropped instead, since
+    /// it would otherwise leak the copy into a lazy region.
+    fn keep_dirty_page(&mut self, page: LocalCap<Page<page_state::Unmapped>>) {
+        let mut region = page.to_region();
+        let zeroed = match self.scratch.as_mut() {
+            Some(scratch) => scratch.temporarily_map_region(&mut region, |mapped| {
+                for byte in mapped.as_mut_slice().iter_mut() {
+                    *byte = 0;
+                }
+                mapped.flush()
+            }),
+            None => return,
+        };
+        if let Ok(Ok(())) = zeroed {
+            self.spare_page = Some(region.to_page());
+        }
+    }
+}
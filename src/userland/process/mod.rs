@@ -10,10 +10,10 @@ use crate::vspace::VSpaceError;
 pub(crate) use crate::arch::userland::process::*;
 
 mod thread;
-pub use thread::{Thread, ThreadSetupError};
+pub use thread::{JoinHandle, Thread, ThreadParts, ThreadSetupError};
 
 mod standard;
-pub use standard::StandardProcess;
+pub use standard::{StandardProcess, StandardProcessParts};
 
 mod self_hosted;
 pub use self_hosted::SelfHostedProcess;
@@ -21,6 +21,10 @@ pub use self_hosted::SelfHostedProcess;
 pub type DefaultStackBitSize = U20;
 pub type DefaultStackPageCount = op!((U1 << U20) / U4096);
 pub type DefaultPrepareThreadCNodeSlots = op!(DefaultStackPageCount + U64);
+/// The number of unmapped pages left below each stack, so that a
+/// thread which overflows its stack faults rather than writing over
+/// whatever is mapped below it.
+pub type StackGuardPageCount = U1;
 
 // TODO - consider renaming for clarity
 pub trait RetypeForSetup: Sized + Send + Sync {
@@ -58,6 +62,8 @@ pub enum ProcessSetupError {
     ProcessParameterHandoffSizeMismatch,
     NotEnoughCNodeSlots,
     ParentMappedMemoryRegionASIDShouldNotMatchChildVSpaceASID,
+    /// The priority or MCP is higher than the authority's own MCP
+    PrioritiesExceedAuthority,
     VSpaceError(VSpaceError),
     SeL4Error(SeL4Error),
     ElfParseError(&'static str),
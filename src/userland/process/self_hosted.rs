@@ -7,7 +7,8 @@ use sel_claw::*;
 use crate::arch::{self, PageBits};
 use crate::cap::{
     role, CNodeRole, CNodeSlotsError, Cap, ChildCNode, DirectRetype, LocalCNode, LocalCNodeSlots,
-    LocalCap, ThreadControlBlock, ThreadPriorityAuthority, Untyped, WCNodeSlotsData,
+    LocalCap, ThreadControlBlock, ThreadPriorities, ThreadPriorityAuthority, Untyped,
+    WCNodeSlotsData,
 };
 use crate::userland::CapRights;
 use crate::vspace::*;
@@ -16,6 +17,7 @@ use super::*;
 
 pub struct SelfHostedProcess<StackBitSize: Unsigned = DefaultStackBitSize> {
     tcb: LocalCap<ThreadControlBlock>,
+    stack_guard: GuardRegion<StackGuardPageCount>,
     _stack_bit_size: PhantomData<StackBitSize>,
 }
 
@@ -49,7 +51,8 @@ impl<StackBitSize: Unsigned> SelfHostedProcess<StackBitSize> {
         mut cap_transfer_slots: LocalCap<WCNodeSlotsData<role::Child>>,
         child_paging_slots: Cap<WCNodeSlotsData<role::Child>, role::Child>,
         priority_authority: &LocalCap<ThreadPriorityAuthority>,
-        fault_source: Option<crate::userland::FaultSource<role::Child>>,
+        priorities: ThreadPriorities,
+        fault_source: Option<crate::userland::FaultSource<crate::userland::FaultSourceRole>>,
     ) -> Result<SelfHostedProcess<StackBitSize>, ProcessSetupError>
     where
         NumPages<StackBitSize>: Add<U2>,
@@ -70,6 +73,9 @@ impl<StackBitSize: Unsigned> SelfHostedProcess<StackBitSize> {
                 ProcessSetupError::ParentMappedMemoryRegionASIDShouldNotMatchChildVSpaceASID,
             );
         }
+        if !priorities.allowed_by(priority_authority) {
+            return Err(ProcessSetupError::PrioritiesExceedAuthority);
+        }
         let (misc_slots, stack_slots) = slots.alloc::<U2>();
         // TODO - lift these checks to compile-time, as static assertions
         // Note - This comparison is conservative because technically
@@ -106,7 +112,7 @@ impl<StackBitSize: Unsigned> SelfHostedProcess<StackBitSize> {
         )?;
 
         // Reserve a guard page before the stack
-        vspace.skip_pages(1)?;
+        let stack_guard = vspace.reserve_guard()?;
 
         // Map the stack to the target address space
         let stack_top = parent_mapped_region.vaddr() + parent_mapped_region.size_bytes();
@@ -172,19 +178,30 @@ impl<StackBitSize: Unsigned> SelfHostedProcess<StackBitSize> {
             .as_result()
             .map_err(|e| ProcessSetupError::SeL4Error(SeL4Error::TCBWriteRegisters(e)))?;
 
-            // TODO - priority management could be exposed once we
-            // plan on actually using it
-            tcb.set_priority(priority_authority, 255)?;
+            tcb.set_priorities(priority_authority, priorities)?;
         }
         Ok(SelfHostedProcess {
             tcb,
+            stack_guard,
             _stack_bit_size: PhantomData,
         })
     }
 
+    /// The unmapped pages below the process's stack, for telling a
+    /// stack overflow apart from other faults with `Fault::classify`.
+    pub fn stack_guard(&self) -> &GuardRegion<StackGuardPageCount> {
+        &self.stack_guard
+    }
+
     pub fn start(self) -> Result<(), SeL4Error> {
         unsafe { seL4_TCB_Resume(self.tcb.cptr) }
             .as_result()
             .map_err(SeL4Error::TCBResume)
     }
+
+    /// Pin the process to a core before it starts.
+    #[cfg(all(KernelEnableSMPSupport, not(KernelIsMCS)))]
+    pub fn set_affinity(&mut self, core: usize) -> Result<(), SeL4Error> {
+        self.tcb.set_affinity(core)
+    }
 }
@@ -2,6 +2,9 @@ use crate::arch::{self, *};
 use crate::cap::*;
 use crate::pow::{Pow, _Pow};
 use crate::userland::rights::CapRights;
+use crate::userland::{
+    Backtrace, CoreDump, CoreDumpError, DebugTarget, FrameStart, MappedChildMemory,
+};
 use crate::vspace::*;
 use core::ops::{Add, Sub};
 
@@ -15,16 +18,50 @@ use super::*;
 /// A standard process in Ferros is a TCB associated with a VSpace
 /// that has:
 ///  * A usable code image mapped/written into it.
-///  * A mapped stack.
+///  * A mapped stack, with an unmapped guard below it.
 ///  * Initial process state (e.g. parameter data) written into a
 ///    `seL4_UserContext` and/or its stack.
 ///  * Said seL4_UserContext written into the TCB.
 ///  * An IPC buffer and CSpace and fault handler associated with that TCB.
 pub struct StandardProcess<StackBitSize: Unsigned = DefaultStackBitSize> {
     tcb: LocalCap<ThreadControlBlock>,
+    cspace: LocalCap<ChildCNode>,
+    stack: WeakMappedMemoryRegion<shared_status::Shared>,
+    /// The stack as it is mapped in the child's address space
+    child_stack: WeakMappedMemoryRegion<shared_status::Shared>,
+    stack_guard: GuardRegion<StackGuardPageCount>,
+    ipc_buffer: MappedMemoryRegion<PageBits, shared_status::Exclusive>,
+    ipc_buffer_ut: LocalCap<Untyped<PageBits>>,
+    tcb_ut: LocalCap<Untyped<<ThreadControlBlock as DirectRetype>::SizeBits>>,
+    slots: WCNodeSlots,
+    /// Whether a `destroy` that failed had already suspended the thread
+    /// and unmapped its memory, which can't be done again once the caps
+    /// that went with them are revoked
+    unmapped: bool,
     _stack_bit_size: PhantomData<StackBitSize>,
 }
 
+/// The resources a `StandardProcess` was built from, handed back
+/// by `StandardProcess::destroy` so they can be used to create a
+/// fresh process.
+pub struct StandardProcessParts<StackBitSize: Unsigned = DefaultStackBitSize>
+where
+    NumPages<StackBitSize>: Add<U2>,
+    Sum<NumPages<StackBitSize>, U2>: Unsigned,
+
+    StackBitSize: IsGreaterOrEqual<PageBits>,
+    StackBitSize: Sub<PageBits>,
+    <StackBitSize as Sub<PageBits>>::Output: Unsigned,
+    <StackBitSize as Sub<PageBits>>::Output: _Pow,
+    Pow<<StackBitSize as Sub<PageBits>>::Output>: Unsigned,
+{
+    pub cspace: LocalCap<ChildCNode>,
+    pub parent_mapped_region: MappedMemoryRegion<StackBitSize, shared_status::Exclusive>,
+    pub ipc_buffer_ut: LocalCap<Untyped<PageBits>>,
+    pub tcb_ut: LocalCap<Untyped<<ThreadControlBlock as DirectRetype>::SizeBits>>,
+    pub slots: LocalCNodeSlots<Sum<NumPages<StackBitSize>, U2>>,
+}
+
 pub enum EntryPoint<'a, T> {
     Fork(extern "C" fn(T) -> ()),
     Elf(&'a [u8]),
@@ -59,7 +96,8 @@ impl<StackBitSize: Unsigned> StandardProcess<StackBitSize> {
         tcb_ut: LocalCap<Untyped<<ThreadControlBlock as DirectRetype>::SizeBits>>,
         slots: LocalCNodeSlots<Sum<NumPages<StackBitSize>, U2>>,
         priority_authority: &LocalCap<ThreadPriorityAuthority>,
-        fault_source: Option<crate::userland::FaultSource<role::Child>>,
+        priorities: ThreadPriorities,
+        fault_source: Option<crate::userland::FaultSource<crate::userland::FaultSourceRole>>,
     ) -> Result<StandardProcess<StackBitSize>, ProcessSetupError>
     where
         NumPages<StackBitSize>: Add<U2>,
@@ -82,7 +120,12 @@ impl<StackBitSize: Unsigned> StandardProcess<StackBitSize> {
                 ProcessSetupError::ParentMappedMemoryRegionASIDShouldNotMatchChildVSpaceASID,
             );
         }
+        if !priorities.allowed_by(priority_authority) {
+            return Err(ProcessSetupError::PrioritiesExceedAuthority);
+        }
 
+        let all_slots: LocalCNodeSlots<Sum<NumPages<StackBitSize>, U2>> =
+            Cap::internal_new(slots.cptr, slots.cap_data.offset);
         let (misc_slots, stack_slots) = slots.alloc::<U2>();
         // TODO - lift these checks to compile-time, as static assertions
         // Note - This comparison is conservative because technically
@@ -95,7 +138,7 @@ impl<StackBitSize: Unsigned> StandardProcess<StackBitSize> {
         }
 
         // Reserve a guard page before the stack
-        vspace.skip_pages(1)?;
+        let stack_guard = vspace.reserve_guard()?;
 
         // Map the stack to the target address space
         let stack_top = parent_mapped_region.vaddr() + parent_mapped_region.size_bytes();
@@ -146,6 +189,7 @@ impl<StackBitSize: Unsigned> StandardProcess<StackBitSize> {
 
         // Allocate and map the ipc buffer
         let (ipc_slots, misc_slots) = misc_slots.alloc();
+        let ipc_buffer_ut_alias = Cap::wrap_cptr(ipc_buffer_ut.cptr);
         let ipc_buffer = ipc_buffer_ut.retype(ipc_slots)?;
         let ipc_buffer = vspace.map_region(
             ipc_buffer.to_region(),
@@ -155,13 +199,22 @@ impl<StackBitSize: Unsigned> StandardProcess<StackBitSize> {
 
         //// allocate the thread control block
         let (tcb_slots, _slots) = misc_slots.alloc();
+        let tcb_ut_alias = Cap::wrap_cptr(tcb_ut.cptr);
         let mut tcb = tcb_ut.retype(tcb_slots)?;
 
+        let cspace_alias = Cap {
+            cptr: cspace.cptr,
+            cap_data: CNode {
+                radix: cspace.cap_data.radix,
+                _role: PhantomData,
+            },
+            _role: PhantomData,
+        };
         tcb.configure(
             cspace,
             fault_source,
             vspace.root(),
-            Some(ipc_buffer.to_page()),
+            Some(ipc_buffer.as_page()),
         )?;
         unsafe {
             seL4_TCB_WriteRegisters(
@@ -175,12 +228,19 @@ impl<StackBitSize: Unsigned> StandardProcess<StackBitSize> {
             .as_result()
             .map_err(|e| ProcessSetupError::SeL4Error(SeL4Error::TCBWriteRegisters(e)))?;
 
-            // TODO - priority management could be exposed once we
-            // plan on actually using it
-            tcb.set_priority(priority_authority, 255)?;
+            tcb.set_priorities(priority_authority, priorities)?;
         }
         Ok(StandardProcess {
             tcb,
+            cspace: cspace_alias,
+            stack: local_stack_pages.weaken(),
+            child_stack: mapped_stack_pages.weaken(),
+            stack_guard,
+            ipc_buffer,
+            ipc_buffer_ut: ipc_buffer_ut_alias,
+            tcb_ut: tcb_ut_alias,
+            slots: all_slots.weaken(),
+            unmapped: false,
             _stack_bit_size: PhantomData,
         })
     }
@@ -206,9 +266,193 @@ impl<StackBitSize: Unsigned> StandardProcess<StackBitSize> {
     }
 
     pub fn start(&mut self) -> Result<(), SeL4Error> {
-        unsafe { seL4_TCB_Resume(self.tcb.cptr) }
-            .as_result()
-            .map_err(SeL4Error::TCBResume)
+        self.tcb.resume()
+    }
+
+    /// Stop the process's thread, e.g. to inspect or change its
+    /// registers. It stays stopped until it's resumed.
+    pub fn suspend(&mut self) -> Result<(), SeL4Error> {
+        self.tcb.suspend()
+    }
+
+    /// Let a suspended process run again.
+    pub fn resume(&mut self) -> Result<(), SeL4Error> {
+        self.tcb.resume()
+    }
+
+    /// Read the thread's registers, e.g. to see where it was when it
+    /// faulted.
+    pub fn read_registers(&self) -> Result<UserContext, SeL4Error> {
+        self.tcb.read_registers()
+    }
+
+    /// Replace the thread's registers, optionally resuming it afterwards.
+    /// This should only be done while the thread is suspended or blocked
+    /// on a fault.
+    pub fn write_registers(
+        &mut self,
+        registers: &UserContext,
+        resume: bool,
+    ) -> Result<(), SeL4Error> {
+        self.tcb.write_registers(registers, resume)
+    }
+
+    /// Copy the process's TCB into `slot`, e.g. so that a debugger
+    /// running as a process of its own can control the thread.
+    pub fn copy_tcb<DestRole: CNodeRole>(
+        &self,
+        parent_cnode: &LocalCap<LocalCNode>,
+        slot: CNodeSlot<DestRole>,
+    ) -> Result<Cap<ThreadControlBlock, DestRole>, SeL4Error> {
+        self.tcb.copy(parent_cnode, slot, CapRights::RWG)
+    }
+
+    /// The process's stack, addressed as it is in the child's address
+    /// space.
+    pub fn stack_memory(&self) -> MappedChildMemory<'_> {
+        MappedChildMemory::new(self.stack.as_slice(), self.child_stack.vaddr())
+    }
+
+    /// The unmapped pages below the process's stack, for telling a
+    /// stack overflow apart from other faults with `Fault::classify`.
+    pub fn stack_guard(&self) -> &GuardRegion<StackGuardPageCount> {
+        &self.stack_guard
+    }
+
+    /// Walk the process's stack from its current registers. This is
+    /// only meaningful while the thread isn't running, e.g. when it's
+    /// blocked on a fault.
+    pub fn backtrace(&self) -> Result<Backtrace, SeL4Error> {
+        let registers = self.read_registers()?;
+        Ok(Backtrace::walk(
+            FrameStart::from(&registers),
+            &self.stack_memory(),
+        ))
+    }
+
+    /// Snapshot the process's registers and all of the writable memory
+    /// mapped in `vspace`, the VSpace it runs in. The stack is read from
+    /// the parent's own mapping of it; everything else has to be read
+    /// through a `MemoryWindow` onto `vspace` when the dump is written,
+    /// apart from the contents of any large-page mappings, which are
+    /// left out. This is only meaningful while the thread isn't running, e.g. when
+    /// it's blocked on a fault.
+    pub fn core_dump<'a>(
+        &'a self,
+        vspace: &VSpace,
+        fault: Option<&crate::arch::fault::Fault>,
+    ) -> Result<CoreDump<'a>, CoreDumpError> {
+        if vspace.asid() != self.stack_guard.asid() {
+            return Err(CoreDumpError::VSpaceError(VSpaceError::ASIDMismatch));
+        }
+        let mut dump = CoreDump::new(self.read_registers()?, fault);
+        dump.add_memory(self.stack_memory())?;
+        let stack_start = self.child_stack.vaddr();
+        let stack_end = stack_start + self.child_stack.size_bytes();
+        for mapping in vspace.mappings()?.iter() {
+            let end = mapping.vaddr() + mapping.size_bytes();
+            if !mapping.rights().is_writable()
+                || (mapping.vaddr() >= stack_start && end <= stack_end)
+            {
+                continue;
+            }
+            dump.add_mapping(vspace, mapping)?;
+        }
+        Ok(dump)
+    }
+
+    /// Stop the process and tear down everything that was created for
+    /// it, returning the resources it was built from.
+    ///
+    /// The thread is suspended, its stack and IPC buffer are unmapped
+    /// from `vspace`, and then every capability derived from the TCB
+    /// and IPC buffer untypeds, along with the child's copies of the
+    /// stack pages, is revoked and deleted. The returned untypeds and
+    /// slots are empty and ready to be reused, and the stack region is
+    /// once again exclusively owned by the parent.
+    ///
+    /// The address range the stack, its guard and the IPC buffer took
+    /// up is only given back to `vspace` if nothing was mapped after
+    /// them, since a VSpace only keeps track of the edges of the
+    /// addresses it has handed out. Otherwise a new process in `vspace`
+    /// takes up fresh addresses, and `vspace` has to have room for them.
+    ///
+    /// If the teardown fails, the process is handed back along with the
+    /// error, and destroying it again picks up where this left off.
+    pub fn destroy(
+        mut self,
+        vspace: &mut VSpace,
+        parent_cnode: &LocalCap<LocalCNode>,
+    ) -> Result<StandardProcessParts<StackBitSize>, (VSpaceError, Self)>
+    where
+        NumPages<StackBitSize>: Add<U2>,
+        Sum<NumPages<StackBitSize>, U2>: Unsigned,
+
+        StackBitSize: IsGreaterOrEqual<PageBits>,
+        StackBitSize: Sub<PageBits>,
+        <StackBitSize as Sub<PageBits>>::Output: Unsigned,
+        <StackBitSize as Sub<PageBits>>::Output: _Pow,
+        Pow<<StackBitSize as Sub<PageBits>>::Output>: Unsigned,
+    {
+        if let Err(e) = self.tear_down(vspace, parent_cnode) {
+            return Err((e, self));
+        }
+
+        // The remaining slots hold the child's copies of the stack pages.
+        let slots: LocalCNodeSlots<Sum<NumPages<StackBitSize>, U2>> =
+            Cap::internal_new(self.slots.cptr, self.slots.cap_data.offset);
+        unsafe { slots.revoke_in_reverse() }
+
+        let parent_mapped_region = self
+            .stack
+            .as_strong::<StackBitSize>()
+            .expect("The stack region size is maintained by the process type");
+
+        Ok(StandardProcessParts {
+            cspace: self.cspace,
+            parent_mapped_region: unsafe { parent_mapped_region.unchecked_to_exclusive() },
+            ipc_buffer_ut: self.ipc_buffer_ut,
+            tcb_ut: self.tcb_ut,
+            slots,
+        })
+    }
+
+    /// The part of `destroy` that can fail, done without taking the
+    /// process apart so that it can be tried again.
+    fn tear_down(
+        &mut self,
+        vspace: &mut VSpace,
+        parent_cnode: &LocalCap<LocalCNode>,
+    ) -> Result<(), VSpaceError> {
+        if vspace.asid() != self.stack_guard.asid() {
+            return Err(VSpaceError::ASIDMismatch);
+        }
+
+        if !self.unmapped {
+            self.tcb.suspend()?;
+
+            // The guard, the stack, the page skipped after it and the IPC
+            // buffer were laid out back to back by `new`. Unmapping a
+            // frame that isn't mapped does nothing, so it doesn't matter
+            // if an earlier attempt got partway through.
+            let range_start = self.stack_guard.vaddr();
+            let range_end = self.ipc_buffer.vaddr() + self.ipc_buffer.size_bytes();
+            let _ = vspace.weak_unmap_region(unsafe { self.child_stack.unchecked_alias() })?;
+            let _ = vspace.unmap_region(unsafe { self.ipc_buffer.unchecked_alias() })?;
+            vspace.release_address_range(range_start, range_end);
+            self.unmapped = true;
+        }
+
+        // Revoking the untypeds destroys the TCB and IPC buffer
+        // along with any copies made of them. Revoking them again
+        // does nothing. Neither the TCB nor the IPC buffer is used
+        // after this: `unmapped` keeps a retry from suspending the TCB,
+        // and `destroy` drops them both.
+        unsafe {
+            self.tcb_ut.revoke(parent_cnode)?;
+            self.ipc_buffer_ut.revoke(parent_cnode)?;
+        }
+        Ok(())
     }
 
     pub fn elim(self) -> usize {
@@ -219,3 +463,93 @@ impl<StackBitSize: Unsigned> StandardProcess<StackBitSize> {
         self.tcb.cptr
     }
 }
+
+/// Hardware breakpoints and single-stepping, for debugging the process.
+/// See the methods of the same names on `ThreadControlBlock`.
+#[cfg(HardwareDebugAPI)]
+impl<StackBitSize: Unsigned> StandardProcess<StackBitSize> {
+    pub fn set_breakpoint(&mut self, number: u16, breakpoint: Breakpoint) -> Result<(), SeL4Error> {
+        self.tcb.set_breakpoint(number, breakpoint)
+    }
+
+    pub fn get_breakpoint(&self, number: u16) -> Result<Option<Breakpoint>, SeL4Error> {
+        self.tcb.get_breakpoint(number)
+    }
+
+    pub fn unset_breakpoint(&mut self, number: u16) -> Result<(), SeL4Error> {
+        self.tcb.unset_breakpoint(number)
+    }
+
+    pub fn configure_single_stepping(
+        &mut self,
+        number: u16,
+        num_instructions: usize,
+    ) -> Result<bool, SeL4Error> {
+        self.tcb.configure_single_stepping(number, num_instructions)
+    }
+}
+
+/// Scheduling contexts, for kernels built with the MCS scheduler. A
+/// process doesn't run until it's bound to a scheduling context with
+/// budget.
+#[cfg(KernelIsMCS)]
+impl<StackBitSize: Unsigned> StandardProcess<StackBitSize> {
+    pub fn bind_sched_context(
+        &mut self,
+        sched_context: &mut LocalCap<SchedContext>,
+    ) -> Result<(), SeL4Error> {
+        sched_context.bind(&self.tcb)
+    }
+
+    pub fn set_timeout_fault_source(
+        &mut self,
+        fault_source: &crate::userland::FaultSource<role::Local>,
+    ) -> Result<(), SeL4Error> {
+        self.tcb.set_timeout_fault_source(fault_source)
+    }
+}
+
+#[cfg(all(KernelEnableSMPSupport, not(KernelIsMCS)))]
+impl<StackBitSize: Unsigned> StandardProcess<StackBitSize> {
+    /// Pin the process to a core. This is best done before it starts.
+    pub fn set_affinity(&mut self, core: usize) -> Result<(), SeL4Error> {
+        self.tcb.set_affinity(core)
+    }
+}
+
+impl<StackBitSize: Unsigned> DebugTarget for StandardProcess<StackBitSize> {
+    fn suspend(&mut self) -> Result<(), SeL4Error> {
+        StandardProcess::suspend(self)
+    }
+
+    fn resume(&mut self) -> Result<(), SeL4Error> {
+        StandardProcess::resume(self)
+    }
+
+    fn read_registers(&self) -> Result<UserContext, SeL4Error> {
+        StandardProcess::read_registers(self)
+    }
+
+    fn write_registers(&mut self, registers: &UserContext) -> Result<(), SeL4Error> {
+        StandardProcess::write_registers(self, registers, false)
+    }
+
+    #[cfg(HardwareDebugAPI)]
+    fn set_breakpoint(&mut self, number: u16, breakpoint: Breakpoint) -> Result<(), SeL4Error> {
+        StandardProcess::set_breakpoint(self, number, breakpoint)
+    }
+
+    #[cfg(HardwareDebugAPI)]
+    fn unset_breakpoint(&mut self, number: u16) -> Result<(), SeL4Error> {
+        StandardProcess::unset_breakpoint(self, number)
+    }
+
+    #[cfg(HardwareDebugAPI)]
+    fn configure_single_stepping(
+        &mut self,
+        number: u16,
+        num_instructions: usize,
+    ) -> Result<bool, SeL4Error> {
+        StandardProcess::configure_single_stepping(self, number, num_instructions)
+    }
+}
@@ -1,6 +1,7 @@
 use crate::arch::*;
 use crate::cap::*;
 use crate::pow::{Pow, _Pow};
+use crate::userland::rights::CapRights;
 use crate::vspace::*;
 use core::ops::Sub;
 
@@ -14,29 +15,108 @@ use super::*;
 /// A thread in Ferros is a TCB associated with a parent VSpace
 /// that has:
 ///  * A usable code image mapped/written into it.
-///  * A mapped stack.
+///  * A mapped stack, optionally with an unmapped guard below it.
 ///  * Initial process state (e.g. parameter data) written into a
 ///    `seL4_UserContext` and/or its stack.
 ///  * Said seL4_UserContext written into the TCB.
 ///  * An IPC buffer and CSpace and fault handler associated with that TCB.
-pub struct Thread<StackBitSize: Unsigned = DefaultStackBitSize> {
+///
+/// The stack has to be mapped in the spawner's address space as well,
+/// since that's where a thread's return value is left for `join`.
+/// Since it's the spawner that maps it, it's up to the spawner to
+/// reserve a guard just below it, with `VSpace::reserve_guard`, before
+/// mapping the stack.
+pub struct Thread<StackBitSize: Unsigned = DefaultStackBitSize, R = ()> {
     tcb: LocalCap<ThreadControlBlock>,
+    resources: ThreadResources<StackBitSize>,
+    _return_type: PhantomData<R>,
+}
+
+/// Everything a thread is built from, kept so that it can be handed
+/// back once the thread has been joined.
+struct ThreadResources<StackBitSize: Unsigned> {
+    cspace: LocalCap<ChildCNode>,
+    stack: WeakMappedMemoryRegion<shared_status::Exclusive>,
+    stack_guard: Option<GuardRegion<StackGuardPageCount>>,
+    ipc_buffer: MappedMemoryRegion<PageBits, shared_status::Exclusive>,
+    tcb_ut: LocalCap<Untyped<<ThreadControlBlock as DirectRetype>::SizeBits>>,
+    exit_notification: LocalCap<Notification>,
+    exit_notification_ut: LocalCap<Untyped<<Notification as DirectRetype>::SizeBits>>,
+    slots: LocalCNodeSlots<U2>,
+    child_slots: ChildCNodeSlots<U2>,
+    /// Where the thread's return value is written, at the top of its
+    /// stack
+    result_vaddr: usize,
     _stack_bit_size: PhantomData<StackBitSize>,
 }
 
-impl<StackBitSize: Unsigned> Thread<StackBitSize> {
+/// The resources a `Thread` was built from, handed back by
+/// `JoinHandle::join` so they can be used to create a fresh thread.
+pub struct ThreadParts<StackBitSize: Unsigned = DefaultStackBitSize>
+where
+    StackBitSize: IsGreaterOrEqual<PageBits>,
+    StackBitSize: Sub<PageBits>,
+    <StackBitSize as Sub<PageBits>>::Output: Unsigned,
+    <StackBitSize as Sub<PageBits>>::Output: _Pow,
+    Pow<<StackBitSize as Sub<PageBits>>::Output>: Unsigned,
+{
+    pub cspace: LocalCap<ChildCNode>,
+    pub stack_region: MappedMemoryRegion<StackBitSize, shared_status::Exclusive>,
+    pub stack_guard: Option<GuardRegion<StackGuardPageCount>>,
+    pub ipc_buffer: MappedMemoryRegion<PageBits, shared_status::Exclusive>,
+    pub tcb_ut: LocalCap<Untyped<<ThreadControlBlock as DirectRetype>::SizeBits>>,
+    pub exit_notification_ut: LocalCap<Untyped<<Notification as DirectRetype>::SizeBits>>,
+    pub slots: LocalCNodeSlots<U2>,
+    pub child_slots: ChildCNodeSlots<U2>,
+}
+
+/// What a thread starts with: its entry point and parameter, plus
+/// where to leave the return value, what to signal once it's there and
+/// its own TCB to suspend afterwards. The parameter is written in its
+/// child form and read back in its local form, which is why it comes
+/// last.
+#[repr(C)]
+struct ThreadStart<T> {
+    function: usize,
+    result_vaddr: usize,
+    exit_notification: usize,
+    tcb: usize,
+    parameter: T,
+}
+
+/// The real entry point of every thread. Runs the thread's function,
+/// stores what it returns, and tells the spawner.
+extern "C" fn thread_start<T, R>(start: ThreadStart<T>) -> ! {
+    let function: extern "C" fn(T) -> R = unsafe { core::mem::transmute(start.function) };
+    let result = function(start.parameter);
+    unsafe {
+        core::ptr::write(start.result_vaddr as *mut R, result);
+        seL4_Signal(start.exit_notification);
+        // Yielding would only let threads of the same priority run, so
+        // stop for good until `join` destroys the thread.
+        seL4_TCB_Suspend(start.tcb);
+    }
+    unreachable!("A suspended thread doesn't run")
+}
+
+impl<StackBitSize: Unsigned, R: Send> Thread<StackBitSize, R> {
     pub fn new<T: RetypeForSetup>(
         virtual_address_space_root: &LocalCap<crate::arch::PagingRoot>,
         cspace: LocalCap<ChildCNode>,
         stack_region: MappedMemoryRegion<StackBitSize, shared_status::Exclusive>,
-        function_descriptor: extern "C" fn(T) -> (),
+        stack_guard: Option<GuardRegion<StackGuardPageCount>>,
+        parent_cnode: &LocalCap<LocalCNode>,
+        function_descriptor: extern "C" fn(T) -> R,
         process_parameter: SetupVer<T>,
         ipc_buffer: MappedMemoryRegion<PageBits, shared_status::Exclusive>,
         tcb_ut: LocalCap<Untyped<<ThreadControlBlock as DirectRetype>::SizeBits>>,
-        slots: LocalCNodeSlots<U1>,
+        exit_notification_ut: LocalCap<Untyped<<Notification as DirectRetype>::SizeBits>>,
+        slots: LocalCNodeSlots<U2>,
+        child_slots: ChildCNodeSlots<U2>,
         priority_authority: &LocalCap<ThreadPriorityAuthority>,
-        fault_source: Option<crate::userland::FaultSource<role::Child>>,
-    ) -> Result<Thread<StackBitSize>, ThreadSetupError>
+        priorities: ThreadPriorities,
+        fault_source: Option<crate::userland::FaultSource<crate::userland::FaultSourceRole>>,
+    ) -> Result<Thread<StackBitSize, R>, ThreadSetupError>
     where
         StackBitSize: IsGreaterOrEqual<PageBits>,
         StackBitSize: Sub<PageBits>,
@@ -47,50 +127,91 @@ impl<StackBitSize: Unsigned> Thread<StackBitSize> {
         if ipc_buffer.asid() != stack_region.asid() {
             return Err(ThreadSetupError::StackRegionASIDMustMatchIPCBufferASID);
         }
+        if let Some(ref guard) = stack_guard {
+            if guard.asid() != stack_region.asid()
+                || guard.vaddr() + guard.size() != stack_region.vaddr()
+            {
+                return Err(ThreadSetupError::StackGuardMustBeBelowStackRegion);
+            }
+        }
+        if !priorities.allowed_by(priority_authority) {
+            return Err(ThreadSetupError::PrioritiesExceedAuthority);
+        }
         // TODO - lift these checks to compile-time, as static assertions
         // Note - This comparison is conservative because technically
         // we can fit some of the params into available registers.
-        if core::mem::size_of::<SetupVer<T>>() > 2usize.pow(StackBitSize::U32) {
+        if core::mem::size_of::<ThreadStart<SetupVer<T>>>() + result_space::<R>()
+            > 2usize.pow(StackBitSize::U32)
+        {
             return Err(ThreadSetupError::ThreadParameterTooBigForStack);
         }
         if core::mem::size_of::<SetupVer<T>>() != core::mem::size_of::<T>() {
             return Err(ThreadSetupError::ThreadParameterHandoffSizeMismatch);
         }
 
-        // Map the stack to the target address space
+        let slots_alias = Cap::internal_new(slots.cptr, slots.cap_data.offset);
+        let (notification_slot, slots) = slots.alloc();
+        let exit_notification_ut_alias = Cap::wrap_cptr(exit_notification_ut.cptr);
+        let exit_notification: LocalCap<Notification> =
+            exit_notification_ut.retype(notification_slot)?;
+
+        //// allocate the thread control block
+        let (tcb_slot, _slots) = slots.alloc();
+        let tcb_ut_alias = Cap::wrap_cptr(tcb_ut.cptr);
+        let mut tcb = tcb_ut.retype(tcb_slot)?;
+
+        // The thread's own copies, which go when `join` revokes the
+        // untypeds, leaving the slots empty for the next thread.
+        let child_slots_alias = Cap::internal_new(child_slots.cptr, child_slots.cap_data.offset);
+        let (exit_notification_slot, child_slots) = child_slots.alloc();
+        let (child_tcb_slot, _child_slots) = child_slots.alloc();
+        // Badged, so that `JoinHandle::is_finished` can tell its signal
+        // apart from none at all.
+        let child_exit_notification = exit_notification.mint(
+            parent_cnode,
+            exit_notification_slot,
+            CapRights::W,
+            Badge::from(1),
+        )?;
+        let child_tcb = tcb.copy(parent_cnode, child_tcb_slot, CapRights::RWG)?;
+
+        // Leave room for the return value at the very top of the stack,
+        // and start the stack proper below it.
         let stack_top = stack_region.vaddr() + stack_region.size_bytes();
-        let mapped_stack_pages = stack_region;
+        let result_vaddr = stack_top - result_space::<R>();
+        let start = ThreadStart {
+            function: function_descriptor as usize,
+            result_vaddr,
+            exit_notification: child_exit_notification.cptr,
+            tcb: child_tcb.cptr,
+            parameter: process_parameter,
+        };
 
-        // map the child stack into local memory so we can copy the contents
-        // of the process params into it
         let (mut registers, param_size_on_stack) = unsafe {
             setup_initial_stack_and_regs(
-                &process_parameter as *const SetupVer<T> as *const usize,
-                core::mem::size_of::<SetupVer<T>>(),
-                stack_top as *mut usize,
-                mapped_stack_pages.vaddr() + mapped_stack_pages.size_bytes(),
+                &start as *const ThreadStart<SetupVer<T>> as *const usize,
+                core::mem::size_of::<ThreadStart<SetupVer<T>>>(),
+                result_vaddr as *mut usize,
+                result_vaddr,
             )
         };
 
-        let stack_pointer =
-            mapped_stack_pages.vaddr() + mapped_stack_pages.size_bytes() - param_size_on_stack;
-
-        registers.sp = stack_pointer;
-        registers.pc = function_descriptor as usize;
-
-        // TODO - Probably ought to suspend or destroy the thread instead of endlessly
-        // yielding
-        set_thread_link_register(&mut registers, yield_forever);
-
-        //// allocate the thread control block
-        let (tcb_slots, _slots) = slots.alloc();
-        let mut tcb = tcb_ut.retype(tcb_slots)?;
+        registers.sp = result_vaddr - param_size_on_stack;
+        registers.pc = thread_start::<T, R> as usize;
 
+        let cspace_alias = Cap {
+            cptr: cspace.cptr,
+            cap_data: CNode {
+                radix: cspace.cap_data.radix,
+                _role: PhantomData,
+            },
+            _role: PhantomData,
+        };
         tcb.configure(
             cspace,
             fault_source,
             virtual_address_space_root,
-            Some(ipc_buffer.to_page()),
+            Some(ipc_buffer.as_page()),
         )?;
         unsafe {
             seL4_TCB_WriteRegisters(
@@ -104,27 +225,147 @@ impl<StackBitSize: Unsigned> Thread<StackBitSize> {
             .as_result()
             .map_err(|e| ThreadSetupError::SeL4Error(SeL4Error::TCBWriteRegisters(e)))?;
 
-            // TODO - priority management could be exposed once we
-            // plan on actually using it
-            tcb.set_priority(priority_authority, 255)?;
+            tcb.set_priorities(priority_authority, priorities)?;
         }
         Ok(Thread {
             tcb,
-            _stack_bit_size: PhantomData,
+            resources: ThreadResources {
+                cspace: cspace_alias,
+                stack: stack_region.weaken(),
+                stack_guard,
+                ipc_buffer,
+                tcb_ut: tcb_ut_alias,
+                exit_notification,
+                exit_notification_ut: exit_notification_ut_alias,
+                slots: slots_alias,
+                child_slots: child_slots_alias,
+                result_vaddr,
+                _stack_bit_size: PhantomData,
+            },
+            _return_type: PhantomData,
         })
     }
 
-    pub fn start(self) -> Result<(), SeL4Error> {
+    /// Start the thread running. The handle returned can be used to
+    /// wait for the thread's function to return.
+    pub fn start(self) -> Result<JoinHandle<R, StackBitSize>, SeL4Error> {
         unsafe { seL4_TCB_Resume(self.tcb.cptr) }
             .as_result()
-            .map_err(SeL4Error::TCBResume)
+            .map_err(SeL4Error::TCBResume)?;
+        Ok(JoinHandle {
+            tcb: self.tcb,
+            resources: self.resources,
+            finished: false,
+            _return_type: PhantomData,
+        })
+    }
+
+    /// The unmapped pages below the thread's stack, if it was given
+    /// any, for telling a stack overflow apart from other faults with
+    /// `Fault::classify`.
+    pub fn stack_guard(&self) -> Option<&GuardRegion<StackGuardPageCount>> {
+        self.resources.stack_guard.as_ref()
+    }
+
+    /// Pin the thread to a core before it starts.
+    #[cfg(all(KernelEnableSMPSupport, not(KernelIsMCS)))]
+    pub fn set_affinity(&mut self, core: usize) -> Result<(), SeL4Error> {
+        self.tcb.set_affinity(core)
+    }
+}
+/// The strictest stack alignment of the supported architectures,
+/// which is aarch64's.
+const STACK_ALIGNMENT_BYTES: usize = 16;
+
+/// The space set aside for a thread's return value, keeping the stack
+/// below it aligned.
+fn result_space<R>() -> usize {
+    let align = core::cmp::max(core::mem::align_of::<R>(), STACK_ALIGNMENT_BYTES);
+    (core::mem::size_of::<R>() + align - 1) / align * align
+}
+
+/// A running thread, from `Thread::start`.
+pub struct JoinHandle<R, StackBitSize: Unsigned = DefaultStackBitSize> {
+    tcb: LocalCap<ThreadControlBlock>,
+    resources: ThreadResources<StackBitSize>,
+    /// Whether `is_finished` has already taken the exit signal
+    finished: bool,
+    _return_type: PhantomData<R>,
+}
+
+impl<R: Send, StackBitSize: Unsigned> JoinHandle<R, StackBitSize>
+where
+    StackBitSize: IsGreaterOrEqual<PageBits>,
+    StackBitSize: Sub<PageBits>,
+    <StackBitSize as Sub<PageBits>>::Output: Unsigned,
+    <StackBitSize as Sub<PageBits>>::Output: _Pow,
+    Pow<<StackBitSize as Sub<PageBits>>::Output>: Unsigned,
+{
+    /// Whether the thread's function has returned, without waiting for
+    /// it to. Once it has, `join` won't block.
+    pub fn is_finished(&mut self) -> bool {
+        if !self.finished {
+            self.finished = self.resources.exit_notification.poll().is_some();
+        }
+        self.finished
+    }
+
+    /// Wait for the thread's function to return, then destroy the
+    /// thread. Gives back the return value along with everything the
+    /// thread was made from.
+    ///
+    /// A thread only signals once its function returns, so if it
+    /// faults or panics instead, this blocks forever. When that might
+    /// happen, give the thread a fault source to hear about its faults
+    /// on, and only join it once `is_finished` says so.
+    pub fn join(
+        mut self,
+        parent_cnode: &LocalCap<LocalCNode>,
+    ) -> Result<(R, ThreadParts<StackBitSize>), SeL4Error> {
+        if !self.finished {
+            self.resources.exit_notification.wait();
+        }
+        let result = unsafe { core::ptr::read(self.resources.result_vaddr as *const R) };
+        self.tcb.suspend()?;
+
+        // Revoking the untypeds destroys the TCB and the exit
+        // notification, including the thread's copies of them. Neither
+        // is used again, as `self` is consumed.
+        let resources = self.resources;
+        unsafe {
+            resources.tcb_ut.revoke(parent_cnode)?;
+            resources.exit_notification_ut.revoke(parent_cnode)?;
+        }
+
+        Ok((
+            result,
+            ThreadParts {
+                cspace: resources.cspace,
+                stack_region: resources
+                    .stack
+                    .as_strong::<StackBitSize>()
+                    .expect("The stack region size is maintained by the thread type"),
+                stack_guard: resources.stack_guard,
+                ipc_buffer: resources.ipc_buffer,
+                tcb_ut: resources.tcb_ut,
+                exit_notification_ut: resources.exit_notification_ut,
+                slots: resources.slots,
+                child_slots: resources.child_slots,
+            },
+        ))
     }
 }
+
 #[derive(Debug)]
 pub enum ThreadSetupError {
     ThreadParameterTooBigForStack,
     ThreadParameterHandoffSizeMismatch,
     StackRegionASIDMustMatchIPCBufferASID,
+    /// The stack guard isn't directly below the stack region, in the
+    /// same address space
+    StackGuardMustBeBelowStackRegion,
+    /// The priority or MCP is higher than the authority's own MCP
+    PrioritiesExceedAuthority,
     SeL4Error(SeL4Error),
 }
 
@@ -0,0 +1,433 @@
+//! An opt-in compact encoding for IPC payloads.
+//!
+//! By default `Caller` and `Responder` bit-copy their `Sized` request
+//! and response types through the IPC buffer, which always costs
+//! `size_of::<T>()` bytes and trusts whatever bits arrive. Types which
+//! implement `WireFormat` can instead be sent with the `*_encoded`
+//! methods, which transfer only as many words as the encoding needs
+//! and validate every value on the way back out.
+//!
+//! Integers are encoded as LEB128 varints (signed ones zigzagged
+//! first), variable-length data is prefixed with its varint length,
+//! and enums are expected to encode a leading tag byte.
+//!
+//! With the `heapless` feature, `heapless::String` and `heapless::Vec`
+//! are encoded the same way as their `arrayvec` counterparts.
+use arrayvec::{Array, ArrayString, ArrayVec};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireError {
+    /// The encoded value does not fit in the available buffer.
+    BufferTooSmall,
+    /// The payload ended before the value was completely decoded.
+    UnexpectedEnd,
+    /// An enum or `Option` tag did not name a known variant.
+    InvalidTag,
+    /// A decoded value is out of range or otherwise invalid for its type.
+    InvalidValue,
+    /// The value was decoded without consuming the whole payload.
+    TrailingBytes,
+}
+
+impl WireError {
+    /// A nonzero number identifying this error, suitable for use
+    /// as a message label.
+    #[cfg_attr(KernelIsMCS, allow(dead_code))]
+    pub(crate) fn to_code(self) -> usize {
+        match self {
+            WireError::BufferTooSmall => 1,
+            WireError::UnexpectedEnd => 2,
+            WireError::InvalidTag => 3,
+            WireError::InvalidValue => 4,
+            WireError::TrailingBytes => 5,
+        }
+    }
+
+    pub(crate) fn from_code(code: usize) -> Option<WireError> {
+        match code {
+            1 => Some(WireError::BufferTooSmall),
+            2 => Some(WireError::UnexpectedEnd),
+            3 => Some(WireError::InvalidTag),
+            4 => Some(WireError::InvalidValue),
+            5 => Some(WireError::TrailingBytes),
+            _ => None,
+        }
+    }
+}
+
+/// A type which can be encoded to and decoded from a byte payload.
+///
+/// Implementations must decode exactly the bytes they encoded, and
+/// must return an error instead of producing a value that violates
+/// the type's invariants.
+pub trait WireFormat: Sized {
+    fn encode(&self, encoder: &mut Encoder) -> Result<(), WireError>;
+    fn decode(decoder: &mut Decoder) -> Result<Self, WireError>;
+}
+
+/// Encode `value` into the front of `buf`, returning the number of
+/// bytes written.
+pub fn encode_into<T: WireFormat>(value: &T, buf: &mut [u8]) -> Result<usize, WireError> {
+    let mut encoder = Encoder::new(buf);
+    value.encode(&mut encoder)?;
+    Ok(encoder.len())
+}
+
+/// Decode a value which must occupy all of `buf`.
+pub fn decode_from<T: WireFormat>(buf: &[u8]) -> Result<T, WireError> {
+    let mut decoder = Decoder::new(buf);
+    let value = T::decode(&mut decoder)?;
+    decoder.finish()?;
+    Ok(value)
+}
+
+/// Writes encoded values into a fixed byte buffer.
+pub struct Encoder<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Encoder<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Encoder { buf, len: 0 }
+    }
+
+    /// Number of bytes written so far
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn write_u8(&mut self, byte: u8) -> Result<(), WireError> {
+        self.write_bytes(&[byte])
+    }
+
+    /// Write `bytes` as-is, without a length prefix.
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), WireError> {
+        let end = self.len + bytes.len();
+        if end > self.buf.len() {
+            return Err(WireError::BufferTooSmall);
+        }
+        self.buf[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    /// Write `bytes` preceded by their length, so they can be read
+    /// back with `Decoder::read_len_prefixed`.
+    pub fn write_len_prefixed(&mut self, bytes: &[u8]) -> Result<(), WireError> {
+        self.write_varint(bytes.len() as u64)?;
+        self.write_bytes(bytes)
+    }
+
+    pub fn write_varint(&mut self, mut value: u64) -> Result<(), WireError> {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                return self.write_u8(byte);
+            }
+            self.write_u8(byte | 0x80)?;
+        }
+    }
+}
+
+/// Reads encoded values out of a byte payload.
+pub struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Decoder { buf, pos: 0 }
+    }
+
+    /// Number of bytes not yet read
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, WireError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    /// Read exactly `count` bytes.
+    pub fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], WireError> {
+        if count > self.remaining() {
+            return Err(WireError::UnexpectedEnd);
+        }
+        let bytes = &self.buf[self.pos..self.pos + count];
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    /// Read bytes written by `Encoder::write_len_prefixed`.
+    pub fn read_len_prefixed(&mut self) -> Result<&'a [u8], WireError> {
+        let count = self.read_varint()?;
+        if count > self.remaining() as u64 {
+            return Err(WireError::UnexpectedEnd);
+        }
+        self.read_bytes(count as usize)
+    }
+
+    pub fn read_varint(&mut self) -> Result<u64, WireError> {
+        let mut value: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            let bits = u64::from(byte & 0x7f);
+            // Reject encodings which overflow 64 bits
+            if shift == 63 && bits > 1 {
+                return Err(WireError::InvalidValue);
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+            if shift > 63 {
+                return Err(WireError::InvalidValue);
+            }
+        }
+    }
+
+    /// Check that the whole payload has been consumed.
+    pub fn finish(self) -> Result<(), WireError> {
+        if self.remaining() == 0 {
+            Ok(())
+        } else {
+            Err(WireError::TrailingBytes)
+        }
+    }
+}
+
+impl WireFormat for () {
+    fn encode(&self, _encoder: &mut Encoder) -> Result<(), WireError> {
+        Ok(())
+    }
+    fn decode(_decoder: &mut Decoder) -> Result<Self, WireError> {
+        Ok(())
+    }
+}
+
+impl WireFormat for bool {
+    fn encode(&self, encoder: &mut Encoder) -> Result<(), WireError> {
+        encoder.write_u8(*self as u8)
+    }
+    fn decode(decoder: &mut Decoder) -> Result<Self, WireError> {
+        match decoder.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(WireError::InvalidValue),
+        }
+    }
+}
+
+impl WireFormat for u8 {
+    fn encode(&self, encoder: &mut Encoder) -> Result<(), WireError> {
+        encoder.write_u8(*self)
+    }
+    fn decode(decoder: &mut Decoder) -> Result<Self, WireError> {
+        decoder.read_u8()
+    }
+}
+
+impl WireFormat for i8 {
+    fn encode(&self, encoder: &mut Encoder) -> Result<(), WireError> {
+        encoder.write_u8(*self as u8)
+    }
+    fn decode(decoder: &mut Decoder) -> Result<Self, WireError> {
+        Ok(decoder.read_u8()? as i8)
+    }
+}
+
+macro_rules! unsigned_varint {
+    ($($t:ty),*) => {
+        $(
+            impl WireFormat for $t {
+                fn encode(&self, encoder: &mut Encoder) -> Result<(), WireError> {
+                    encoder.write_varint(*self as u64)
+                }
+                fn decode(decoder: &mut Decoder) -> Result<Self, WireError> {
+                    let value = decoder.read_varint()?;
+                    if value > <$t>::max_value() as u64 {
+                        return Err(WireError::InvalidValue);
+                    }
+                    Ok(value as $t)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! signed_varint {
+    ($($t:ty),*) => {
+        $(
+            impl WireFormat for $t {
+                fn encode(&self, encoder: &mut Encoder) -> Result<(), WireError> {
+                    let value = *self as i64;
+                    encoder.write_varint(((value << 1) ^ (value >> 63)) as u64)
+                }
+                fn decode(decoder: &mut Decoder) -> Result<Self, WireError> {
+                    let zigzag = decoder.read_varint()?;
+                    let value = ((zigzag >> 1) as i64) ^ -((zigzag & 1) as i64);
+                    if value < <$t>::min_value() as i64 || value > <$t>::max_value() as i64 {
+                        return Err(WireError::InvalidValue);
+                    }
+                    Ok(value as $t)
+                }
+            }
+        )*
+    };
+}
+
+unsigned_varint!(u16, u32, u64, usize);
+signed_varint!(i16, i32, i64, isize);
+
+impl WireFormat for char {
+    fn encode(&self, encoder: &mut Encoder) -> Result<(), WireError> {
+        (*self as u32).encode(encoder)
+    }
+    fn decode(decoder: &mut Decoder) -> Result<Self, WireError> {
+        core::char::from_u32(u32::decode(decoder)?).ok_or(WireError::InvalidValue)
+    }
+}
+
+impl<T: WireFormat> WireFormat for Option<T> {
+    fn encode(&self, encoder: &mut Encoder) -> Result<(), WireError> {
+        match self {
+            None => encoder.write_u8(0),
+            Some(value) => {
+                encoder.write_u8(1)?;
+                value.encode(encoder)
+            }
+        }
+    }
+    fn decode(decoder: &mut Decoder) -> Result<Self, WireError> {
+        match decoder.read_u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(decoder)?)),
+            _ => Err(WireError::InvalidTag),
+        }
+    }
+}
+
+impl<T: WireFormat, E: WireFormat> WireFormat for Result<T, E> {
+    fn encode(&self, encoder: &mut Encoder) -> Result<(), WireError> {
+        match self {
+            Ok(value) => {
+                encoder.write_u8(0)?;
+                value.encode(encoder)
+            }
+            Err(error) => {
+                encoder.write_u8(1)?;
+                error.encode(encoder)
+            }
+        }
+    }
+    fn decode(decoder: &mut Decoder) -> Result<Self, WireError> {
+        match decoder.read_u8()? {
+            0 => Ok(Ok(T::decode(decoder)?)),
+            1 => Ok(Err(E::decode(decoder)?)),
+            _ => Err(WireError::InvalidTag),
+        }
+    }
+}
+
+macro_rules! tuple_wire_format {
+    ($($name:ident),+) => {
+        impl<$($name: WireFormat),+> WireFormat for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode(&self, encoder: &mut Encoder) -> Result<(), WireError> {
+                let ($($name,)+) = self;
+                $($name.encode(encoder)?;)+
+                Ok(())
+            }
+            fn decode(decoder: &mut Decoder) -> Result<Self, WireError> {
+                Ok(($($name::decode(decoder)?,)+))
+            }
+        }
+    };
+}
+
+tuple_wire_format!(A);
+tuple_wire_format!(A, B);
+tuple_wire_format!(A, B, C);
+tuple_wire_format!(A, B, C, D);
+
+impl<A: Array<Item = u8> + Copy> WireFormat for ArrayString<A> {
+    fn encode(&self, encoder: &mut Encoder) -> Result<(), WireError> {
+        encoder.write_len_prefixed(self.as_bytes())
+    }
+    fn decode(decoder: &mut Decoder) -> Result<Self, WireError> {
+        let bytes = decoder.read_len_prefixed()?;
+        let s = core::str::from_utf8(bytes).map_err(|_| WireError::InvalidValue)?;
+        ArrayString::from(s).map_err(|_| WireError::InvalidValue)
+    }
+}
+
+impl<A: Array> WireFormat for ArrayVec<A>
+where
+    A::Item: WireFormat,
+{
+    fn encode(&self, encoder: &mut Encoder) -> Result<(), WireError> {
+        encoder.write_varint(self.len() as u64)?;
+        for item in self.iter() {
+            item.encode(encoder)?;
+        }
+        Ok(())
+    }
+    fn decode(decoder: &mut Decoder) -> Result<Self, WireError> {
+        let count = decoder.read_varint()?;
+        let mut items = ArrayVec::new();
+        if count > items.capacity() as u64 {
+            return Err(WireError::InvalidValue);
+        }
+        for _ in 0..count {
+            items.push(A::Item::decode(decoder)?);
+        }
+        Ok(items)
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl<const N: usize> WireFormat for heapless::String<N> {
+    fn encode(&self, encoder: &mut Encoder) -> Result<(), WireError> {
+        encoder.write_len_prefixed(self.as_bytes())
+    }
+    fn decode(decoder: &mut Decoder) -> Result<Self, WireError> {
+        let bytes = decoder.read_len_prefixed()?;
+        let s = core::str::from_utf8(bytes).map_err(|_| WireError::InvalidValue)?;
+        let mut string = heapless::String::new();
+        string.push_str(s).map_err(|_| WireError::InvalidValue)?;
+        Ok(string)
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl<T: WireFormat, const N: usize> WireFormat for heapless::Vec<T, N> {
+    fn encode(&self, encoder: &mut Encoder) -> Result<(), WireError> {
+        encoder.write_varint(self.len() as u64)?;
+        for item in self.iter() {
+            item.encode(encoder)?;
+        }
+        Ok(())
+    }
+    fn decode(decoder: &mut Decoder) -> Result<Self, WireError> {
+        let count = decoder.read_varint()?;
+        if count > N as u64 {
+            return Err(WireError::InvalidValue);
+        }
+        let mut items = heapless::Vec::new();
+        for _ in 0..count {
+            items
+                .push(T::decode(decoder)?)
+                .map_err(|_| WireError::InvalidValue)?;
+        }
+        Ok(items)
+    }
+}
@@ -0,0 +1,197 @@
+//! Sharing an ELF image's writable segments between the processes
+//! made from it.
+//!
+//! An `ElfDataTemplate` loads the segments once. Each process made with
+//! `VSpace::new_from_elf_copy_on_write` maps copies of the template's
+//! pages read-only, and the `Pager` looking after it gives it a private
+//! copy of a page the first time it writes there. Spawning many
+//! processes from one image then only costs the data pages each of
+//! them changes.
+
+use core::marker::PhantomData;
+
+use arrayvec::ArrayVec;
+use typenum::*;
+
+use crate::arch::{self, PageBits, PageBytes, PagingRoot};
+use crate::bootstrap::UserImage;
+use crate::cap::{
+    memory_kind, page_state, role, InternalASID, LocalCNode, LocalCNodeSlots, LocalCap, Page,
+    UnassignedASID, Untyped, WCNodeSlots, WUntyped, WeakCapRange, WeakCopyError,
+};
+use crate::userland::CapRights;
+
+use super::{
+    iterate_by_page, load_elf_page, ElfProc, ScratchRegion, VSpace, VSpaceError,
+    WritableSegmentPages, PAGE_MASK,
+};
+
+/// The most writable segments an `ElfDataTemplate` can hold
+pub const MAX_ELF_DATA_SEGMENTS: usize = 4;
+
+/// Where one of the image's writable segments lives, and which of the
+/// template's pages back it
+#[derive(Clone, Copy)]
+pub(crate) struct ElfDataSegment {
+    pub(crate) vaddr: usize,
+    pub(crate) size: usize,
+    pub(crate) first_page: usize,
+    pub(crate) vm_attributes: arch::VMAttributes,
+}
+
+/// The writable segments of an ELF image, loaded into pages which are
+/// never mapped writable anywhere, for processes to share until they
+/// write to them.
+pub struct ElfDataTemplate {
+    pages: WeakCapRange<Page<page_state::Unmapped>, role::Local>,
+    segments: ArrayVec<[ElfDataSegment; MAX_ELF_DATA_SEGMENTS]>,
+}
+
+impl ElfDataTemplate {
+    /// Load the writable segments of `elf_data`, using the same slots
+    /// and memory `VSpace::new_from_elf` would.
+    pub fn new<E: ElfProc>(
+        elf_data: &[u8],
+        page_slots: LocalCNodeSlots<E::RequiredPages>,
+        elf_writable_mem: LocalCap<Untyped<E::RequiredMemoryBits>>,
+        local_vspace_scratch: &mut ScratchRegion,
+    ) -> Result<Self, VSpaceError> {
+        let elf = xmas_elf::ElfFile::new(elf_data).map_err(VSpaceError::ElfParseError)?;
+
+        let mut page_slots = page_slots.weaken();
+        let pages = elf_writable_mem.weaken().retype_pages(&mut page_slots)?;
+        let mut pages_iter = WeakCapRange::<_, role::Local>::new(
+            pages.start_cptr,
+            Page {
+                state: page_state::Unmapped {},
+                _granule: PhantomData,
+            },
+            pages.len(),
+        )
+        .into_iter();
+
+        let mut segments = ArrayVec::new();
+        let mut next_page = 0;
+        for program_header in elf
+            .program_iter()
+            .filter(|h| h.get_type() == Ok(xmas_elf::program::Type::Load) && h.flags().is_write())
+        {
+            let segment_vaddr = program_header.virtual_addr() as usize;
+            let mem_size = program_header.mem_size() as usize;
+            let src_offset = program_header.offset() as usize;
+            let file_size = program_header.file_size() as usize;
+
+            let first_page = next_page;
+            for (page_start, page_end) in iterate_by_page(segment_vaddr, segment_vaddr + mem_size) {
+                let mut region = pages_iter
+                    .next()
+                    .ok_or(VSpaceError::InsufficientResourcesForElf)?
+                    .to_region();
+                local_vspace_scratch.temporarily_map_region::<PageBits, _, _>(
+                    &mut region,
+                    |mapped| {
+                        load_elf_page(
+                            mapped.as_mut_slice(),
+                            elf_data,
+                            segment_vaddr,
+                            src_offset,
+                            file_size,
+                            page_start,
+                            page_end,
+                        );
+                        mapped.flush()
+                    },
+                )??;
+                next_page += 1;
+            }
+
+            segments
+                .try_push(ElfDataSegment {
+                    vaddr: segment_vaddr & !PAGE_MASK,
+                    size: (next_page - first_page) * PageBytes::USIZE,
+                    first_page,
+                    vm_attributes: if program_header.flags().is_execute() {
+                        arch::vm_attributes::PROGRAM_CODE
+                    } else {
+                        arch::vm_attributes::PROGRAM_DATA
+                    },
+                })
+                .map_err(|_| VSpaceError::TooManyElfDataSegments)?;
+        }
+
+        Ok(ElfDataTemplate { pages, segments })
+    }
+
+    pub(crate) fn pages_cptr(&self) -> usize {
+        self.pages.start_cptr
+    }
+
+    pub(crate) fn segments(&self) -> &[ElfDataSegment] {
+        &self.segments
+    }
+}
+
+/// A process's read-only copies of an `ElfDataTemplate`'s pages, made
+/// by `VSpace::new_from_elf_copy_on_write`. Hand it to the `Pager`
+/// looking after the process with `Pager::add_elf_data`. The copies'
+/// caps must stay where they are for as long as that pager is around.
+pub struct ElfDataMapping {
+    asid: InternalASID,
+    frames_cptr: usize,
+}
+
+impl ElfDataMapping {
+    pub(crate) fn asid(&self) -> InternalASID {
+        self.asid
+    }
+
+    pub(crate) fn frames_cptr(&self) -> usize {
+        self.frames_cptr
+    }
+}
+
+impl VSpace {
+    /// Like `new_from_elf`, but rather than loading the image's
+    /// writable segments into memory of its own, the new VSpace maps
+    /// copies of `template`'s pages read-only.
+    pub fn new_from_elf_copy_on_write<E: ElfProc>(
+        paging_root: LocalCap<PagingRoot>,
+        asid: LocalCap<UnassignedASID>,
+        slots: WCNodeSlots,
+        paging_untyped: LocalCap<WUntyped<memory_kind::General>>,
+        // Things relating to user image code
+        elf_data: &[u8],
+        page_slots: LocalCNodeSlots<E::RequiredPages>,
+        template: &ElfDataTemplate,
+        user_image: &UserImage<role::Local>,
+        parent_cnode: &LocalCap<LocalCNode>,
+    ) -> Result<(Self, ElfDataMapping), VSpaceError> {
+        let mut page_slots = page_slots.weaken();
+        let frames = template
+            .pages
+            .copy(parent_cnode, &mut page_slots, CapRights::R)
+            .map_err(|e| match e {
+                WeakCopyError::NotEnoughSlots => VSpaceError::InsufficientResourcesForElf,
+                WeakCopyError::SeL4Error(e) => VSpaceError::SeL4Error(e),
+            })?;
+        let frames_cptr = frames.start_cptr;
+
+        let vspace = Self::new_from_elf_internal(
+            paging_root,
+            asid,
+            slots,
+            paging_untyped,
+            elf_data,
+            page_slots,
+            frames,
+            WritableSegmentPages::CopyOnWrite,
+            user_image,
+            parent_cnode,
+        )?;
+        let mapping = ElfDataMapping {
+            asid: vspace.asid(),
+            frames_cptr,
+        };
+        Ok((vspace, mapping))
+    }
+}
@@ -0,0 +1,413 @@
+//! A record of what a `VSpace` has mapped where, so that the memory
+//! behind its mappings can be reached from outside of it, e.g. to dump
+//! a faulted child's core or let a debugger at its memory.
+use core::marker::PhantomData;
+
+use arrayvec::ArrayVec;
+use sel_claw::*;
+
+use crate::arch::{self, PageBits, PageBytes};
+use crate::cap::{page_state, Cap, InternalASID, LocalCNodeSlot, LocalCap, Page};
+use crate::error::{ErrorExt, SeL4Error};
+use crate::userland::CapRights;
+use typenum::Unsigned;
+
+use super::{ScratchRegion, VSpace, VSpaceError};
+
+/// The most runs of mappings a `VSpace` keeps a record of.
+pub const MAX_RECORDED_MAPPINGS: usize = 32;
+
+/// A run of frames with consecutive cptrs, mapped at consecutive
+/// addresses with the same rights.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    vaddr: usize,
+    start_cptr: usize,
+    granule_bits: u8,
+    count: usize,
+    rights: CapRights,
+}
+
+impl Mapping {
+    pub fn vaddr(&self) -> usize {
+        self.vaddr
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        self.count << self.granule_bits
+    }
+
+    pub fn rights(&self) -> CapRights {
+        self.rights
+    }
+
+    /// Whether the mapping is made of single pages, the only frames a
+    /// `MemoryWindow` can reach.
+    pub(crate) fn is_page_granular(&self) -> bool {
+        self.granule_bits == PageBits::U8
+    }
+
+    /// How many frames the mapping is made of.
+    pub(crate) fn frame_count(&self) -> usize {
+        self.count
+    }
+
+    pub(crate) fn start_cptr(&self) -> usize {
+        self.start_cptr
+    }
+
+    /// The same mapping, of copies of its frame caps starting at
+    /// `start_cptr`.
+    pub(crate) fn with_start_cptr(self, start_cptr: usize) -> Mapping {
+        Mapping { start_cptr, ..self }
+    }
+
+    /// Whether `vaddr` falls inside this mapping.
+    pub fn contains(&self, vaddr: usize) -> bool {
+        vaddr >= self.vaddr && vaddr - self.vaddr < self.size_bytes()
+    }
+
+    fn end(&self) -> usize {
+        self.vaddr + self.size_bytes()
+    }
+
+    /// The part of this mapping which lies in `[start, end)`, if any.
+    fn clip(&self, start: usize, end: usize) -> Option<Mapping> {
+        let granule_mask = (1 << self.granule_bits) - 1;
+        // Only whole granules can be mapped or unmapped.
+        let start = core::cmp::max(start, self.vaddr) & !granule_mask;
+        let end = (core::cmp::min(end, self.end()) + granule_mask) & !granule_mask;
+        if start >= end {
+            return None;
+        }
+        Some(Mapping {
+            vaddr: start,
+            start_cptr: self.start_cptr + ((start - self.vaddr) >> self.granule_bits),
+            granule_bits: self.granule_bits,
+            count: (end - start) >> self.granule_bits,
+            rights: self.rights,
+        })
+    }
+}
+
+/// The mappings made in a `VSpace`, as far as there is room to keep
+/// track of them.
+#[derive(Debug, Clone, Default)]
+pub(super) struct MappingRecord {
+    mappings: ArrayVec<[Mapping; MAX_RECORDED_MAPPINGS]>,
+    /// Set once a mapping couldn't be recorded, after which the record
+    /// can't be relied on to be complete.
+    overflowed: bool,
+}
+
+impl MappingRecord {
+    /// Record that `count` frames of `1 << granule_bits` bytes, starting
+    /// with the one at `start_cptr`, have been mapped at `vaddr`. This
+    /// replaces whatever was recorded there before.
+    pub(super) fn observe_mapping(
+        &mut self,
+        vaddr: usize,
+        start_cptr: usize,
+        granule_bits: u8,
+        count: usize,
+        rights: CapRights,
+    ) {
+        let mapping = Mapping {
+            vaddr,
+            start_cptr,
+            granule_bits,
+            count,
+            rights,
+        };
+        self.observe_unmapping(vaddr, mapping.end());
+        // Runs of frames mapped one at a time, such as an image's pages,
+        // are kept together.
+        if let Some(previous) = self.mappings.iter_mut().find(|m| {
+            m.end() == vaddr
+                && m.start_cptr + m.count == start_cptr
+                && m.granule_bits == granule_bits
+                && m.rights == rights
+        }) {
+            previous.count += count;
+            return;
+        }
+        if self.mappings.try_push(mapping).is_err() {
+            self.overflowed = true;
+        }
+    }
+
+    /// Forget whatever was recorded as mapped in `[start, end)`.
+    pub(super) fn observe_unmapping(&mut self, start: usize, end: usize) {
+        let mut index = 0;
+        while index < self.mappings.len() {
+            let mapping = self.mappings[index];
+            if mapping.end() <= start || mapping.vaddr >= end {
+                index += 1;
+                continue;
+            }
+            self.mappings.remove(index);
+            for part in [
+                mapping.clip(mapping.vaddr, start),
+                mapping.clip(end, mapping.end()),
+            ]
+            .iter()
+            .flatten()
+            {
+                if self.mappings.try_push(*part).is_err() {
+                    self.overflowed = true;
+                }
+            }
+        }
+    }
+
+    /// Every recorded mapping, or `None` if some weren't recorded.
+    pub(super) fn mappings(&self) -> Option<&[Mapping]> {
+        if self.overflowed {
+            None
+        } else {
+            Some(&self.mappings)
+        }
+    }
+}
+
+impl VSpace {
+    /// What this VSpace has mapped where, as runs of frames. This is
+    /// only available while all of them fit in the record, which holds
+    /// `MAX_RECORDED_MAPPINGS` runs.
+    pub fn mappings(&self) -> Result<&[Mapping], VSpaceError> {
+        self.mappings
+            .mappings()
+            .ok_or(VSpaceError::TooManyMappingsToRecord)
+    }
+
+    /// The recorded mapping which holds `vaddr`.
+    pub fn mapping_at(&self, vaddr: usize) -> Result<&Mapping, VSpaceError> {
+        self.mappings()?
+            .iter()
+            .find(|m| m.contains(vaddr))
+            .ok_or(VSpaceError::AddressNotMapped)
+    }
+}
+
+/// Reaches the memory mapped in another `VSpace` by mapping copies of
+/// its frame caps into a scratch region of the local `VSpace`, a page
+/// at a time.
+pub struct MemoryWindow<'a> {
+    mappings: &'a [Mapping],
+    /// The ASID of the VSpace the mappings are in
+    asid: InternalASID,
+    scratch: &'a mut ScratchRegion,
+    /// Where the copy of each frame cap goes while it's mapped
+    slot: LocalCNodeSlot,
+}
+
+impl<'a> MemoryWindow<'a> {
+    /// A window onto what `vspace` has mapped, which is only available
+    /// while all of its mappings fit in its record.
+    pub fn new(
+        vspace: &'a VSpace,
+        scratch: &'a mut ScratchRegion,
+        slot: LocalCNodeSlot,
+    ) -> Result<Self, VSpaceError> {
+        Ok(MemoryWindow::over(
+            vspace.mappings()?,
+            vspace.asid(),
+            scratch,
+            slot,
+        ))
+    }
+
+    /// A window onto `mappings`, whose frame caps are in the local
+    /// CSpace, e.g. ones copied in by whoever set up this process.
+    pub(crate) fn over(
+        mappings: &'a [Mapping],
+        asid: InternalASID,
+        scratch: &'a mut ScratchRegion,
+        slot: LocalCNodeSlot,
+    ) -> Self {
+        MemoryWindow {
+            mappings,
+            asid,
+            scratch,
+            slot,
+        }
+    }
+
+    pub(crate) fn asid(&self) -> InternalASID {
+        self.asid
+    }
+
+    /// Fill `buffer` from the memory at `vaddr`.
+    pub fn read(&mut self, vaddr: usize, buffer: &mut [u8]) -> Result<(), VSpaceError> {
+        self.for_each_page(vaddr, buffer.len(), Access::Read, |page, done| {
+            let len = page.len();
+            buffer[done..done + len].copy_from_slice(page);
+        })
+    }
+
+    /// Write `data` into the memory at `vaddr`, which has to be mapped
+    /// writable.
+    pub fn write(&mut self, vaddr: usize, data: &[u8]) -> Result<(), VSpaceError> {
+        self.for_each_page(vaddr, data.len(), Access::Write, |page, done| {
+            let len = page.len();
+            page.copy_from_slice(&data[done..done + len]);
+        })
+    }
+
+    /// Write `data` into the memory at `vaddr` like `write`, then bring
+    /// the instruction cache up to date with it, e.g. to patch code.
+    pub fn write_instructions(&mut self, vaddr: usize, data: &[u8]) -> Result<(), VSpaceError> {
+        self.for_each_page(
+            vaddr,
+            data.len(),
+            Access::WriteInstructions,
+            |page, done| {
+                let len = page.len();
+                page.copy_from_slice(&data[done..done + len]);
+            },
+        )
+    }
+
+    /// Give back the slot the frame caps were copied into.
+    pub fn into_slot(self) -> LocalCNodeSlot {
+        self.slot
+    }
+
+    /// Call `f` with the part of `[vaddr, vaddr + len)` in each page it
+    /// touches, mapped locally, along with how many bytes came before
+    /// that part.
+    fn for_each_page<F>(
+        &mut self,
+        vaddr: usize,
+        len: usize,
+        access: Access,
+        mut f: F,
+    ) -> Result<(), VSpaceError>
+    where
+        F: FnMut(&mut [u8], usize),
+    {
+        let end = vaddr
+            .checked_add(len)
+            .ok_or(VSpaceError::ExceededAddressableSpace)?;
+        let mut next = vaddr;
+        while next < end {
+            let page_start = next & !(PageBytes::USIZE - 1);
+            let page_end = core::cmp::min(page_start + PageBytes::USIZE, end);
+            let mapping = *self
+                .mappings
+                .iter()
+                .find(|m| m.contains(next))
+                .ok_or(VSpaceError::AddressNotMapped)?;
+            if !mapping.is_page_granular() {
+                return Err(VSpaceError::UnsupportedGranule);
+            }
+            if access != Access::Read && !mapping.rights.is_writable() {
+                return Err(VSpaceError::AddressNotWritable);
+            }
+            let frame_cptr = mapping.start_cptr + ((page_start - mapping.vaddr) >> PageBits::U8);
+            let done = next - vaddr;
+            self.with_frame_mapped(frame_cptr, mapping.rights, access, |local| {
+                f(&mut local[next - page_start..page_end - page_start], done)
+            })?;
+            next = page_end;
+        }
+        Ok(())
+    }
+
+    /// Copy the cap to the frame at `frame_cptr` into the slot, map the
+    /// copy at the start of the scratch region while `f` runs, then
+    /// delete it again.
+    fn with_frame_mapped<F: FnOnce(&mut [u8])>(
+        &mut self,
+        frame_cptr: usize,
+        rights: CapRights,
+        access: Access,
+        f: F,
+    ) -> Result<(), VSpaceError> {
+        let cnode_cptr = self.slot.cptr;
+        let slot_offset = self.slot.cap_data.offset;
+        unsafe {
+            seL4_CNode_Copy(
+                cnode_cptr,          // _service
+                slot_offset,         // index
+                seL4_WordBits as u8, // depth
+                cnode_cptr,          // src_root
+                frame_cptr,          // src_index
+                seL4_WordBits as u8, // src_depth
+                rights.into(),       // rights
+            )
+        }
+        .as_result()
+        .map_err(SeL4Error::CNodeCopy)?;
+
+        let local_vaddr = self.scratch.reserved_region.vaddr;
+        let copy: LocalCap<Page<page_state::Unmapped>> = Cap {
+            cptr: slot_offset,
+            cap_data: Page {
+                state: page_state::Unmapped {},
+                _granule: PhantomData,
+            },
+            _role: PhantomData,
+        };
+        let mapped =
+            unsafe {
+                copy.unchecked_page_map(
+                    local_vaddr,
+                    &mut self.scratch.paging_root,
+                    rights,
+                    arch::vm_attributes::DEFAULT,
+                )
+            }
+            .map(|_| {
+                let mapped: LocalCap<Page<page_state::Mapped>> = Cap {
+                    cptr: slot_offset,
+                    cap_data: Page {
+                        state: page_state::Mapped {
+                            vaddr: local_vaddr,
+                            asid: self.scratch.reserved_region.asid,
+                            rights,
+                        },
+                        _granule: PhantomData,
+                    },
+                    _role: PhantomData,
+                };
+                f(unsafe {
+                    core::slice::from_raw_parts_mut(local_vaddr as *mut u8, PageBytes::USIZE)
+                });
+                mapped
+            });
+        let unmapped = match mapped {
+            Ok(mapped) => {
+                let unified = if access == Access::WriteInstructions {
+                    mapped.unify_instructions()
+                } else {
+                    Ok(())
+                };
+                let unmapped = mapped.unmap().map(|_| ());
+                unified.and(unmapped)
+            }
+            Err(e) => Err(e),
+        };
+
+        let deleted = unsafe {
+            seL4_CNode_Delete(
+                cnode_cptr,          // _service
+                slot_offset,         // index
+                seL4_WordBits as u8, // depth
+            )
+        }
+        .as_result()
+        .map_err(SeL4Error::CNodeDelete);
+        unmapped?;
+        deleted?;
+        Ok(())
+    }
+}
+
+/// What a `MemoryWindow` is after with the memory it maps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+    WriteInstructions,
+}
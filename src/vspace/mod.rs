@@ -18,10 +18,16 @@ use crate::cap::{
     PhantomCap, RetypeError, UnassignedASID, Untyped, WCNodeSlots, WCNodeSlotsData, WUntyped,
     WeakCapRange, WeakCopyError,
 };
-use crate::error::SeL4Error;
+use crate::error::{KernelError, SeL4Error};
 use crate::pow::{Pow, _Pow};
 use crate::userland::CapRights;
+#[cfg(not(KernelIsMCS))]
+mod elf_data;
+mod mappings;
 mod region;
+#[cfg(not(KernelIsMCS))]
+pub use elf_data::*;
+pub use mappings::*;
 pub use region::*;
 
 include!(concat!(env!("OUT_DIR"), "/KERNEL_RETYPE_FAN_OUT_LIMIT"));
@@ -44,6 +50,11 @@ pub trait ElfProc: Sized {
 
     /// How much memory is needed for the process stack, as a bitsize.
     type StackSizeBits: Unsigned;
+
+    /// The image's function symbols, sorted by address, for symbolizing
+    /// backtraces. `ferros-build` only fills these in with its `symbols`
+    /// feature.
+    const SYMBOLS: &'static [crate::userland::Symbol] = &[];
 }
 
 pub trait VSpaceState: private::SealedVSpaceState {}
@@ -75,6 +86,11 @@ pub mod vspace_state {
 /// N.B. A "Granule" is "one of the constituent members of a layer", or
 /// "the level one level down from the current level".
 pub trait Maps<LowerLevel: CapType> {
+    /// How many bits of address space each granule of this layer
+    /// covers. Frames of at least this size are mapped directly into
+    /// this layer rather than into a granule below it.
+    const GRANULE_SIZE_BITS: usize;
+
     /// Map the level/layer down relative to this layer.
     /// E.G. for a PageTable, this would map a Page.
     /// E.G. for a PageDirectory, this would map a PageTable.
@@ -156,6 +172,18 @@ pub enum VSpaceError {
     InvalidRegionSize,
     ElfParseError(&'static str),
     InsufficientResourcesForElf,
+    /// More runs of mappings have been made than a VSpace can keep a
+    /// record of.
+    TooManyMappingsToRecord,
+    /// Nothing is recorded as mapped at the address.
+    AddressNotMapped,
+    /// The memory at the address isn't mapped writable.
+    AddressNotWritable,
+    /// The memory is mapped in granules bigger than a page.
+    UnsupportedGranule,
+    /// The ELF image has more writable segments than an
+    /// `ElfDataTemplate` can hold.
+    TooManyElfDataSegments,
 }
 
 impl From<RetypeError> for VSpaceError {
@@ -191,6 +219,43 @@ pub trait PagingLayer {
         utb: &mut WUTBuddy,
         slots: &mut WCNodeSlots,
     ) -> Result<(), MappingError>;
+
+    /// How many bits of address space each `Item` covers.
+    const ITEM_SIZE_BITS: usize;
+
+    /// Map a frame of `1 << GranuleBits` bytes directly into whichever
+    /// layer holds frames of that size: the highest one whose `Item`s
+    /// are no bigger than the frame. Layers on the way there are
+    /// created as needed, as in `map_layer`.
+    fn map_frame<GranuleBits: Unsigned>(
+        &mut self,
+        frame: &LocalCap<Page<page_state::Unmapped, GranuleBits>>,
+        addr: usize,
+        root: &mut LocalCap<PagingRoot>,
+        rights: CapRights,
+        vm_attributes: arch::VMAttributes,
+        utb: &mut WUTBuddy,
+        slots: &mut WCNodeSlots,
+    ) -> Result<(), MappingError>;
+}
+
+/// Map a frame into the layer that holds it, reporting a missing layer
+/// as `MappingError::Overflow`.
+fn map_frame_here<GranuleBits: Unsigned>(
+    frame: &LocalCap<Page<page_state::Unmapped, GranuleBits>>,
+    addr: usize,
+    root: &mut LocalCap<PagingRoot>,
+    rights: CapRights,
+    vm_attributes: arch::VMAttributes,
+) -> Result<(), MappingError> {
+    if addr % (1 << GranuleBits::USIZE) != 0 {
+        return Err(MappingError::AddrNotPageAligned);
+    }
+    match unsafe { frame.unchecked_page_map(addr, root, rights, vm_attributes) } {
+        Ok(_) => Ok(()),
+        Err(SeL4Error::PageMap(KernelError::FailedLookup)) => Err(MappingError::Overflow),
+        Err(e) => Err(MappingError::PageMapFailure(e)),
+    }
 }
 
 /// `PagingTop` represents the root of an address space structure.
@@ -219,6 +284,22 @@ impl PagingLayer for PagingTop {
         self.layer
             .map_granule(item, addr, root, rights, vm_attributes)
     }
+
+    const ITEM_SIZE_BITS: usize = <PagingRoot as Maps<PagingRootLowerLevel>>::GRANULE_SIZE_BITS;
+
+    fn map_frame<GranuleBits: Unsigned>(
+        &mut self,
+        frame: &LocalCap<Page<page_state::Unmapped, GranuleBits>>,
+        addr: usize,
+        root: &mut LocalCap<PagingRoot>,
+        rights: CapRights,
+        vm_attributes: arch::VMAttributes,
+        _utb: &mut WUTBuddy,
+        _slots: &mut WCNodeSlots,
+    ) -> Result<(), MappingError> {
+        // The root is always present, so there's nothing to create.
+        map_frame_here(frame, addr, root, rights, vm_attributes)
+    }
 }
 
 /// `PagingRec` represents an intermediate layer. It is of type `CurrentLevel`,
@@ -267,11 +348,65 @@ where
             res => res,
         }
     }
+
+    const ITEM_SIZE_BITS: usize = <CurrentLevel as Maps<LowerLevel>>::GRANULE_SIZE_BITS;
+
+    fn map_frame<GranuleBits: Unsigned>(
+        &mut self,
+        frame: &LocalCap<Page<page_state::Unmapped, GranuleBits>>,
+        addr: usize,
+        root: &mut LocalCap<PagingRoot>,
+        rights: CapRights,
+        vm_attributes: arch::VMAttributes,
+        utb: &mut WUTBuddy,
+        mut slots: &mut WCNodeSlots,
+    ) -> Result<(), MappingError> {
+        // Frames as big as the layer above's granules live up there.
+        if GranuleBits::USIZE >= UpperLevel::ITEM_SIZE_BITS {
+            return self
+                .next
+                .map_frame(frame, addr, root, rights, vm_attributes, utb, slots);
+        }
+        match map_frame_here(frame, addr, root, rights, vm_attributes) {
+            Err(MappingError::Overflow) => {
+                let ut = utb.alloc(slots, <UpperLevel::Item as DirectRetype>::SizeBits::U8)?;
+                let next_item = ut.retype::<UpperLevel::Item>(&mut slots)?;
+                self.next
+                    .map_layer(&next_item, addr, root, rights, vm_attributes, utb, slots)?;
+                map_frame_here(frame, addr, root, rights, vm_attributes)
+            }
+            res => res,
+        }
+    }
 }
 
 // 2^12 / PageCount
 pub type NumPages<Size> = Pow<op!(Size - PageBits)>;
 
+/// The number of `1 << GranuleBits` byte frames in `1 << Size` bytes.
+pub type NumGranules<Size, GranuleBits> = Pow<op!(Size - GranuleBits)>;
+
+/// The biggest frames that a region of `1 << Self` bytes can be made
+/// of, out of the page, large page, section and block sizes the
+/// architecture can map. Implemented for every size in `arch`.
+pub trait LargestGranule {
+    type Bits: Unsigned;
+}
+
+/// Picks `Larger` for `True` and `Smaller` for `False`, so that
+/// `LargestGranule` can compare the size with each frame size in turn.
+pub trait PickGranule<Larger, Smaller> {
+    type Output;
+}
+
+impl<Larger, Smaller> PickGranule<Larger, Smaller> for True {
+    type Output = Larger;
+}
+
+impl<Larger, Smaller> PickGranule<Larger, Smaller> for False {
+    type Output = Smaller;
+}
+
 pub enum ProcessCodeImageConfig<'a> {
     ReadOnly,
     /// Use when you need to be able to write to statics in the child process
@@ -301,6 +436,8 @@ pub struct VSpace<State: VSpaceState = vspace_state::Imaged, CapRole: CNodeRole
     untyped: WUTBuddy<CapRole>,
     slots: Cap<WCNodeSlotsData<CapRole>, CapRole>,
     available_address_range: AvailableAddressRange,
+    /// What has been mapped where, for reaching the memory behind it.
+    mappings: MappingRecord,
     _state: PhantomData<State>,
 }
 
@@ -319,6 +456,7 @@ impl VSpace<vspace_state::Empty, role::Local> {
             untyped: ut_buddy::weak_ut_buddy(untyped),
             slots,
             available_address_range: AvailableAddressRange::default(),
+            mappings: MappingRecord::default(),
             _state: PhantomData,
         })
     }
@@ -355,16 +493,21 @@ impl<State: VSpaceState> VSpace<State, role::Local> {
                 &mut self.untyped,
                 &mut self.slots,
             )
-            .map(|_| Cap {
-                cptr: page.cptr,
-                _role: PhantomData,
-                cap_data: Page {
-                    state: page_state::Mapped {
-                        asid: self.asid,
-                        vaddr: address,
-                        rights,
+            .map(|_| {
+                self.mappings
+                    .observe_mapping(address, page.cptr, PageBits::U8, 1, rights);
+                Cap {
+                    cptr: page.cptr,
+                    _role: PhantomData,
+                    cap_data: Page {
+                        state: page_state::Mapped {
+                            asid: self.asid,
+                            vaddr: address,
+                            rights,
+                        },
+                        _granule: PhantomData,
                     },
-                },
+                }
             })
             .map_err(|e| match e {
                 MappingError::PageMapFailure(se) | MappingError::IntermediateLayerFailure(se) => {
@@ -411,22 +554,123 @@ fn iterate_by_page(start: usize, end: usize) -> impl Iterator<Item = (usize, usi
     ByPageIterator { next: start, end }
 }
 
+/// What `VSpace::new_from_elf_internal` does with the pages of an ELF
+/// image's writable segments
+enum WritableSegmentPages<'a> {
+    /// Fill fresh pages with the segments' contents, through the given
+    /// scratch region, and map them read-write.
+    Private(&'a mut ScratchRegion),
+    /// Map copies of an `ElfDataTemplate`'s pages read-only, for a
+    /// `Pager` to copy when they're written to.
+    #[cfg(not(KernelIsMCS))]
+    CopyOnWrite,
+}
+
+/// Fill `dest_mem`, the page of a writable segment which covers
+/// `page_start` up to `page_end`, with whatever the file has for that
+/// part of the segment, and zeroes everywhere else.
+fn load_elf_page(
+    dest_mem: &mut [u8],
+    elf_data: &[u8],
+    segment_vaddr: usize,
+    src_offset: usize,
+    file_size: usize,
+    page_start: usize,
+    page_end: usize,
+) {
+    // zero out the whole page
+    for dest in &mut dest_mem[..] {
+        *dest = 0;
+    }
+
+    // if this overlaps with any file-provided data, copy it over
+    if page_start < (segment_vaddr + file_size) {
+        let src_start = src_offset + (page_start - segment_vaddr);
+        let src_end = core::cmp::min(
+            src_offset + (page_end - segment_vaddr),
+            src_offset + file_size,
+        );
+
+        let data_start_in_page = src_start & PAGE_MASK;
+        let data_end_in_page = src_end & PAGE_MASK;
+        let data_end_in_page = if data_end_in_page == 0 {
+            arch::PageBytes::USIZE
+        } else {
+            data_end_in_page
+        };
+        let dest_slice = &mut dest_mem[data_start_in_page..data_end_in_page];
+        dest_slice.copy_from_slice(&elf_data[src_start..src_end]);
+    }
+}
+
 impl VSpace<vspace_state::Imaged, role::Local> {
     /// Unmap a region.
-    pub fn unmap_region<SizeBits: Unsigned, SS: SharedStatus>(
+    pub fn unmap_region<SizeBits: Unsigned, SS: SharedStatus, GranuleBits: Unsigned>(
         &mut self,
-        region: MappedMemoryRegion<SizeBits, SS>,
-    ) -> Result<UnmappedMemoryRegion<SizeBits, SS>, VSpaceError>
+        region: MappedMemoryRegion<SizeBits, SS, role::Local, GranuleBits>,
+    ) -> Result<UnmappedMemoryRegion<SizeBits, SS, role::Local, GranuleBits>, VSpaceError>
     where
-        SizeBits: IsGreaterOrEqual<PageBits>,
-        SizeBits: Sub<PageBits>,
-        <SizeBits as Sub<PageBits>>::Output: Unsigned,
-        <SizeBits as Sub<PageBits>>::Output: _Pow,
-        Pow<<SizeBits as Sub<PageBits>>::Output>: Unsigned,
+        SizeBits: IsGreaterOrEqual<GranuleBits>,
+        SizeBits: Sub<GranuleBits>,
+        <SizeBits as Sub<GranuleBits>>::Output: Unsigned,
+        <SizeBits as Sub<GranuleBits>>::Output: _Pow,
+        Pow<<SizeBits as Sub<GranuleBits>>::Output>: Unsigned,
     {
-        self.weak_unmap_region(region.weaken())
-            .and_then(|r| r.as_strong::<SizeBits>())
+        if self.asid != region.asid() {
+            return Err(VSpaceError::ASIDMismatch);
+        }
+        let start_cptr = region.caps.start_cptr;
+        let kind = region.kind;
+        let vaddr = region.vaddr();
+        self.mappings
+            .observe_unmapping(vaddr, vaddr + region.size_bytes());
+        for granule_cap in region.caps.into_iter() {
+            let _ = granule_cap.unmap()?;
+        }
+        Ok(MemoryRegion::unchecked_new(
+            start_cptr,
+            page_state::Unmapped,
+            kind,
+        ))
     }
+    /// Change the rights and attributes of a mapped region without
+    /// moving it, by mapping each of its frames again in place. A loader
+    /// can use this to turn the pages it wrote code into read-execute,
+    /// and a producer to hand a buffer it filled over read-only.
+    ///
+    /// If a frame can't be remapped, the ones before it keep their new
+    /// protection and the region still reports its old rights.
+    pub fn protect_region<SizeBits: Unsigned, SS: SharedStatus, GranuleBits: Unsigned>(
+        &mut self,
+        region: &mut MappedMemoryRegion<SizeBits, SS, role::Local, GranuleBits>,
+        rights: CapRights,
+        vm_attributes: arch::VMAttributes,
+    ) -> Result<(), VSpaceError>
+    where
+        SizeBits: IsGreaterOrEqual<GranuleBits>,
+        SizeBits: Sub<GranuleBits>,
+        <SizeBits as Sub<GranuleBits>>::Output: Unsigned,
+        <SizeBits as Sub<GranuleBits>>::Output: _Pow,
+        Pow<<SizeBits as Sub<GranuleBits>>::Output>: Unsigned,
+    {
+        if self.asid != region.asid() {
+            return Err(VSpaceError::ASIDMismatch);
+        }
+        let root = &mut self.root;
+        region
+            .caps
+            .for_each(|granule_cap| granule_cap.remap(root, rights, vm_attributes))?;
+        region.caps.start_cap_data.state.rights = rights;
+        self.mappings.observe_mapping(
+            region.vaddr(),
+            region.caps.start_cptr,
+            GranuleBits::U8,
+            NumGranules::<SizeBits, GranuleBits>::USIZE,
+            rights,
+        );
+        Ok(())
+    }
+
     /// Unmap a weak region.
     pub fn weak_unmap_region<SS: SharedStatus>(
         &mut self,
@@ -437,6 +681,9 @@ impl VSpace<vspace_state::Imaged, role::Local> {
         }
         let start_cptr = region.caps.start_cptr;
         let size_bits = region.size_bits();
+        let vaddr = region.vaddr();
+        self.mappings
+            .observe_unmapping(vaddr, vaddr + region.size_bytes());
         for page_cap in region.caps.into_iter() {
             let _ = self.unmap_page(page_cap)?;
         }
@@ -471,6 +718,7 @@ impl VSpace<vspace_state::Imaged, role::Local> {
             untyped,
             slots: _,
             available_address_range,
+            mappings,
             ..
         } = self;
         let child_root = root.move_to_slot(src_cnode, child_root_slot)?;
@@ -490,6 +738,7 @@ impl VSpace<vspace_state::Imaged, role::Local> {
             untyped: child_untyped,
             slots: child_paging_slots,
             available_address_range,
+            mappings,
             _state: PhantomData,
         })
     }
@@ -535,14 +784,43 @@ impl VSpace<vspace_state::Imaged, role::Local> {
         user_image: &UserImage<role::Local>,
         parent_cnode: &LocalCap<LocalCNode>,
         local_vspace_scratch: &mut ScratchRegion,
+    ) -> Result<Self, VSpaceError> {
+        let writable_pages = elf_writable_mem.retype_pages(&mut page_slots)?;
+        Self::new_from_elf_internal(
+            paging_root,
+            asid,
+            slots,
+            paging_untyped,
+            elf_data,
+            page_slots,
+            writable_pages,
+            WritableSegmentPages::Private(local_vspace_scratch),
+            user_image,
+            parent_cnode,
+        )
+    }
+
+    /// Map an ELF image's segments into a fresh VSpace. Each page of
+    /// the writable segments is taken from `writable_pages` in turn,
+    /// and what's done with it depends on `writable`.
+    fn new_from_elf_internal(
+        paging_root: LocalCap<PagingRoot>,
+        asid: LocalCap<UnassignedASID>,
+        slots: WCNodeSlots,
+        paging_untyped: LocalCap<WUntyped<memory_kind::General>>,
+        elf_data: &[u8],
+        mut page_slots: WCNodeSlots,
+        writable_pages: WeakCapRange<Page<page_state::Unmapped>, role::Local>,
+        mut writable: WritableSegmentPages,
+        user_image: &UserImage<role::Local>,
+        parent_cnode: &LocalCap<LocalCNode>,
     ) -> Result<Self, VSpaceError> {
         let mut vspace =
             VSpace::<vspace_state::Empty>::new(paging_root, asid, slots, paging_untyped)?;
 
         let elf = xmas_elf::ElfFile::new(elf_data).map_err(VSpaceError::ElfParseError)?;
 
-        let mut writable_segment_pages_iter =
-            elf_writable_mem.retype_pages(&mut page_slots)?.into_iter();
+        let mut writable_segment_pages_iter = writable_pages.into_iter();
 
         for program_header in elf
             .program_iter()
@@ -562,7 +840,7 @@ impl VSpace<vspace_state::Imaged, role::Local> {
 
             if flags.is_write() {
                 // Writable segments need to be copied into memory owned by the
-                // new process.
+                // new process, or mapped read-only for a pager to copy.
 
                 // how much space this segment occupies in memory. For writable
                 // segments, this is often larger than the size in the file, for
@@ -586,45 +864,35 @@ impl VSpace<vspace_state::Imaged, role::Local> {
                         .next()
                         .ok_or(VSpaceError::InsufficientResourcesForElf)?;
 
-                    let mut unmapped_region = dest_page.to_region();
-                    let _ = local_vspace_scratch.temporarily_map_region::<PageBits, _, _>(
-                        &mut unmapped_region,
-                        |temp_mapped_region| {
-                            let dest_mem = temp_mapped_region.as_mut_slice();
-
-                            // zero out the whole page
-                            for dest in &mut dest_mem[..] {
-                                *dest = 0;
-                            }
-
-                            // if this overlaps with any file-provided data, copy it over
-                            if target_vaddr_start < (target_vaddr + file_size) {
-                                let src_start = src_offset + (target_vaddr_start - target_vaddr);
-                                let src_end = core::cmp::min(
-                                    src_offset + (target_vaddr_end - target_vaddr),
-                                    src_offset + file_size,
-                                );
-
-                                let data_start_in_page = src_start & PAGE_MASK;
-                                let data_end_in_page = src_end & PAGE_MASK;
-                                let data_end_in_page = if data_end_in_page == 0 {
-                                    arch::PageBytes::USIZE
-                                } else {
-                                    data_end_in_page
-                                };
-                                let dest_slice =
-                                    &mut dest_mem[data_start_in_page..data_end_in_page];
-                                dest_slice.copy_from_slice(&elf_data[src_start..src_end]);
-                            }
-
-                            temp_mapped_region.flush().unwrap();
-                        },
-                    );
+                    let (page, rights) = match writable {
+                        WritableSegmentPages::Private(ref mut local_vspace_scratch) => {
+                            let mut unmapped_region = dest_page.to_region();
+                            let _ = local_vspace_scratch.temporarily_map_region::<PageBits, _, _>(
+                                &mut unmapped_region,
+                                |temp_mapped_region| {
+                                    load_elf_page(
+                                        temp_mapped_region.as_mut_slice(),
+                                        elf_data,
+                                        target_vaddr,
+                                        src_offset,
+                                        file_size,
+                                        target_vaddr_start,
+                                        target_vaddr_end,
+                                    );
+                                    temp_mapped_region.flush().unwrap();
+                                },
+                            );
+                            (unmapped_region.to_page(), CapRights::RW)
+                        }
+                        // The template's page already holds the contents.
+                        #[cfg(not(KernelIsMCS))]
+                        WritableSegmentPages::CopyOnWrite => (dest_page, CapRights::R),
+                    };
 
                     let _ = vspace.map_page_at_addr_without_watermarking(
-                        unmapped_region.to_page(),
+                        page,
                         curr_page_vaddr,
-                        CapRights::RW,
+                        rights,
                         vm_attrs,
                     )?;
 
@@ -686,6 +954,7 @@ impl VSpace<vspace_state::Imaged, role::Local> {
             untyped: vspace.untyped,
             slots: vspace.slots,
             available_address_range: vspace.available_address_range,
+            mappings: vspace.mappings,
             _state: PhantomData,
         };
 
@@ -786,6 +1055,7 @@ impl VSpace<vspace_state::Imaged, role::Local> {
             untyped: vspace.untyped,
             slots: vspace.slots,
             available_address_range: vspace.available_address_range,
+            mappings: vspace.mappings,
             _state: PhantomData,
         })
     }
@@ -812,32 +1082,65 @@ impl VSpace<vspace_state::Imaged, role::Local> {
             untyped: ut_buddy::weak_ut_buddy(ut),
             slots: cslots,
             available_address_range,
+            mappings: MappingRecord::default(),
             asid: asid.cap_data.asid,
             _state: PhantomData,
         }
     }
 
-    pub fn map_region_at_addr<SizeBits: Unsigned, SS: SharedStatus>(
+    /// Map a region at the given address, which must be aligned to the
+    /// region's granule size.
+    pub fn map_region_at_addr<SizeBits: Unsigned, SS: SharedStatus, GranuleBits: Unsigned>(
         &mut self,
-        region: UnmappedMemoryRegion<SizeBits, SS>,
+        region: UnmappedMemoryRegion<SizeBits, SS, role::Local, GranuleBits>,
         vaddr: usize,
         rights: CapRights,
         vm_attributes: arch::VMAttributes,
     ) -> Result<
-        MappedMemoryRegion<SizeBits, SS>,
-        (VSpaceError, Option<UnmappedMemoryRegion<SizeBits, SS>>),
+        MappedMemoryRegion<SizeBits, SS, role::Local, GranuleBits>,
+        (
+            VSpaceError,
+            Option<UnmappedMemoryRegion<SizeBits, SS, role::Local, GranuleBits>>,
+        ),
     >
     where
-        SizeBits: IsGreaterOrEqual<PageBits>,
-        SizeBits: Sub<PageBits>,
-        <SizeBits as Sub<PageBits>>::Output: Unsigned,
-        <SizeBits as Sub<PageBits>>::Output: _Pow,
-        Pow<<SizeBits as Sub<PageBits>>::Output>: Unsigned,
+        SizeBits: IsGreaterOrEqual<GranuleBits>,
+        SizeBits: Sub<GranuleBits>,
+        <SizeBits as Sub<GranuleBits>>::Output: Unsigned,
+        <SizeBits as Sub<GranuleBits>>::Output: _Pow,
+        Pow<<SizeBits as Sub<GranuleBits>>::Output>: Unsigned,
     {
-        match self.weak_map_region_at_addr(region.weaken(), vaddr, rights, vm_attributes) {
-            Ok(r) => Ok(r.as_strong::<SizeBits>().map_err(|e| (e, None))?),
-            Err((e, r)) => Err((e, r.as_strong::<SizeBits>().ok())),
+        // Verify that we can fit this region into the address space.
+        if vaddr.checked_add(region.size_bytes()) == None {
+            return Err((VSpaceError::ExceededAddressableSpace, Some(region)));
+        }
+        let cptr = region.caps.start_cptr;
+        let kind = region.kind;
+        let unmapped = || MemoryRegion::unchecked_new(cptr, page_state::Unmapped, kind);
+
+        if let Err(e) = self.map_granules(region.caps, vaddr, rights, vm_attributes) {
+            return Err((e, Some(unmapped())));
         }
+        let mapped = MemoryRegion::unchecked_new(
+            cptr,
+            page_state::Mapped {
+                vaddr,
+                asid: self.asid,
+                rights,
+            },
+            kind,
+        );
+        if let Err(e) = self
+            .available_address_range
+            .observe_mapping(vaddr, SizeBits::U8)
+        {
+            // Roll the mapping back.
+            return match self.unmap_region(mapped) {
+                Ok(r) => Err((e, Some(r))),
+                Err(_) => Err((e, None)),
+            };
+        }
+        Ok(mapped)
     }
 
     pub fn weak_map_region_at_addr<SS: SharedStatus>(
@@ -930,6 +1233,7 @@ impl VSpace<vspace_state::Imaged, role::Local> {
                                         asid: self.asid(),
                                         rights,
                                     },
+                                    _granule: PhantomData,
                                 },
                                 1,
                             ));
@@ -955,6 +1259,13 @@ impl VSpace<vspace_state::Imaged, role::Local> {
                 WeakMemoryRegion::unchecked_new(cptr, page_state::Unmapped, kind, size_bits),
             ));
         }
+        self.mappings.observe_mapping(
+            vaddr,
+            cptr,
+            PageBits::U8,
+            (mapping_vaddr - vaddr) >> PageBits::USIZE,
+            rights,
+        );
 
         Ok(WeakMappedMemoryRegion::unchecked_new(
             cptr,
@@ -969,18 +1280,25 @@ impl VSpace<vspace_state::Imaged, role::Local> {
     }
 
     /// Map a region of memory at some address, I don't care where.
-    pub fn map_region<SizeBits: Unsigned>(
+    ///
+    /// Regions made of granules bigger than a page are placed at an
+    /// address aligned to the granule size, so that each one can be
+    /// mapped as a single large page, section or block.
+    pub fn map_region<SizeBits: Unsigned, GranuleBits: Unsigned>(
         &mut self,
-        region: UnmappedMemoryRegion<SizeBits, shared_status::Exclusive>,
+        region: UnmappedMemoryRegion<SizeBits, shared_status::Exclusive, role::Local, GranuleBits>,
         rights: CapRights,
         vm_attributes: arch::VMAttributes,
-    ) -> Result<MappedMemoryRegion<SizeBits, shared_status::Exclusive>, VSpaceError>
+    ) -> Result<
+        MappedMemoryRegion<SizeBits, shared_status::Exclusive, role::Local, GranuleBits>,
+        VSpaceError,
+    >
     where
-        SizeBits: IsGreaterOrEqual<PageBits>,
-        SizeBits: Sub<PageBits>,
-        <SizeBits as Sub<PageBits>>::Output: Unsigned,
-        <SizeBits as Sub<PageBits>>::Output: _Pow,
-        Pow<<SizeBits as Sub<PageBits>>::Output>: Unsigned,
+        SizeBits: IsGreaterOrEqual<GranuleBits>,
+        SizeBits: Sub<GranuleBits>,
+        <SizeBits as Sub<GranuleBits>>::Output: Unsigned,
+        <SizeBits as Sub<GranuleBits>>::Output: _Pow,
+        Pow<<SizeBits as Sub<GranuleBits>>::Output>: Unsigned,
     {
         self.map_region_internal(region, rights, vm_attributes)
     }
@@ -1043,6 +1361,10 @@ impl VSpace<vspace_state::Imaged, role::Local> {
         let vaddr = mapped_region.vaddr();
         let dest_init_cptr = dest_slots.cap_data.offset;
 
+        // The memory can't be reached through the caps once they've
+        // moved.
+        self.mappings
+            .observe_unmapping(vaddr, vaddr + mapped_region.size_bytes());
         for (page, slot) in mapped_region
             .caps
             .into_iter()
@@ -1069,22 +1391,25 @@ impl VSpace<vspace_state::Imaged, role::Local> {
     /// The incoming `UnmappedMemoryRegion` is only borrowed and one
     /// also gets back a new `MappedMemoryRegion` indexed with the
     /// status `Shared`.
-    pub fn map_shared_region<SizeBits: Unsigned>(
+    pub fn map_shared_region<SizeBits: Unsigned, GranuleBits: Unsigned>(
         &mut self,
-        region: &UnmappedMemoryRegion<SizeBits, shared_status::Shared>,
+        region: &UnmappedMemoryRegion<SizeBits, shared_status::Shared, role::Local, GranuleBits>,
         rights: CapRights,
         vm_attributes: arch::VMAttributes,
-        slots: LocalCNodeSlots<NumPages<SizeBits>>,
+        slots: LocalCNodeSlots<NumGranules<SizeBits, GranuleBits>>,
         cnode: &LocalCap<LocalCNode>,
-    ) -> Result<MappedMemoryRegion<SizeBits, shared_status::Shared>, VSpaceError>
+    ) -> Result<
+        MappedMemoryRegion<SizeBits, shared_status::Shared, role::Local, GranuleBits>,
+        VSpaceError,
+    >
     where
-        SizeBits: IsGreaterOrEqual<PageBits>,
-        SizeBits: Sub<PageBits>,
-        <SizeBits as Sub<PageBits>>::Output: Unsigned,
-        <SizeBits as Sub<PageBits>>::Output: _Pow,
-        Pow<<SizeBits as Sub<PageBits>>::Output>: Unsigned,
+        SizeBits: IsGreaterOrEqual<GranuleBits>,
+        SizeBits: Sub<GranuleBits>,
+        <SizeBits as Sub<GranuleBits>>::Output: Unsigned,
+        <SizeBits as Sub<GranuleBits>>::Output: _Pow,
+        Pow<<SizeBits as Sub<GranuleBits>>::Output>: Unsigned,
     {
-        let unmapped_sr: UnmappedMemoryRegion<_, shared_status::Shared> =
+        let unmapped_sr: UnmappedMemoryRegion<_, shared_status::Shared, _, GranuleBits> =
             UnmappedMemoryRegion::from_caps(region.caps.copy(cnode, slots, rights)?, region.kind);
         self.map_region_internal(unmapped_sr, rights, vm_attributes)
     }
@@ -1120,28 +1445,38 @@ impl VSpace<vspace_state::Imaged, role::Local> {
     /// address space in which this region will be mapped—that
     /// unmapped region can be consumed and a mapped region is
     /// returned.
-    pub fn map_shared_region_and_consume<SizeBits: Unsigned>(
+    pub fn map_shared_region_and_consume<SizeBits: Unsigned, GranuleBits: Unsigned>(
         &mut self,
-        region: UnmappedMemoryRegion<SizeBits, shared_status::Shared>,
+        region: UnmappedMemoryRegion<SizeBits, shared_status::Shared, role::Local, GranuleBits>,
         rights: CapRights,
         vm_attributes: arch::VMAttributes,
-    ) -> Result<MappedMemoryRegion<SizeBits, shared_status::Shared>, VSpaceError>
+    ) -> Result<
+        MappedMemoryRegion<SizeBits, shared_status::Shared, role::Local, GranuleBits>,
+        VSpaceError,
+    >
     where
-        SizeBits: IsGreaterOrEqual<PageBits>,
-        SizeBits: Sub<PageBits>,
-        <SizeBits as Sub<PageBits>>::Output: Unsigned,
-        <SizeBits as Sub<PageBits>>::Output: _Pow,
-        Pow<<SizeBits as Sub<PageBits>>::Output>: Unsigned,
+        SizeBits: IsGreaterOrEqual<GranuleBits>,
+        SizeBits: Sub<GranuleBits>,
+        <SizeBits as Sub<GranuleBits>>::Output: Unsigned,
+        <SizeBits as Sub<GranuleBits>>::Output: _Pow,
+        Pow<<SizeBits as Sub<GranuleBits>>::Output>: Unsigned,
     {
         self.map_region_internal(region, rights, vm_attributes)
     }
 
-    fn map_region_internal<SizeBits: Unsigned, SSIn: SharedStatus, SSOut: SharedStatus>(
+    /// Map a _copy-on-write_ region of memory at some address, I don't
+    /// care where. Like `map_shared_region`, the region's caps are
+    /// copied into the slots provided and the region itself is only
+    /// borrowed, but the copy is always mapped read-only. Hand the
+    /// mapped region to the `Pager` looking after this address space so
+    /// that writes to it get a private copy of the page.
+    pub fn map_copy_on_write_region<SizeBits: Unsigned>(
         &mut self,
-        region: UnmappedMemoryRegion<SizeBits, SSIn>,
-        rights: CapRights,
+        region: &UnmappedMemoryRegion<SizeBits, shared_status::CopyOnWrite>,
         vm_attributes: arch::VMAttributes,
-    ) -> Result<MappedMemoryRegion<SizeBits, SSOut>, VSpaceError>
+        slots: LocalCNodeSlots<NumPages<SizeBits>>,
+        cnode: &LocalCap<LocalCNode>,
+    ) -> Result<MappedMemoryRegion<SizeBits, shared_status::CopyOnWrite>, VSpaceError>
     where
         SizeBits: IsGreaterOrEqual<PageBits>,
         SizeBits: Sub<PageBits>,
@@ -1149,8 +1484,102 @@ impl VSpace<vspace_state::Imaged, role::Local> {
         <SizeBits as Sub<PageBits>>::Output: _Pow,
         Pow<<SizeBits as Sub<PageBits>>::Output>: Unsigned,
     {
-        self.weak_map_region_internal(region.weaken(), rights, vm_attributes)
-            .and_then(|r| r.as_strong::<SizeBits>())
+        let unmapped: UnmappedMemoryRegion<_, shared_status::CopyOnWrite> =
+            UnmappedMemoryRegion::from_caps(
+                region.caps.copy(cnode, slots, CapRights::R)?,
+                region.kind,
+            );
+        self.map_region_internal(unmapped, CapRights::R, vm_attributes)
+    }
+
+    fn map_region_internal<
+        SizeBits: Unsigned,
+        GranuleBits: Unsigned,
+        SSIn: SharedStatus,
+        SSOut: SharedStatus,
+    >(
+        &mut self,
+        region: UnmappedMemoryRegion<SizeBits, SSIn, role::Local, GranuleBits>,
+        rights: CapRights,
+        vm_attributes: arch::VMAttributes,
+    ) -> Result<MappedMemoryRegion<SizeBits, SSOut, role::Local, GranuleBits>, VSpaceError>
+    where
+        SizeBits: IsGreaterOrEqual<GranuleBits>,
+        SizeBits: Sub<GranuleBits>,
+        <SizeBits as Sub<GranuleBits>>::Output: Unsigned,
+        <SizeBits as Sub<GranuleBits>>::Output: _Pow,
+        Pow<<SizeBits as Sub<GranuleBits>>::Output>: Unsigned,
+    {
+        let starting_address = self
+            .available_address_range
+            .auto_propose_region_start(SizeBits::U8, GranuleBits::U8)
+            .map_err(|_| VSpaceError::InsufficientAddressSpaceAvailableToMapRegion)?;
+        let mapped_region = MemoryRegion::unchecked_new(
+            region.caps.start_cptr,
+            page_state::Mapped {
+                vaddr: starting_address,
+                asid: self.asid(),
+                rights,
+            },
+            region.kind,
+        );
+        self.map_granules(region.caps, starting_address, rights, vm_attributes)?;
+        self.available_address_range
+            .observe_mapping(starting_address, SizeBits::U8)?;
+        Ok(mapped_region)
+    }
+
+    /// Map each of a region's granules in turn, starting at `vaddr`. If
+    /// one of them can't be mapped, the ones before it are unmapped
+    /// again.
+    fn map_granules<GranuleBits: Unsigned, Count: Unsigned>(
+        &mut self,
+        granules: CapRange<Page<page_state::Unmapped, GranuleBits>, role::Local, Count>,
+        vaddr: usize,
+        rights: CapRights,
+        vm_attributes: arch::VMAttributes,
+    ) -> Result<(), VSpaceError> {
+        let start_cptr = granules.start_cptr;
+        let mut mapping_vaddr = vaddr;
+        for (index, granule_cap) in granules.into_iter().enumerate() {
+            if let Err(e) = self.layers.map_frame(
+                &granule_cap,
+                mapping_vaddr,
+                &mut self.root,
+                rights,
+                vm_attributes,
+                &mut self.untyped,
+                &mut self.slots,
+            ) {
+                let mapped_so_far: WeakCapRange<Page<page_state::Mapped, GranuleBits>, _> =
+                    WeakCapRange::new(
+                        start_cptr,
+                        Page {
+                            state: page_state::Mapped {
+                                vaddr,
+                                asid: self.asid,
+                                rights,
+                            },
+                            _granule: PhantomData,
+                        },
+                        index,
+                    );
+                let _ = mapped_so_far
+                    .into_iter()
+                    .try_for_each(|granule_cap| granule_cap.unmap().map(|_| ()));
+                return Err(match e {
+                    MappingError::PageMapFailure(se)
+                    | MappingError::IntermediateLayerFailure(se) => VSpaceError::SeL4Error(se),
+                    e => VSpaceError::MappingError(e),
+                });
+            }
+            // It's safe to do a direct addition as we've already
+            // determined that this region will fit here.
+            mapping_vaddr += 1 << GranuleBits::USIZE;
+        }
+        self.mappings
+            .observe_mapping(vaddr, start_cptr, GranuleBits::U8, Count::USIZE, rights);
+        Ok(())
     }
     fn weak_map_region_internal<SSIn: SharedStatus, SSOut: SharedStatus>(
         &mut self,
@@ -1160,7 +1589,7 @@ impl VSpace<vspace_state::Imaged, role::Local> {
     ) -> Result<WeakMappedMemoryRegion<SSOut>, VSpaceError> {
         let starting_address = self
             .available_address_range
-            .auto_propose_region_start(region.size_bits())
+            .auto_propose_region_start(region.size_bits(), PageBits::U8)
             .map_err(|_| VSpaceError::InsufficientAddressSpaceAvailableToMapRegion)?;
 
         // create the mapped region first because we need to pluck out
@@ -1201,6 +1630,13 @@ impl VSpace<vspace_state::Imaged, role::Local> {
             // determined that this region will fit here.
             vaddr += PageBytes::USIZE;
         }
+        self.mappings.observe_mapping(
+            starting_address,
+            mapped_region.caps.start_cptr,
+            PageBits::U8,
+            (vaddr - starting_address) >> PageBits::USIZE,
+            rights,
+        );
 
         Ok(mapped_region)
     }
@@ -1209,7 +1645,7 @@ impl VSpace<vspace_state::Imaged, role::Local> {
         for _ in 0..count {
             let starting_address = self
                 .available_address_range
-                .auto_propose_region_start(PageBits::U8)
+                .auto_propose_region_start(PageBits::U8, PageBits::U8)
                 .map_err(|_| VSpaceError::ExceededAddressableSpace)?;
             self.available_address_range
                 .observe_mapping(starting_address, PageBits::U8)?;
@@ -1217,6 +1653,14 @@ impl VSpace<vspace_state::Imaged, role::Local> {
         Ok(())
     }
 
+    /// Give back the address range `[start, end)`, whose mappings have
+    /// all been removed, so that it can be handed out again. Only a
+    /// range at the edge of what's been claimed can be reclaimed;
+    /// anything else stays claimed.
+    pub(crate) fn release_address_range(&mut self, start: usize, end: usize) {
+        self.available_address_range.observe_unmapping(start, end)
+    }
+
     pub fn reserve<PageCount: Unsigned>(
         &mut self,
         sacrificial_page: LocalCap<Page<page_state::Unmapped>>,
@@ -1226,6 +1670,77 @@ impl VSpace<vspace_state::Imaged, role::Local> {
     {
         ReservedRegion::new(self, sacrificial_page)
     }
+
+    /// Reserve address space that is never to be mapped, e.g. as a
+    /// guard below a stack, so that running off the end of the stack
+    /// faults instead of reaching whatever is mapped next to it. Unlike
+    /// `reserve`, this doesn't need a page, since there's no call for
+    /// paging structures behind a guard.
+    pub fn reserve_guard<PageCount: Unsigned>(
+        &mut self,
+    ) -> Result<GuardRegion<PageCount>, VSpaceError>
+    where
+        PageCount: IsGreaterOrEqual<U1, Output = True>,
+    {
+        let ReservedRegion { vaddr, asid, .. } = self.reserve_unbacked::<PageCount>()?;
+        Ok(GuardRegion {
+            vaddr,
+            asid,
+            _page_count: PhantomData,
+        })
+    }
+
+    /// Claim address space without making any paging structures for it.
+    pub(crate) fn reserve_unbacked<PageCount: Unsigned>(
+        &mut self,
+    ) -> Result<ReservedRegion<PageCount>, VSpaceError>
+    where
+        PageCount: IsGreaterOrEqual<U1, Output = True>,
+    {
+        let vaddr = self
+            .available_address_range
+            .auto_propose_region_start(PageBits::U8, PageBits::U8)
+            .map_err(|_| VSpaceError::ExceededAddressableSpace)?;
+        self.skip_pages(PageCount::USIZE)?;
+        Ok(ReservedRegion {
+            vaddr,
+            asid: self.asid(),
+            _page_count: PhantomData,
+        })
+    }
+}
+
+/// Address space from `VSpace::reserve_guard` that is never mapped.
+/// Unlike a `ReservedRegion` it has no paging structures behind it,
+/// so it can't be used as scratch, only checked against fault
+/// addresses.
+pub struct GuardRegion<PageCount: Unsigned> {
+    vaddr: usize,
+    asid: InternalASID,
+    _page_count: PhantomData<PageCount>,
+}
+
+impl<PageCount: Unsigned> GuardRegion<PageCount>
+where
+    PageCount: IsGreaterOrEqual<U1, Output = True>,
+{
+    pub fn size(&self) -> usize {
+        PageCount::USIZE * crate::arch::PageBytes::USIZE
+    }
+
+    /// The address of the region's first page
+    pub fn vaddr(&self) -> usize {
+        self.vaddr
+    }
+
+    pub(crate) fn asid(&self) -> InternalASID {
+        self.asid
+    }
+
+    /// Whether `vaddr` falls inside this region.
+    pub fn contains(&self, vaddr: usize) -> bool {
+        vaddr >= self.vaddr && vaddr - self.vaddr < self.size()
+    }
 }
 
 /// A region of memory in a VSpace that has been reserved
@@ -1235,6 +1750,10 @@ impl VSpace<vspace_state::Imaged, role::Local> {
 /// so mapping individual pages to this region should require
 /// no overhead resources whatsoever.
 ///
+/// The regions behind a `LazyRegion` are the exception: nothing is
+/// mapped into them up front, so they have no paging structures and
+/// are never used as scratch.
+///
 /// Note that the type parameter regarding default size matches
 /// the currently defaulted number of pages allowed for a process
 /// stack.
@@ -1252,6 +1771,22 @@ where
         PageCount::USIZE * crate::arch::PageBytes::USIZE
     }
 
+    /// The address of the region's first page
+    pub fn vaddr(&self) -> usize {
+        self.vaddr
+    }
+
+    // Only the pager, which the MCS scheduler doesn't have, needs it
+    #[cfg_attr(KernelIsMCS, allow(dead_code))]
+    pub(crate) fn asid(&self) -> InternalASID {
+        self.asid
+    }
+
+    /// Whether `vaddr` falls inside this region.
+    pub fn contains(&self, vaddr: usize) -> bool {
+        vaddr >= self.vaddr && vaddr - self.vaddr < self.size()
+    }
+
     pub fn new(
         vspace: &mut VSpace,
         sacrificial_page: LocalCap<Page<page_state::Unmapped>>,
@@ -1377,6 +1912,72 @@ impl<PageCount: Unsigned> ScratchRegion<PageCount> {
 
         Ok(res)
     }
+
+    /// Copy the contents of frame `src` into frame `dest`, mapping the
+    /// former read-only and the latter read-write next to each other at
+    /// the start of the scratch region while it happens.
+    #[cfg_attr(KernelIsMCS, allow(dead_code))]
+    pub(crate) fn copy_page(
+        &mut self,
+        src: &LocalCap<Page<page_state::Unmapped>>,
+        dest: &LocalCap<Page<page_state::Unmapped>>,
+    ) -> Result<(), VSpaceError>
+    where
+        PageCount: IsGreaterOrEqual<U2, Output = True>,
+    {
+        let src_vaddr = self.reserved_region.vaddr;
+        let dest_vaddr = src_vaddr + PageBytes::USIZE;
+        let asid = self.reserved_region.asid;
+        // synthesize the mapped caps so they can be unmapped again
+        let mapped = |cptr, vaddr, rights| -> LocalCap<Page<page_state::Mapped>> {
+            Cap {
+                cptr,
+                cap_data: Page {
+                    state: page_state::Mapped {
+                        vaddr,
+                        asid,
+                        rights,
+                    },
+                    _granule: PhantomData,
+                },
+                _role: PhantomData,
+            }
+        };
+
+        unsafe {
+            src.unchecked_page_map(
+                src_vaddr,
+                &mut self.paging_root,
+                CapRights::R,
+                arch::vm_attributes::DEFAULT,
+            )?;
+        }
+        let src_mapped = mapped(src.cptr, src_vaddr, CapRights::R);
+        if let Err(e) = unsafe {
+            dest.unchecked_page_map(
+                dest_vaddr,
+                &mut self.paging_root,
+                CapRights::RW,
+                arch::vm_attributes::DEFAULT,
+            )
+        } {
+            src_mapped.unmap()?;
+            return Err(e.into());
+        }
+        let dest_mapped = mapped(dest.cptr, dest_vaddr, CapRights::RW);
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                src_vaddr as *const u8,
+                dest_vaddr as *mut u8,
+                PageBytes::USIZE,
+            );
+        }
+
+        src_mapped.unmap()?;
+        dest_mapped.unmap()?;
+        Ok(())
+    }
 }
 
 /// A dual-cursor address range tracker that maintains
@@ -1418,12 +2019,33 @@ impl AvailableAddressRange {
         Ok(())
     }
 
-    fn auto_propose_region_start(&self, size_bits: u8) -> Result<usize, CouldNotAllocateRegion> {
+    /// Move the watermark back over `[start, end)` if the range sits
+    /// right against it.
+    fn observe_unmapping(&mut self, start: usize, end: usize) {
+        if end == self.bottom {
+            self.bottom = start;
+        } else if start == self.top {
+            self.top = end;
+        }
+    }
+
+    /// Propose the lowest available start for a region which is aligned
+    /// to `1 << align_bits` bytes.
+    fn auto_propose_region_start(
+        &self,
+        size_bits: u8,
+        align_bits: u8,
+    ) -> Result<usize, CouldNotAllocateRegion> {
         if self.bottom > self.top {
             return Err(CouldNotAllocateRegion);
         }
         let size_bytes = bytes_from_size_bits(size_bits);
-        let proposed_start = self.bottom;
+        let align_mask = bytes_from_size_bits(align_bits) - 1;
+        let proposed_start = self
+            .bottom
+            .checked_add(align_mask)
+            .ok_or(CouldNotAllocateRegion)?
+            & !align_mask;
         let proposed_end = proposed_start
             .checked_add(size_bytes)
             .ok_or(CouldNotAllocateRegion)?;
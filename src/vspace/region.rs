@@ -4,17 +4,17 @@ use core::ops::Sub;
 
 use typenum::*;
 
-use super::{KernelRetypeFanOutLimit, NumPages, VSpaceError};
-use crate::arch::{self, PageBits, PageBytes};
+use super::{KernelRetypeFanOutLimit, LargestGranule, NumGranules, NumPages, VSpaceError};
+use crate::arch::{self, PageBits};
 use crate::cap::{
-    memory_kind, page_state, role, CNode, CNodeRole, CNodeSlots, Cap, CapRange, InternalASID,
-    LocalCNodeSlots, LocalCap, MemoryKind, Page, PageState, RetypeError, Untyped, WCNodeSlots,
-    WUntyped, WeakCapRange, WeakMemoryKind,
+    memory_kind, page_state, role, CNode, CNodeRole, CNodeSlots, Cap, CapRange, DirectRetype,
+    InternalASID, LocalCNodeSlot, LocalCNodeSlots, LocalCap, MemoryKind, Page, PageState,
+    RetypeError, Untyped, WCNodeSlots, WUntyped, WeakCapRange, WeakMemoryKind,
 };
 use crate::error::SeL4Error;
 
 use crate::pow::{Pow, _Pow};
-use crate::userland::CapRights;
+use crate::userland::{CapRights, Transferable};
 
 pub trait SharedStatus: private::SealedSharedStatus {}
 
@@ -26,27 +26,41 @@ pub mod shared_status {
 
     pub struct Exclusive;
     impl SharedStatus for Exclusive {}
+
+    /// Shared read-only, with each address space that writes to it
+    /// getting a private copy of the page it wrote to.
+    pub struct CopyOnWrite;
+    impl SharedStatus for CopyOnWrite {}
 }
 
 mod private {
-    use super::shared_status::{Exclusive, Shared};
+    use super::shared_status::{CopyOnWrite, Exclusive, Shared};
     pub trait SealedSharedStatus {}
     impl SealedSharedStatus for Shared {}
     impl SealedSharedStatus for Exclusive {}
+    impl SealedSharedStatus for CopyOnWrite {}
 }
 /// A `1 << SizeBits` bytes region of unmapped memory. It can be
 /// shared or owned exclusively. The ramifications of its shared
 /// status are described more completely in the `mapped_shared_region`
 /// function description.
 #[allow(type_alias_bounds)]
-pub type UnmappedMemoryRegion<SizeBits, ShStatus, CapRole: CNodeRole = role::Local> =
-    MemoryRegion<page_state::Unmapped, SizeBits, ShStatus, CapRole>;
+pub type UnmappedMemoryRegion<
+    SizeBits,
+    ShStatus,
+    CapRole: CNodeRole = role::Local,
+    GranuleBits = PageBits,
+> = MemoryRegion<page_state::Unmapped, SizeBits, ShStatus, CapRole, GranuleBits>;
 /// A memory region which is mapped into an address space, meaning it
 /// has a virtual address and an associated asid in which that virtual
 /// address is valid.
 #[allow(type_alias_bounds)]
-pub type MappedMemoryRegion<SizeBits, ShStatus, CapRole: CNodeRole = role::Local> =
-    MemoryRegion<page_state::Mapped, SizeBits, ShStatus, CapRole>;
+pub type MappedMemoryRegion<
+    SizeBits,
+    ShStatus,
+    CapRole: CNodeRole = role::Local,
+    GranuleBits = PageBits,
+> = MemoryRegion<page_state::Mapped, SizeBits, ShStatus, CapRole, GranuleBits>;
 #[allow(type_alias_bounds)]
 pub type WeakUnmappedMemoryRegion<ShStatus, CapRole: CNodeRole = role::Local> =
     WeakMemoryRegion<page_state::Unmapped, ShStatus, CapRole>;
@@ -58,33 +72,44 @@ pub type WeakMappedMemoryRegion<ShStatus, CapRole: CNodeRole = role::Local> =
 /// shared or owned exclusively. The ramifications of its shared
 /// status are described more completely in the `mapped_shared_region`
 /// function description.
+///
+/// The region is made up of `1 << GranuleBits` byte frames, which are
+/// single pages unless the region was retyped with bigger granules, in
+/// which case it is mapped with large pages, sections or blocks.
 pub struct MemoryRegion<
     State: PageState,
     SizeBits: Unsigned,
     SS: SharedStatus,
     CapRole: CNodeRole = role::Local,
+    GranuleBits: Unsigned = PageBits,
 > where
-    // Forces regions to be page-aligned.
-    SizeBits: IsGreaterOrEqual<PageBits>,
-    SizeBits: Sub<PageBits>,
-    <SizeBits as Sub<PageBits>>::Output: Unsigned,
-    <SizeBits as Sub<PageBits>>::Output: _Pow,
-    Pow<<SizeBits as Sub<PageBits>>::Output>: Unsigned,
+    // Forces regions to be made of whole granules.
+    SizeBits: IsGreaterOrEqual<GranuleBits>,
+    SizeBits: Sub<GranuleBits>,
+    <SizeBits as Sub<GranuleBits>>::Output: Unsigned,
+    <SizeBits as Sub<GranuleBits>>::Output: _Pow,
+    Pow<<SizeBits as Sub<GranuleBits>>::Output>: Unsigned,
 {
-    pub(super) caps: CapRange<Page<State>, CapRole, NumPages<SizeBits>>,
+    pub(super) caps:
+        CapRange<Page<State, GranuleBits>, CapRole, NumGranules<SizeBits, GranuleBits>>,
     pub(super) kind: WeakMemoryKind,
     _size_bits: PhantomData<SizeBits>,
     _shared_status: PhantomData<SS>,
 }
 
-impl<State: PageState, SizeBits: Unsigned, SS: SharedStatus, CapRole: CNodeRole>
-    MemoryRegion<State, SizeBits, SS, CapRole>
+impl<
+        State: PageState,
+        SizeBits: Unsigned,
+        SS: SharedStatus,
+        CapRole: CNodeRole,
+        GranuleBits: Unsigned,
+    > MemoryRegion<State, SizeBits, SS, CapRole, GranuleBits>
 where
-    SizeBits: IsGreaterOrEqual<PageBits>,
-    SizeBits: Sub<PageBits>,
-    <SizeBits as Sub<PageBits>>::Output: Unsigned,
-    <SizeBits as Sub<PageBits>>::Output: _Pow,
-    Pow<<SizeBits as Sub<PageBits>>::Output>: Unsigned,
+    SizeBits: IsGreaterOrEqual<GranuleBits>,
+    SizeBits: Sub<GranuleBits>,
+    <SizeBits as Sub<GranuleBits>>::Output: Unsigned,
+    <SizeBits as Sub<GranuleBits>>::Output: _Pow,
+    Pow<<SizeBits as Sub<GranuleBits>>::Output>: Unsigned,
 {
     pub const SIZE_BYTES: usize = 1 << SizeBits::USIZE;
 
@@ -98,10 +123,23 @@ where
         Self::SIZE_BYTES
     }
 
+    /// The local cptr of the cap for the region's first frame. The rest
+    /// follow it.
+    #[cfg_attr(KernelIsMCS, allow(dead_code))]
+    pub(crate) fn start_cptr(&self) -> usize {
+        self.caps.start_cptr
+    }
+
+    /// The number of bits needed to address each of the frames that
+    /// make up this region.
+    pub fn granule_bits(&self) -> u8 {
+        GranuleBits::U8
+    }
+
     pub(super) fn from_caps(
-        caps: CapRange<Page<State>, CapRole, NumPages<SizeBits>>,
+        caps: CapRange<Page<State, GranuleBits>, CapRole, NumGranules<SizeBits, GranuleBits>>,
         kind: WeakMemoryKind,
-    ) -> MemoryRegion<State, SizeBits, SS, CapRole> {
+    ) -> MemoryRegion<State, SizeBits, SS, CapRole, GranuleBits> {
         MemoryRegion {
             caps,
             kind,
@@ -116,28 +154,54 @@ where
         kind: WeakMemoryKind,
     ) -> Self {
         MemoryRegion {
-            caps: CapRange::new(local_page_caps_offset_cptr, Page { state }),
+            caps: CapRange::new(
+                local_page_caps_offset_cptr,
+                Page {
+                    state,
+                    _granule: PhantomData,
+                },
+            ),
             kind,
             _size_bits: PhantomData,
             _shared_status: PhantomData,
         }
     }
-    pub fn weaken(self) -> WeakMemoryRegion<State, SS, CapRole> {
-        WeakMemoryRegion::try_from_caps(self.caps.weaken(), self.kind, SizeBits::U8)
-            .expect("Cap page slots to memory region size invariant maintained by type signature")
+
+    /// Reclaim exclusive ownership of a region that may have been
+    /// shared.
+    ///
+    /// The caller must ensure that every other copy of this region's
+    /// page capabilities has already been deleted.
+    pub(crate) unsafe fn unchecked_to_exclusive(
+        self,
+    ) -> MemoryRegion<State, SizeBits, shared_status::Exclusive, CapRole, GranuleBits> {
+        MemoryRegion::from_caps(self.caps, self.kind)
     }
 
-    /// N.B. until MemoryKind tracking is added to Page, this is a lossy
-    /// conversion that will assume the Region was for General memory
-    pub(crate) fn to_page(self) -> LocalCap<Page<State>>
+    /// Another handle to the same frames.
+    ///
+    /// The caller must ensure that whatever one of the handles is used
+    /// for, such as unmapping the frames, doesn't leave the other one
+    /// misstating their state for longer than it's kept around.
+    pub(crate) unsafe fn unchecked_alias(&self) -> Self
     where
-        SizeBits: IsEqual<PageBits, Output = True>,
+        Page<State, GranuleBits>: Clone,
     {
-        Cap {
-            cptr: self.caps.start_cptr,
-            cap_data: self.caps.start_cap_data,
-            _role: PhantomData,
-        }
+        MemoryRegion::from_caps(
+            CapRange::new(self.caps.start_cptr, self.caps.start_cap_data.clone()),
+            self.kind,
+        )
+    }
+
+    /// Revoke every copy of this region's page capabilities, such as
+    /// those handed out by `share`, which unmaps them wherever they
+    /// were mapped.
+    ///
+    /// The caller must ensure that no region built from those copies
+    /// is used afterwards, since its pages are gone, along with its
+    /// memory if it was mapped.
+    pub unsafe fn revoke(&self, cnode: &LocalCap<CNode<CapRole>>) -> Result<(), SeL4Error> {
+        self.caps.for_each(|page| page.revoke(cnode))
     }
 
     pub fn paddr(&self) -> Result<usize, SeL4Error> {
@@ -159,13 +223,19 @@ where
         rights: CapRights,
     ) -> Result<
         (
-            MemoryRegion<page_state::Unmapped, SizeBits, shared_status::Shared, DestRole>,
-            MemoryRegion<State, SizeBits, shared_status::Shared, CapRole>,
+            MemoryRegion<
+                page_state::Unmapped,
+                SizeBits,
+                shared_status::Shared,
+                DestRole,
+                GranuleBits,
+            >,
+            MemoryRegion<State, SizeBits, shared_status::Shared, CapRole, GranuleBits>,
         ),
         VSpaceError,
     >
     where
-        CNodeSlotCount: IsEqual<NumPages<SizeBits>, Output = True>,
+        CNodeSlotCount: IsEqual<NumGranules<SizeBits, GranuleBits>, Output = True>,
     {
         let pages_offset = self.caps.start_cptr;
         let original_mapped_state = self.caps.start_cap_data.state;
@@ -181,6 +251,7 @@ where
                     pages_offset,
                     Page {
                         state: original_mapped_state,
+                        _granule: PhantomData,
                     },
                 ),
                 self.kind,
@@ -189,6 +260,77 @@ where
     }
 }
 
+/// A region made of a single granule is backed by one capability, so
+/// it can be sent over IPC. The receiver's copy shares its memory with
+/// the sender's, and is assumed to be general memory rather than a
+/// device's.
+impl<SizeBits: Unsigned> Transferable
+    for UnmappedMemoryRegion<SizeBits, shared_status::Shared, role::Local, SizeBits>
+where
+    SizeBits: IsGreaterOrEqual<SizeBits>,
+    SizeBits: Sub<SizeBits>,
+    <SizeBits as Sub<SizeBits>>::Output: Unsigned,
+    <SizeBits as Sub<SizeBits>>::Output: _Pow,
+    Pow<<SizeBits as Sub<SizeBits>>::Output>: Unsigned,
+{
+    type ReceiveSlot = LocalCNodeSlot;
+
+    fn cptr(&self) -> Option<usize> {
+        Some(self.caps.start_cptr)
+    }
+
+    fn receive_slot(slot: &LocalCNodeSlot) -> Option<&LocalCNodeSlot> {
+        Some(slot)
+    }
+
+    unsafe fn received(slot: LocalCNodeSlot) -> Self {
+        let (_, offset, _) = slot.elim();
+        MemoryRegion::unchecked_new(offset, page_state::Unmapped, WeakMemoryKind::General)
+    }
+}
+
+impl<State: PageState, SizeBits: Unsigned, SS: SharedStatus, CapRole: CNodeRole>
+    MemoryRegion<State, SizeBits, SS, CapRole>
+where
+    SizeBits: IsGreaterOrEqual<PageBits>,
+    SizeBits: Sub<PageBits>,
+    <SizeBits as Sub<PageBits>>::Output: Unsigned,
+    <SizeBits as Sub<PageBits>>::Output: _Pow,
+    Pow<<SizeBits as Sub<PageBits>>::Output>: Unsigned,
+{
+    pub fn weaken(self) -> WeakMemoryRegion<State, SS, CapRole> {
+        WeakMemoryRegion::try_from_caps(self.caps.weaken(), self.kind, SizeBits::U8)
+            .expect("Cap page slots to memory region size invariant maintained by type signature")
+    }
+
+    /// N.B. until MemoryKind tracking is added to Page, this is a lossy
+    /// conversion that will assume the Region was for General memory
+    pub(crate) fn to_page(self) -> LocalCap<Page<State>>
+    where
+        SizeBits: IsEqual<PageBits, Output = True>,
+    {
+        Cap {
+            cptr: self.caps.start_cptr,
+            cap_data: self.caps.start_cap_data,
+            _role: PhantomData,
+        }
+    }
+
+    /// Like `to_page`, but leaves the region in place for callers that
+    /// only need to name the page.
+    pub(crate) fn as_page(&self) -> LocalCap<Page<State>>
+    where
+        SizeBits: IsEqual<PageBits, Output = True>,
+        Page<State>: Clone,
+    {
+        Cap {
+            cptr: self.caps.start_cptr,
+            cap_data: self.caps.start_cap_data.clone(),
+            _role: PhantomData,
+        }
+    }
+}
+
 impl LocalCap<Page<page_state::Unmapped>> {
     /// N.B. until MemoryKind tracking is added to Page, this is a lossy
     /// conversion that will assume the Page was for General memory
@@ -235,21 +377,78 @@ where
         Ok(UnmappedMemoryRegion::from_caps(page_caps, kind.weaken()))
     }
 
+    /// Like `to_shared`, except that every address space the region is
+    /// mapped into only gets to read it. A `Pager` looking after one of
+    /// those address spaces gives it a private copy of any page it
+    /// writes to, so the pages stay shared until they're modified.
+    pub fn to_copy_on_write(self) -> UnmappedMemoryRegion<SizeBits, shared_status::CopyOnWrite> {
+        UnmappedMemoryRegion::from_caps(self.caps, self.kind)
+    }
+}
+
+impl<SizeBits: Unsigned, GranuleBits: Unsigned>
+    UnmappedMemoryRegion<SizeBits, shared_status::Exclusive, role::Local, GranuleBits>
+where
+    SizeBits: IsGreaterOrEqual<GranuleBits>,
+    SizeBits: Sub<GranuleBits>,
+    <SizeBits as Sub<GranuleBits>>::Output: Unsigned,
+    <SizeBits as Sub<GranuleBits>>::Output: _Pow,
+    Pow<<SizeBits as Sub<GranuleBits>>::Output>: Unsigned,
+{
+    /// Retype the memory into `1 << GranuleBits` byte frames rather
+    /// than pages, so that it takes fewer slots to hold and is mapped
+    /// with large pages, sections or blocks.
+    pub fn new_with_granules(
+        ut: LocalCap<Untyped<SizeBits>>,
+        slots: LocalCNodeSlots<NumGranules<SizeBits, GranuleBits>>,
+    ) -> Result<Self, crate::error::SeL4Error>
+    where
+        Page<page_state::Unmapped, GranuleBits>: DirectRetype,
+        Pow<<SizeBits as Sub<GranuleBits>>::Output>:
+            IsLessOrEqual<KernelRetypeFanOutLimit, Output = True>,
+    {
+        let kind = ut.cap_data.kind;
+        let granule_caps = ut.retype_granules(slots)?;
+        Ok(UnmappedMemoryRegion::from_caps(granule_caps, kind.weaken()))
+    }
+
+    /// Like `new_with_granules`, but with the biggest granule that the
+    /// region's size allows rather than one picked by the caller. A
+    /// region smaller than any large page is made of pages as usual.
+    ///
+    /// The untyped is aligned to its own size, so every frame is too,
+    /// and `VSpace::map_region` picks an address aligned to match.
+    pub fn new_with_largest_granules(
+        ut: LocalCap<Untyped<SizeBits>>,
+        slots: LocalCNodeSlots<NumGranules<SizeBits, GranuleBits>>,
+    ) -> Result<Self, crate::error::SeL4Error>
+    where
+        SizeBits: LargestGranule<Bits = GranuleBits>,
+        Page<page_state::Unmapped, GranuleBits>: DirectRetype,
+        Pow<<SizeBits as Sub<GranuleBits>>::Output>:
+            IsLessOrEqual<KernelRetypeFanOutLimit, Output = True>,
+    {
+        Self::new_with_granules(ut, slots)
+    }
+
     /// A shared region of memory can be duplicated. When it is
     /// mapped, it's _borrowed_ rather than consumed allowing for its
     /// remapping into other address spaces.
-    pub fn to_shared(self) -> UnmappedMemoryRegion<SizeBits, shared_status::Shared> {
+    pub fn to_shared(
+        self,
+    ) -> UnmappedMemoryRegion<SizeBits, shared_status::Shared, role::Local, GranuleBits> {
         UnmappedMemoryRegion::from_caps(self.caps, self.kind)
     }
 }
 
-impl<SizeBits: Unsigned, SS: SharedStatus> MappedMemoryRegion<SizeBits, SS>
+impl<SizeBits: Unsigned, SS: SharedStatus, GranuleBits: Unsigned>
+    MappedMemoryRegion<SizeBits, SS, role::Local, GranuleBits>
 where
-    SizeBits: IsGreaterOrEqual<PageBits>,
-    SizeBits: Sub<PageBits>,
-    <SizeBits as Sub<PageBits>>::Output: Unsigned,
-    <SizeBits as Sub<PageBits>>::Output: _Pow,
-    Pow<<SizeBits as Sub<PageBits>>::Output>: Unsigned,
+    SizeBits: IsGreaterOrEqual<GranuleBits>,
+    SizeBits: Sub<GranuleBits>,
+    <SizeBits as Sub<GranuleBits>>::Output: Unsigned,
+    <SizeBits as Sub<GranuleBits>>::Output: _Pow,
+    Pow<<SizeBits as Sub<GranuleBits>>::Output>: Unsigned,
 {
     pub fn vaddr(&self) -> usize {
         self.caps.start_cap_data.state.vaddr
@@ -274,7 +473,7 @@ where
     pub fn flush(&self) -> Result<(), SeL4Error> {
         self.caps.for_each::<SeL4Error, _>(|cap| {
             unsafe {
-                arch::flush_page(cap.cptr)?;
+                arch::flush_page::<GranuleBits>(cap.cptr)?;
             }
             Ok(())
         })?;
@@ -283,13 +482,14 @@ where
     }
 
     pub fn flush_range(&self, vaddr: usize, size: usize) -> Result<(), SeL4Error> {
-        let bottom = vaddr & !0xFFF;
-        let top = vaddr + cmp::max(PageBytes::USIZE, size);
+        let granule_bytes = 1 << GranuleBits::USIZE;
+        let bottom = vaddr & !(granule_bytes - 1);
+        let top = vaddr + cmp::max(granule_bytes, size);
         let range = bottom..top;
         self.caps.for_each::<SeL4Error, _>(|cap| {
             if range.contains(&cap.vaddr()) {
                 unsafe {
-                    arch::flush_page(cap.cptr)?;
+                    arch::flush_page::<GranuleBits>(cap.cptr)?;
                 }
             }
             Ok(())
@@ -311,7 +511,16 @@ where
             self.kind,
         )
     }
+}
 
+impl<SizeBits: Unsigned, SS: SharedStatus> MappedMemoryRegion<SizeBits, SS>
+where
+    SizeBits: IsGreaterOrEqual<PageBits>,
+    SizeBits: Sub<PageBits>,
+    <SizeBits as Sub<PageBits>>::Output: Unsigned,
+    <SizeBits as Sub<PageBits>>::Output: _Pow,
+    Pow<<SizeBits as Sub<PageBits>>::Output>: Unsigned,
+{
     /// Halve a region into two regions.
     pub fn split(
         self,
@@ -352,6 +561,7 @@ where
                             asid: self.asid(),
                             rights: self.rights(),
                         },
+                        _granule: PhantomData,
                     },
                 ),
                 kind: self.kind,
@@ -367,6 +577,7 @@ where
                             asid: self.asid(),
                             rights: self.rights(),
                         },
+                        _granule: PhantomData,
                     },
                 ),
                 kind: self.kind,
@@ -426,6 +637,7 @@ where
                             asid: a.asid(),
                             rights: a.rights(),
                         },
+                        _granule: PhantomData,
                     },
                 ),
                 kind: a.kind,
@@ -470,7 +682,14 @@ impl<State: PageState, SS: SharedStatus> WeakMemoryRegion<State, SS, role::Local
         let num_pages = num_pages(size_bits)
             .expect("Calling functions maintain the invariant that the size_bits is over the size of a page");
         WeakMemoryRegion {
-            caps: WeakCapRange::new(local_page_caps_offset_cptr, Page { state }, num_pages),
+            caps: WeakCapRange::new(
+                local_page_caps_offset_cptr,
+                Page {
+                    state,
+                    _granule: PhantomData,
+                },
+                num_pages,
+            ),
             kind,
             size_bits,
             _shared_status: PhantomData,
@@ -503,7 +722,7 @@ impl<State: PageState, SS: SharedStatus, CapRole: CNodeRole> WeakMemoryRegion<St
         })
     }
 
-    pub(super) fn as_strong<SizeBits: Unsigned>(
+    pub(crate) fn as_strong<SizeBits: Unsigned>(
         self,
     ) -> Result<MemoryRegion<State, SizeBits, SS, CapRole>, VSpaceError>
     where
@@ -531,6 +750,24 @@ impl<State: PageState, SS: SharedStatus, CapRole: CNodeRole> WeakMemoryRegion<St
             _shared_status: PhantomData,
         }
     }
+
+    /// Another handle to the same frames. See
+    /// `MemoryRegion::unchecked_alias` for what the caller must ensure.
+    pub(crate) unsafe fn unchecked_alias(&self) -> Self
+    where
+        Page<State>: Clone,
+    {
+        WeakMemoryRegion {
+            caps: WeakCapRange::new(
+                self.caps.start_cptr,
+                self.caps.start_cap_data.clone(),
+                self.caps.len(),
+            ),
+            kind: self.kind,
+            size_bits: self.size_bits,
+            _shared_status: PhantomData,
+        }
+    }
 }
 
 impl<SS: SharedStatus, CapRole: CNodeRole> WeakMappedMemoryRegion<SS, CapRole> {